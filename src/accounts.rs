@@ -0,0 +1,209 @@
+//! Named accounts from a config file.
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::{client::ClientCLIConfig, non_empty_string::NonEmptyString, session::LoginCLIConfig};
+
+/// Accounts CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct AccountsCLIConfig {
+    /// Config file with named accounts.
+    ///
+    /// TOML file with one `[accounts.<name>]` table per account. Every table has a `username`
+    /// and may have a credential (`password`, `password_file`, or `recover_code`), a `server_url`,
+    /// and a `session_cache`.
+    #[clap(long, env = "TUTANOTA_CLI_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Name of the account in the config file.
+    ///
+    /// Values from the config file take precedence over arguments and environment variables.
+    #[clap(long, env = "TUTANOTA_CLI_ACCOUNT", requires = "config")]
+    account: Option<String>,
+}
+
+impl AccountsCLIConfig {
+    /// Apply selected account, if any, to the other configs.
+    pub(crate) async fn apply(
+        &self,
+        client_cfg: &mut ClientCLIConfig,
+        login_cfg: &mut LoginCLIConfig,
+    ) -> Result<()> {
+        let (Some(path), Some(name)) = (&self.config, &self.account) else {
+            return Ok(());
+        };
+
+        let config = ConfigFile::load(path)
+            .await
+            .with_context(|| format!("load config file `{}`", path.display()))?;
+        let account = config
+            .accounts
+            .get(name)
+            .with_context(|| format!("account not found: `{name}`"))?;
+        account.apply(name, client_cfg, login_cfg);
+
+        Ok(())
+    }
+}
+
+/// Config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    accounts: BTreeMap<String, Account>,
+}
+
+impl ConfigFile {
+    async fn load(path: &Path) -> Result<Self> {
+        let s = tokio::fs::read_to_string(path).await.context("read")?;
+        Self::parse(&s)
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        let config: Self = toml::from_str(s).context("parse")?;
+        for (name, account) in &config.accounts {
+            account
+                .check()
+                .with_context(|| format!("account `{name}`"))?;
+        }
+        Ok(config)
+    }
+}
+
+/// Single account in the config file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Account {
+    username: NonEmptyString,
+    password: Option<NonEmptyString>,
+    password_file: Option<PathBuf>,
+    recover_code: Option<NonEmptyString>,
+    server_url: Option<String>,
+    session_cache: Option<PathBuf>,
+}
+
+impl Account {
+    /// Check that at most one credential is set.
+    fn check(&self) -> Result<()> {
+        let credentials = [
+            self.password.is_some(),
+            self.password_file.is_some(),
+            self.recover_code.is_some(),
+        ];
+        ensure!(
+            credentials.into_iter().filter(|set| *set).count() <= 1,
+            "only one of `password`, `password_file`, and `recover_code` may be set"
+        );
+        Ok(())
+    }
+
+    fn apply(&self, name: &str, client_cfg: &mut ClientCLIConfig, login_cfg: &mut LoginCLIConfig) {
+        login_cfg.username = Some(self.username.clone());
+
+        if self.password.is_some() || self.password_file.is_some() || self.recover_code.is_some() {
+            login_cfg.password = self.password.clone();
+            login_cfg.password_file = self.password_file.clone();
+            login_cfg.password_stdin = false;
+            login_cfg.recover_code = self.recover_code.clone();
+        }
+
+        if let Some(server_url) = &self.server_url {
+            client_cfg.server_url = server_url.clone();
+        }
+
+        // every account gets its own session cache
+        if let Some(session_cache) = &self.session_cache {
+            login_cfg.session_cache = Some(session_cache.clone());
+        } else if let Some(session_cache) = &login_cfg.session_cache {
+            let mut path = session_cache.as_os_str().to_owned();
+            path.push(format!(".{name}"));
+            login_cfg.session_cache = Some(path.into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Parser)]
+    struct Args {
+        #[clap(flatten)]
+        client_cfg: ClientCLIConfig,
+
+        #[clap(flatten)]
+        login_cfg: LoginCLIConfig,
+    }
+
+    const CONFIG: &str = r#"
+[accounts.work]
+username = "work@example.com"
+password_file = "/secret/work"
+server_url = "https://tuta.example.com"
+
+[accounts.private]
+username = "me@example.com"
+"#;
+
+    #[test]
+    fn test_apply() {
+        let config = ConfigFile::parse(CONFIG).unwrap();
+
+        let mut args = Args::parse_from([
+            "x",
+            "--username=other@example.com",
+            "--password=foo",
+            "--session-cache=session.json",
+        ]);
+        config.accounts["work"].apply("work", &mut args.client_cfg, &mut args.login_cfg);
+        assert_eq!(args.login_cfg.username.as_deref(), Some("work@example.com"));
+        assert_eq!(args.login_cfg.password, None);
+        assert_eq!(
+            args.login_cfg.password_file,
+            Some(PathBuf::from("/secret/work"))
+        );
+        assert_eq!(args.client_cfg.server_url, "https://tuta.example.com");
+        assert_eq!(
+            args.login_cfg.session_cache,
+            Some(PathBuf::from("session.json.work"))
+        );
+
+        // credentials are kept if the account has none
+        let mut args = Args::parse_from(["x", "--password=foo"]);
+        config.accounts["private"].apply("private", &mut args.client_cfg, &mut args.login_cfg);
+        assert_eq!(args.login_cfg.username.as_deref(), Some("me@example.com"));
+        assert_eq!(args.login_cfg.password.as_deref(), Some("foo"));
+        assert_eq!(args.login_cfg.session_cache, None);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = toml::from_str::<ConfigFile>("[accounts.a]\nuser = \"x\"").unwrap_err();
+        assert!(err.to_string().contains("unknown field `user`"), "{err}");
+
+        let err = toml::from_str::<ConfigFile>("[accounts.a]\nusername = \"\"").unwrap_err();
+        assert!(err.to_string().contains("cannot be empty"), "{err}");
+
+        for credentials in [
+            "password = \"x\"\nrecover_code = \"y\"",
+            "password = \"x\"\npassword_file = \"/p\"",
+        ] {
+            let err = ConfigFile::parse(&format!(
+                "[accounts.a]\nusername = \"me@example.com\"\n{credentials}"
+            ))
+            .unwrap_err();
+            assert_eq!(
+                format!("{err:#}"),
+                "account `a`: only one of `password`, `password_file`, and `recover_code` may be \
+                 set",
+            );
+        }
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::{bail, Context, Result};
 use reqwest::Method;
 
 use crate::{
-    client::{Client, Prefix, Request, DEFAULT_HOST},
+    client::{Client, Prefix, Request},
     proto::{
         enums::ArchiveDataType,
         messages::{
@@ -32,13 +32,13 @@ pub(crate) async fn get_mail_blob(
     let resp: Vec<MailDetailsBlob> = client
         .do_json(Request {
             method: Method::GET,
-            host: &access.server_url,
+            host: Some(&access.server_url),
             prefix: Prefix::Tutanota,
             path: &format!("maildetailsblob/{archive_id}"),
             data: &(),
             access_token: None,
             query: &[
-                ("accessToken", &session.access_token.to_string()),
+                ("accessToken", &session.access_token.expose()),
                 ("ids", &[blob_id].join(",")),
                 ("blobAccessToken", &access.blob_access_token),
             ],
@@ -62,7 +62,7 @@ pub(crate) async fn get_mail_draft_blob(
     let resp: Vec<MailDetailsBlob> = client
         .do_json(Request {
             method: Method::GET,
-            host: DEFAULT_HOST,
+            host: None,
             prefix: Prefix::Tutanota,
             path: &format!("maildetailsdraft/{archive_id}"),
             data: &(),
@@ -97,16 +97,17 @@ pub(crate) async fn get_attachment_blob(
     .await
     .context("get blob access")?;
 
+    // attachments can be large, so a broken connection should not start over
     let data = client
-        .do_bytes(Request {
+        .do_bytes_resumable(Request {
             method: Method::GET,
-            host: &access.server_url,
+            host: Some(&access.server_url),
             prefix: Prefix::Storage,
             path: "blobservice",
             data: &(),
             access_token: None,
             query: &[
-                ("accessToken", &session.access_token.to_string()),
+                ("accessToken", &session.access_token.expose()),
                 ("blobAccessToken", &access.blob_access_token),
                 (
                     "_body",
@@ -153,7 +154,7 @@ async fn get_access(
     let resp: BlobAccessTokenServiceResponse = client
         .do_json(Request {
             method: Method::POST,
-            host: DEFAULT_HOST,
+            host: None,
             prefix: Prefix::Storage,
             path: "blobaccesstokenservice",
             data: &req,
@@ -0,0 +1,538 @@
+//! Calendar export.
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Utc};
+use chrono_tz::Tz;
+use clap::Parser;
+use futures::{Stream, StreamExt, TryStreamExt};
+use tracing::{debug, info, warn};
+
+use crate::{
+    client::{Client, Prefix},
+    content_lines::{escape_text, ContentLines},
+    crypto::encryption::{decrypt_key, decrypt_value},
+    file_output::write_to_file,
+    proto::{
+        binary::Base64String,
+        enums::GroupType,
+        ids::CUSTOM_MIN_ID,
+        keys::Key,
+        messages::{CalendarEventResponse, CalendarGroupRootResponse, CalendarRepeatRule},
+    },
+    session::{GroupKeys, Session},
+};
+
+/// Download calendar CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct DownloadCalendarCLIConfig {
+    /// Target iCalendar file that holds the events of all calendars.
+    #[clap(long, action)]
+    path: PathBuf,
+}
+
+/// Decrypted calendar event.
+#[derive(Debug)]
+pub(crate) struct Event {
+    uid: String,
+    summary: String,
+    description: String,
+    location: String,
+    start: DateTime<Utc>,
+
+    /// Exclusive end.
+    end: DateTime<Utc>,
+    repeat: Option<RepeatRule>,
+}
+
+#[derive(Debug)]
+struct RepeatRule {
+    frequency: Frequency,
+    interval: u32,
+    end: RepeatEnd,
+
+    /// Time zone in which the event repeats, `None` if it is missing or unknown.
+    time_zone: Option<Tz>,
+
+    /// Start times of occurrences that were deleted.
+    excluded: Vec<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Annually,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RepeatEnd {
+    Never,
+    Count(u32),
+
+    /// Exclusive end, like the end of an event.
+    Until(DateTime<Utc>),
+}
+
+impl Event {
+    /// List events of all calendars of the account, including shared ones.
+    pub(crate) async fn list(
+        client: &Client,
+        session: &Session,
+    ) -> Result<impl Stream<Item = Result<Self>>> {
+        let mut lists = vec![];
+        for membership in session
+            .user_data
+            .memberships
+            .iter()
+            .filter(|m| m.group_type == GroupType::Calendar)
+        {
+            let root: CalendarGroupRootResponse = client
+                .get_entity(None, &membership.group, Some(&session.access_token))
+                .await
+                .context("get calendar group root")?;
+            debug!(
+                group = membership.group.as_str(),
+                short_events = root.short_events.as_str(),
+                long_events = root.long_events.as_str(),
+                "calendar found",
+            );
+
+            // events that span multiple days are stored separately
+            lists.push(root.short_events);
+            lists.push(root.long_events);
+        }
+
+        let group_keys = Arc::clone(&session.group_keys);
+        let streams = lists
+            .into_iter()
+            .map(|list| {
+                client.stream::<CalendarEventResponse>(
+                    Prefix::Tutanota,
+                    &format!("calendarevent/{list}"),
+                    Some(&session.access_token),
+                    Some(CUSTOM_MIN_ID),
+                    false,
+                )
+            })
+            .collect::<Vec<_>>();
+        let stream = futures::stream::iter(streams).flatten().and_then(move |e| {
+            let group_keys = Arc::clone(&group_keys);
+            async move { Self::decode(e, &group_keys) }
+        });
+
+        Ok(stream)
+    }
+
+    fn decode(resp: CalendarEventResponse, group_keys: &GroupKeys) -> Result<Self> {
+        let session_key = decrypt_key(
+            group_keys
+                .get(&resp.owner_group, resp.owner_key_version)
+                .context("getting owner group key")?,
+            resp.owner_enc_session_key,
+        )
+        .context("decrypting session key")?;
+        let dec = |v: &Base64String| decrypt_string(session_key, v);
+
+        let uid = match &resp.uid {
+            Some(uid) => dec(uid).context("decrypt uid")?,
+            None => String::new(),
+        };
+        let repeat = resp
+            .repeat_rule
+            .as_ref()
+            .map(|r| RepeatRule::decode(r, session_key))
+            .transpose()
+            .context("decrypt repeat rule")?;
+
+        Ok(Self {
+            uid: if uid.is_empty() {
+                resp.id[1].clone()
+            } else {
+                uid
+            },
+            summary: dec(&resp.summary).context("decrypt summary")?,
+            description: dec(&resp.description).context("decrypt description")?,
+            location: dec(&resp.location).context("decrypt location")?,
+            start: decrypt_date(session_key, &resp.start_time).context("decrypt start time")?,
+            end: decrypt_date(session_key, &resp.end_time).context("decrypt end time")?,
+            repeat,
+        })
+    }
+
+    /// Events without time are stored from midnight to midnight in UTC.
+    fn is_all_day(&self) -> bool {
+        self.start < self.end && is_utc_midnight(self.start) && is_utc_midnight(self.end)
+    }
+
+    /// Time zone of the local times, only set for recurring events that are not all-day.
+    fn time_zone(&self) -> Option<Tz> {
+        if self.is_all_day() {
+            return None;
+        }
+        self.repeat.as_ref().and_then(|r| r.time_zone)
+    }
+
+    /// Format date as `DATE` for all-day events, as local `DATE-TIME` in the
+    /// [time zone](Self::time_zone) of the event if it has one, and in UTC otherwise.
+    fn format_date(&self, date: DateTime<Utc>) -> String {
+        if self.is_all_day() {
+            date.format("%Y%m%d").to_string()
+        } else if let Some(tz) = self.time_zone() {
+            date.with_timezone(&tz).format("%Y%m%dT%H%M%S").to_string()
+        } else {
+            format_utc(date)
+        }
+    }
+
+    /// Emit event as `VEVENT` component.
+    ///
+    /// `stamp` is the creation time of the iCalendar object. Recurring events that are not all-day
+    /// use local times with the `TZID` of their repeat rule, so that they keep their time across
+    /// daylight saving time changes. The `TZID` is the IANA name, which calendar clients resolve
+    /// themselves, no `VTIMEZONE` is emitted. Other times are in UTC.
+    fn vevent(&self, lines: &mut ContentLines, stamp: DateTime<Utc>) {
+        let date_name = |name: &str| {
+            if self.is_all_day() {
+                format!("{name};VALUE=DATE")
+            } else if let Some(tz) = self.time_zone() {
+                format!("{name};TZID={}", tz.name())
+            } else {
+                name.to_owned()
+            }
+        };
+
+        lines.push("BEGIN", "VEVENT");
+        lines.push("UID", &escape_text(&self.uid));
+        lines.push("DTSTAMP", &format_utc(stamp));
+        lines.push(&date_name("DTSTART"), &self.format_date(self.start));
+        lines.push(&date_name("DTEND"), &self.format_date(self.end));
+        lines.push("SUMMARY", &escape_text(&self.summary));
+        if !self.location.is_empty() {
+            lines.push("LOCATION", &escape_text(&self.location));
+        }
+        if !self.description.is_empty() {
+            lines.push("DESCRIPTION", &escape_text(&self.description));
+        }
+        if let Some(repeat) = &self.repeat {
+            let freq = match repeat.frequency {
+                Frequency::Daily => "DAILY",
+                Frequency::Weekly => "WEEKLY",
+                Frequency::Monthly => "MONTHLY",
+                Frequency::Annually => "YEARLY",
+            };
+            let mut rule = format!("FREQ={freq};INTERVAL={}", repeat.interval);
+            match repeat.end {
+                RepeatEnd::Never => {}
+                RepeatEnd::Count(count) => {
+                    rule.push_str(&format!(";COUNT={count}"));
+                }
+                RepeatEnd::Until(until) => {
+                    // `UNTIL` is inclusive and must be in UTC if the start has a time zone
+                    let until = if self.is_all_day() {
+                        self.format_date(until - Duration::days(1))
+                    } else {
+                        format_utc(until - Duration::seconds(1))
+                    };
+                    rule.push_str(&format!(";UNTIL={until}"));
+                }
+            }
+            lines.push("RRULE", &rule);
+
+            if !repeat.excluded.is_empty() {
+                let dates = repeat
+                    .excluded
+                    .iter()
+                    .map(|d| self.format_date(*d))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                lines.push(&date_name("EXDATE"), &dates);
+            }
+        }
+        lines.push("END", "VEVENT");
+    }
+}
+
+impl RepeatRule {
+    fn decode(resp: &CalendarRepeatRule, session_key: Key) -> Result<Self> {
+        let frequency = match decrypt_string(session_key, &resp.frequency)
+            .context("decrypt frequency")?
+            .as_str()
+        {
+            "0" => Frequency::Daily,
+            "1" => Frequency::Weekly,
+            "2" => Frequency::Monthly,
+            "3" => Frequency::Annually,
+            other => bail!("unknown frequency: {other}"),
+        };
+        let interval = decrypt_string(session_key, &resp.interval)
+            .context("decrypt interval")?
+            .parse()
+            .context("invalid interval")?;
+        let end_value = || {
+            resp.end_value
+                .as_ref()
+                .context("end value missing")
+                .and_then(|v| decrypt_string(session_key, v).context("decrypt end value"))
+        };
+        let end = match decrypt_string(session_key, &resp.end_type)
+            .context("decrypt end type")?
+            .as_str()
+        {
+            "0" => RepeatEnd::Never,
+            "1" => RepeatEnd::Count(end_value()?.parse().context("invalid count")?),
+            "2" => RepeatEnd::Until(parse_millis(&end_value()?).context("invalid end date")?),
+            other => bail!("unknown end type: {other}"),
+        };
+        let time_zone = match &resp.time_zone {
+            Some(v) => {
+                let name = decrypt_string(session_key, v).context("decrypt time zone")?;
+                parse_time_zone(&name)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            frequency,
+            interval,
+            end,
+            time_zone,
+            excluded: resp.excluded_dates.iter().map(|d| d.date.0).collect(),
+        })
+    }
+}
+
+/// Parse IANA time zone name, unknown ones fall back to UTC.
+fn parse_time_zone(name: &str) -> Option<Tz> {
+    if name.is_empty() {
+        return None;
+    }
+    match name.parse() {
+        Ok(tz) => Some(tz),
+        Err(e) => {
+            warn!(time_zone = name, %e, "unknown time zone, using UTC");
+            None
+        }
+    }
+}
+
+fn is_utc_midnight(date: DateTime<Utc>) -> bool {
+    date.timestamp() % 86_400 == 0 && date.timestamp_subsec_nanos() == 0
+}
+
+fn format_utc(date: DateTime<Utc>) -> String {
+    date.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+fn decrypt_string(session_key: Key, value: &Base64String) -> Result<String> {
+    String::from_utf8(decrypt_value(session_key, value)?).context("invalid UTF8 string")
+}
+
+/// Dates are encrypted as string of milliseconds since the Unix epoch.
+fn decrypt_date(session_key: Key, value: &Base64String) -> Result<DateTime<Utc>> {
+    parse_millis(&decrypt_string(session_key, value)?)
+}
+
+fn parse_millis(s: &str) -> Result<DateTime<Utc>> {
+    let millis = s.parse().context("invalid number")?;
+    DateTime::from_timestamp_millis(millis).with_context(|| format!("invalid time: {millis}"))
+}
+
+/// Emit events as iCalendar object.
+fn vcalendar(events: &[Event], stamp: DateTime<Utc>) -> String {
+    let mut lines = ContentLines::default();
+    lines.push("BEGIN", "VCALENDAR");
+    lines.push("VERSION", "2.0");
+    lines.push("PRODID", "-//tatutanatata//EN");
+    for event in events {
+        event.vevent(&mut lines, stamp);
+    }
+    lines.push("END", "VCALENDAR");
+    lines.into_string()
+}
+
+/// Download events of all calendars into a single iCalendar file.
+pub(crate) async fn download_calendar(
+    client: &Client,
+    session: &Session,
+    cfg: DownloadCalendarCLIConfig,
+) -> Result<()> {
+    let events = Event::list(client, session)
+        .await
+        .context("get events")?
+        .try_collect::<Vec<_>>()
+        .await
+        .context("poll event")?;
+
+    let ics = vcalendar(&events, Utc::now());
+    write_to_file(ics.as_bytes(), &cfg.path)
+        .await
+        .with_context(|| format!("write iCalendar file: `{}`", cfg.path.display()))?;
+
+    info!(
+        events = events.len(),
+        path = %cfg.path.display(),
+        "calendar downloaded",
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_vcalendar() {
+        let events = [
+            Event {
+                uid: "meeting@example.com".to_owned(),
+                summary: "Weekly sync; team".to_owned(),
+                description: "Agenda:\n- news".to_owned(),
+                location: "Room 1".to_owned(),
+                start: date("2024-03-04T10:00:00Z"),
+                end: date("2024-03-04T10:30:00Z"),
+                repeat: Some(RepeatRule {
+                    frequency: Frequency::Weekly,
+                    interval: 2,
+                    end: RepeatEnd::Until(date("2024-06-01T00:00:00Z")),
+                    time_zone: None,
+                    excluded: vec![date("2024-03-18T10:00:00Z"), date("2024-04-01T10:00:00Z")],
+                }),
+            },
+            Event {
+                uid: "birthday@example.com".to_owned(),
+                summary: "Birthday".to_owned(),
+                description: "".to_owned(),
+                location: "".to_owned(),
+                start: date("2024-05-06T00:00:00Z"),
+                end: date("2024-05-07T00:00:00Z"),
+                repeat: Some(RepeatRule {
+                    frequency: Frequency::Annually,
+                    interval: 1,
+                    end: RepeatEnd::Never,
+                    time_zone: None,
+                    excluded: vec![],
+                }),
+            },
+            Event {
+                uid: "trip@example.com".to_owned(),
+                summary: "Trip".to_owned(),
+                description: "".to_owned(),
+                location: "".to_owned(),
+                start: date("2024-07-01T00:00:00Z"),
+                end: date("2024-07-08T00:00:00Z"),
+                repeat: Some(RepeatRule {
+                    frequency: Frequency::Daily,
+                    interval: 1,
+                    end: RepeatEnd::Until(date("2024-07-03T00:00:00Z")),
+                    time_zone: None,
+                    excluded: vec![date("2024-07-02T00:00:00Z")],
+                }),
+            },
+            Event {
+                uid: "call@example.com".to_owned(),
+                summary: "Call".to_owned(),
+                description: "".to_owned(),
+                location: "".to_owned(),
+                start: date("2024-08-01T00:00:00Z"),
+                end: date("2024-08-01T01:00:00Z"),
+                repeat: Some(RepeatRule {
+                    frequency: Frequency::Monthly,
+                    interval: 1,
+                    end: RepeatEnd::Count(3),
+                    time_zone: None,
+                    excluded: vec![],
+                }),
+            },
+            Event {
+                uid: "standup@example.com".to_owned(),
+                summary: "Standup".to_owned(),
+                description: "".to_owned(),
+                location: "".to_owned(),
+                start: date("2024-03-25T09:00:00Z"),
+                end: date("2024-03-25T09:15:00Z"),
+                repeat: Some(RepeatRule {
+                    frequency: Frequency::Daily,
+                    interval: 1,
+                    end: RepeatEnd::Until(date("2024-04-03T00:00:00Z")),
+                    time_zone: Some(Tz::Europe__Berlin),
+                    // after the switch to daylight saving time
+                    excluded: vec![date("2024-04-01T08:00:00Z")],
+                }),
+            },
+        ];
+
+        let ics = vcalendar(&events, date("2024-01-02T03:04:05Z"));
+        insta::assert_snapshot!(ics.replace("\r\n", "\n"), @r###"
+        BEGIN:VCALENDAR
+        VERSION:2.0
+        PRODID:-//tatutanatata//EN
+        BEGIN:VEVENT
+        UID:meeting@example.com
+        DTSTAMP:20240102T030405Z
+        DTSTART:20240304T100000Z
+        DTEND:20240304T103000Z
+        SUMMARY:Weekly sync\; team
+        LOCATION:Room 1
+        DESCRIPTION:Agenda:\n- news
+        RRULE:FREQ=WEEKLY;INTERVAL=2;UNTIL=20240531T235959Z
+        EXDATE:20240318T100000Z,20240401T100000Z
+        END:VEVENT
+        BEGIN:VEVENT
+        UID:birthday@example.com
+        DTSTAMP:20240102T030405Z
+        DTSTART;VALUE=DATE:20240506
+        DTEND;VALUE=DATE:20240507
+        SUMMARY:Birthday
+        RRULE:FREQ=YEARLY;INTERVAL=1
+        END:VEVENT
+        BEGIN:VEVENT
+        UID:trip@example.com
+        DTSTAMP:20240102T030405Z
+        DTSTART;VALUE=DATE:20240701
+        DTEND;VALUE=DATE:20240708
+        SUMMARY:Trip
+        RRULE:FREQ=DAILY;INTERVAL=1;UNTIL=20240702
+        EXDATE;VALUE=DATE:20240702
+        END:VEVENT
+        BEGIN:VEVENT
+        UID:call@example.com
+        DTSTAMP:20240102T030405Z
+        DTSTART:20240801T000000Z
+        DTEND:20240801T010000Z
+        SUMMARY:Call
+        RRULE:FREQ=MONTHLY;INTERVAL=1;COUNT=3
+        END:VEVENT
+        BEGIN:VEVENT
+        UID:standup@example.com
+        DTSTAMP:20240102T030405Z
+        DTSTART;TZID=Europe/Berlin:20240325T100000
+        DTEND;TZID=Europe/Berlin:20240325T101500
+        SUMMARY:Standup
+        RRULE:FREQ=DAILY;INTERVAL=1;UNTIL=20240402T235959Z
+        EXDATE;TZID=Europe/Berlin:20240401T100000
+        END:VEVENT
+        END:VCALENDAR
+        "###);
+    }
+
+    #[test]
+    fn test_parse_time_zone() {
+        assert_eq!(parse_time_zone("Europe/Berlin"), Some(Tz::Europe__Berlin));
+        assert_eq!(parse_time_zone(""), None);
+        assert_eq!(parse_time_zone("Mars/Olympus_Mons"), None);
+    }
+
+    #[test]
+    fn test_is_utc_midnight() {
+        assert!(is_utc_midnight(date("2024-01-01T00:00:00Z")));
+        assert!(!is_utc_midnight(date("2024-01-01T00:00:00.001Z")));
+        assert!(!is_utc_midnight(date("2024-01-01T00:00:00+01:00")));
+    }
+}
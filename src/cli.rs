@@ -0,0 +1,301 @@
+//! Command line interface.
+use std::{ffi::OsString, path::PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
+use futures::TryStreamExt;
+
+use crate::{
+    accounts::AccountsCLIConfig,
+    calendar::{download_calendar, DownloadCalendarCLIConfig},
+    client::{Client, ClientCLIConfig},
+    completions::{write_completions, CompletionsCLIConfig},
+    constants::VERSION_STRING,
+    contacts::{
+        download_contacts, list_contacts, DownloadContactsCLIConfig, ListContactsCLIConfig,
+    },
+    count::{count, CountCLIConfig},
+    download::{
+        download, download_one, sync, DownloadCLIConfig, DownloadOneCLIConfig, SyncCLIConfig,
+    },
+    folders::{folder_line, folder_tree, Folder, ListFoldersCLIConfig},
+    imap::{serve_imap, ServeImapCLIConfig},
+    logging::{setup_logging, LoggingCLIConfig},
+    manpage::{write_manpages, ManpageCLIConfig},
+    raw::{raw_get, RawGetCLIConfig},
+    retry::{retry_failed, RetryFailedCLIConfig},
+    session::{LoginCLIConfig, LogoutAllCLIConfig, Session},
+    signal::FutureSignalExt,
+    verify::{verify_archive, VerifyArchiveCLIConfig},
+    whoami::{list_aliases, whoami, ListAliasesCLIConfig, WhoamiCLIConfig},
+};
+
+pub use crate::exit_code::exit_code;
+
+/// CLI args.
+#[derive(Debug, Parser)]
+#[command(
+    about = "CLI (Command Line Interface) for Tutanota/Tuta, mostly meant for mass export.",
+    version = VERSION_STRING,
+)]
+pub(crate) struct Args {
+    /// Logging config.
+    #[clap(flatten)]
+    logging_cfg: LoggingCLIConfig,
+
+    /// Load environment variables from given file instead of `.env` in the current directory.
+    ///
+    /// Unlike the implicit `.env`, the file must exist. Variables that are already set take
+    /// precedence.
+    //
+    // read by `env_file_arg` before parsing, so that the file can provide values for other args
+    #[allow(dead_code)]
+    #[clap(long)]
+    env_file: Option<PathBuf>,
+
+    /// Dump JSON responses of server to given folder.
+    ///
+    /// This is useful for development and debugging.
+    #[clap(long)]
+    debug_dump_json_to: Option<PathBuf>,
+
+    /// Accounts config.
+    #[clap(flatten)]
+    accounts_cfg: AccountsCLIConfig,
+
+    /// HTTP client config.
+    #[clap(flatten)]
+    client_cfg: ClientCLIConfig,
+
+    /// Login config.
+    #[clap(flatten)]
+    login_cfg: LoginCLIConfig,
+
+    /// Command
+    #[clap(subcommand)]
+    command: Command,
+}
+
+/// Command
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List folders.
+    ListFolders(ListFoldersCLIConfig),
+
+    /// Download emails for given folder.
+    Download(DownloadCLIConfig),
+
+    /// Download a single email by its ID to an EML file.
+    DownloadOne(DownloadOneCLIConfig),
+
+    /// Download emails again that failed with `download --keep-going --failed-file=<file>`.
+    RetryFailed(RetryFailedCLIConfig),
+
+    /// Download emails that are newer than the ones of the last sync, e.g. for nightly backups.
+    Sync(SyncCLIConfig),
+
+    /// Count emails in given folder without downloading them.
+    Count(CountCLIConfig),
+
+    /// Print account details, e.g. to check that the login works.
+    Whoami(WhoamiCLIConfig),
+
+    /// List the mail addresses of the account, i.e. the primary address and all aliases.
+    ListAliases(ListAliasesCLIConfig),
+
+    /// List all sessions of the account and delete them, e.g. after a device was lost.
+    LogoutAll(LogoutAllCLIConfig),
+
+    /// List contacts.
+    ListContacts(ListContactsCLIConfig),
+
+    /// Download all contacts into a single vCard file.
+    DownloadContacts(DownloadContactsCLIConfig),
+
+    /// Download events of all calendars into a single iCalendar file.
+    DownloadCalendar(DownloadCalendarCLIConfig),
+
+    /// Check downloaded EML files for damage and, optionally, for mails that are missing.
+    ///
+    /// This only logs in to Tutanota if a folder is selected.
+    VerifyArchive(VerifyArchiveCLIConfig),
+
+    /// Serve downloaded Maildirs and mbox files via a local read-only IMAP server (experimental).
+    ///
+    /// This does not log in to Tutanota.
+    ServeImap(ServeImapCLIConfig),
+
+    /// Print shell completion script to stdout.
+    ///
+    /// This does not log in to Tutanota.
+    Completions(CompletionsCLIConfig),
+
+    /// Print the raw response of an authenticated GET request, meant for debugging the API.
+    #[command(hide = true)]
+    RawGet(RawGetCLIConfig),
+
+    /// Print man page in roff format to stdout, meant for packagers.
+    ///
+    /// This does not log in to Tutanota.
+    #[command(hide = true)]
+    Manpage(ManpageCLIConfig),
+}
+
+/// Find `--env-file` in raw command line arguments.
+fn env_file_arg(args: impl Iterator<Item = OsString>) -> Option<PathBuf> {
+    let mut args = args.skip(1);
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        } else if arg == "--env-file" {
+            path = args.next().map(PathBuf::from);
+        } else if let Some(p) = arg.to_str().and_then(|arg| arg.strip_prefix("--env-file=")) {
+            path = Some(PathBuf::from(p));
+        }
+    }
+    path
+}
+
+/// Run CLI with arguments from the command line.
+///
+/// This is the entry point of the `tatutanatata` binary.
+pub async fn run() -> Result<()> {
+    match env_file_arg(std::env::args_os()) {
+        Some(path) => {
+            dotenvy::from_path(&path)
+                .with_context(|| format!("load env file: `{}`", path.display()))?;
+        }
+        None => {
+            dotenvy::dotenv().ok();
+        }
+    }
+    let mut args = Args::parse();
+    setup_logging(args.logging_cfg).context("logging setup")?;
+
+    match args.command {
+        Command::ServeImap(cfg) => {
+            return serve_imap(cfg)
+                .cancel_on_signal()
+                .await
+                .context("serve IMAP");
+        }
+        Command::Completions(cfg) => {
+            write_completions(cfg, &mut std::io::stdout());
+            return Ok(());
+        }
+        Command::Manpage(cfg) => {
+            return write_manpages(cfg).await.context("write man pages");
+        }
+        Command::VerifyArchive(cfg) if !cfg.needs_login() => {
+            return verify_archive(None, cfg)
+                .cancel_on_signal()
+                .await
+                .context("verify archive");
+        }
+        _ => {}
+    }
+
+    args.accounts_cfg
+        .apply(&mut args.client_cfg, &mut args.login_cfg)
+        .await
+        .context("apply account")?;
+
+    let client = Client::try_new(args.client_cfg, args.debug_dump_json_to)
+        .await
+        .context("set up client")?;
+
+    let session = Session::login(args.login_cfg, &client)
+        .await
+        .context("perform login")?;
+
+    let cmd_res = exec_cmd(&client, &session, args.command)
+        .cancel_on_signal()
+        .await
+        .context("execute command");
+    let logout_res = session.logout(&client).await.context("logout");
+
+    match (cmd_res, logout_res) {
+        (Err(e), _) => Err(e),
+        (_, Err(e)) => Err(e),
+        (Ok(()), Ok(())) => Ok(()),
+    }
+}
+
+async fn exec_cmd(client: &Client, session: &Session, cmd: Command) -> Result<()> {
+    match cmd {
+        Command::ListFolders(cfg) => {
+            let folders = Folder::list(client, session)
+                .await
+                .context("get folders")?
+                .try_collect::<Vec<_>>()
+                .await
+                .context("poll folder")?;
+
+            if cfg.json {
+                for f in &folders {
+                    println!("{}", serde_json::to_string(f).context("serialize folder")?);
+                }
+                return Ok(());
+            }
+
+            let folders = if cfg.flat {
+                folders.iter().map(|f| (0, f)).collect()
+            } else {
+                folder_tree(&folders)
+            };
+            for (depth, f) in folders {
+                println!("{}", folder_line(depth, f, cfg.with_ids));
+            }
+
+            Ok(())
+        }
+        Command::Download(cfg) => download(client, session, cfg).await,
+        Command::DownloadOne(cfg) => download_one(client, session, cfg).await,
+        Command::RetryFailed(cfg) => retry_failed(client, session, cfg).await,
+        Command::Sync(cfg) => sync(client, session, cfg).await,
+        Command::Count(cfg) => count(client, session, cfg).await,
+        Command::Whoami(cfg) => whoami(client, session, cfg).await,
+        Command::ListAliases(cfg) => list_aliases(client, session, cfg).await,
+        Command::LogoutAll(cfg) => session.logout_all(client, cfg).await,
+        Command::ListContacts(cfg) => list_contacts(client, session, cfg).await,
+        Command::DownloadContacts(cfg) => download_contacts(client, session, cfg).await,
+        Command::DownloadCalendar(cfg) => download_calendar(client, session, cfg).await,
+        Command::VerifyArchive(cfg) => verify_archive(Some((client, session)), cfg).await,
+        Command::RawGet(cfg) => raw_get(client, session, cfg).await,
+        Command::ServeImap(_) | Command::Completions(_) | Command::Manpage(_) => {
+            unreachable!("handled before login")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_file(args: &[&str]) -> Option<PathBuf> {
+        env_file_arg(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn test_env_file_arg() {
+        assert_eq!(env_file(&["x", "list-folders"]), None);
+        assert_eq!(
+            env_file(&["x", "--env-file", "/etc/secrets.env", "list-folders"]),
+            Some(PathBuf::from("/etc/secrets.env")),
+        );
+        assert_eq!(
+            env_file(&["x", "-v", "--env-file=a.env", "sync", "--path=out"]),
+            Some(PathBuf::from("a.env")),
+        );
+        assert_eq!(env_file(&["x", "--env-file"]), None);
+        assert_eq!(env_file(&["x", "--", "--env-file=a.env"]), None);
+        assert_eq!(env_file(&["--env-file=a.env"]), None, "program name");
+    }
+
+    #[test]
+    fn test_env_file_parses() {
+        let args = Args::parse_from(["x", "--env-file=a.env", "list-folders"]);
+        assert_eq!(args.env_file, Some(PathBuf::from("a.env")));
+    }
+}
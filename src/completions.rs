@@ -0,0 +1,39 @@
+//! Shell completions.
+use std::io::Write;
+
+use clap::{CommandFactory, Parser};
+use clap_complete::{generate, Shell};
+
+use crate::cli::Args;
+
+/// Completions CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct CompletionsCLIConfig {
+    /// Shell to generate the completion script for.
+    #[clap(value_enum)]
+    shell: Shell,
+}
+
+/// Write completion script for the configured shell.
+pub(crate) fn write_completions(cfg: CompletionsCLIConfig, out: &mut dyn Write) {
+    generate(cfg.shell, &mut Args::command(), env!("CARGO_PKG_NAME"), out);
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    #[test]
+    fn test_write_completions() {
+        for shell in Shell::value_variants() {
+            let mut out = vec![];
+            write_completions(CompletionsCLIConfig { shell: *shell }, &mut out);
+
+            let script = String::from_utf8(out).unwrap();
+            assert!(script.contains("download-contacts"), "{shell}");
+            assert!(script.contains("verbose-http"), "{shell}");
+        }
+    }
+}
@@ -1,4 +1,19 @@
+//! Compression of mail content and output archives.
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
 use anyhow::{Context, Result};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use clap::ValueEnum;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use tracing::warn;
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::file_output::rename;
 
 pub(crate) fn decompress_value(v: &[u8]) -> Result<Vec<u8>> {
     if v.is_empty() {
@@ -26,9 +41,253 @@ pub(crate) fn decompress_value(v: &[u8]) -> Result<Vec<u8>> {
     }
 }
 
+/// Archive format for `--compress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum ArchiveFormat {
+    /// Gzip-compressed tar archive.
+    Gzip,
+
+    /// Zip archive.
+    Zip,
+}
+
+/// Archive that is written entry by entry.
+///
+/// Like [`AtomicFile`](crate::file_output::AtomicFile), the archive is written to a temporary
+/// file that is only moved to the target path by [`finish`](Self::finish), so an interrupted
+/// run never leaves a truncated archive behind. Entries are compressed on the blocking thread
+/// pool.
+#[derive(Debug)]
+pub(crate) struct ArchiveWriter {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    /// `None` once finished.
+    inner: Arc<Mutex<Option<ArchiveInner>>>,
+}
+
+enum ArchiveInner {
+    Tar(Box<tar::Builder<GzEncoder<BufWriter<File>>>>),
+    Zip(Box<ZipWriter<BufWriter<File>>>),
+}
+
+impl std::fmt::Debug for ArchiveInner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tar(_) => f.debug_tuple("Tar").finish_non_exhaustive(),
+            Self::Zip(_) => f.debug_tuple("Zip").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl ArchiveWriter {
+    /// Create temporary archive file for given target path.
+    pub(crate) async fn create(path: &Path, format: ArchiveFormat) -> Result<Self> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".part");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let f = tokio::fs::File::create(&tmp_path)
+            .await
+            .context("create temp archive")?
+            .into_std()
+            .await;
+        let f = BufWriter::new(f);
+        let inner = match format {
+            ArchiveFormat::Gzip => {
+                let mut builder = tar::Builder::new(GzEncoder::new(f, Compression::default()));
+                builder.mode(tar::HeaderMode::Deterministic);
+                ArchiveInner::Tar(Box::new(builder))
+            }
+            ArchiveFormat::Zip => ArchiveInner::Zip(Box::new(ZipWriter::new(f))),
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            tmp_path,
+            inner: Arc::new(Mutex::new(Some(inner))),
+        })
+    }
+
+    /// Append file with given relative name and modification time.
+    pub(crate) async fn append(
+        &self,
+        name: &Path,
+        mtime: DateTime<Utc>,
+        data: Vec<u8>,
+    ) -> Result<()> {
+        let inner = Arc::clone(&self.inner);
+        let name = name
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        tokio::task::spawn_blocking(move || {
+            let mut inner = inner.lock().expect("not poisoned");
+            match inner.as_mut().context("archive already finished")? {
+                ArchiveInner::Tar(builder) => {
+                    append_tar_entry(builder, &name, mtime.timestamp().max(0) as u64, &data)?;
+                }
+                ArchiveInner::Zip(zip) => {
+                    let options = SimpleFileOptions::default()
+                        .compression_method(CompressionMethod::Deflated)
+                        .last_modified_time(zip_date_time(mtime))
+                        .unix_permissions(0o644);
+                    zip.start_file(name, options).context("start zip entry")?;
+                    zip.write_all(&data).context("write zip entry")?;
+                }
+            }
+            Ok(())
+        })
+        .await
+        .context("join archive task")?
+    }
+
+    /// Finalize archive and move it to its target path.
+    ///
+    /// The temporary file is removed on failure.
+    pub(crate) async fn finish(self) -> Result<()> {
+        let inner = Arc::clone(&self.inner);
+        let res = async {
+            tokio::task::spawn_blocking(move || {
+                let inner = inner
+                    .lock()
+                    .expect("not poisoned")
+                    .take()
+                    .context("archive already finished")?;
+                let f = match inner {
+                    ArchiveInner::Tar(builder) => builder
+                        .into_inner()
+                        .context("finish tar")?
+                        .finish()
+                        .context("finish gzip")?,
+                    ArchiveInner::Zip(zip) => zip.finish().context("finish zip")?,
+                };
+                let f = f.into_inner().context("flush temp archive")?;
+                f.sync_all().context("sync temp archive")?;
+                Ok(()) as Result<()>
+            })
+            .await
+            .context("join archive task")??;
+
+            rename(&self.tmp_path, &self.path).await.context("rename")?;
+            Ok(())
+        }
+        .await;
+
+        if res.is_err() {
+            self.abort().await;
+        }
+
+        res
+    }
+
+    /// Discard archive and remove temporary file.
+    pub(crate) async fn abort(self) {
+        drop(self.inner);
+        if let Err(e) = tokio::fs::remove_file(&self.tmp_path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(%e, path=%self.tmp_path.display(), "cannot remove temp file");
+            }
+        }
+    }
+}
+
+/// Write named buffers into a gzip-compressed tar archive.
+///
+/// Names are relative paths with `/` as separator. Returns the writer once the archive is
+/// complete.
+pub fn write_tar_gz<W, I, N>(w: W, entries: I) -> Result<W>
+where
+    W: Write,
+    I: IntoIterator<Item = (N, Vec<u8>)>,
+    N: AsRef<str>,
+{
+    let mut builder = tar::Builder::new(GzEncoder::new(w, Compression::default()));
+    builder.mode(tar::HeaderMode::Deterministic);
+
+    for (name, data) in entries {
+        append_tar_entry(&mut builder, name.as_ref(), 0, &data)?;
+    }
+
+    builder
+        .into_inner()
+        .context("finish tar")?
+        .finish()
+        .context("finish gzip")
+}
+
+/// Read all files of a gzip-compressed tar archive, see [`write_tar_gz`].
+///
+/// Returns names and content in archive order. Entries that are not regular files are skipped.
+pub fn read_tar_gz<R>(r: R) -> Result<Vec<(String, Vec<u8>)>>
+where
+    R: Read,
+{
+    let mut archive = tar::Archive::new(GzDecoder::new(r));
+    let mut out = vec![];
+
+    for entry in archive.entries().context("read tar")? {
+        let mut entry = entry.context("read tar entry")?;
+        if entry.header().entry_type() != tar::EntryType::Regular {
+            continue;
+        }
+
+        let name = entry
+            .path()
+            .context("read tar entry name")?
+            .to_string_lossy()
+            .into_owned();
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry
+            .read_to_end(&mut data)
+            .with_context(|| format!("read tar entry `{name}`"))?;
+        out.push((name, data));
+    }
+
+    Ok(out)
+}
+
+fn append_tar_entry<W>(
+    builder: &mut tar::Builder<W>,
+    name: &str,
+    mtime: u64,
+    data: &[u8],
+) -> Result<()>
+where
+    W: Write,
+{
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    header.set_entry_type(tar::EntryType::Regular);
+    builder
+        .append_data(&mut header, name, data)
+        .with_context(|| format!("append `{name}` to tar"))
+}
+
+/// Convert timestamp to the MS-DOS format used by zip, which cannot represent dates before 1980.
+fn zip_date_time(ts: DateTime<Utc>) -> zip::DateTime {
+    u16::try_from(ts.year())
+        .ok()
+        .and_then(|year| {
+            zip::DateTime::from_date_and_time(
+                year,
+                ts.month() as u8,
+                ts.day() as u8,
+                ts.hour() as u8,
+                ts.minute() as u8,
+                ts.second() as u8,
+            )
+            .ok()
+        })
+        .unwrap_or_default()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::decompress_value;
+    use super::*;
 
     #[test]
     fn test_decompress_value() {
@@ -43,6 +302,98 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn test_archive_writer() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let date = DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+            .unwrap()
+            .to_utc();
+
+        for format in [ArchiveFormat::Gzip, ArchiveFormat::Zip] {
+            let path = dir.path().join(format!("{format:?}"));
+            let archive = ArchiveWriter::create(&path, format).await.unwrap();
+            archive
+                .append(Path::new("a.eml"), date, b"foo".to_vec())
+                .await
+                .unwrap();
+            archive
+                .append(Path::new("sub/b.eml"), date, b"bar".to_vec())
+                .await
+                .unwrap();
+            assert!(!path.exists());
+            archive.finish().await.unwrap();
+            assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+            let f = std::fs::File::open(&path).unwrap();
+            let entries = match format {
+                ArchiveFormat::Gzip => read_tar_gz(f).unwrap(),
+                ArchiveFormat::Zip => {
+                    let mut zip = zip::ZipArchive::new(f).unwrap();
+                    (0..zip.len())
+                        .map(|i| {
+                            let mut entry = zip.by_index(i).unwrap();
+                            let mut data = vec![];
+                            entry.read_to_end(&mut data).unwrap();
+                            (entry.name().unwrap().into_owned(), data)
+                        })
+                        .collect()
+                }
+            };
+            assert_eq!(
+                entries,
+                [
+                    ("a.eml".to_owned(), b"foo".to_vec()),
+                    ("sub/b.eml".to_owned(), b"bar".to_vec()),
+                ],
+            );
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_tar_gz_roundtrip() {
+        let long_name = format!("{}/mail.eml", "x".repeat(150));
+        let entries = vec![
+            ("a.eml".to_owned(), b"foo".to_vec()),
+            ("sub/Hällö 🎉.eml".to_owned(), vec![]),
+            (long_name, vec![0xff; 100_000]),
+        ];
+
+        let compressed = write_tar_gz(vec![], entries.clone()).unwrap();
+        assert_eq!(read_tar_gz(compressed.as_slice()).unwrap(), entries);
+
+        let empty = write_tar_gz(vec![], Vec::<(String, Vec<u8>)>::new()).unwrap();
+        assert_eq!(read_tar_gz(empty.as_slice()).unwrap(), []);
+
+        read_tar_gz(b"foo".as_slice()).unwrap_err();
+    }
+
+    #[test]
+    fn test_zip_date_time() {
+        let dt = zip_date_time(
+            DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                .unwrap()
+                .to_utc(),
+        );
+        assert_eq!(
+            (
+                dt.year(),
+                dt.month(),
+                dt.day(),
+                dt.hour(),
+                dt.minute(),
+                dt.second()
+            ),
+            // two-second resolution
+            (2020, 3, 4, 11, 22, 32),
+        );
+
+        assert_eq!(
+            zip_date_time(DateTime::from_timestamp(0, 0).unwrap()),
+            zip::DateTime::default(),
+        );
+    }
+
     #[track_caller]
     fn assert_compress_value_roundtrip(v: &[u8]) {
         let compressed = compress_value(v);
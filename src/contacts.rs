@@ -0,0 +1,392 @@
+//! Contacts export.
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Context, Result};
+use clap::Parser;
+use futures::{Stream, TryStreamExt};
+use serde::Serialize;
+use tracing::{debug, info};
+
+use crate::{
+    client::{Client, Prefix},
+    content_lines::{escape_text, ContentLines},
+    crypto::encryption::{decrypt_key, decrypt_value},
+    file_output::write_to_file,
+    folders::get_membership,
+    proto::{
+        binary::Base64String,
+        enums::GroupType,
+        keys::Key,
+        messages::{ContactListResponse, ContactResponse, ContactValue, RootInstanceResponse},
+    },
+    session::{GroupKeys, Session},
+};
+
+/// Root ID of the contact list type, i.e. the encoded app name and type ID.
+const CONTACT_LIST_ROOT_ID: &str = "CHR1dGFub3RhAACZ";
+
+/// List contacts CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct ListContactsCLIConfig {
+    /// Print one JSON object per contact and line.
+    #[clap(long, action)]
+    json: bool,
+}
+
+/// Download contacts CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct DownloadContactsCLIConfig {
+    /// Target vCard file that holds all contacts.
+    #[clap(long, action)]
+    path: PathBuf,
+}
+
+/// Decrypted contact.
+#[derive(Debug, Serialize)]
+pub(crate) struct Contact {
+    id: String,
+    first_name: String,
+    last_name: String,
+    nickname: String,
+    title: String,
+    company: String,
+    role: String,
+
+    /// Birthday in ISO 8601 format, either `YYYY-MM-DD` or `--MM-DD` without year.
+    birthday: Option<String>,
+    comment: String,
+    mail_addresses: Vec<LabeledValue>,
+    phone_numbers: Vec<LabeledValue>,
+    addresses: Vec<LabeledValue>,
+}
+
+/// Email address, phone number, or postal address with a label like `work`.
+#[derive(Debug, Serialize)]
+struct LabeledValue {
+    label: String,
+    value: String,
+}
+
+/// Labels of address types, the same for email and postal addresses.
+const ADDRESS_LABELS: &[&str] = &["home", "work", "other"];
+
+/// Labels of phone number types.
+const PHONE_LABELS: &[&str] = &["home", "work", "mobile", "fax", "other"];
+
+impl Contact {
+    /// List all contacts of the account.
+    pub(crate) async fn list(
+        client: &Client,
+        session: &Session,
+    ) -> Result<impl Stream<Item = Result<Self>>> {
+        let group = get_membership(session, GroupType::Contact)
+            .context("get contact group")?
+            .group;
+
+        let root: RootInstanceResponse = client
+            .get_entity(
+                Some(&group),
+                CONTACT_LIST_ROOT_ID,
+                Some(&session.access_token),
+            )
+            .await
+            .context("get contact list root")?;
+        let contact_list: ContactListResponse = client
+            .get_entity(None, &root.reference, Some(&session.access_token))
+            .await
+            .context("get contact list")?;
+        debug!(
+            contacts = contact_list.contacts.as_str(),
+            "contact list found"
+        );
+
+        let group_keys = Arc::clone(&session.group_keys);
+        let stream = client
+            .stream::<ContactResponse>(
+                Prefix::Tutanota,
+                &format!("contact/{}", contact_list.contacts),
+                Some(&session.access_token),
+                None,
+                false,
+            )
+            .and_then(move |c| {
+                let group_keys = Arc::clone(&group_keys);
+                async move { Self::decode(c, &group_keys) }
+            });
+
+        Ok(stream)
+    }
+
+    fn decode(resp: ContactResponse, group_keys: &GroupKeys) -> Result<Self> {
+        let session_key = decrypt_key(
+            group_keys
+                .get(&resp.owner_group, resp.owner_key_version)
+                .context("getting owner group key")?,
+            resp.owner_enc_session_key,
+        )
+        .context("decrypting session key")?;
+        let dec = |v: &Base64String| decrypt_string(session_key, v);
+        let dec_opt = |v: &Option<Base64String>| v.as_ref().map(dec).transpose();
+        let dec_values = |values: &[ContactValue], labels: &[&str]| {
+            values
+                .iter()
+                .map(|v| {
+                    let value_type = dec(&v.value_type).context("decrypt type")?;
+                    let label = match value_type.parse::<usize>().ok().and_then(|t| labels.get(t)) {
+                        Some(label) => (*label).to_owned(),
+                        None => dec(&v.custom_type_name).context("decrypt custom type")?,
+                    };
+                    Ok(LabeledValue {
+                        label,
+                        value: dec(&v.address).context("decrypt value")?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+
+        Ok(Self {
+            id: resp.id[1].clone(),
+            first_name: dec(&resp.first_name).context("decrypt first name")?,
+            last_name: dec(&resp.last_name).context("decrypt last name")?,
+            nickname: dec_opt(&resp.nickname)
+                .context("decrypt nickname")?
+                .unwrap_or_default(),
+            title: dec_opt(&resp.title)
+                .context("decrypt title")?
+                .unwrap_or_default(),
+            company: dec(&resp.company).context("decrypt company")?,
+            role: dec(&resp.role).context("decrypt role")?,
+            birthday: dec_opt(&resp.birthday_iso)
+                .context("decrypt birthday")?
+                .filter(|b| !b.is_empty()),
+            comment: dec(&resp.comment).context("decrypt comment")?,
+            mail_addresses: dec_values(&resp.mail_addresses, ADDRESS_LABELS)
+                .context("decrypt mail addresses")?,
+            phone_numbers: dec_values(&resp.phone_numbers, PHONE_LABELS)
+                .context("decrypt phone numbers")?,
+            addresses: dec_values(&resp.addresses, ADDRESS_LABELS).context("decrypt addresses")?,
+        })
+    }
+
+    /// Name for display, falls back to the company and the first email address.
+    fn display_name(&self) -> String {
+        let name = format!("{} {}", self.first_name, self.last_name);
+        let name = name.trim();
+        if !name.is_empty() {
+            return name.to_owned();
+        }
+        if !self.company.is_empty() {
+            return self.company.clone();
+        }
+        self.mail_addresses
+            .first()
+            .map(|a| a.value.clone())
+            .unwrap_or_default()
+    }
+
+    /// Emit contact as vCard 3.0, which is understood by most address books.
+    fn vcard(&self) -> String {
+        let mut lines = ContentLines::default();
+        lines.push("BEGIN", "VCARD");
+        lines.push("VERSION", "3.0");
+        lines.push("UID", &escape_text(&self.id));
+        lines.push("FN", &escape_text(&self.display_name()));
+        lines.push(
+            "N",
+            &format!(
+                "{};{};;{};",
+                escape_text(&self.last_name),
+                escape_text(&self.first_name),
+                escape_text(&self.title),
+            ),
+        );
+        if !self.nickname.is_empty() {
+            lines.push("NICKNAME", &escape_text(&self.nickname));
+        }
+        if !self.company.is_empty() {
+            lines.push("ORG", &escape_text(&self.company));
+        }
+        if !self.role.is_empty() {
+            lines.push("TITLE", &escape_text(&self.role));
+        }
+        if let Some(birthday) = &self.birthday {
+            lines.push("BDAY", &escape_text(birthday));
+        }
+        for addr in &self.mail_addresses {
+            lines.push(
+                &with_type("EMAIL;TYPE=INTERNET", &addr.label),
+                &escape_text(&addr.value),
+            );
+        }
+        for number in &self.phone_numbers {
+            lines.push(
+                &with_type("TEL", &number.label),
+                &escape_text(&number.value),
+            );
+        }
+        for addr in &self.addresses {
+            // Tuta stores postal addresses as free text, put it into the street component
+            lines.push(
+                &with_type("ADR", &addr.label),
+                &format!(";;{};;;;", escape_text(&addr.value)),
+            );
+        }
+        if !self.comment.is_empty() {
+            lines.push("NOTE", &escape_text(&self.comment));
+        }
+        lines.push("END", "VCARD");
+        lines.into_string()
+    }
+}
+
+/// Add `TYPE` parameter for labels that vCard knows, custom labels are dropped.
+fn with_type(name: &str, label: &str) -> String {
+    let vcard_type = match label {
+        "home" => "HOME",
+        "work" => "WORK",
+        "mobile" => "CELL",
+        "fax" => "FAX",
+        _ => return name.to_owned(),
+    };
+    if name.contains(";TYPE=") {
+        format!("{name},{vcard_type}")
+    } else {
+        format!("{name};TYPE={vcard_type}")
+    }
+}
+
+fn decrypt_string(session_key: Key, value: &Base64String) -> Result<String> {
+    String::from_utf8(decrypt_value(session_key, value)?).context("invalid UTF8 string")
+}
+
+/// List contacts.
+pub(crate) async fn list_contacts(
+    client: &Client,
+    session: &Session,
+    cfg: ListContactsCLIConfig,
+) -> Result<()> {
+    let contacts = Contact::list(client, session)
+        .await
+        .context("get contacts")?
+        .try_collect::<Vec<_>>()
+        .await
+        .context("poll contact")?;
+
+    for c in &contacts {
+        if cfg.json {
+            println!("{}", serde_json::to_string(c).context("serialize contact")?);
+        } else {
+            let emails = c
+                .mail_addresses
+                .iter()
+                .map(|a| a.value.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            println!("{}\t{}", c.display_name(), emails);
+        }
+    }
+
+    Ok(())
+}
+
+/// Download all contacts into a single vCard file.
+pub(crate) async fn download_contacts(
+    client: &Client,
+    session: &Session,
+    cfg: DownloadContactsCLIConfig,
+) -> Result<()> {
+    let contacts = Contact::list(client, session)
+        .await
+        .context("get contacts")?
+        .try_collect::<Vec<_>>()
+        .await
+        .context("poll contact")?;
+
+    let vcards = contacts.iter().map(Contact::vcard).collect::<String>();
+    write_to_file(vcards.as_bytes(), &cfg.path)
+        .await
+        .with_context(|| format!("write vCard file: `{}`", cfg.path.display()))?;
+
+    info!(
+        contacts = contacts.len(),
+        path = %cfg.path.display(),
+        "contacts downloaded",
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labeled(label: &str, value: &str) -> LabeledValue {
+        LabeledValue {
+            label: label.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_vcard() {
+        let contact = Contact {
+            id: "c1".to_owned(),
+            first_name: "Jane".to_owned(),
+            last_name: "Doe".to_owned(),
+            nickname: "".to_owned(),
+            title: "Dr.".to_owned(),
+            company: "ACME, Inc.".to_owned(),
+            role: "".to_owned(),
+            birthday: Some("--03-04".to_owned()),
+            comment: "line 1\nline 2".to_owned(),
+            mail_addresses: vec![
+                labeled("work", "jane@example.com"),
+                labeled("school", "jane@example.edu"),
+            ],
+            phone_numbers: vec![labeled("mobile", "+1 555 0100")],
+            addresses: vec![labeled("home", "Main St. 1\n12345 Town")],
+        };
+
+        insta::assert_snapshot!(contact.vcard().replace("\r\n", "\n"), @r###"
+        BEGIN:VCARD
+        VERSION:3.0
+        UID:c1
+        FN:Jane Doe
+        N:Doe;Jane;;Dr.;
+        ORG:ACME\, Inc.
+        BDAY:--03-04
+        EMAIL;TYPE=INTERNET,WORK:jane@example.com
+        EMAIL;TYPE=INTERNET:jane@example.edu
+        TEL;TYPE=CELL:+1 555 0100
+        ADR;TYPE=HOME:;;Main St. 1\n12345 Town;;;;
+        NOTE:line 1\nline 2
+        END:VCARD
+        "###);
+        assert!(contact.vcard().ends_with("END:VCARD\r\n"));
+    }
+
+    #[test]
+    fn test_display_name() {
+        let mut contact = Contact {
+            id: "c1".to_owned(),
+            first_name: "".to_owned(),
+            last_name: "".to_owned(),
+            nickname: "".to_owned(),
+            title: "".to_owned(),
+            company: "".to_owned(),
+            role: "".to_owned(),
+            birthday: None,
+            comment: "".to_owned(),
+            mail_addresses: vec![labeled("work", "jane@example.com")],
+            phone_numbers: vec![],
+            addresses: vec![],
+        };
+        assert_eq!(contact.display_name(), "jane@example.com");
+
+        contact.company = "ACME".to_owned();
+        assert_eq!(contact.display_name(), "ACME");
+
+        contact.last_name = "Doe".to_owned();
+        assert_eq!(contact.display_name(), "Doe");
+    }
+}
@@ -0,0 +1,91 @@
+//! Content lines as used by vCard (RFC 6350) and iCalendar (RFC 5545).
+
+/// Maximum length of a physical line in octets, excluding the line break.
+const LINE_MAX_OCTETS: usize = 75;
+
+/// Escape text value.
+pub(crate) fn escape_text(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            ';' => out.push_str("\\;"),
+            ',' => out.push_str("\\,"),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\r' | '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Content lines, separated by `\r\n` and folded after [`LINE_MAX_OCTETS`].
+#[derive(Debug, Default)]
+pub(crate) struct ContentLines(String);
+
+impl ContentLines {
+    /// Add line, `value` must already be escaped.
+    ///
+    /// `name` may include parameters, e.g. `EMAIL;TYPE=WORK`.
+    pub(crate) fn push(&mut self, name: &str, value: &str) {
+        let line = format!("{name}:{value}");
+
+        let mut rest = line.as_str();
+        let mut max = LINE_MAX_OCTETS;
+        loop {
+            let mut end = rest.len().min(max);
+            while !rest.is_char_boundary(end) {
+                end -= 1;
+            }
+            let (chunk, tail) = rest.split_at(end);
+            self.0.push_str(chunk);
+            self.0.push_str("\r\n");
+            if tail.is_empty() {
+                break;
+            }
+
+            // continuation lines start with a space that counts towards the limit
+            self.0.push(' ');
+            rest = tail;
+            max = LINE_MAX_OCTETS - 1;
+        }
+    }
+
+    pub(crate) fn into_string(self) -> String {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape_text() {
+        assert_eq!(
+            escape_text("a\\b;c,d\ne\r\nf\rg"),
+            "a\\\\b\\;c\\,d\\ne\\nf\\ng"
+        );
+    }
+
+    #[test]
+    fn test_push() {
+        let mut lines = ContentLines::default();
+        lines.push("BEGIN", "VCARD");
+        lines.push("NOTE", &"ä".repeat(50));
+        let s = lines.into_string();
+
+        let physical = s.split_terminator("\r\n").collect::<Vec<_>>();
+        assert_eq!(physical[0], "BEGIN:VCARD");
+        assert!(physical.iter().all(|l| l.len() <= LINE_MAX_OCTETS));
+        assert!(physical[2..].iter().all(|l| l.starts_with(' ')));
+        assert_eq!(
+            physical[1..]
+                .iter()
+                .map(|l| l.strip_prefix(' ').unwrap_or(l))
+                .collect::<String>(),
+            format!("NOTE:{}", "ä".repeat(50)),
+        );
+    }
+}
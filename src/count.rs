@@ -0,0 +1,95 @@
+//! Mail count.
+use anyhow::{ensure, Context, Result};
+use clap::{ArgGroup, Parser};
+use futures::TryStreamExt;
+
+use crate::{
+    client::Client,
+    filter::FilterCLIConfig,
+    folders::{Folder, SystemFolder},
+    mails::Mail,
+    session::Session,
+};
+
+/// Count CLI config.
+#[derive(Debug, Parser)]
+#[clap(group(
+    ArgGroup::new("selection")
+        .required(true)
+        .args(["folder", "folder_id", "system_folder"]),
+))]
+pub(crate) struct CountCLIConfig {
+    /// Folder name.
+    #[clap(long, action)]
+    folder: Option<String>,
+
+    /// Folder ID, as shown by `list-folders --with-ids`.
+    #[clap(long, action)]
+    folder_id: Option<String>,
+
+    /// System folder, independent of the language of the account.
+    #[clap(long, action, value_enum)]
+    system_folder: Option<SystemFolder>,
+
+    /// Filter config.
+    #[clap(flatten)]
+    filter_cfg: FilterCLIConfig,
+
+    /// Also sum up the size of all attachments.
+    ///
+    /// This needs one additional request per mail that has attachments.
+    #[clap(long, action)]
+    attachment_size: bool,
+}
+
+/// Count mails in the configured folder without downloading them.
+pub(crate) async fn count(client: &Client, session: &Session, cfg: CountCLIConfig) -> Result<()> {
+    let folders = Folder::list(client, session)
+        .await
+        .context("get folders")?
+        .try_collect::<Vec<_>>()
+        .await
+        .context("list folders")?;
+    let folder = Folder::find(
+        &folders,
+        cfg.folder.as_deref(),
+        cfg.folder_id.as_deref(),
+        cfg.system_folder,
+    )?;
+
+    let filter = cfg.filter_cfg.build().context("set up filter")?;
+    ensure!(
+        !filter.needs_details(),
+        "`--to` needs the mail content and is not supported when counting"
+    );
+
+    let start = cfg.filter_cfg.start_cursor();
+    let (count, attachment_size) =
+        Mail::list(client, session, folder, start.as_deref(), false)
+            .try_filter(|mail| futures::future::ready(filter.matches(mail)))
+            .try_fold((0u64, 0u64), |(count, size), mail| {
+                let cfg = &cfg;
+
+                async move {
+                    let size =
+                        if cfg.attachment_size {
+                            size + mail.attachments_size(client, session).await.with_context(
+                                || format!("get attachment size: `{}`", mail.ui_url(client)),
+                            )?
+                        } else {
+                            size
+                        };
+                    Ok((count + 1, size))
+                }
+            })
+            .await
+            .context("list mails")?;
+
+    if cfg.attachment_size {
+        println!("{count} mails, {attachment_size} bytes in attachments");
+    } else {
+        println!("{count}");
+    }
+
+    Ok(())
+}
@@ -1,6 +1,6 @@
 use std::ops::Deref;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, ensure, Context, Result};
 use argon2::PasswordHasher;
 use base64::prelude::*;
 use sha2::{Digest, Sha256};
@@ -10,6 +10,12 @@ use crate::proto::{binary::Base64Url, enums::KdfVersion, keys::Key};
 #[derive(Debug, Clone, Copy)]
 pub(crate) struct UserPassphraseKey(Key);
 
+impl From<Key> for UserPassphraseKey {
+    fn from(k: Key) -> Self {
+        Self(k)
+    }
+}
+
 impl AsRef<Key> for UserPassphraseKey {
     fn as_ref(&self) -> &Key {
         &self.0
@@ -80,14 +86,43 @@ pub(crate) fn derive_passkey(
     }
 }
 
-pub(crate) fn encode_auth_verifier(passkey: &UserPassphraseKey) -> Base64Url {
+pub(crate) fn encode_auth_verifier(key: &Key) -> Base64Url {
     let mut hasher = Sha256::new();
-    hasher.update(passkey.0);
+    hasher.update(key);
     let hashed = hasher.finalize().to_vec();
 
     Base64Url::from(hashed)
 }
 
+/// Parse recovery code.
+///
+/// The code is the hex-encoded recovery key, potentially split into blocks by whitespace.
+pub(crate) fn parse_recover_code(s: &str) -> Result<Key> {
+    let digits = s
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| {
+            c.to_digit(16)
+                .map(|d| d as u8)
+                .with_context(|| format!("invalid hex digit: {c:?}"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    ensure!(digits.len() % 2 == 0, "odd number of hex digits");
+
+    let bytes = digits
+        .chunks_exact(2)
+        .map(|pair| (pair[0] << 4) | pair[1])
+        .collect::<Vec<_>>();
+
+    if let Ok(k) = bytes.as_slice().try_into() {
+        Ok(Key::Aes256(k))
+    } else if let Ok(k) = bytes.as_slice().try_into() {
+        Ok(Key::Aes128(k))
+    } else {
+        bail!("invalid key length: {}", bytes.len())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -111,4 +146,34 @@ mod tests {
             "4PBZ1KNEu2DB1OpKjqz8lRTIxq55ESO05TYmjLAQvM8",
         );
     }
+
+    #[test]
+    fn test_parse_recover_code() {
+        assert_eq!(
+            parse_recover_code("0102030405060708090a0b0c0d0e0f10").unwrap(),
+            Key::Aes128(hex_literal::hex!("0102030405060708090a0b0c0d0e0f10")),
+        );
+        assert_eq!(
+            parse_recover_code(
+                "0102 0304 0506 0708 090A 0B0C 0D0E 0F10\n1112 1314 1516 1718 191a 1b1c 1d1e 1f20"
+            )
+            .unwrap(),
+            Key::Aes256(hex_literal::hex!(
+                "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20"
+            )),
+        );
+
+        assert_eq!(
+            parse_recover_code("0102x").unwrap_err().to_string(),
+            "invalid hex digit: 'x'",
+        );
+        assert_eq!(
+            parse_recover_code("010").unwrap_err().to_string(),
+            "odd number of hex digits",
+        );
+        assert_eq!(
+            parse_recover_code("0102").unwrap_err().to_string(),
+            "invalid key length: 2",
+        );
+    }
 }
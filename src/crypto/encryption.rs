@@ -16,6 +16,12 @@ type HmacSha256 = Hmac<Sha256>;
 
 const IV_LEN: usize = 16;
 
+/// Leading byte of values that are protected by AES-CBC + HMAC-SHA256.
+///
+/// This is the only authenticated format the official client produces, there is no AES-GCM
+/// format (yet).
+const MAC_ENABLED_PREFIX: u8 = 1;
+
 pub(crate) fn decrypt_key(encryption_key: Key, key_to_be_decrypted: EncryptedKey) -> Result<Key> {
     let encrypted = match key_to_be_decrypted {
         EncryptedKey::Aes128NoMac(_) | EncryptedKey::Aes256NoMac(_) => {
@@ -26,7 +32,9 @@ pub(crate) fn decrypt_key(encryption_key: Key, key_to_be_decrypted: EncryptedKey
             data.extend_from_slice(key_to_be_decrypted.as_ref());
             data
         }
-        EncryptedKey::Aes128WithMac(_) => key_to_be_decrypted.deref().to_vec(),
+        EncryptedKey::Aes128WithMac(_) | EncryptedKey::Aes256WithMac(_) => {
+            key_to_be_decrypted.deref().to_vec()
+        }
     };
 
     let decrypted = decrypt(encryption_key, &encrypted, false)?;
@@ -35,27 +43,45 @@ pub(crate) fn decrypt_key(encryption_key: Key, key_to_be_decrypted: EncryptedKey
         EncryptedKey::Aes128NoMac(_) | EncryptedKey::Aes128WithMac(_) => {
             Ok(Key::Aes128(decrypted.try_into().expect("checked length")))
         }
-        EncryptedKey::Aes256NoMac(_) => {
+        EncryptedKey::Aes256NoMac(_) | EncryptedKey::Aes256WithMac(_) => {
             Ok(Key::Aes256(decrypted.try_into().expect("checked length")))
         }
     }
 }
 
+/// Decrypt value, e.g. a mail body or an attachment blob.
+///
+/// The MAC is verified if present. Values encrypted with AES-256 must always have a MAC (like in
+/// the official client), otherwise a truncated or extended value would silently skip
+/// verification.
 pub(crate) fn decrypt_value(encryption_key: Key, value: &[u8]) -> Result<Vec<u8>> {
     if value.is_empty() {
         return Ok(vec![]);
     }
 
+    if matches!(encryption_key, Key::Aes256(_)) && !has_mac(value) {
+        bail!("mac missing")
+    }
+
     decrypt(encryption_key, value, true)
 }
 
+/// MAC-protected values have an odd length because of the leading version byte.
+fn has_mac(value: &[u8]) -> bool {
+    value.len() % 2 == 1
+}
+
 fn decrypt(encryption_key: Key, value: &[u8], padding: bool) -> Result<Vec<u8>> {
-    let (encryption_key, value) = if value.len() % 2 == 1 {
+    let (encryption_key, value) = if has_mac(value) {
         // use mac
         const MAC_LEN: usize = 32;
         if value.len() < MAC_LEN + 1 {
             bail!("mac missing")
         }
+        let version = value[0];
+        if version != MAC_ENABLED_PREFIX {
+            bail!("unsupported ciphertext format: {version}")
+        }
         let payload = &value[1..(value.len() - MAC_LEN)];
         let mac = &value[value.len() - MAC_LEN..];
         let subkeys = Subkeys::from(encryption_key);
@@ -141,12 +167,126 @@ impl From<Key> for Subkeys {
     }
 }
 
+/// Encrypt value with a random IV and a MAC.
+pub(crate) fn encrypt_value(encryption_key: Key, value: &[u8]) -> Result<Vec<u8>> {
+    let mut iv = [0u8; IV_LEN];
+    getrandom::getrandom(&mut iv)
+        .map_err(|e| anyhow!("{e}"))
+        .context("generate IV")?;
+    Ok(encrypt(encryption_key, iv, value, true, true))
+}
+
+/// Encrypt like the official client does.
+pub(crate) fn encrypt(
+    key: Key,
+    iv: [u8; IV_LEN],
+    plain: &[u8],
+    padding: bool,
+    mac: bool,
+) -> Vec<u8> {
+    let (encryption_key, mac_key) = if mac {
+        let subkeys = Subkeys::from(key);
+        (subkeys.encryption_key, Some(subkeys.mac_key))
+    } else {
+        (key, None)
+    };
+
+    use cbc::cipher::BlockEncryptMut;
+
+    let encrypted = match (encryption_key, padding) {
+        (Key::Aes128(k), true) => cbc::Encryptor::<aes::Aes128>::new(&k.into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(plain),
+        (Key::Aes128(k), false) => cbc::Encryptor::<aes::Aes128>::new(&k.into(), &iv.into())
+            .encrypt_padded_vec_mut::<NoPadding>(plain),
+        (Key::Aes256(k), true) => cbc::Encryptor::<aes::Aes256>::new(&k.into(), &iv.into())
+            .encrypt_padded_vec_mut::<Pkcs7>(plain),
+        (Key::Aes256(k), false) => cbc::Encryptor::<aes::Aes256>::new(&k.into(), &iv.into())
+            .encrypt_padded_vec_mut::<NoPadding>(plain),
+    };
+    let mut payload = iv.to_vec();
+    payload.extend(encrypted);
+
+    match mac_key {
+        Some(mac_key) => {
+            let mut m = HmacSha256::new_from_slice(&mac_key).unwrap();
+            m.update(&payload);
+            let mut out = vec![MAC_ENABLED_PREFIX];
+            out.extend(payload);
+            out.extend(m.finalize().into_bytes());
+            out
+        }
+        None => payload,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use hex_literal::hex;
 
     use super::*;
 
+    #[test]
+    fn test_encrypt_value() {
+        for k in [Key::Aes128([1; 16]), Key::Aes256([2; 32])] {
+            let a = encrypt_value(k, b"foo").unwrap();
+            let b = encrypt_value(k, b"foo").unwrap();
+            assert_ne!(a, b, "random IV");
+            assert_eq!(decrypt_value(k, &a).unwrap(), b"foo");
+            assert_eq!(decrypt_value(k, &b).unwrap(), b"foo");
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_value() {
+        let k128 = Key::Aes128([1; 16]);
+        let k256 = Key::Aes256([2; 32]);
+        let iv = [3; IV_LEN];
+
+        for plain in [&b""[..], b"x", b"hello world, this is longer than a block"] {
+            let v = encrypt(k128, iv, plain, true, false);
+            assert_eq!(decrypt_value(k128, &v).unwrap(), plain);
+
+            let v = encrypt(k128, iv, plain, true, true);
+            assert_eq!(decrypt_value(k128, &v).unwrap(), plain);
+
+            let v = encrypt(k256, iv, plain, true, true);
+            assert_eq!(decrypt_value(k256, &v).unwrap(), plain);
+
+            // AES-256 always requires a MAC
+            let v = encrypt(k256, iv, plain, true, false);
+            assert_eq!(
+                decrypt_value(k256, &v).unwrap_err().to_string(),
+                "mac missing",
+            );
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_key() {
+        let k128 = Key::Aes128([1; 16]);
+        let k256 = Key::Aes256([2; 32]);
+        let fixed_iv = [128u8 + 8; IV_LEN];
+        let iv = [3; IV_LEN];
+
+        // AES-128 encryption keys use a fixed IV and no MAC
+        let encrypted = encrypt(k128, fixed_iv, &k128, false, false);
+        let encrypted = EncryptedKey::Aes128NoMac(encrypted[IV_LEN..].try_into().unwrap());
+        assert_eq!(decrypt_key(k128, encrypted).unwrap(), k128);
+
+        let encrypted = encrypt(k128, fixed_iv, &k256, false, false);
+        let encrypted = EncryptedKey::Aes256NoMac(encrypted[IV_LEN..].try_into().unwrap());
+        assert_eq!(decrypt_key(k128, encrypted).unwrap(), k256);
+
+        // AES-256 encryption keys use a random IV and a MAC
+        let encrypted = encrypt(k256, iv, &k128, false, true);
+        let encrypted = EncryptedKey::Aes128WithMac(encrypted.try_into().unwrap());
+        assert_eq!(decrypt_key(k256, encrypted).unwrap(), k128);
+
+        let encrypted = encrypt(k256, iv, &k256, false, true);
+        let encrypted = EncryptedKey::Aes256WithMac(encrypted.try_into().unwrap());
+        assert_eq!(decrypt_key(k256, encrypted).unwrap(), k256);
+    }
+
     #[test]
     fn test_decrypt_key() {
         assert_eq!(
@@ -203,5 +343,22 @@ mod tests {
             decrypt_value(k, &v_broken).unwrap_err().to_string(),
             "HMAC verification",
         );
+
+        let mut v_unknown_format = v;
+        v_unknown_format[0] = 2;
+        assert_eq!(
+            decrypt_value(k, &v_unknown_format).unwrap_err().to_string(),
+            "unsupported ciphertext format: 2",
+        );
+
+        // dropping a byte must not skip the MAC check
+        assert_eq!(
+            decrypt_value(k, &v[..v.len() - 1]).unwrap_err().to_string(),
+            "mac missing",
+        );
+        assert_eq!(
+            decrypt_value(k, &v[1..]).unwrap_err().to_string(),
+            "mac missing",
+        );
     }
 }
@@ -0,0 +1,1666 @@
+//! Mail download.
+use std::{
+    collections::HashSet,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::{ArgGroup, Parser};
+use futures::{StreamExt, TryStreamExt};
+use indicatif::{HumanBytes, HumanDuration};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tracing::{debug, info, warn};
+
+use crate::{
+    client::Client,
+    compression::{ArchiveFormat, ArchiveWriter},
+    eml::{emit_eml, verify_eml, write_eml},
+    exit_code::ErrorKind,
+    file_output::{
+        attachment_file_names, escape_file_string, portable_file_name, transliterate,
+        write_to_file, AtomicFile, EmlIndex, Maildir, Mbox, OutputFormat,
+    },
+    filename_template::{FilenameTemplate, FilenameValues, NameClaims, DEFAULT_FILENAME_TEMPLATE},
+    filter::{Filter, FilterCLIConfig},
+    folders::{Folder, SystemFolder},
+    json::{emit_json, emit_json_line},
+    mails::{DownloadedMail, Mail},
+    manifest::{Manifest, ManifestEntry},
+    progress::{folder_progress, progress_supported},
+    proto::ids::is_generated_id,
+    resume::ResumeState,
+    retry::{write_failed, FailedMail},
+    session::Session,
+};
+
+/// Download CLI config.
+#[derive(Debug, Parser)]
+#[clap(group(
+    ArgGroup::new("selection")
+        .required(true)
+        .args(["folder", "folder_id", "system_folder", "all_folders"]),
+))]
+#[clap(group(
+    ArgGroup::new("output")
+        .required(true)
+        .args(["path", "stdout"]),
+))]
+pub(crate) struct DownloadCLIConfig {
+    /// Concurrent downloads.
+    #[clap(long, alias = "concurrency", action, default_value_t = NonZeroUsize::new(5).expect("not zero"))]
+    concurrent_downloads: NonZeroUsize,
+
+    /// Folder name.
+    #[clap(long, action)]
+    folder: Option<String>,
+
+    /// Folder ID, as shown by `list-folders --with-ids`.
+    ///
+    /// Use this if the folder name is ambiguous.
+    #[clap(long, action)]
+    folder_id: Option<String>,
+
+    /// System folder, independent of the language of the account.
+    #[clap(long, action, value_enum)]
+    system_folder: Option<SystemFolder>,
+
+    /// Download all folders.
+    ///
+    /// Every folder is written to its own subdirectory of the target path that is named after the
+    /// folder.
+    #[clap(long, action)]
+    all_folders: bool,
+
+    /// Skip this folder in `--all-folders` mode, can be repeated.
+    ///
+    /// Matches the folder name, the folder ID, or a system folder type like `spam` or `trash`.
+    #[clap(long, action, conflicts_with_all = ["folder", "folder_id", "system_folder"])]
+    exclude: Vec<String>,
+
+    /// Continue with the next mail or folder if one fails, report all failures at the end.
+    #[clap(long, action)]
+    keep_going: bool,
+
+    /// Write the IDs of failed mails to this file, so that they can be downloaded with
+    /// `retry-failed`.
+    ///
+    /// The file is overwritten in every run and is empty if no mail failed.
+    #[clap(long, action, requires = "keep_going")]
+    failed_file: Option<PathBuf>,
+
+    /// Target path.
+    #[clap(long, action)]
+    path: Option<PathBuf>,
+
+    /// Write mails to stdout as JSON Lines instead of writing files, e.g. for custom pipelines.
+    ///
+    /// Every line is a JSON object like the ones of `--format=json --json-include-eml`. Lines are
+    /// written as soon as a mail was downloaded, so they are not ordered.
+    #[clap(
+        long,
+        action,
+        conflicts_with_all = [
+            "format",
+            "filename_template",
+            "ascii_filenames",
+            "json_include_eml",
+            "resume",
+            "extract_attachments",
+            "dry_run",
+            "force",
+            "manifest",
+            "json",
+            "compress",
+        ],
+    )]
+    stdout: bool,
+
+    /// Output format.
+    #[clap(long, action, value_enum, default_value_t = OutputFormat::Eml)]
+    format: OutputFormat,
+
+    /// File name template for EML and JSON output, relative to the target path.
+    ///
+    /// Placeholders: `{date}` (receive date), `{subject}` (escaped, first 64 characters), `{id}`
+    /// (mail ID), `{from}` (escaped sender address). Use `/` to create subdirectories. The `.eml`
+    /// or `.json` extension is appended automatically.
+    ///
+    /// Downloaded mails are recorded in an index within the target path, so changing the template
+    /// does not lead to duplicates.
+    #[clap(long, action, default_value = DEFAULT_FILENAME_TEMPLATE)]
+    filename_template: FilenameTemplate,
+
+    /// Only use portable ASCII file and folder names, e.g. for FAT32 or exFAT drives.
+    ///
+    /// Non-ASCII characters are transliterated, characters that are reserved on Windows as well as
+    /// trailing dots and spaces are removed, and reserved device names like `CON` get a `_` suffix.
+    #[clap(long, action)]
+    ascii_filenames: bool,
+
+    /// Add the full mail in EML format as base64 string to JSON output.
+    #[clap(long, action)]
+    json_include_eml: bool,
+
+    /// Filter config.
+    #[clap(flatten)]
+    filter_cfg: FilterCLIConfig,
+
+    /// Resume interrupted download.
+    ///
+    /// The progress is recorded in a state file within the target path. Mails that were moved into
+    /// the folder after the last run but that are older than the last downloaded mail are NOT
+    /// picked up.
+    #[clap(long, action)]
+    resume: bool,
+
+    /// Also write attachments as separate files.
+    ///
+    /// They are placed in a directory next to the EML file that has the same name as the EML file
+    /// (without the extension). For maildir and mbox output, they are placed in a directory
+    /// `attachments/<unique name or mail ID>` within the output directory.
+    #[clap(long, action)]
+    extract_attachments: bool,
+
+    /// Do not download attachments larger than this many bytes.
+    ///
+    /// Skipped attachments are replaced by a short note in EML output and marked as `skipped` in
+    /// JSON output. The size is the one reported by the server, i.e. before decryption.
+    #[clap(long, action)]
+    max_attachment_size: Option<u64>,
+
+    /// Only print the files that would be written, do not download anything.
+    #[clap(long, action)]
+    dry_run: bool,
+
+    /// Write a `.tatutanatata-manifest.json` with the ID, date, subject, sender, file, and size of
+    /// every mail into the output directory of every folder.
+    ///
+    /// Later runs extend the manifest, mails that were deleted locally or on the server are not
+    /// removed from it.
+    #[clap(long, action)]
+    manifest: bool,
+
+    /// Download and overwrite mails that already exist in the output, e.g. to repair files that
+    /// were damaged in a previous run.
+    ///
+    /// Not supported for mbox output.
+    #[clap(long, action)]
+    force: bool,
+
+    /// Only overwrite existing mails with `--force` if they fail the checks of `verify-archive`,
+    /// intact files are kept.
+    ///
+    /// Only supported for EML and Maildir output.
+    #[clap(long, action, requires = "force")]
+    verify: bool,
+
+    /// Download newest mails first.
+    #[clap(long, action, conflicts_with = "resume")]
+    newest_first: bool,
+
+    /// Stop after this many mails were downloaded.
+    ///
+    /// Mails that already exist in the output do not count, unless `--limit-includes-existing` is
+    /// set.
+    #[clap(long, action)]
+    limit: Option<NonZeroUsize>,
+
+    /// Count mails that already exist towards `--limit`.
+    #[clap(long, action, requires = "limit")]
+    limit_includes_existing: bool,
+
+    /// Show progress.
+    ///
+    /// Only has an effect if stdout and stderr are terminals. Disabled for `--dry-run`.
+    #[clap(long, action)]
+    progress: bool,
+
+    /// Count mails before downloading them, so that the progress has a total.
+    ///
+    /// This lists every folder twice.
+    #[clap(long, action, requires = "progress")]
+    progress_total: bool,
+
+    /// Print the final summary as JSON object to stdout instead of text to stderr.
+    #[clap(long, action)]
+    json: bool,
+
+    /// Write all EML files into a single compressed archive at the target path.
+    ///
+    /// File names within the archive follow `--filename-template`. The archive is written from
+    /// scratch in every run and only moved to the target path once it is complete.
+    #[clap(
+        long,
+        action,
+        value_enum,
+        conflicts_with_all = ["all_folders", "resume", "extract_attachments"],
+    )]
+    compress: Option<ArchiveFormat>,
+}
+
+/// Sync CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct SyncCLIConfig {
+    /// Concurrent downloads.
+    #[clap(long, alias = "concurrency", action, default_value_t = NonZeroUsize::new(5).expect("not zero"))]
+    concurrent_downloads: NonZeroUsize,
+
+    /// Only sync the folder with this name instead of all folders.
+    #[clap(long, action, conflicts_with_all = ["folder_id", "system_folder"])]
+    folder: Option<String>,
+
+    /// Only sync the folder with this ID instead of all folders.
+    #[clap(long, action, conflicts_with = "system_folder")]
+    folder_id: Option<String>,
+
+    /// Only sync this system folder instead of all folders.
+    #[clap(long, action, value_enum)]
+    system_folder: Option<SystemFolder>,
+
+    /// Skip this folder when syncing all folders, see `download --help`.
+    #[clap(long, action, conflicts_with_all = ["folder", "folder_id", "system_folder"])]
+    exclude: Vec<String>,
+
+    /// Continue with the next mail or folder if one fails, report all failures at the end.
+    #[clap(long, action)]
+    keep_going: bool,
+
+    /// Write the IDs of failed mails to this file, see `download --help`.
+    #[clap(long, action, requires = "keep_going")]
+    failed_file: Option<PathBuf>,
+
+    /// Target path.
+    ///
+    /// Unless a single folder is selected, every folder is synced to its own subdirectory.
+    #[clap(long, action)]
+    path: PathBuf,
+
+    /// Output format.
+    #[clap(long, action, value_enum, default_value_t = OutputFormat::Eml)]
+    format: OutputFormat,
+
+    /// File name template for EML and JSON output, see `download --help`.
+    #[clap(long, action, default_value = DEFAULT_FILENAME_TEMPLATE)]
+    filename_template: FilenameTemplate,
+
+    /// Only use portable ASCII file and folder names, see `download --help`.
+    #[clap(long, action)]
+    ascii_filenames: bool,
+
+    /// Add the full mail in EML format as base64 string to JSON output.
+    #[clap(long, action)]
+    json_include_eml: bool,
+
+    /// Also write attachments as separate files, see `download --help`.
+    #[clap(long, action)]
+    extract_attachments: bool,
+
+    /// Do not download attachments larger than this many bytes, see `download --help`.
+    #[clap(long, action)]
+    max_attachment_size: Option<u64>,
+
+    /// Write a `.tatutanatata-manifest.json` for every folder, see `download --help`.
+    #[clap(long, action)]
+    manifest: bool,
+
+    /// Only print the files that would be written, do not download anything.
+    #[clap(long, action)]
+    dry_run: bool,
+
+    /// Show progress.
+    #[clap(long, action)]
+    progress: bool,
+
+    /// Print the final summary as JSON object to stdout instead of text to stderr.
+    #[clap(long, action)]
+    json: bool,
+}
+
+impl From<SyncCLIConfig> for DownloadCLIConfig {
+    fn from(cfg: SyncCLIConfig) -> Self {
+        let all_folders =
+            cfg.folder.is_none() && cfg.folder_id.is_none() && cfg.system_folder.is_none();
+        Self {
+            concurrent_downloads: cfg.concurrent_downloads,
+            folder: cfg.folder,
+            folder_id: cfg.folder_id,
+            system_folder: cfg.system_folder,
+            all_folders,
+            exclude: cfg.exclude,
+            keep_going: cfg.keep_going,
+            failed_file: cfg.failed_file,
+            path: Some(cfg.path),
+            stdout: false,
+            format: cfg.format,
+            filename_template: cfg.filename_template,
+            ascii_filenames: cfg.ascii_filenames,
+            json_include_eml: cfg.json_include_eml,
+            filter_cfg: FilterCLIConfig::default(),
+            // the resume state is the per-folder cursor
+            resume: true,
+            extract_attachments: cfg.extract_attachments,
+            max_attachment_size: cfg.max_attachment_size,
+            dry_run: cfg.dry_run,
+            manifest: cfg.manifest,
+            force: false,
+            verify: false,
+            newest_first: false,
+            limit: None,
+            limit_includes_existing: false,
+            progress: cfg.progress,
+            progress_total: false,
+            json: cfg.json,
+            compress: None,
+        }
+    }
+}
+
+/// Download-one CLI config.
+#[derive(Debug, Parser)]
+#[clap(group(
+    ArgGroup::new("output")
+        .required(true)
+        .args(["path", "stdout"]),
+))]
+pub(crate) struct DownloadOneCLIConfig {
+    /// Mail ID.
+    #[clap(long, action, value_parser = parse_generated_id)]
+    id: String,
+
+    /// ID of the mail list that contains the mail.
+    ///
+    /// This is the `mails` field of `list-folders --json`.
+    #[clap(long, action, value_parser = parse_generated_id)]
+    list_id: String,
+
+    /// Target EML file.
+    #[clap(long, action)]
+    path: Option<PathBuf>,
+
+    /// Write EML to stdout instead of a file, e.g. to pipe it into another tool.
+    #[clap(long, action)]
+    stdout: bool,
+}
+
+fn parse_generated_id(s: &str) -> Result<String> {
+    ensure!(
+        is_generated_id(s),
+        "not a valid ID, expected 12 characters of `-`, `_`, digits, and letters"
+    );
+    Ok(s.to_owned())
+}
+
+impl DownloadCLIConfig {
+    /// Target path, empty for `--stdout`.
+    fn path(&self) -> &Path {
+        self.path.as_deref().unwrap_or(Path::new(""))
+    }
+
+    /// Directory that holds the output at `path` and auxiliary files like the resume state.
+    fn output_dir<'a>(&self, path: &'a Path) -> &'a Path {
+        match (self.format, self.compress) {
+            (OutputFormat::Eml | OutputFormat::Json | OutputFormat::Maildir, None) => path,
+            (OutputFormat::Mbox, _) | (_, Some(_)) => match path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent,
+                _ => Path::new("."),
+            },
+        }
+    }
+
+    /// Output path for given folder in `--all-folders` mode.
+    fn folder_path(&self, dir_name: &str) -> PathBuf {
+        let dir = self.path().join(dir_name);
+        match self.format {
+            OutputFormat::Eml | OutputFormat::Json | OutputFormat::Maildir => dir,
+            OutputFormat::Mbox => dir.join(format!("{dir_name}.mbox")),
+        }
+    }
+}
+
+/// What happened to a single mail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MailOutcome {
+    /// Mail was downloaded, or would be downloaded in dry-run mode.
+    Downloaded {
+        /// Bytes written, including extracted attachments.
+        bytes: u64,
+
+        /// Attachments that exceeded `--max-attachment-size`.
+        skipped_attachments: usize,
+    },
+
+    /// Target file already existed.
+    AlreadyExists,
+
+    /// Mail was downloaded but its content did not match the filter.
+    Filtered,
+
+    /// Mail was not processed because `--limit` was reached.
+    LimitReached,
+
+    /// Download or output failed, only used with `--keep-going`.
+    Failed,
+}
+
+/// Output target.
+#[derive(Debug)]
+enum Output {
+    Eml {
+        claims: NameClaims,
+        index: Box<EmlIndex>,
+    },
+    Maildir(Maildir),
+    Mbox(Box<Mbox>),
+    Archive {
+        claims: NameClaims,
+
+        /// `None` in dry-run mode.
+        archive: Option<ArchiveWriter>,
+    },
+
+    /// JSON Lines, shared by concurrent downloads so that lines are not interleaved.
+    Stdout(tokio::sync::Mutex<tokio::io::Stdout>),
+}
+
+/// Location of a single mail within the [`Output`].
+#[derive(Debug)]
+enum Target {
+    File(PathBuf),
+    Maildir { unique: String, path: PathBuf },
+    Mbox { path: PathBuf },
+    Archive { name: PathBuf },
+    Stdout,
+}
+
+impl Target {
+    fn path(&self) -> &Path {
+        match self {
+            Self::File(path) => path,
+            Self::Maildir { path, .. } => path,
+            Self::Mbox { path } => path,
+            Self::Archive { name } => name,
+            Self::Stdout => Path::new("-"),
+        }
+    }
+}
+
+/// Counters over all processed mails.
+#[derive(Debug, Default, Clone)]
+struct Summary {
+    downloaded: usize,
+    already_exists: usize,
+    bytes: u64,
+    skipped_attachments: usize,
+
+    failed: Vec<FailedMail>,
+}
+
+impl Summary {
+    fn add(&mut self, folder: &Folder, mail: &Mail, outcome: MailOutcome) {
+        match outcome {
+            MailOutcome::Downloaded {
+                bytes,
+                skipped_attachments,
+            } => {
+                self.downloaded += 1;
+                self.bytes += bytes;
+                self.skipped_attachments += skipped_attachments;
+            }
+            MailOutcome::AlreadyExists => self.already_exists += 1,
+            MailOutcome::Failed => self.failed.push(FailedMail {
+                list_id: folder.mails.clone(),
+                mail_id: mail.mail_id.clone(),
+            }),
+            MailOutcome::Filtered | MailOutcome::LimitReached => {}
+        }
+    }
+
+    fn merge(&mut self, other: Self) {
+        self.downloaded += other.downloaded;
+        self.already_exists += other.already_exists;
+        self.bytes += other.bytes;
+        self.skipped_attachments += other.skipped_attachments;
+        self.failed.extend(other.failed);
+    }
+
+    /// Write failed mails to `--failed-file`.
+    async fn write_failed(&self, cfg: &DownloadCLIConfig) -> Result<()> {
+        match &cfg.failed_file {
+            Some(path) if !cfg.dry_run => write_failed(path, &self.failed).await,
+            _ => Ok(()),
+        }
+    }
+
+    /// Print summary at the end of a run.
+    fn print(&self, cfg: &DownloadCLIConfig, elapsed: Duration) -> Result<()> {
+        if cfg.json {
+            let json = serde_json::json!({
+                "downloaded": self.downloaded,
+                "already_exists": self.already_exists,
+                "bytes": self.bytes,
+                "skipped_attachments": self.skipped_attachments,
+                "failed": self.failed.iter().map(|m| &m.mail_id).collect::<Vec<_>>(),
+                "elapsed_secs": elapsed.as_secs_f64(),
+                "dry_run": cfg.dry_run,
+            });
+            println!(
+                "{}",
+                serde_json::to_string(&json).context("serialize summary")?
+            );
+        } else if cfg.dry_run {
+            println!(
+                "{} new, {} already exist",
+                self.downloaded, self.already_exists
+            );
+        } else {
+            eprintln!(
+                "downloaded {} mails ({}), {} already existed, took {}",
+                self.downloaded,
+                HumanBytes(self.bytes),
+                self.already_exists,
+                HumanDuration(elapsed),
+            );
+            if self.skipped_attachments > 0 {
+                eprintln!(
+                    "skipped {} attachments that exceed the size limit",
+                    self.skipped_attachments,
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Remaining number of mails that may be processed, shared between concurrent downloads.
+#[derive(Debug)]
+struct Limit(Option<AtomicUsize>);
+
+impl Limit {
+    /// Take one slot, returns `false` if the limit is exhausted.
+    fn take(&self) -> bool {
+        match &self.0 {
+            Some(remaining) => remaining
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok(),
+            None => true,
+        }
+    }
+
+    /// Give back a slot that was taken for a mail that was not written after all.
+    fn release(&self) {
+        if let Some(remaining) = &self.0 {
+            remaining.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+/// Download emails for the configured folder(s).
+pub(crate) async fn download(
+    client: &Client,
+    session: &Session,
+    cfg: DownloadCLIConfig,
+) -> Result<()> {
+    let start = Instant::now();
+    if cfg.compress.is_some() && cfg.format != OutputFormat::Eml {
+        bail!("`--compress` only supports EML output");
+    }
+    if cfg.json_include_eml && cfg.format != OutputFormat::Json {
+        bail!("`--json-include-eml` requires `--format=json`");
+    }
+    if cfg.force && cfg.format == OutputFormat::Mbox {
+        bail!("`--force` cannot overwrite mails in mbox output");
+    }
+    if cfg.verify && !matches!(cfg.format, OutputFormat::Eml | OutputFormat::Maildir) {
+        bail!("`--verify` only supports EML and Maildir output");
+    }
+    let filter = cfg.filter_cfg.build().context("set up filter")?;
+    if cfg.dry_run && filter.needs_details() {
+        bail!("`--to` needs the mail content and cannot be combined with `--dry-run`");
+    }
+
+    let folders = Folder::list(client, session)
+        .await
+        .context("get folders")?
+        .try_collect::<Vec<_>>()
+        .await
+        .context("list folders")?;
+    let limit = Limit(cfg.limit.map(|n| AtomicUsize::new(n.get())));
+
+    if !cfg.all_folders {
+        let folder = Folder::find(
+            &folders,
+            cfg.folder.as_deref(),
+            cfg.folder_id.as_deref(),
+            cfg.system_folder,
+        )?;
+        let summary =
+            download_folder(client, session, &cfg, &filter, &limit, folder, cfg.path()).await?;
+        summary.print(&cfg, start.elapsed())?;
+        summary.write_failed(&cfg).await?;
+        return check_failures(&[], 1, &summary.failed);
+    }
+
+    let folders = exclude_folders(&folders, &cfg.exclude);
+
+    let mut summary = Summary::default();
+    let mut dir_names = HashSet::with_capacity(folders.len());
+    let mut failed = vec![];
+    for folder in &folders {
+        let path = cfg.folder_path(&folder_dir_name(&cfg, folder, &mut dir_names));
+
+        info!(
+            folder = folder.name.as_str(),
+            path = %path.display(),
+            "download folder",
+        );
+        let res = download_folder(client, session, &cfg, &filter, &limit, folder, &path)
+            .await
+            .with_context(|| format!("download folder `{}`", folder.name));
+        match res {
+            Ok(folder_summary) => summary.merge(folder_summary),
+            Err(e) if cfg.keep_going => {
+                warn!(
+                    folder = folder.name.as_str(),
+                    error = format!("{e:#}"),
+                    "folder failed"
+                );
+                failed.push(folder.name.as_str());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    summary.print(&cfg, start.elapsed())?;
+    summary.write_failed(&cfg).await?;
+    check_failures(&failed, folders.len(), &summary.failed)
+}
+
+/// Remove folders that match any `--exclude`.
+fn exclude_folders<'a>(folders: &'a [Folder], exclude: &[String]) -> Vec<&'a Folder> {
+    for exclude in exclude {
+        if !folders.iter().any(|f| f.matches(exclude)) {
+            warn!(exclude = exclude.as_str(), "excluded folder does not exist");
+        }
+    }
+    folders
+        .iter()
+        .filter(|f| {
+            let excluded = exclude.iter().any(|exclude| f.matches(exclude));
+            if excluded {
+                info!(folder = f.name.as_str(), "skip excluded folder");
+            }
+            !excluded
+        })
+        .collect()
+}
+
+/// Directory name for given folder in `--all-folders` mode.
+///
+/// `dir_names` holds the lower-cased names that are already taken.
+fn folder_dir_name(
+    cfg: &DownloadCLIConfig,
+    folder: &Folder,
+    dir_names: &mut HashSet<String>,
+) -> String {
+    // folder names are not unique and may be empty after escaping
+    let mut dir_name = if cfg.ascii_filenames {
+        portable_file_name(&escape_file_string(&transliterate(&folder.name)))
+    } else {
+        escape_file_string(&folder.name)
+    };
+    if dir_name.is_empty() {
+        dir_name = "folder".to_owned();
+    }
+    if !dir_names.insert(dir_name.to_lowercase()) {
+        dir_name = format!("{dir_name} {}", escape_file_string(&folder.id));
+        dir_names.insert(dir_name.to_lowercase());
+    }
+    dir_name
+}
+
+/// Report folders and mails that failed in `--keep-going` mode.
+pub(crate) fn check_failures(
+    failed_folders: &[&str],
+    folders: usize,
+    failed_mails: &[FailedMail],
+) -> Result<()> {
+    let mut msgs = vec![];
+    if !failed_folders.is_empty() {
+        msgs.push(format!(
+            "{} of {} folders failed: {}",
+            failed_folders.len(),
+            folders,
+            failed_folders.join(", "),
+        ));
+    }
+    if !failed_mails.is_empty() {
+        msgs.push(format!(
+            "{} mails failed: {}",
+            failed_mails.len(),
+            failed_mails
+                .iter()
+                .map(|m| m.mail_id.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+        ));
+    }
+
+    if msgs.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(msgs.join("; ")).context(ErrorKind::PartialDownload))
+    }
+}
+
+/// Download mails that are newer than the ones of the last sync.
+///
+/// The newest mail ID of every folder is persisted in the resume state, so only new mails are
+/// listed. Local files are never deleted, even if the mail was deleted on the server.
+pub(crate) async fn sync(client: &Client, session: &Session, cfg: SyncCLIConfig) -> Result<()> {
+    download(client, session, cfg.into()).await
+}
+
+/// Download single mail without listing its folder.
+pub(crate) async fn download_one(
+    client: &Client,
+    session: &Session,
+    cfg: DownloadOneCLIConfig,
+) -> Result<()> {
+    let Some(path) = &cfg.path else {
+        let mail = get_and_download(client, session, &cfg.list_id, &cfg.id).await?;
+        return write_eml(&mail, &mut tokio::io::stdout())
+            .await
+            .context("emit eml");
+    };
+    download_eml(client, session, &cfg.list_id, &cfg.id, path).await
+}
+
+/// Get single mail by ID and download its content.
+async fn get_and_download(
+    client: &Client,
+    session: &Session,
+    list_id: &str,
+    mail_id: &str,
+) -> Result<DownloadedMail> {
+    let mail = Mail::get(client, session, list_id, mail_id).await?;
+
+    info!(mail_id = mail.mail_id.as_str(), "download");
+
+    Arc::new(mail)
+        .download(client, session)
+        .await
+        .context("download mail")
+}
+
+/// Download single mail to an EML file at `path`.
+pub(crate) async fn download_eml(
+    client: &Client,
+    session: &Session,
+    list_id: &str,
+    mail_id: &str,
+    path: &Path,
+) -> Result<()> {
+    let mail = get_and_download(client, session, list_id, mail_id).await?;
+    write_eml_file(&mail, path).await
+}
+
+/// Write downloaded mail to an EML file at `path`, which is replaced atomically.
+pub(crate) async fn write_eml_file(mail: &DownloadedMail, path: &Path) -> Result<()> {
+    debug!(target_file = %path.display(), "write eml");
+
+    let mut f = AtomicFile::create(path, false).await?;
+    match write_eml(mail, f.writer()).await {
+        Ok(()) => {
+            f.commit().await?;
+            Ok(())
+        }
+        Err(e) => {
+            f.abort().await;
+            Err(e).context("emit eml")
+        }
+    }
+}
+
+/// Download emails of a single folder to `path`.
+async fn download_folder(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    filter: &Filter,
+    limit: &Limit,
+    folder: &Folder,
+    path: &Path,
+) -> Result<Summary> {
+    let output_dir = cfg.output_dir(path);
+
+    // ensure output exists
+    if !cfg.dry_run && !cfg.stdout {
+        tokio::fs::create_dir_all(output_dir)
+            .await
+            .context("create output dir")?;
+    }
+
+    debug!(mails = folder.mails.as_str(), "download mails from folder");
+
+    let since_cursor = cfg.filter_cfg.start_cursor();
+    let resume_start = if cfg.resume {
+        ResumeState::load(output_dir, &folder.id)
+            .await
+            .context("load resume state")?
+            .map(|state| state.last_mail_id)
+    } else {
+        None
+    };
+    // both are lower bounds, use the more restrictive one
+    let start = if cfg.newest_first {
+        None
+    } else {
+        resume_start.max(since_cursor.clone())
+    };
+
+    let output = match cfg.format {
+        _ if cfg.stdout => Output::Stdout(tokio::sync::Mutex::new(tokio::io::stdout())),
+        OutputFormat::Eml if cfg.compress.is_some() => Output::Archive {
+            claims: NameClaims::default(),
+            archive: match (cfg.compress, cfg.dry_run) {
+                (Some(format), false) => Some(
+                    ArchiveWriter::create(path, format)
+                        .await
+                        .context("create archive")?,
+                ),
+                _ => None,
+            },
+        },
+        OutputFormat::Eml | OutputFormat::Json => Output::Eml {
+            claims: NameClaims::default(),
+            index: Box::new(
+                EmlIndex::open(path, !cfg.dry_run)
+                    .await
+                    .context("open eml index")?,
+            ),
+        },
+        OutputFormat::Maildir => Output::Maildir(
+            Maildir::open(path, !cfg.dry_run)
+                .await
+                .context("open maildir")?,
+        ),
+        OutputFormat::Mbox => Output::Mbox(Box::new(
+            Mbox::open(path, !cfg.dry_run).await.context("open mbox")?,
+        )),
+    };
+
+    let manifest = if cfg.manifest && !cfg.dry_run {
+        // archives are written from scratch, so their manifest is as well
+        let manifest = if cfg.compress.is_some() {
+            Manifest::new(folder)
+        } else {
+            Manifest::load(output_dir, folder)
+                .await
+                .context("load manifest")?
+        };
+        Some(Mutex::new(manifest))
+    } else {
+        None
+    };
+
+    let progress = if cfg.progress && !cfg.dry_run && progress_supported() {
+        let total = if cfg.progress_total {
+            let total = Mail::list(client, session, folder, start.as_deref(), false)
+                .try_filter(|mail| futures::future::ready(filter.matches(mail)))
+                .try_fold(0u64, |n, _mail| futures::future::ready(Ok(n + 1)))
+                .await
+                .context("count mails")?;
+            Some(match cfg.limit {
+                Some(limit) => total.min(limit.get() as u64),
+                None => total,
+            })
+        } else {
+            None
+        };
+        Some(folder_progress(&folder.name, total))
+    } else {
+        None
+    };
+
+    let progress_ref = progress.as_ref();
+    let summary = Mail::list(client, session, folder, start.as_deref(), cfg.newest_first)
+        // in descending order, the lower bound ends the listing instead
+        .try_take_while(|mail| {
+            futures::future::ready(Ok(since_cursor
+                .as_deref()
+                .map(|cursor| mail.mail_id.as_str() >= cursor)
+                .unwrap_or(true)))
+        })
+        .try_filter(|mail| futures::future::ready(filter.matches(mail)))
+        // Existence checks and limit slots are handled one mail at a time in listing order, so that
+        // concurrent downloads never write a mail that lies beyond the limit.
+        .map(|mail| {
+            let output = &output;
+            let manifest = manifest.as_ref();
+
+            // claim file names in listing order, so that collisions are resolved the same way in
+            // every run
+            let name_taken = match (&mail, output) {
+                (Ok(mail), Output::Eml { claims, .. } | Output::Archive { claims, .. }) => {
+                    !claims.claim(&eml_name(cfg, mail, false), &mail.mail_id)
+                }
+                _ => false,
+            };
+
+            async move {
+                let mail = mail.context("list mail")?;
+                let prepared = prepare_mail(
+                    client, cfg, output, manifest, limit, path, &mail, name_taken,
+                )
+                .await;
+                Ok((mail, prepared)) as Result<_>
+            }
+        })
+        .buffered(1)
+        .map(|item| {
+            let output = &output;
+            let manifest = manifest.as_ref();
+
+            async move {
+                let (mail, prepared) = item?;
+                let res = match prepared {
+                    Ok(Prepared::Done(outcome)) => Ok(outcome),
+                    Ok(Prepared::Download { target, exists }) => {
+                        download_mail(
+                            client, session, cfg, filter, output, manifest, limit, path, &mail,
+                            target, exists,
+                        )
+                        .await
+                    }
+                    Err(e) => Err(e),
+                };
+                let outcome = match res {
+                    Ok(outcome) => outcome,
+                    Err(e) if cfg.keep_going => {
+                        warn!(
+                            folder_id = mail.folder_id.as_str(),
+                            mail_id = mail.mail_id.as_str(),
+                            error = format!("{e:#}"),
+                            "mail failed",
+                        );
+                        MailOutcome::Failed
+                    }
+                    Err(e) => return Err(e),
+                };
+                Ok((mail, outcome)) as Result<_>
+            }
+        })
+        // keep order so that the resume state never skips over unfinished mails
+        .buffered(cfg.concurrent_downloads.get())
+        .try_take_while(|(_mail, outcome)| {
+            futures::future::ready(Ok(*outcome != MailOutcome::LimitReached))
+        })
+        .try_fold(
+            Summary::default(),
+            |mut summary, (mail, outcome): (Arc<Mail>, _)| async move {
+                summary.add(folder, &mail, outcome);
+                if let Some(progress) = progress_ref {
+                    progress.inc(1);
+                }
+
+                // never move the cursor past a failed mail, so that the next run retries it
+                if cfg.resume && !cfg.dry_run && summary.failed.is_empty() {
+                    ResumeState {
+                        folder_id: folder.id.clone(),
+                        last_mail_id: mail.mail_id.clone(),
+                    }
+                    .store(output_dir)
+                    .await?;
+                }
+
+                Ok(summary)
+            },
+        )
+        .await;
+
+    let summary = match output {
+        Output::Archive {
+            archive: Some(archive),
+            ..
+        } => match summary {
+            Ok(summary) => {
+                archive.finish().await.context("finish archive")?;
+                summary
+            }
+            Err(e) => {
+                archive.abort().await;
+                return Err(e);
+            }
+        },
+        _ => summary?,
+    };
+
+    if let Some(manifest) = manifest {
+        manifest
+            .into_inner()
+            .expect("not poisoned")
+            .store(output_dir)
+            .await?;
+    }
+
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    Ok(summary)
+}
+
+/// EML or JSON file name, relative to the output path.
+fn eml_name(cfg: &DownloadCLIConfig, mail: &Mail, with_id: bool) -> PathBuf {
+    let mut name = eml_file_name(&cfg.filename_template, cfg.ascii_filenames, mail, with_id);
+    if cfg.format == OutputFormat::Json {
+        name.set_extension("json");
+    }
+    name
+}
+
+/// EML file name rendered from the template, relative to the output path.
+pub(crate) fn eml_file_name(
+    filename_template: &FilenameTemplate,
+    ascii_filenames: bool,
+    mail: &Mail,
+    with_id: bool,
+) -> PathBuf {
+    filename_template.render(
+        FilenameValues {
+            date: mail.date,
+            subject: &mail.subject,
+            id: &mail.mail_id,
+            from: &mail.sender.mail,
+        },
+        with_id,
+        ascii_filenames,
+    )
+}
+
+/// Output file of a mail in a directory of EML or JSON files, and whether it exists already.
+///
+/// `name` renders the file name relative to `path`, with or without the mail ID. The index is
+/// robust against template changes and renamed mails, file names are only checked for mails that
+/// are not indexed, e.g. output of older versions.
+pub(crate) async fn eml_target(
+    index: &EmlIndex,
+    path: &Path,
+    mail: &Mail,
+    name_taken: bool,
+    name: impl Fn(bool) -> PathBuf,
+) -> Result<(PathBuf, bool)> {
+    let indexed = index.get(&mail.mail_id).await.context("check eml index")?;
+    if let Some(indexed) = indexed {
+        return Ok((indexed, true));
+    }
+
+    // A file with the plain name may belong to a different mail, so the name with the ID takes
+    // precedence.
+    let with_id = path.join(name(true));
+    let with_id_exists = tokio::fs::try_exists(&with_id)
+        .await
+        .context("check file existence")?;
+    let (target_file, exists) = if name_taken || with_id_exists {
+        (with_id, with_id_exists)
+    } else {
+        let target_file = path.join(name(false));
+        let exists = tokio::fs::try_exists(&target_file)
+            .await
+            .context("check file existence")?;
+        if exists
+            && index
+                .owner(&target_file)
+                .is_some_and(|owner| owner != mail.mail_id)
+        {
+            // different mail with the same name, `with_id` does not exist
+            (with_id, false)
+        } else {
+            (target_file, exists)
+        }
+    };
+    if exists {
+        index
+            .insert(&mail.mail_id, &target_file)
+            .await
+            .context("update eml index")?;
+    }
+
+    Ok((target_file, exists))
+}
+
+/// Add mail to `--manifest`.
+fn add_to_manifest(
+    manifest: &Mutex<Manifest>,
+    output_dir: &Path,
+    mail: &Mail,
+    file: &Path,
+    size: Option<u64>,
+) {
+    let file = file.strip_prefix(output_dir).unwrap_or(file).to_owned();
+    manifest
+        .lock()
+        .expect("not poisoned")
+        .insert(ManifestEntry::new(mail, file, size));
+}
+
+/// Result of [`prepare_mail`].
+#[derive(Debug)]
+enum Prepared {
+    /// Nothing to download.
+    Done(MailOutcome),
+
+    /// Download the mail to `target`, which already exists if `--force` is set.
+    Download { target: Target, exists: bool },
+}
+
+/// Find the output location of a mail, skip it if it exists already, and take a `--limit` slot.
+#[allow(clippy::too_many_arguments)]
+async fn prepare_mail(
+    client: &Client,
+    cfg: &DownloadCLIConfig,
+    output: &Output,
+    manifest: Option<&Mutex<Manifest>>,
+    limit: &Limit,
+    path: &Path,
+    mail: &Mail,
+    name_taken: bool,
+) -> Result<Prepared> {
+    if cfg.limit_includes_existing && !limit.take() {
+        return Ok(Prepared::Done(MailOutcome::LimitReached));
+    }
+
+    let (target, exists) = match output {
+        Output::Eml { index, .. } => {
+            let (target_file, exists) = eml_target(index, path, mail, name_taken, |with_id| {
+                eml_name(cfg, mail, with_id)
+            })
+            .await?;
+            (Target::File(target_file), exists)
+        }
+        Output::Maildir(maildir) => {
+            let unique = Maildir::unique_name(mail.date, &mail.mail_id);
+            let exists = maildir.contains(&unique);
+            let path = maildir.message_path(&unique, &mail.maildir_flags());
+            (Target::Maildir { unique, path }, exists)
+        }
+        Output::Mbox(mbox) => (
+            Target::Mbox {
+                path: mbox.path().to_owned(),
+            },
+            mbox.contains(&mail.mail_id),
+        ),
+        Output::Archive { .. } => {
+            // the archive is always written from scratch
+            let target = Target::Archive {
+                name: eml_name(cfg, mail, name_taken),
+            };
+            (target, false)
+        }
+        Output::Stdout(_) => (Target::Stdout, false),
+    };
+    let target_file = target.path();
+
+    // the file of an existing Maildir message has the flags of the earlier run
+    let existing_file = match (&target, output) {
+        (Target::Maildir { unique, .. }, Output::Maildir(maildir)) => {
+            maildir.existing_path(unique).unwrap_or(target_file)
+        }
+        _ => target_file,
+    };
+    if exists && !overwrite_existing(cfg.force, cfg.verify, existing_file).await {
+        info!(
+            folder_id = mail.folder_id.as_str(),
+            mail_id = mail.mail_id.as_str(),
+            target_file = %target_file.display(),
+            ui_url = mail.ui_url(client).as_str(),
+            "already exists",
+        );
+
+        if cfg.dry_run {
+            println!("exists\t{}", target_file.display());
+        }
+
+        if let Some(manifest) = manifest {
+            let (file, size) = match &target {
+                // a single mbox file holds all mails
+                Target::Mbox { .. } => (target_file, None),
+                _ => (
+                    existing_file,
+                    tokio::fs::metadata(existing_file)
+                        .await
+                        .ok()
+                        .map(|m| m.len()),
+                ),
+            };
+            add_to_manifest(manifest, cfg.output_dir(path), mail, file, size);
+        }
+
+        return Ok(Prepared::Done(MailOutcome::AlreadyExists));
+    }
+
+    if !cfg.limit_includes_existing && !limit.take() {
+        return Ok(Prepared::Done(MailOutcome::LimitReached));
+    }
+
+    Ok(Prepared::Download { target, exists })
+}
+
+/// Check if an existing mail is downloaded again, see `--force` and `--verify`.
+async fn overwrite_existing(force: bool, verify: bool, file: &Path) -> bool {
+    if !force {
+        return false;
+    }
+    if !verify {
+        return true;
+    }
+
+    let res = match tokio::fs::read(file).await {
+        Ok(content) => verify_eml(&content),
+        Err(e) => Err(e).context("read file"),
+    };
+    match res {
+        Ok(()) => false,
+        Err(e) => {
+            info!(
+                target_file = %file.display(),
+                error = format!("{e:#}"),
+                "verification failed",
+            );
+            true
+        }
+    }
+}
+
+/// Download a mail that was prepared by [`prepare_mail`].
+#[allow(clippy::too_many_arguments)]
+async fn download_mail(
+    client: &Client,
+    session: &Session,
+    cfg: &DownloadCLIConfig,
+    filter: &Filter,
+    output: &Output,
+    manifest: Option<&Mutex<Manifest>>,
+    limit: &Limit,
+    path: &Path,
+    mail: &Arc<Mail>,
+    target: Target,
+    exists: bool,
+) -> Result<MailOutcome> {
+    let target_file = target.path();
+
+    if cfg.dry_run {
+        let action = if exists { "overwrite" } else { "new" };
+        println!("{action}\t{}", target_file.display());
+        return Ok(MailOutcome::Downloaded {
+            bytes: 0,
+            skipped_attachments: 0,
+        });
+    }
+
+    if exists {
+        info!(
+            folder_id = mail.folder_id.as_str(),
+            mail_id = mail.mail_id.as_str(),
+            target_file = %target_file.display(),
+            ui_url = mail.ui_url(client).as_str(),
+            "overwrite",
+        );
+    } else {
+        info!(
+            folder_id = mail.folder_id.as_str(),
+            mail_id = mail.mail_id.as_str(),
+            target_file = %target_file.display(),
+            ui_url = mail.ui_url(client).as_str(),
+            "download",
+        );
+    }
+
+    let mail = Arc::clone(mail)
+        .download_with_limit(client, session, cfg.max_attachment_size)
+        .await
+        .with_context(|| format!("download mail: `{}`", mail.ui_url(client)))?;
+
+    if !filter.matches_details(&mail) {
+        debug!(mail_id = mail.mail.mail_id.as_str(), "filtered by content");
+        limit.release();
+        return Ok(MailOutcome::Filtered);
+    }
+
+    let emit_context = || format!("emit eml: `{}`", mail.mail.ui_url(client));
+    let mut bytes = match (&target, output) {
+        (Target::File(path), _) => {
+            // stream to disk, attachments may be large
+            async {
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent)
+                        .await
+                        .context("create parent dir")?;
+                }
+                let mut f = AtomicFile::create(path, false).await?;
+                let res = if cfg.format == OutputFormat::Json {
+                    match emit_json(&mail, cfg.json_include_eml) {
+                        Ok(json) => f.writer().write_all(&json).await.context("write json"),
+                        Err(e) => Err(e),
+                    }
+                } else {
+                    write_eml(&mail, f.writer()).await
+                };
+                match res {
+                    Ok(()) => f.commit().await,
+                    Err(e) => {
+                        f.abort().await;
+                        Err(e).with_context(emit_context)
+                    }
+                }
+            }
+            .await
+        }
+        (Target::Maildir { unique, .. }, Output::Maildir(maildir)) => {
+            let eml = emit_eml(&mail).with_context(emit_context)?;
+            maildir
+                .write(unique, &mail.mail.maildir_flags(), eml.as_bytes())
+                .await?;
+            Ok(eml.len() as u64)
+        }
+        (Target::Mbox { .. }, Output::Mbox(mbox)) => {
+            let eml = emit_eml(&mail).with_context(emit_context)?;
+            mbox.append(
+                &mail.mail.mail_id,
+                &mail.mail.sender.mail,
+                mail.mail.date,
+                eml.as_bytes(),
+            )
+            .await?;
+            Ok(eml.len() as u64)
+        }
+        (Target::Archive { name }, Output::Archive { archive, .. }) => {
+            let archive = archive.as_ref().context("archive not opened for writing")?;
+            let eml = emit_eml(&mail).with_context(emit_context)?;
+            let bytes = eml.len() as u64;
+            archive
+                .append(name, mail.mail.date, eml.into_bytes())
+                .await?;
+            Ok(bytes)
+        }
+        (Target::Stdout, Output::Stdout(stdout)) => {
+            let line = emit_json_line(&mail).with_context(emit_context)?;
+            write_line(stdout, &line).await?;
+            Ok(line.len() as u64)
+        }
+        _ => unreachable!("target belongs to output"),
+    }
+    .with_context(|| format!("write output file: `{}`", target_file.display()))?;
+
+    if let Some(manifest) = manifest {
+        add_to_manifest(
+            manifest,
+            cfg.output_dir(path),
+            &mail.mail,
+            target_file,
+            Some(bytes),
+        );
+    }
+
+    if let (Output::Eml { index, .. }, Target::File(path)) = (output, &target) {
+        index
+            .insert(&mail.mail.mail_id, path)
+            .await
+            .context("update eml index")?;
+    }
+
+    if cfg.extract_attachments && !mail.attachments.is_empty() {
+        let attachment_dir = match &target {
+            Target::File(path) => path.with_extension(""),
+            Target::Maildir { unique, .. } => cfg.output_dir(path).join("attachments").join(unique),
+            Target::Mbox { .. } => cfg
+                .output_dir(path)
+                .join("attachments")
+                .join(&mail.mail.mail_id),
+            Target::Archive { .. } | Target::Stdout => unreachable!("checked by CLI parser"),
+        };
+        tokio::fs::create_dir_all(&attachment_dir)
+            .await
+            .context("create attachment dir")?;
+
+        let names = attachment_file_names(mail.attachments.iter().map(|a| a.name.as_str()));
+        for (attachment, name) in mail.attachments.iter().zip(names) {
+            let path = attachment_dir.join(name);
+            write_to_file(&attachment.data, &path)
+                .await
+                .with_context(|| format!("write attachment: `{}`", path.display()))?;
+            bytes += attachment.data.len() as u64;
+        }
+    }
+
+    Ok(MailOutcome::Downloaded {
+        bytes,
+        skipped_attachments: mail.skipped_attachments.len(),
+    })
+}
+
+/// Write a line of JSON Lines.
+///
+/// The lock is held for the whole line, so that concurrent downloads do not interleave.
+async fn write_line<W>(out: &tokio::sync::Mutex<W>, line: &[u8]) -> Result<()>
+where
+    W: AsyncWrite + Send + Unpin,
+{
+    let mut out = out.lock().await;
+    out.write_all(line).await.context("write line")?;
+    out.flush().await.context("flush stdout")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use crate::{mails::Address, proto::keys::Key};
+
+    use super::*;
+
+    fn folder(id: &str, name: &str) -> Folder {
+        Folder {
+            name: name.to_owned(),
+            mails: format!("mails-{id}"),
+            id: id.to_owned(),
+            parent: None,
+            system_folder: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_line() {
+        let mail = |id: usize| DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: format!("mail_{id}"),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                session_key: Key::Aes256([0; 32]),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "multi\nline".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
+            }),
+            headers: None,
+            body: "<p>hello</p>\n".repeat(1000).into_bytes(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
+        };
+        let mails = (0..10).map(mail).collect::<Vec<_>>();
+
+        let lines = mails
+            .iter()
+            .map(|m| emit_json_line(m).unwrap())
+            .collect::<Vec<_>>();
+
+        let out = tokio::sync::Mutex::new(vec![]);
+        futures::future::try_join_all(lines.iter().map(|line| write_line(&out, line)))
+            .await
+            .unwrap();
+        let out = out.into_inner();
+
+        // one complete JSON object per line
+        let mut ids = out
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let json = serde_json::from_slice::<serde_json::Value>(line).unwrap();
+                assert!(json["eml"].is_string());
+                json["id"].as_str().unwrap().to_owned()
+            })
+            .collect::<Vec<_>>();
+        ids.sort();
+        assert_eq!(
+            ids,
+            (0..10).map(|id| format!("mail_{id}")).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_sync_folder_selection() {
+        let cfg = DownloadCLIConfig::from(
+            SyncCLIConfig::try_parse_from(["sync", "--path=out", "--folder=Inbox", "--keep-going"])
+                .unwrap(),
+        );
+        assert!(cfg.keep_going);
+        assert!(!cfg.all_folders);
+        assert_eq!(cfg.folder.as_deref(), Some("Inbox"));
+
+        let cfg = DownloadCLIConfig::from(
+            SyncCLIConfig::try_parse_from(["sync", "--path=out", "--keep-going"]).unwrap(),
+        );
+        assert!(cfg.all_folders);
+    }
+
+    #[test]
+    fn test_check_failures() {
+        check_failures(&[], 1, &[]).unwrap();
+
+        let e = check_failures(&["Work", "Spam"], 3, &[]).unwrap_err();
+        assert_eq!(
+            format!("{e:#}"),
+            "download incomplete: 2 of 3 folders failed: Work, Spam",
+        );
+
+        let failed = [FailedMail {
+            list_id: "list".to_owned(),
+            mail_id: "mail".to_owned(),
+        }];
+        let e = check_failures(&["Work"], 3, &failed).unwrap_err();
+        assert_eq!(
+            format!("{e:#}"),
+            "download incomplete: 1 of 3 folders failed: Work; 1 mails failed: mail",
+        );
+        assert_eq!(e.downcast_ref(), Some(&ErrorKind::PartialDownload));
+    }
+
+    #[test]
+    fn test_exclude_folders() {
+        let mut spam = folder("s", "Junk");
+        spam.system_folder = Some(SystemFolder::Spam);
+        let folders = [
+            folder("a", "Inbox"),
+            spam,
+            folder("b", "Work"),
+            folder("c", "Work"),
+        ];
+
+        let exclude = |exclude: &[&str]| {
+            let exclude = exclude.iter().map(|e| (*e).to_owned()).collect::<Vec<_>>();
+            exclude_folders(&folders, &exclude)
+                .into_iter()
+                .map(|f| f.id.as_str())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(exclude(&[]), ["a", "s", "b", "c"]);
+        assert_eq!(exclude(&["spam", "b"]), ["a", "c"]);
+        assert_eq!(exclude(&["Work", "unknown"]), ["a", "s"]);
+        assert_eq!(exclude(&["inbox"]), ["a", "s", "b", "c"]);
+    }
+
+    #[test]
+    fn test_folder_dir_name() {
+        let cfg =
+            DownloadCLIConfig::try_parse_from(["download", "--path=out", "--all-folders"]).unwrap();
+        let mut dir_names = HashSet::new();
+        let names = [
+            folder("a", "Inbox"),
+            folder("b", "inbox"),
+            folder("c", ""),
+            folder("d", "a/b"),
+            folder("e", "Inbox"),
+        ]
+        .iter()
+        .map(|f| folder_dir_name(&cfg, f, &mut dir_names))
+        .collect::<Vec<_>>();
+        assert_eq!(names, ["Inbox", "inbox b", "folder", "ab", "Inbox e"]);
+        assert_eq!(cfg.folder_path("Inbox"), Path::new("out/Inbox"));
+
+        let cfg = DownloadCLIConfig::try_parse_from([
+            "download",
+            "--path=out",
+            "--all-folders",
+            "--format=mbox",
+            "--ascii-filenames",
+        ])
+        .unwrap();
+        let name = folder_dir_name(&cfg, &folder("a", "Entwürfe"), &mut HashSet::new());
+        assert_eq!(name, "Entwurfe");
+        assert_eq!(
+            cfg.folder_path(&name),
+            Path::new("out/Entwurfe/Entwurfe.mbox")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_overwrite_existing() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let mut reference = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        reference.push("tests/reference/2024-02-18-16h54m17s-Test.eml");
+        let content = std::fs::read(&reference).unwrap();
+
+        let intact = dir.path().join("intact.eml");
+        std::fs::write(&intact, &content).unwrap();
+        let truncated = dir.path().join("truncated.eml");
+        std::fs::write(&truncated, &content[..content.len() / 2]).unwrap();
+        let missing = dir.path().join("missing.eml");
+
+        for file in [&intact, &truncated, &missing] {
+            assert!(!overwrite_existing(false, false, file).await);
+            assert!(overwrite_existing(true, false, file).await);
+        }
+
+        assert!(!overwrite_existing(true, true, &intact).await);
+        assert!(overwrite_existing(true, true, &truncated).await);
+        assert!(overwrite_existing(true, true, &missing).await);
+
+        // repaired file is kept by the next run
+        write_to_file(&content, &truncated).await.unwrap();
+        assert!(!overwrite_existing(true, true, &truncated).await);
+    }
+}
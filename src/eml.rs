@@ -1,74 +1,272 @@
 use std::sync::OnceLock;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, ensure, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
+use futures::FutureExt;
 use itertools::Itertools;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
 
 use crate::{
-    mails::{Address, DownloadedMail},
+    mails::{Address, Attachment, DownloadedMail, SkippedAttachment},
     proto::binary::Base64String,
 };
 
 static LINE_ENDING_RE: OnceLock<regex::Regex> = OnceLock::new();
 static CONTENT_TYPE_RE: OnceLock<regex::Regex> = OnceLock::new();
 static START_WITH_SPACES_RE: OnceLock<regex::Regex> = OnceLock::new();
+static BOUNDARY_RE: OnceLock<regex::Regex> = OnceLock::new();
 const NEWLINE: &str = "\r\n";
 
-pub(crate) fn emit_eml(mail: &DownloadedMail) -> Result<String> {
-    let mut lines = Vec::new();
+/// Line break followed by whitespace, continues a header on the next line.
+const FOLD: &str = "\r\n ";
+
+/// Maximum number of bytes encoded in a single RFC 2047 encoded word.
+///
+/// Encoded words must not be longer than 75 characters, the prefix and suffix take 12 of them and
+/// base64 needs 4 characters per 3 bytes.
+const ENCODED_WORD_MAX_BYTES: usize = 45;
+
+/// Emit mail in EML (RFC 822) format.
+///
+/// This is a convenience wrapper around [`write_eml`] that keeps the whole mail in memory.
+pub fn emit_eml(mail: &DownloadedMail) -> Result<String> {
+    let mut buf = Vec::new();
+    write_eml(mail, &mut buf)
+        .now_or_never()
+        .expect("writing to memory never blocks")?;
+    String::from_utf8(buf).context("EML is valid UTF-8")
+}
+
+/// Write mail in EML (RFC 822) format.
+///
+/// If the server stored the original headers (i.e. for received mails), they are passed through
+/// verbatim, only `Content-Type` is replaced because the MIME structure is rebuilt. Otherwise (e.g.
+/// for mails sent or drafted in Tuta), headers are synthesized from the metadata. `Message-ID`,
+/// `In-Reply-To`, and `References` come from the conversation entries.
+///
+/// The original `Date` header keeps the time zone of the sender. If there is none, it is derived
+/// from the receive date in UTC. A missing `Message-ID` is derived from the mail ID, so exporting
+/// the same mail again yields the same ID.
+///
+/// Attachments that were not downloaded (see [`DownloadedMail::skipped_attachments`]) are
+/// replaced by a short text part, so the mail still shows that they existed.
+///
+/// Body and attachments are encoded piece by piece, so the encoded mail is never fully held in
+/// memory.
+pub async fn write_eml<W>(mail: &DownloadedMail, w: &mut W) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let mut lines = Lines::new(w);
 
     // headers
     let boundary = "----------79Bu5A16qPEYcVIZL@tutanota".to_owned();
     if let Some(headers) = &mail.headers {
         let headers = split_header_lines(headers);
-        let mut headers = remove_content_type(headers).context("filter content type header")?;
+        let headers = remove_content_type(headers).context("filter content type header")?;
 
-        lines.append(&mut headers);
+        for header in &headers {
+            lines.push(header).await?;
+        }
+        if !has_header(&headers, "Date") {
+            lines.push(&date_header(mail.mail.date)).await?;
+        }
+        if !has_header(&headers, "Message-ID") {
+            lines.push(&message_id_header(mail)).await?;
+        }
     } else {
-        synthesize_headers(mail, &mut lines);
+        for header in synthesize_headers(mail) {
+            lines.push(&header).await?;
+        }
     }
-    lines.push(format!(
-        "Content-Type: multipart/related; boundary=\"{}\"",
-        boundary
-    ));
+
+    // Inline images are bundled with the body in a `multipart/related` part, other attachments
+    // are added next to it in a `multipart/mixed` part. The outer part is skipped if there is
+    // only one kind.
+    let body_html = String::from_utf8_lossy(&mail.body);
+    let (inline, attachments): (Vec<_>, Vec<_>) = mail
+        .attachments
+        .iter()
+        .partition(|a| is_inline(a, &body_html));
+    let related_boundary = if attachments.is_empty() && mail.skipped_attachments.is_empty() {
+        lines
+            .push(&format!(
+                "Content-Type: multipart/related; boundary=\"{}\"",
+                boundary
+            ))
+            .await?;
+        boundary.clone()
+    } else {
+        lines
+            .push(&format!(
+                "Content-Type: multipart/mixed; boundary=\"{}\"",
+                boundary
+            ))
+            .await?;
+        if inline.is_empty() {
+            boundary.clone()
+        } else {
+            let related_boundary = "----------79Bu5A16qPEYcVIZR@tutanota".to_owned();
+            write_intermediate_delimiter(&mut lines, &boundary).await?;
+            lines
+                .push(&format!(
+                    "Content-Type: multipart/related; boundary=\"{}\"",
+                    related_boundary
+                ))
+                .await?;
+            related_boundary
+        }
+    };
 
     // body
-    write_intermediate_delimiter(&mut lines, &boundary);
-    let body = Base64String::from(mail.body.clone());
-    lines.push("Content-Type: text/html; charset=UTF-8".to_owned());
-    lines.push("Content-Transfer-Encoding: base64".to_owned());
-    lines.push("".to_owned());
-    write_chunked(&mut lines, &body.to_string());
+    write_intermediate_delimiter(&mut lines, &related_boundary).await?;
+    lines.push("Content-Type: text/html; charset=UTF-8").await?;
+    if prefer_quoted_printable(&mail.body) {
+        lines
+            .push("Content-Transfer-Encoding: quoted-printable")
+            .await?;
+        lines.push("").await?;
+        write_quoted_printable(&mut lines, &mail.body).await?;
+    } else {
+        lines.push("Content-Transfer-Encoding: base64").await?;
+        lines.push("").await?;
+        write_base64(&mut lines, &mail.body).await?;
+    }
+
+    // inline images
+    for attachment in &inline {
+        write_intermediate_delimiter(&mut lines, &related_boundary).await?;
+        write_attachment(&mut lines, attachment, "inline").await?;
+    }
+    if related_boundary != boundary {
+        write_final_delimiter(&mut lines, &related_boundary).await?;
+    }
 
     // attachments
-    for attachment in &mail.attachments {
-        write_intermediate_delimiter(&mut lines, &boundary);
-        lines.push(format!(
-            "Content-Type: {}; name={}",
-            attachment.mime_type,
-            utf8_header_value(&attachment.name)
-        ));
-        lines.push("Content-Transfer-Encoding: base64".to_owned());
-        lines.push(format!(
-            "Content-Disposition: attachment; filename={}",
-            utf8_header_value(&attachment.name)
-        ));
-        if let Some(cid) = &attachment.cid {
-            lines.push(format!("Content-Id: <{}>", cid));
+    for attachment in &attachments {
+        write_intermediate_delimiter(&mut lines, &boundary).await?;
+        write_attachment(&mut lines, attachment, "attachment").await?;
+    }
+    for skipped in &mail.skipped_attachments {
+        write_intermediate_delimiter(&mut lines, &boundary).await?;
+        write_skipped_attachment(&mut lines, skipped).await?;
+    }
+
+    write_final_delimiter(&mut lines, &boundary).await?;
+    w.flush().await.context("flush")?;
+    Ok(())
+}
+
+/// Writes lines separated by [`NEWLINE`] (CRLF, as required by RFC 5322), without a trailing one.
+struct Lines<'a, W> {
+    w: &'a mut W,
+    first: bool,
+}
+
+impl<'a, W> Lines<'a, W>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    fn new(w: &'a mut W) -> Self {
+        Self { w, first: true }
+    }
+
+    async fn push(&mut self, line: &str) -> Result<()> {
+        if !self.first {
+            self.w
+                .write_all(NEWLINE.as_bytes())
+                .await
+                .context("write newline")?;
         }
-        lines.push("".to_owned());
-        write_chunked(
-            &mut lines,
-            &Base64String::from(attachment.data.clone()).to_string(),
-        );
+        self.first = false;
+        self.w
+            .write_all(line.as_bytes())
+            .await
+            .context("write line")?;
+        Ok(())
     }
+}
 
-    write_final_delimiter(&mut lines, &boundary);
-    Ok(lines.join(NEWLINE))
+/// Check if attachment is an inline image that is referenced by the HTML body.
+fn is_inline(attachment: &Attachment, body_html: &str) -> bool {
+    match &attachment.cid {
+        Some(cid) => body_html.contains(&format!("cid:{cid}")),
+        None => false,
+    }
+}
+
+async fn write_attachment<W>(
+    lines: &mut Lines<'_, W>,
+    attachment: &Attachment,
+    disposition: &str,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    lines
+        .push(&format!(
+            "Content-Type: {}; {}",
+            single_line(&attachment.mime_type),
+            name_param(&attachment.name)
+        ))
+        .await?;
+    lines.push("Content-Transfer-Encoding: base64").await?;
+    lines
+        .push(&format!(
+            "Content-Disposition: {}; {}",
+            disposition,
+            filename_param(&attachment.name)
+        ))
+        .await?;
+    if let Some(cid) = &attachment.cid {
+        lines
+            .push(&format!("Content-Id: <{}>", single_line(cid)))
+            .await?;
+    }
+    lines.push("").await?;
+    write_base64(lines, &attachment.data).await
+}
+
+/// Write text part that takes the place of an attachment that was not downloaded.
+async fn write_skipped_attachment<W>(
+    lines: &mut Lines<'_, W>,
+    skipped: &SkippedAttachment,
+) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    lines
+        .push("Content-Type: text/plain; charset=UTF-8")
+        .await?;
+    lines
+        .push("Content-Transfer-Encoding: quoted-printable")
+        .await?;
+    lines.push("Content-Disposition: inline").await?;
+    lines.push("").await?;
+    let text = format!(
+        "Attachment not downloaded because it exceeds the size limit: {} ({}, {} bytes)",
+        skipped.name, skipped.mime_type, skipped.size,
+    );
+    write_quoted_printable(lines, text.as_bytes()).await
 }
 
 /// Create headers from metadata.
-fn synthesize_headers(mail: &DownloadedMail, lines: &mut Vec<String>) {
+fn synthesize_headers(mail: &DownloadedMail) -> Vec<String> {
+    let mut lines = vec![];
     lines.push(address_header("From", [&mail.mail.sender]));
+    lines.push(date_header(mail.mail.date));
+    lines.push(message_id_header(mail));
+    if let Some(in_reply_to) = mail.references.last() {
+        lines.push(format!("In-Reply-To: {}", angle_brackets(in_reply_to)));
+        lines.push(format!(
+            "References: {}",
+            mail.references
+                .iter()
+                .map(|id| angle_brackets(id))
+                .join(FOLD)
+        ));
+    }
     lines.push("MIME-Version: 1.0".to_owned());
 
     if mail.mail.subject.is_empty() {
@@ -89,6 +287,40 @@ fn synthesize_headers(mail: &DownloadedMail, lines: &mut Vec<String>) {
     if !mail.to.is_empty() {
         lines.push(address_header("To", &mail.to));
     }
+
+    lines
+}
+
+/// Create `Date` header in RFC 5322 format, e.g. `Wed, 4 Mar 2020 11:22:33 +0000`.
+fn date_header(date: DateTime<Utc>) -> String {
+    format!("Date: {}", date.to_rfc2822())
+}
+
+/// Create `Message-ID` header, synthesized from the mail ID if Tuta did not store one.
+fn message_id_header(mail: &DownloadedMail) -> String {
+    let message_id = match &mail.message_id {
+        Some(message_id) => angle_brackets(message_id),
+        None => format!("<{}@tatutanatata.invalid>", mail.mail.mail_id),
+    };
+    format!("Message-ID: {message_id}")
+}
+
+/// Wrap message ID in angle brackets, unless it already is.
+fn angle_brackets(message_id: &str) -> String {
+    let message_id = single_line(message_id.trim());
+    if message_id.starts_with('<') && message_id.ends_with('>') {
+        message_id.into_owned()
+    } else {
+        format!("<{message_id}>")
+    }
+}
+
+/// Check if any header line has the given name, ignoring case.
+fn has_header(headers: &[String], name: &str) -> bool {
+    headers.iter().any(|h| {
+        h.split_once(':')
+            .is_some_and(|(n, _)| n.eq_ignore_ascii_case(name))
+    })
 }
 
 /// Create address headers
@@ -101,13 +333,27 @@ fn address_header<'a>(
         header,
         addrs
             .into_iter()
-            .map(|addr| format!("{} <{}>", utf8_header_value(&addr.name), addr.mail))
-            .join(","),
+            .map(|addr| {
+                let mail = single_line(&addr.mail);
+                if addr.name.is_empty() {
+                    format!("<{}>", mail)
+                } else {
+                    format!("{} <{}>", utf8_header_value(&addr.name), mail)
+                }
+            })
+            .join(&format!(",{FOLD}")),
     )
 }
 
+/// Replace line breaks in server-provided header values by spaces.
+///
+/// They would otherwise end up as lone `\r` or `\n` in the output or end the header early.
+fn single_line(s: &str) -> std::borrow::Cow<'_, str> {
+    line_ending_re().replace_all(s, " ")
+}
+
 fn line_ending_re() -> &'static regex::Regex {
-    LINE_ENDING_RE.get_or_init(|| regex::Regex::new(r#"\r?\n"#).expect("valid regex"))
+    LINE_ENDING_RE.get_or_init(|| regex::Regex::new(r#"\r\n|\r|\n"#).expect("valid regex"))
 }
 
 fn content_type_re() -> &'static regex::Regex {
@@ -124,11 +370,20 @@ fn start_with_spaces_re() -> &'static regex::Regex {
 }
 
 /// Upstream provides `\n` line endings for headers but we need `\r\n`
+///
+/// Lone `\r` are treated as line endings too, so that every line is terminated by exactly one
+/// `\r\n` in the output.
+///
+/// Trailing empty lines are removed since they would end the header section early.
 fn split_header_lines(headers: &str) -> Vec<String> {
-    line_ending_re()
+    let mut lines = line_ending_re()
         .split(headers)
         .map(|s| s.to_owned())
-        .collect()
+        .collect::<Vec<_>>();
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    lines
 }
 
 /// Remove content type from headers
@@ -155,33 +410,386 @@ fn remove_content_type(headers: Vec<String>) -> Result<Vec<String>> {
 }
 
 /// See <https://www.w3.org/Protocols/rfc1341/7_2_Multipart.html>.
-fn write_intermediate_delimiter(lines: &mut Vec<String>, boundary: &str) {
-    lines.push("".to_owned());
-    lines.push(format!("--{}", boundary));
+async fn write_intermediate_delimiter<W>(lines: &mut Lines<'_, W>, boundary: &str) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    lines.push("").await?;
+    lines.push(&format!("--{}", boundary)).await
 }
 
 /// See <https://www.w3.org/Protocols/rfc1341/7_2_Multipart.html>.
-fn write_final_delimiter(lines: &mut Vec<String>, boundary: &str) {
-    lines.push("".to_owned());
-    lines.push(format!("--{}--", boundary));
+async fn write_final_delimiter<W>(lines: &mut Lines<'_, W>, boundary: &str) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    lines.push("").await?;
+    lines.push(&format!("--{}--", boundary)).await
+}
+
+/// Characters per base64 line.
+const BASE64_LINE_LENGTH: usize = 78;
+
+/// Number of lines that are encoded at once.
+///
+/// `BASE64_LINE_LENGTH * BASE64_LINES_PER_BLOCK` must be a multiple of 4, so that blocks can be
+/// encoded independently.
+const BASE64_LINES_PER_BLOCK: usize = 64;
+
+/// Write base64-encoded data, split into lines.
+async fn write_base64<W>(lines: &mut Lines<'_, W>, data: &[u8]) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    const BLOCK_BYTES: usize = BASE64_LINE_LENGTH * BASE64_LINES_PER_BLOCK / 4 * 3;
+
+    for block in data.chunks(BLOCK_BYTES) {
+        let encoded = Base64String::from(block).to_string();
+        for line in encoded.as_bytes().chunks(BASE64_LINE_LENGTH) {
+            lines
+                .push(std::str::from_utf8(line).expect("base64 is ASCII"))
+                .await?;
+        }
+    }
+    Ok(())
 }
 
-fn write_chunked(lines: &mut Vec<String>, s: &str) {
-    for chunk in &s.chars().chunks(78) {
-        lines.push(chunk.collect());
+/// Characters per quoted-printable line, including the `=` of soft line breaks.
+const QUOTED_PRINTABLE_LINE_LENGTH: usize = 76;
+
+/// Check if quoted-printable is the better encoding for the text, i.e. if it is mostly ASCII.
+///
+/// Every escaped byte takes 3 characters instead of 1, while base64 takes 4 characters per 3 bytes,
+/// so quoted-printable is shorter as long as less than a sixth of the bytes must be escaped. It is
+/// also readable in the raw mail.
+fn prefer_quoted_printable(data: &[u8]) -> bool {
+    let escaped = data
+        .iter()
+        .filter(|b| !matches!(b, b' ' | b'\t' | b'\r' | b'\n' | b'!'..=b'<' | b'>'..=b'~'))
+        .count();
+    escaped * 6 < data.len()
+}
+
+/// Write quoted-printable encoded data, split into lines.
+///
+/// CRLF in the data becomes a line break, lone CR and LF are escaped so that the data survives
+/// unchanged. Whitespace at the end of a line is escaped since transports may strip it. The last
+/// line ends with a soft line break, so that the empty line before the next MIME delimiter is not
+/// part of the data. A `-` at the start of a line is escaped, so no line can match the boundary.
+///
+/// See <https://www.rfc-editor.org/rfc/rfc2045#section-6.7>.
+async fn write_quoted_printable<W>(lines: &mut Lines<'_, W>, data: &[u8]) -> Result<()>
+where
+    W: AsyncWrite + Unpin + Send,
+{
+    let is_crlf = |idx: usize| data.get(idx) == Some(&b'\r') && data.get(idx + 1) == Some(&b'\n');
+
+    let mut line = String::with_capacity(QUOTED_PRINTABLE_LINE_LENGTH);
+    let mut idx = 0;
+    while idx < data.len() {
+        if is_crlf(idx) {
+            lines.push(&line).await?;
+            line.clear();
+            idx += 2;
+            continue;
+        }
+
+        let b = data[idx];
+        let at_line_end = idx + 1 == data.len() || is_crlf(idx + 1);
+        let literal = match b {
+            b' ' | b'\t' => !at_line_end,
+            b'!'..=b'<' | b'>'..=b'~' => true,
+            _ => false,
+        };
+        let len = if literal { 1 } else { 3 };
+
+        // keep room for the soft line break
+        if line.len() + len > QUOTED_PRINTABLE_LINE_LENGTH - 1 {
+            line.push('=');
+            lines.push(&line).await?;
+            line.clear();
+        }
+
+        // a line starting with `-` could be mistaken for the MIME boundary
+        let literal = literal && !(b == b'-' && line.is_empty());
+        if literal {
+            line.push(b as char);
+        } else {
+            line.push_str(&format!("={b:02X}"));
+        }
+        idx += 1;
     }
+
+    line.push('=');
+    lines.push(&line).await
 }
 
+/// Encode header value as RFC 2047 encoded words.
+///
+/// Long values are split into multiple words, on character boundaries, that are placed on separate
+/// lines.
+///
 /// See <https://www.rfc-editor.org/rfc/rfc2047>.
 fn utf8_header_value(s: &str) -> String {
+    let mut words = vec![];
+    let mut rest = s;
+    loop {
+        let mut end = rest.len().min(ENCODED_WORD_MAX_BYTES);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        words.push(utf8_param_value(chunk));
+
+        rest = tail;
+        if rest.is_empty() {
+            break;
+        }
+    }
+    words.join(FOLD)
+}
+
+/// Encode parameter value (e.g. a file name) as a single RFC 2047 encoded word.
+///
+/// This is not covered by the RFC but widely understood, unlike folded parameters.
+fn utf8_param_value(s: &str) -> String {
     format!("=?UTF-8?B?{}?=", Base64String::from(s.as_bytes()))
 }
 
+/// Maximum length of a parameter value that is written as quoted string and of a single RFC 2231
+/// section.
+const PARAM_SECTION_MAX_CHARS: usize = 60;
+
+/// Create `name` parameter of `Content-Type`.
+///
+/// Values that are not short ASCII strings are encoded via [`utf8_param_value`], for clients that
+/// do not support RFC 2231.
+fn name_param(value: &str) -> String {
+    let value = quoted_param_value(value).unwrap_or_else(|| utf8_param_value(value));
+    format!("name={value}")
+}
+
+/// Create `filename` parameter of `Content-Disposition`.
+///
+/// Values that are not short ASCII strings are percent-encoded as described in RFC 2231. Long
+/// values are split into numbered sections on separate lines, which keeps header lines short.
+fn filename_param(value: &str) -> String {
+    if let Some(quoted) = quoted_param_value(value) {
+        return format!("filename={quoted}");
+    }
+
+    // Sections are split between characters, since many clients decode them separately.
+    let mut sections = vec![];
+    let mut section = String::new();
+    for c in value.chars() {
+        let mut buf = [0; 4];
+        let encoded = c
+            .encode_utf8(&mut buf)
+            .bytes()
+            .map(|b| {
+                if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+                    char::from(b).to_string()
+                } else {
+                    format!("%{b:02X}")
+                }
+            })
+            .join("");
+        if section.len() + encoded.len() > PARAM_SECTION_MAX_CHARS {
+            sections.push(std::mem::take(&mut section));
+        }
+        section.push_str(&encoded);
+    }
+    sections.push(section);
+
+    if let [section] = sections.as_slice() {
+        return format!("filename*=UTF-8''{section}");
+    }
+    sections
+        .iter()
+        .enumerate()
+        .map(|(idx, section)| {
+            let charset = if idx == 0 { "UTF-8''" } else { "" };
+            format!("filename*{idx}*={charset}{section}")
+        })
+        .join(&format!(";{FOLD}"))
+}
+
+/// Quote parameter value if it is a short string of printable ASCII characters.
+fn quoted_param_value(value: &str) -> Option<String> {
+    let printable = value.bytes().all(|b| b.is_ascii_graphic() || b == b' ');
+    (printable && value.len() <= PARAM_SECTION_MAX_CHARS)
+        .then(|| format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"")))
+}
+
+/// Maximum nesting of MIME parts accepted by [`verify_eml`].
+const MAX_PART_DEPTH: usize = 16;
+
+/// Check that an EML file is well-formed, e.g. to find files that were truncated or damaged.
+///
+/// Every line must end with CRLF and headers must be well-formed. Multipart bodies must contain
+/// their final delimiter, and base64 and quoted-printable parts must decode. The content itself
+/// (e.g. addresses or the HTML body) is not checked.
+pub(crate) fn verify_eml(content: &[u8]) -> Result<()> {
+    for (idx, b) in content.iter().enumerate() {
+        let lone = match b {
+            b'\r' => content.get(idx + 1) != Some(&b'\n'),
+            b'\n' => idx == 0 || content[idx - 1] != b'\r',
+            _ => false,
+        };
+        ensure!(!lone, "lone line break at byte {idx}");
+    }
+    verify_part(content, 0)
+}
+
+fn verify_part(content: &[u8], depth: usize) -> Result<()> {
+    ensure!(depth <= MAX_PART_DEPTH, "MIME parts nested too deeply");
+
+    let (header, body) = if let Some(body) = content.strip_prefix(b"\r\n") {
+        (&b""[..], body)
+    } else {
+        let pos = find(content, b"\r\n\r\n").context("no empty line after the headers")?;
+        (&content[..pos], &content[pos + 4..])
+    };
+
+    // unfolded headers, names in lower case
+    let mut fields: Vec<(String, String)> = vec![];
+    for (idx, line) in String::from_utf8_lossy(header).split(NEWLINE).enumerate() {
+        if line.starts_with([' ', '\t']) {
+            let (_name, value) = fields
+                .last_mut()
+                .with_context(|| format!("header line {} continues nothing", idx + 1))?;
+            value.push_str(line);
+            continue;
+        }
+        let (name, value) = line
+            .split_once(':')
+            .with_context(|| format!("header line {} has no name", idx + 1))?;
+        ensure!(
+            !name.is_empty() && name.bytes().all(|b| b.is_ascii_graphic()),
+            "invalid name of header line {}",
+            idx + 1,
+        );
+        fields.push((name.to_ascii_lowercase(), value.trim().to_owned()));
+    }
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    let content_type = field("content-type").unwrap_or_default();
+    if content_type
+        .get(..10)
+        .is_some_and(|t| t.eq_ignore_ascii_case("multipart/"))
+    {
+        let boundary = boundary_re()
+            .captures(content_type)
+            .and_then(|c| c.get(1).or_else(|| c.get(2)))
+            .context("multipart without boundary")?
+            .as_str();
+
+        let mut parts = vec![];
+        let mut current: Option<Vec<&[u8]>> = None;
+        let mut complete = false;
+        for line in split_crlf(body) {
+            let Some(rest) = line
+                .strip_prefix(b"--")
+                .and_then(|l| l.strip_prefix(boundary.as_bytes()))
+            else {
+                if let Some(current) = &mut current {
+                    current.push(line);
+                }
+                continue;
+            };
+            let rest = rest.trim_ascii_end();
+            if rest.is_empty() || rest == b"--" {
+                parts.extend(current.take().map(|lines| lines.join(&b"\r\n"[..])));
+                if rest == b"--" {
+                    complete = true;
+                    break;
+                }
+                current = Some(vec![]);
+            } else if let Some(current) = &mut current {
+                current.push(line);
+            }
+        }
+        ensure!(
+            complete,
+            "final delimiter of `{boundary}` is missing, file may be truncated"
+        );
+        ensure!(!parts.is_empty(), "multipart `{boundary}` has no parts");
+
+        for (idx, part) in parts.iter().enumerate() {
+            verify_part(part, depth + 1)
+                .with_context(|| format!("part #{} of `{boundary}`", idx + 1))?;
+        }
+        return Ok(());
+    }
+
+    match field("content-transfer-encoding")
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+        .as_str()
+    {
+        "base64" => {
+            let data = body
+                .iter()
+                .copied()
+                .filter(|b| !b.is_ascii_whitespace())
+                .collect::<Vec<_>>();
+            STANDARD.decode(data).context("invalid base64 body")?;
+        }
+        "quoted-printable" => {
+            for (idx, _) in body.iter().enumerate().filter(|(_, b)| **b == b'=') {
+                let rest = &body[idx + 1..];
+                let valid = rest.is_empty()
+                    || rest.starts_with(b"\r\n")
+                    || rest
+                        .get(..2)
+                        .is_some_and(|h| h.iter().all(u8::is_ascii_hexdigit));
+                if !valid {
+                    bail!("invalid quoted-printable escape at byte {idx} of body");
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Position of the first occurrence of `needle`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+/// Split data into lines at CRLF.
+fn split_crlf(data: &[u8]) -> Vec<&[u8]> {
+    let mut lines = vec![];
+    let mut rest = data;
+    while let Some(pos) = find(rest, NEWLINE.as_bytes()) {
+        lines.push(&rest[..pos]);
+        rest = &rest[pos + 2..];
+    }
+    lines.push(rest);
+    lines
+}
+
+fn boundary_re() -> &'static regex::Regex {
+    BOUNDARY_RE.get_or_init(|| {
+        regex::RegexBuilder::new(r#"boundary\s*=\s*(?:"([^"]*)"|([^;\s]+))"#)
+            .case_insensitive(true)
+            .build()
+            .expect("valid regex")
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
-    use chrono::DateTime;
+    use chrono::FixedOffset;
+    use mail_parser::MimeHeaders;
 
     use crate::{
         mails::{Attachment, Mail},
@@ -209,6 +817,10 @@ mod tests {
                     name: "Me".to_owned(),
                 },
                 attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
             }),
             headers: Some(
                 "From: foo@example.com\nContent-Type: multipart/related; boundary=\"myboundary\""
@@ -219,20 +831,122 @@ mod tests {
             bcc: vec![],
             cc: vec![],
             to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
         })
         .unwrap();
         insta::assert_snapshot!(eml, @r###"
         From: foo@example.com
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
         Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
 
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: text/html; charset=UTF-8
-        Content-Transfer-Encoding: base64
+        Content-Transfer-Encoding: quoted-printable
+
+        hello world=
+
+        ------------79Bu5A16qPEYcVIZL@tutanota--
+        "###);
+    }
+
+    #[test]
+    fn test_original_headers_preserved() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                session_key: Key::Aes256([0; 32]),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Re: Hello".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
+            }),
+            headers: Some(
+                "Received: from a.example.com\n\tby b.example.com;\n\tWed, 04 Mar 2020 11:22:33 +0000\nMessage-ID: <2@example.com>\nIn-Reply-To: <1@example.com>\nReferences: <0@example.com>\n <1@example.com>\nList-Id: <list.example.com>\nContent-Type: text/plain;\n charset=utf-8\nSubject: Re: Hello\n\n"
+                    .to_owned(),
+            ),
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        Received: from a.example.com
+        	by b.example.com;
+        	Wed, 04 Mar 2020 11:22:33 +0000
+        Message-ID: <2@example.com>
+        In-Reply-To: <1@example.com>
+        References: <0@example.com>
+         <1@example.com>
+        List-Id: <list.example.com>
+        Subject: Re: Hello
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
 
-        aGVsbG8gd29ybGQ=
+        ------------79Bu5A16qPEYcVIZL@tutanota
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: quoted-printable
+
+        hello world=
 
         ------------79Bu5A16qPEYcVIZL@tutanota--
         "###);
+
+        let parsed = mail_parser::MessageParser::default()
+            .parse(eml.as_bytes())
+            .unwrap();
+        assert_eq!(parsed.message_id(), Some("2@example.com"));
+        assert_eq!(parsed.in_reply_to().as_text(), Some("1@example.com"));
+        assert_eq!(
+            parsed.references().as_text_list().unwrap(),
+            ["0@example.com", "1@example.com"],
+        );
+    }
+
+    #[test]
+    fn test_date_time_zone() {
+        let mut mail = mail_for_roundtrip(
+            Some("From: foo@example.com\nDate: Wed, 4 Mar 2020 06:22:33 -0500\nSubject: Hällö Привет"),
+            vec![],
+        );
+        let eml = emit_eml(&mail).unwrap();
+        let parsed = mail_parser::MessageParser::default()
+            .parse(eml.as_bytes())
+            .unwrap();
+        let date =
+            DateTime::<FixedOffset>::parse_from_rfc3339(&parsed.date().unwrap().to_rfc3339())
+                .unwrap();
+        assert_eq!(date.offset().local_minus_utc(), -5 * 3600);
+        assert_eq!(date.to_utc(), mail.mail.date);
+        assert_eq!(eml.matches("Date:").count(), 1, "{eml}");
+
+        // without original `Date` header, fall back to the receive date in UTC
+        mail.headers = Some("From: foo@example.com\nSubject: Hällö Привет".to_owned());
+        let eml = emit_eml(&mail).unwrap();
+        assert!(
+            eml.contains("\r\nDate: Wed, 4 Mar 2020 11:22:33 +0000\r\n"),
+            "{eml}"
+        );
     }
 
     #[test]
@@ -254,6 +968,10 @@ mod tests {
                     name: "Me".to_owned(),
                 },
                 attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
             }),
             headers: Some("From: foo@example.com\nContent-Type: text/plain".to_owned()),
             body: b"hello world".to_vec(),
@@ -261,17 +979,22 @@ mod tests {
             bcc: vec![],
             cc: vec![],
             to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
         })
         .unwrap();
         insta::assert_snapshot!(eml, @r###"
         From: foo@example.com
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
         Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
 
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: text/html; charset=UTF-8
-        Content-Transfer-Encoding: base64
+        Content-Transfer-Encoding: quoted-printable
 
-        aGVsbG8gd29ybGQ=
+        hello world=
 
         ------------79Bu5A16qPEYcVIZL@tutanota--
         "###);
@@ -296,6 +1019,10 @@ mod tests {
                     name: "Me".to_owned(),
                 },
                 attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
             }),
             headers: Some("From: foo@example.com\ncontent-type: text/plain".to_owned()),
             body: b"hello world".to_vec(),
@@ -303,17 +1030,22 @@ mod tests {
             bcc: vec![],
             cc: vec![],
             to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
         })
         .unwrap();
         insta::assert_snapshot!(eml, @r###"
         From: foo@example.com
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
         Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
 
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: text/html; charset=UTF-8
-        Content-Transfer-Encoding: base64
+        Content-Transfer-Encoding: quoted-printable
 
-        aGVsbG8gd29ybGQ=
+        hello world=
 
         ------------79Bu5A16qPEYcVIZL@tutanota--
         "###);
@@ -338,6 +1070,10 @@ mod tests {
                     name: "Me".to_owned(),
                 },
                 attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
             }),
             headers: Some(
                 "From: foo@example.com\nContent-Type: multipart/related;\n\tboundary=\"myboundary\"\nFoo: bar\nContent-Type: text/plain\nFoo2: bar2"
@@ -348,19 +1084,24 @@ mod tests {
             bcc: vec![],
             cc: vec![],
             to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
         })
         .unwrap();
         insta::assert_snapshot!(eml, @r###"
         From: foo@example.com
         Foo: bar
         Foo2: bar2
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
         Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
 
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: text/html; charset=UTF-8
-        Content-Transfer-Encoding: base64
+        Content-Transfer-Encoding: quoted-printable
 
-        aGVsbG8gd29ybGQ=
+        hello world=
 
         ------------79Bu5A16qPEYcVIZL@tutanota--
         "###);
@@ -385,6 +1126,10 @@ mod tests {
                     name: "Me".to_owned(),
                 },
                 attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
             }),
             headers: Some("From: foo@example.com\nFoo: bar".to_owned()),
             body: b"hello world".to_vec(),
@@ -392,18 +1137,23 @@ mod tests {
             bcc: vec![],
             cc: vec![],
             to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
         })
         .unwrap();
         insta::assert_snapshot!(eml, @r###"
         From: foo@example.com
         Foo: bar
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
         Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
 
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: text/html; charset=UTF-8
-        Content-Transfer-Encoding: base64
+        Content-Transfer-Encoding: quoted-printable
 
-        aGVsbG8gd29ybGQ=
+        hello world=
 
         ------------79Bu5A16qPEYcVIZL@tutanota--
         "###);
@@ -432,6 +1182,10 @@ mod tests {
                     ["c".to_owned(), "d".to_owned()],
                     ["e".to_owned(), "f".to_owned()],
                 ],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
             }),
             headers: Some(
                 "From: foo@example.com\nContent-Type: multipart/related; boundary=\"myboundary\""
@@ -461,22 +1215,27 @@ mod tests {
             bcc: vec![],
             cc: vec![],
             to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
         })
         .unwrap();
         insta::assert_snapshot!(eml, @r###"
         From: foo@example.com
-        Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
+        Content-Type: multipart/mixed; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
 
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: text/html; charset=UTF-8
-        Content-Transfer-Encoding: base64
+        Content-Transfer-Encoding: quoted-printable
 
-        aGVsbG8gd29ybGQ=
+        hello world=
 
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: image/jpeg; name==?UTF-8?B?ZsO2by5qcGc=?=
         Content-Transfer-Encoding: base64
-        Content-Disposition: attachment; filename==?UTF-8?B?ZsO2by5qcGc=?=
+        Content-Disposition: attachment; filename*=UTF-8''f%C3%B6o.jpg
         Content-Id: <cid001>
 
         Zm9vYmFy
@@ -484,15 +1243,15 @@ mod tests {
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: image/new; name==?UTF-8?B?w6U=?=
         Content-Transfer-Encoding: base64
-        Content-Disposition: attachment; filename==?UTF-8?B?w6U=?=
+        Content-Disposition: attachment; filename*=UTF-8''%C3%A5
         Content-Id: <cid002>
 
         eA==
 
         ------------79Bu5A16qPEYcVIZL@tutanota
-        Content-Type: x/y; name==?UTF-8?B?c29tZXRoaW5n?=
+        Content-Type: x/y; name="something"
         Content-Transfer-Encoding: base64
-        Content-Disposition: attachment; filename==?UTF-8?B?c29tZXRoaW5n?=
+        Content-Disposition: attachment; filename="something"
 
         eGNkZGQ=
 
@@ -500,6 +1259,172 @@ mod tests {
         "###);
     }
 
+    #[test]
+    fn test_skipped_attachment() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                session_key: Key::Aes256([0; 32]),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![["a".to_owned(), "b".to_owned()]],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
+            }),
+            headers: Some("From: foo@example.com".to_owned()),
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![SkippedAttachment {
+                cid: None,
+                mime_type: "video/mp4".to_owned(),
+                name: "vacation.mp4".to_owned(),
+                size: 123_456_789,
+            }],
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
+        Content-Type: multipart/mixed; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
+
+        ------------79Bu5A16qPEYcVIZL@tutanota
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: quoted-printable
+
+        hello world=
+
+        ------------79Bu5A16qPEYcVIZL@tutanota
+        Content-Type: text/plain; charset=UTF-8
+        Content-Transfer-Encoding: quoted-printable
+        Content-Disposition: inline
+
+        Attachment not downloaded because it exceeds the size limit: vacation.mp4 (=
+        video/mp4, 123456789 bytes)=
+
+        ------------79Bu5A16qPEYcVIZL@tutanota--
+        "###);
+        verify_eml(eml.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_inline_image() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                session_key: Key::Aes256([0; 32]),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![
+                    ["a".to_owned(), "b".to_owned()],
+                    ["c".to_owned(), "d".to_owned()],
+                ],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
+            }),
+            headers: Some("From: foo@example.com".to_owned()),
+            body: b"<img src=\"cid:cid001\">".to_vec(),
+            attachments: vec![
+                Attachment {
+                    cid: Some("cid001".to_owned()),
+                    mime_type: "image/png".to_owned(),
+                    name: "logo.png".to_owned(),
+                    data: b"png".to_vec(),
+                },
+                Attachment {
+                    cid: Some("cid002".to_owned()),
+                    mime_type: "text/plain".to_owned(),
+                    name: "notes.txt".to_owned(),
+                    data: b"notes".to_vec(),
+                },
+            ],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: foo@example.com
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
+        Content-Type: multipart/mixed; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
+
+        ------------79Bu5A16qPEYcVIZL@tutanota
+        Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZR@tutanota"
+
+        ------------79Bu5A16qPEYcVIZR@tutanota
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: quoted-printable
+
+        <img src=3D"cid:cid001">=
+
+        ------------79Bu5A16qPEYcVIZR@tutanota
+        Content-Type: image/png; name="logo.png"
+        Content-Transfer-Encoding: base64
+        Content-Disposition: inline; filename="logo.png"
+        Content-Id: <cid001>
+
+        cG5n
+
+        ------------79Bu5A16qPEYcVIZR@tutanota--
+
+        ------------79Bu5A16qPEYcVIZL@tutanota
+        Content-Type: text/plain; name="notes.txt"
+        Content-Transfer-Encoding: base64
+        Content-Disposition: attachment; filename="notes.txt"
+        Content-Id: <cid002>
+
+        bm90ZXM=
+
+        ------------79Bu5A16qPEYcVIZL@tutanota--
+        "###);
+
+        let parsed = mail_parser::MessageParser::default()
+            .parse(eml.as_bytes())
+            .unwrap();
+        assert_eq!(parsed.body_html(0).unwrap(), "<img src=\"cid:cid001\">");
+        let parts = parsed
+            .attachments()
+            .map(|a| (a.attachment_name().unwrap(), a.content_id()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parts,
+            [("logo.png", Some("cid001")), ("notes.txt", Some("cid002"))],
+        );
+    }
+
     #[test]
     fn test_synthesize_headers_minimal() {
         let eml = emit_eml(&DownloadedMail {
@@ -519,6 +1444,10 @@ mod tests {
                     name: "Mé".to_owned(),
                 },
                 attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
             }),
             headers: None,
             body: b"hello world".to_vec(),
@@ -526,19 +1455,24 @@ mod tests {
             bcc: vec![],
             cc: vec![],
             to: vec![],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
         })
         .unwrap();
         insta::assert_snapshot!(eml, @r###"
         From: =?UTF-8?B?TcOp?= <foo@example.com>
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
         MIME-Version: 1.0
         Subject: =?UTF-8?B?SMOkbGzDtg==?=
         Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
 
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: text/html; charset=UTF-8
-        Content-Transfer-Encoding: base64
+        Content-Transfer-Encoding: quoted-printable
 
-        aGVsbG8gd29ybGQ=
+        hello world=
 
         ------------79Bu5A16qPEYcVIZL@tutanota--
         "###);
@@ -563,6 +1497,10 @@ mod tests {
                     name: "Mé".to_owned(),
                 },
                 attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
             }),
             headers: None,
             body: b"hello world".to_vec(),
@@ -597,24 +1535,481 @@ mod tests {
                     name: "Óther 6".to_owned(),
                 },
             ],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
         })
         .unwrap();
         insta::assert_snapshot!(eml, @r###"
         From: =?UTF-8?B?TcOp?= <foo@example.com>
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
         MIME-Version: 1.0
         Subject: =?UTF-8?B?SMOkbGzDtg==?=
-        BCC: =?UTF-8?B?w5N0aGVyIDE=?= <bar1@example.com>,=?UTF-8?B?w5N0aGVyIDI=?= <bar2@example.com>
-        CC: =?UTF-8?B?w5N0aGVyIDM=?= <bar3@example.com>,=?UTF-8?B?w5N0aGVyIDQ=?= <bar4@example.com>
-        To: =?UTF-8?B?w5N0aGVyIDU=?= <bar5@example.com>,=?UTF-8?B?w5N0aGVyIDY=?= <bar6@example.com>
+        BCC: =?UTF-8?B?w5N0aGVyIDE=?= <bar1@example.com>,
+         =?UTF-8?B?w5N0aGVyIDI=?= <bar2@example.com>
+        CC: =?UTF-8?B?w5N0aGVyIDM=?= <bar3@example.com>,
+         =?UTF-8?B?w5N0aGVyIDQ=?= <bar4@example.com>
+        To: =?UTF-8?B?w5N0aGVyIDU=?= <bar5@example.com>,
+         =?UTF-8?B?w5N0aGVyIDY=?= <bar6@example.com>
         Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
 
         ------------79Bu5A16qPEYcVIZL@tutanota
         Content-Type: text/html; charset=UTF-8
-        Content-Transfer-Encoding: base64
+        Content-Transfer-Encoding: quoted-printable
+
+        hello world=
+
+        ------------79Bu5A16qPEYcVIZL@tutanota--
+        "###);
+    }
+
+    #[test]
+    fn test_synthesize_headers_encoding() {
+        let eml = emit_eml(&DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                session_key: Key::Aes256([0; 32]),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Привет, мир! Это длинная тема письма для проверки".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: format!("Máx {}", "🎉".repeat(12)),
+                },
+                attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
+            }),
+            headers: None,
+            body: b"hello world".to_vec(),
+            attachments: vec![],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![Address {
+                mail: "bar@example.com".to_owned(),
+                name: "".to_owned(),
+            }],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
+        })
+        .unwrap();
+        insta::assert_snapshot!(eml, @r###"
+        From: =?UTF-8?B?TcOheCDwn46J8J+OifCfjonwn46J8J+OifCfjonwn46J8J+OifCfjonwn46J?=
+         =?UTF-8?B?8J+OifCfjok=?= <foo@example.com>
+        Date: Wed, 4 Mar 2020 11:22:33 +0000
+        Message-ID: <mail_id@tatutanatata.invalid>
+        MIME-Version: 1.0
+        Subject: =?UTF-8?B?0J/RgNC40LLQtdGCLCDQvNC40YAhINCt0YLQviDQtNC70LjQvdC90LDRjyA=?=
+         =?UTF-8?B?0YLQtdC80LAg0L/QuNGB0YzQvNCwINC00LvRjyDQv9GA0L7QstC10YDQutC4?=
+        To: <bar@example.com>
+        Content-Type: multipart/related; boundary="----------79Bu5A16qPEYcVIZL@tutanota"
+
+        ------------79Bu5A16qPEYcVIZL@tutanota
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: quoted-printable
 
-        aGVsbG8gd29ybGQ=
+        hello world=
 
         ------------79Bu5A16qPEYcVIZL@tutanota--
         "###);
+
+        let parsed = mail_parser::MessageParser::default()
+            .parse(eml.as_bytes())
+            .unwrap();
+        assert_eq!(
+            parsed.subject(),
+            Some("Привет, мир! Это длинная тема письма для проверки"),
+        );
+        assert_eq!(
+            parsed.from().unwrap().first().unwrap().name(),
+            Some(format!("Máx {}", "🎉".repeat(12)).as_str()),
+        );
+    }
+
+    #[test]
+    fn test_synthesize_headers_reply() {
+        let mut mail = mail_for_roundtrip(None, vec![]);
+        mail.message_id = Some("2@tutanota.de".to_owned());
+        mail.references = vec!["0@example.com".to_owned(), "<1@example.com>".to_owned()];
+        let eml = emit_eml(&mail).unwrap();
+        assert!(
+            eml.contains(
+                "In-Reply-To: <1@example.com>\r\nReferences: <0@example.com>\r\n <1@example.com>\r\n"
+            ),
+            "{eml}"
+        );
+        let parsed = mail_parser::MessageParser::default()
+            .parse(eml.as_bytes())
+            .unwrap();
+        assert_eq!(parsed.message_id(), Some("2@tutanota.de"), "{eml}");
+        assert_eq!(
+            parsed.in_reply_to().as_text(),
+            Some("1@example.com"),
+            "{eml}"
+        );
+        assert_eq!(
+            parsed.references().as_text_list().unwrap(),
+            ["0@example.com", "1@example.com"],
+            "{eml}"
+        );
+
+        // without stored ID, it is derived from the mail ID
+        mail.message_id = None;
+        mail.references = vec![];
+        let eml = emit_eml(&mail).unwrap();
+        let parsed = mail_parser::MessageParser::default()
+            .parse(eml.as_bytes())
+            .unwrap();
+        assert_eq!(
+            parsed.message_id(),
+            Some("mail_id@tatutanatata.invalid"),
+            "{eml}"
+        );
+        assert_eq!(parsed.in_reply_to().as_text(), None, "{eml}");
+    }
+
+    fn mail_for_roundtrip(headers: Option<&str>, attachments: Vec<Attachment>) -> DownloadedMail {
+        DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                session_key: Key::Aes256([0; 32]),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö Привет".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Mé".to_owned(),
+                },
+                attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: false,
+                replied: false,
+                forwarded: false,
+            }),
+            headers: headers.map(|h| h.to_owned()),
+            body: "<p>hello wörld</p>".repeat(10).into_bytes(),
+            attachments,
+            bcc: vec![],
+            cc: vec![],
+            to: vec![Address {
+                mail: "bar@example.com".to_owned(),
+                name: "Óther".to_owned(),
+            }],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![],
+        }
+    }
+
+    /// Parse output with a real MIME parser to make sure that other clients can read it.
+    #[test]
+    fn test_roundtrip_parser() {
+        let attachments = || {
+            vec![
+                Attachment {
+                    cid: Some("cid001".to_owned()),
+                    mime_type: "image/jpeg".to_owned(),
+                    name: "föo.jpg".to_owned(),
+                    data: b"foobar".to_vec(),
+                },
+                Attachment {
+                    cid: None,
+                    mime_type: "application/octet-stream".to_owned(),
+                    name: "some file.bin".to_owned(),
+                    data: (0..=255).cycle().take(1000).collect(),
+                },
+                Attachment {
+                    cid: None,
+                    mime_type: "application/pdf".to_owned(),
+                    name: "年次報告書 Годовой отчёт \"final\" version (2).pdf".to_owned(),
+                    data: b"%PDF".to_vec(),
+                },
+            ]
+        };
+
+        for (headers, attachments) in [
+            // synthesized headers, no attachments
+            (None, vec![]),
+            // synthesized headers, attachments
+            (None, attachments()),
+            // plain text mail with original headers
+            (
+                Some("From: =?UTF-8?B?TcOp?= <foo@example.com>\nTo: bar@example.com\nSubject: =?UTF-8?B?SMOkbGzDtiDQn9GA0LjQstC10YI=?=\nMIME-Version: 1.0\nContent-Type: text/plain;\n charset=utf-8"),
+                vec![],
+            ),
+            // original headers, attachments
+            (
+                Some("From: foo@example.com\nSubject: =?UTF-8?B?SMOkbGzDtiDQn9GA0LjQstC10YI=?=\nContent-Type: multipart/mixed; boundary=\"foo\""),
+                attachments(),
+            ),
+        ] {
+            let mail = mail_for_roundtrip(headers, attachments);
+            let eml = emit_eml(&mail).unwrap();
+            verify_eml(eml.as_bytes()).unwrap();
+            let parsed = mail_parser::MessageParser::default()
+                .parse(eml.as_bytes())
+                .unwrap();
+
+            assert_eq!(parsed.subject(), Some("Hällö Привет"), "{eml}");
+            let from = parsed.from().unwrap().first().unwrap();
+            assert_eq!(from.address(), Some("foo@example.com"), "{eml}");
+            assert_eq!(
+                parsed.body_html(0).unwrap(),
+                String::from_utf8(mail.body.clone()).unwrap(),
+                "{eml}",
+            );
+
+            let parsed_attachments = parsed.attachments().collect::<Vec<_>>();
+            assert_eq!(parsed_attachments.len(), mail.attachments.len(), "{eml}");
+            for (expected, actual) in mail.attachments.iter().zip(parsed_attachments) {
+                assert_eq!(actual.attachment_name(), Some(expected.name.as_str()), "{eml}");
+                assert_eq!(actual.contents(), expected.data, "{eml}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_filename_param() {
+        assert_eq!(
+            filename_param("some file.bin"),
+            r#"filename="some file.bin""#
+        );
+        assert_eq!(filename_param(r#"a "b" \c"#), r#"filename="a \"b\" \\c""#);
+        assert_eq!(
+            filename_param("報告書.pdf"),
+            "filename*=UTF-8''%E5%A0%B1%E5%91%8A%E6%9B%B8.pdf"
+        );
+        assert_eq!(
+            filename_param("отчёт за 2024 год, итоговая версия.pdf"),
+            "filename*0*=UTF-8''%D0%BE%D1%82%D1%87%D1%91%D1%82%20%D0%B7%D0%B0%202024%20;\r\n \
+            filename*1*=%D0%B3%D0%BE%D0%B4%2C%20%D0%B8%D1%82%D0%BE%D0%B3%D0%BE%D0%B2;\r\n \
+            filename*2*=%D0%B0%D1%8F%20%D0%B2%D0%B5%D1%80%D1%81%D0%B8%D1%8F.pdf"
+        );
+        assert_eq!(
+            filename_param("line\nbreak"),
+            "filename*=UTF-8''line%0Abreak"
+        );
+
+        assert_eq!(name_param("notes.txt"), r#"name="notes.txt""#);
+        assert_eq!(
+            name_param("報告書.pdf"),
+            "name==?UTF-8?B?5aCx5ZGK5pu4LnBkZg==?="
+        );
+    }
+
+    #[test]
+    fn test_verify_eml() {
+        let mail = mail_for_roundtrip(
+            None,
+            vec![Attachment {
+                cid: None,
+                mime_type: "application/octet-stream".to_owned(),
+                name: "some file.bin".to_owned(),
+                data: (0..=255).cycle().take(1000).collect(),
+            }],
+        );
+        let eml = emit_eml(&mail).unwrap();
+        verify_eml(eml.as_bytes()).unwrap();
+
+        let err = |eml: &str| format!("{:#}", verify_eml(eml.as_bytes()).unwrap_err());
+
+        assert_eq!(
+            err(&eml[..eml.len() / 2]),
+            "final delimiter of `----------79Bu5A16qPEYcVIZL@tutanota` is missing, file may be truncated",
+        );
+        assert_eq!(
+            err(&eml.replacen("Subject", "\nSubject", 1)),
+            format!("lone line break at byte {}", eml.find("Subject").unwrap()),
+        );
+        assert_eq!(
+            err(&eml.replacen("Subject:", "Subject", 1)),
+            "header line 5 has no name"
+        );
+        assert_eq!(err("Subject: x"), "no empty line after the headers");
+        assert_eq!(err(" x: y\r\n\r\nbody"), "header line 1 continues nothing");
+
+        let base64_start = eml.find("\r\n\r\nAAEC").unwrap() + 4;
+        let mut damaged = eml.clone();
+        damaged.replace_range(base64_start..base64_start + 1, "!");
+        assert_eq!(
+            err(&damaged),
+            "part #2 of `----------79Bu5A16qPEYcVIZL@tutanota`: invalid base64 body: Invalid symbol 33, offset 0.",
+        );
+
+        assert_eq!(
+            err(&eml.replacen("hello w=C3=B6rld", "hello w=C3=Xrld", 1)),
+            "part #1 of `----------79Bu5A16qPEYcVIZL@tutanota`: invalid quoted-printable escape at byte 13 of body",
+        );
+    }
+
+    /// Every line must end with CRLF, existing CRLF must not be doubled.
+    #[test]
+    fn test_crlf_line_endings() {
+        let attachments = || {
+            vec![
+                Attachment {
+                    cid: Some("cid\n001".to_owned()),
+                    mime_type: "text/plain\r\n".to_owned(),
+                    name: "a\nb.txt".to_owned(),
+                    data: b"line 1\nline 2\r\nline 3\r".to_vec(),
+                },
+                Attachment {
+                    cid: None,
+                    mime_type: "application/octet-stream".to_owned(),
+                    name: "some file.bin".to_owned(),
+                    data: (0..=255).cycle().take(1000).collect(),
+                },
+            ]
+        };
+
+        for headers in [
+            None,
+            Some("From: foo@example.com\nSubject: LF\nReferences: <0@example.com>\n <1@example.com>\n"),
+            Some("From: foo@example.com\r\nSubject: CRLF\r\nContent-Type: text/plain;\r\n charset=utf-8\r\n\r\n"),
+            Some("From: foo@example.com\rSubject: CR\r\nTo: bar@example.com\n\r\n"),
+        ] {
+            let mut mail = mail_for_roundtrip(headers, attachments());
+            mail.to.push(Address {
+                mail: "baz@example.com\n".to_owned(),
+                name: "Line\nBreak".to_owned(),
+            });
+            let eml = emit_eml(&mail).unwrap();
+            let bytes = eml.as_bytes();
+
+            for (idx, b) in bytes.iter().enumerate() {
+                match b {
+                    b'\r' => assert_eq!(bytes.get(idx + 1), Some(&b'\n'), "lone CR at {idx}: {eml}"),
+                    b'\n' => assert!(idx > 0 && bytes[idx - 1] == b'\r', "lone LF at {idx}: {eml}"),
+                    _ => {}
+                }
+            }
+            assert!(!eml.contains("\r\r"), "{eml}");
+
+            // the header section must not end early
+            let (header_section, _) = eml.split_once("\r\n\r\n").unwrap();
+            assert!(header_section.contains("Content-Type: multipart/"), "{eml}");
+        }
+    }
+
+    #[test]
+    fn test_transfer_encoding() {
+        let text = format!(
+            "<p>Grüße, a=b </p>\t\r\n<p>tab</p>\nlone LF\r\n{}",
+            "x".repeat(100)
+        );
+        let binary = (0..=255).cycle().take(1000).collect::<Vec<u8>>();
+
+        let mut mail = mail_for_roundtrip(None, vec![]);
+        mail.body = text.clone().into_bytes();
+        mail.attachments = vec![Attachment {
+            cid: None,
+            mime_type: "application/octet-stream".to_owned(),
+            name: "some file.bin".to_owned(),
+            data: binary.clone(),
+        }];
+        let eml = emit_eml(&mail).unwrap();
+        let (_headers, body) = eml.split_once("\r\n\r\n").unwrap();
+        insta::assert_snapshot!(body.lines().take(12).join("\n"), @r###"
+        ------------79Bu5A16qPEYcVIZL@tutanota
+        Content-Type: text/html; charset=UTF-8
+        Content-Transfer-Encoding: quoted-printable
+
+        <p>Gr=C3=BC=C3=9Fe, a=3Db </p>=09
+        <p>tab</p>=0Alone LF
+        xxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxxx=
+        xxxxxxxxxxxxxxxxxxxxxxxxx=
+
+        ------------79Bu5A16qPEYcVIZL@tutanota
+        Content-Type: application/octet-stream; name="some file.bin"
+        Content-Transfer-Encoding: base64
+        "###);
+
+        let parsed = mail_parser::MessageParser::default()
+            .parse(eml.as_bytes())
+            .unwrap();
+        assert_eq!(parsed.body_html(0).unwrap(), text);
+        assert_eq!(parsed.attachments().next().unwrap().contents(), binary);
+
+        // mostly non-ASCII body
+        let text = "<p>Привет, мир!</p>".repeat(10);
+        mail.body = text.clone().into_bytes();
+        mail.attachments = vec![];
+        let eml = emit_eml(&mail).unwrap();
+        assert!(
+            eml.contains(
+                "Content-Type: text/html; charset=UTF-8\r\nContent-Transfer-Encoding: base64\r\n"
+            ),
+            "{eml}"
+        );
+        let parsed = mail_parser::MessageParser::default()
+            .parse(eml.as_bytes())
+            .unwrap();
+        assert_eq!(parsed.body_html(0).unwrap(), text);
+    }
+
+    /// Body lines must never look like the MIME boundary.
+    #[test]
+    fn test_quoted_printable_boundary() {
+        let text = format!(
+            "------------79Bu5A16qPEYcVIZL@tutanota\r\n-a-\r\n{}-x",
+            "y".repeat(75)
+        );
+
+        let mut buf = Vec::new();
+        write_quoted_printable(&mut Lines::new(&mut buf), text.as_bytes())
+            .now_or_never()
+            .unwrap()
+            .unwrap();
+        let encoded = String::from_utf8(buf).unwrap();
+        insta::assert_snapshot!(encoded.replace(NEWLINE, "\n"), @r###"
+        =2D-----------79Bu5A16qPEYcVIZL@tutanota
+        =2Da-
+        yyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyyy=
+        =2Dx=
+        "###);
+
+        let mut mail = mail_for_roundtrip(None, vec![]);
+        mail.body = text.clone().into_bytes();
+        let eml = emit_eml(&mail).unwrap();
+        let parsed = mail_parser::MessageParser::default()
+            .parse(eml.as_bytes())
+            .unwrap();
+        assert_eq!(parsed.body_html(0).unwrap(), text);
+    }
+
+    /// Base64 encoding in blocks must match encoding everything at once.
+    #[test]
+    fn test_write_base64_blocks() {
+        for len in [0, 1, 58, 59, 3743, 3744, 3745, 10_000] {
+            let data = (0..=255).cycle().take(len).collect::<Vec<u8>>();
+
+            let mut buf = Vec::new();
+            write_base64(&mut Lines::new(&mut buf), &data)
+                .now_or_never()
+                .unwrap()
+                .unwrap();
+
+            let encoded = Base64String::from(data).to_string();
+            let expected = encoded
+                .as_bytes()
+                .chunks(BASE64_LINE_LENGTH)
+                .map(|line| std::str::from_utf8(line).unwrap())
+                .join(NEWLINE);
+            assert_eq!(String::from_utf8(buf).unwrap(), expected, "{len}");
+        }
     }
 }
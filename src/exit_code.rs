@@ -0,0 +1,154 @@
+//! Process exit codes that distinguish failure categories.
+use reqwest::StatusCode;
+
+use crate::client::error_status;
+
+/// Category of an error, attached as context so that [`exit_code`] can find it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ErrorKind {
+    /// Requested folder does not exist.
+    FolderNotFound,
+
+    /// Some folders or mails failed, the rest was downloaded.
+    PartialDownload,
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FolderNotFound => write!(f, "folder not found"),
+            Self::PartialDownload => write!(f, "download incomplete"),
+        }
+    }
+}
+
+/// Any other error.
+pub(crate) const EXIT_FAILURE: u8 = 1;
+
+/// Login failed or the session was rejected, e.g. because of a wrong password.
+///
+/// `2` is used by the argument parser for invalid usage.
+pub(crate) const EXIT_AUTH: u8 = 3;
+
+/// Server not reachable, e.g. because the network is down.
+pub(crate) const EXIT_NETWORK: u8 = 4;
+
+/// See [`ErrorKind::FolderNotFound`].
+pub(crate) const EXIT_FOLDER_NOT_FOUND: u8 = 5;
+
+/// See [`ErrorKind::PartialDownload`].
+pub(crate) const EXIT_PARTIAL_DOWNLOAD: u8 = 6;
+
+/// Map error to the process exit code.
+pub fn exit_code(e: &anyhow::Error) -> u8 {
+    match e.downcast_ref::<ErrorKind>() {
+        Some(ErrorKind::FolderNotFound) => return EXIT_FOLDER_NOT_FOUND,
+        Some(ErrorKind::PartialDownload) => return EXIT_PARTIAL_DOWNLOAD,
+        None => {}
+    }
+
+    if matches!(
+        error_status(e),
+        Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+    ) {
+        return EXIT_AUTH;
+    }
+
+    let network = e
+        .chain()
+        .filter_map(|e| e.downcast_ref::<reqwest::Error>())
+        .any(|e| e.is_connect() || e.is_timeout());
+    if network {
+        return EXIT_NETWORK;
+    }
+
+    EXIT_FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{anyhow, Context};
+    use clap::Parser;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::{
+        client::{Client, ClientCLIConfig, Prefix, Request},
+        folders::Folder,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_exit_code_folder_not_found() {
+        let folders = [Folder {
+            name: "Inbox".to_owned(),
+            mails: "mails".to_owned(),
+            id: "id".to_owned(),
+            parent: None,
+            system_folder: None,
+        }];
+        let e = Folder::find(&folders, Some("Outbox"), None, None)
+            .context("download")
+            .context("execute command")
+            .unwrap_err();
+        assert_eq!(exit_code(&e), EXIT_FOLDER_NOT_FOUND);
+        assert_eq!(
+            format!("{e:#}"),
+            "execute command: download: folder not found"
+        );
+
+        let e = Folder::find(&folders, None, Some("id"), None);
+        assert!(e.is_ok());
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let e = anyhow!("1 of 2 folders failed: Inbox").context(ErrorKind::PartialDownload);
+        assert_eq!(exit_code(&e), EXIT_PARTIAL_DOWNLOAD);
+
+        assert_eq!(exit_code(&anyhow!("other")), EXIT_FAILURE);
+    }
+
+    #[tokio::test]
+    async fn test_exit_code_http() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let mut buf = vec![];
+            while !buf.ends_with(b"\r\n\r\n") {
+                buf.push(conn.read_u8().await.unwrap());
+            }
+            conn.write_all(b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        let client = Client::try_new(
+            ClientCLIConfig::parse_from([
+                "tatutanatata",
+                &format!("--server-url=http://{addr}"),
+                "--allow-insecure-http",
+                "--max-retries=0",
+            ]),
+            None,
+        )
+        .await
+        .unwrap();
+        let e = client
+            .do_json::<_, serde_json::Value>(Request::new(Prefix::Sys, "session", &()))
+            .await
+            .context("log in")
+            .unwrap_err();
+        assert_eq!(exit_code(&e), EXIT_AUTH);
+        server.await.unwrap();
+
+        // nothing listens on the port anymore
+        let e = client
+            .do_json::<_, serde_json::Value>(Request::new(Prefix::Sys, "session", &()))
+            .await
+            .context("log in")
+            .unwrap_err();
+        assert_eq!(exit_code(&e), EXIT_NETWORK);
+    }
+}
@@ -1,28 +1,132 @@
-use std::path::Path;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Context, Result};
-use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncWriteExt, BufWriter},
+};
 use tracing::warn;
+use unicode_normalization::UnicodeNormalization;
 
 pub(crate) async fn write_to_file(content: &[u8], path: &Path) -> Result<()> {
-    let tmp_path = path.with_extension(".part");
-    let mut f = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .create(true)
-        .open(&tmp_path)
-        .await
-        .context("open temp file")?;
+    write_to_file_impl(content, path, false).await
+}
+
+/// Same as [`write_to_file`] but only the current user can read the file.
+///
+/// Use this for secrets like access tokens.
+pub(crate) async fn write_to_private_file(content: &[u8], path: &Path) -> Result<()> {
+    write_to_file_impl(content, path, true).await
+}
+
+/// Write file atomically.
+///
+/// See [`AtomicFile`].
+async fn write_to_file_impl(content: &[u8], path: &Path, private: bool) -> Result<()> {
+    let mut f = AtomicFile::create(path, private).await?;
+    match f.writer().write_all(content).await {
+        Ok(()) => f.commit().await.map(|_size| ()),
+        Err(e) => {
+            f.abort().await;
+            Err(e).context("write to temp file")
+        }
+    }
+}
+
+/// File that is written atomically.
+///
+/// The content is written to a temporary file in the same directory that is renamed on
+/// [`commit`](Self::commit), so the target is either missing or complete, even if the process
+/// crashes.
+#[derive(Debug)]
+pub(crate) struct AtomicFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl AtomicFile {
+    /// Create temporary file for given target path.
+    ///
+    /// If `private` is set, only the current user can read the file.
+    pub(crate) async fn create(path: &Path, private: bool) -> Result<Self> {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".part");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut options = OpenOptions::new();
+        options.write(true).truncate(true).create(true);
+        #[cfg(unix)]
+        if private {
+            options.mode(0o600);
+        }
+        #[cfg(not(unix))]
+        let _ = private;
+        let f = options.open(&tmp_path).await.context("open temp file")?;
+
+        // `mode` only applies when the file is created, a leftover temp file keeps its permissions
+        #[cfg(unix)]
+        if private {
+            use std::os::unix::fs::PermissionsExt;
+
+            f.set_permissions(std::fs::Permissions::from_mode(0o600))
+                .await
+                .context("restrict temp file permissions")?;
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            tmp_path,
+            writer: BufWriter::new(f),
+        })
+    }
+
+    /// Writer for the content.
+    pub(crate) fn writer(&mut self) -> &mut BufWriter<File> {
+        &mut self.writer
+    }
+
+    /// Sync content to disk and move file to its target path.
+    ///
+    /// Returns the file size in bytes. The temporary file is removed on failure.
+    pub(crate) async fn commit(mut self) -> Result<u64> {
+        let res = async {
+            self.writer.flush().await.context("flush temp file")?;
+            let f = self.writer.get_ref();
+            f.sync_all().await.context("sync temp file")?;
+            let size = f.metadata().await.context("get temp file size")?.len();
+            self.writer.shutdown().await.context("close temp file")?;
+
+            rename(&self.tmp_path, &self.path).await.context("rename")?;
 
-    f.write_all(content).await.context("write to temp file")?;
-    f.shutdown().await.context("close temp file")?;
+            Ok(size)
+        }
+        .await;
+
+        if res.is_err() {
+            self.abort().await;
+        }
 
-    rename(&tmp_path, path).await.context("rename")?;
+        res
+    }
 
-    Ok(())
+    /// Discard content and remove temporary file.
+    pub(crate) async fn abort(self) {
+        drop(self.writer);
+        if let Err(e) = tokio::fs::remove_file(&self.tmp_path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!(%e, path=%self.tmp_path.display(), "cannot remove temp file");
+            }
+        }
+    }
 }
 
-async fn rename(old: &Path, new: &Path) -> Result<(), std::io::Error> {
+pub(crate) async fn rename(old: &Path, new: &Path) -> Result<(), std::io::Error> {
     // some file systems like SMB may not sync immediately and return "not found" shortly after file
     // creation
 
@@ -54,6 +158,455 @@ pub(crate) fn escape_file_string(s: &str) -> String {
         .collect()
 }
 
+/// Device names that Windows reserves in every directory, with any extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Transliterate to ASCII, e.g. `Grüße` to `Grusse`.
+///
+/// Characters without an ASCII equivalent are dropped.
+pub(crate) fn transliterate(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.nfkd() {
+        match c {
+            c if c.is_ascii() => out.push(c),
+            'ß' => out.push_str("ss"),
+            'æ' => out.push_str("ae"),
+            'Æ' => out.push_str("AE"),
+            'œ' => out.push_str("oe"),
+            'Œ' => out.push_str("OE"),
+            'ø' => out.push('o'),
+            'Ø' => out.push('O'),
+            'đ' => out.push('d'),
+            'Đ' => out.push('D'),
+            'ł' => out.push('l'),
+            'Ł' => out.push('L'),
+            'þ' => out.push_str("th"),
+            'Þ' => out.push_str("TH"),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Make file name portable to restrictive file systems like FAT32 and exFAT.
+///
+/// Non-ASCII characters are transliterated, characters that are reserved on Windows as well as
+/// trailing dots and spaces are removed, and reserved device names like `CON` get a `_` suffix.
+pub(crate) fn portable_file_name(s: &str) -> String {
+    let mut name = transliterate(s)
+        .chars()
+        .filter(|c| {
+            !c.is_ascii_control()
+                && !matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+        })
+        .collect::<String>();
+    name.truncate(name.trim_end_matches(['.', ' ']).len());
+
+    let stem = name.split('.').next().unwrap_or_default().trim_end();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        name.insert(stem.len(), '_');
+    }
+    name
+}
+
+/// Output format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// One `.eml` file per mail.
+    Eml,
+
+    /// Maildir with `cur`, `new`, and `tmp` subdirectories.
+    Maildir,
+
+    /// Single mbox file (mboxrd flavor), `--path` is the file.
+    Mbox,
+
+    /// One `.json` file per mail with decoded fields instead of the raw mail.
+    Json,
+}
+
+/// Sidecar index of EML output that maps mail IDs to file paths.
+///
+/// File names depend on the file name template and on the mail metadata, so the index keeps
+/// re-runs from downloading mails again after either of them changed.
+#[derive(Debug)]
+pub(crate) struct EmlIndex {
+    dir: PathBuf,
+
+    /// Paths, relative to `dir`, of all mails that were listed when opening the index.
+    existing: HashMap<String, PathBuf>,
+
+    /// Reverse of `existing`, including mails that were recorded since opening the index.
+    owners: std::sync::Mutex<HashMap<PathBuf, String>>,
+
+    /// Open index file, `None` if opened read-only.
+    file: Option<tokio::sync::Mutex<File>>,
+}
+
+impl EmlIndex {
+    /// Name of the index file within the output directory.
+    const FILE_NAME: &'static str = ".tatutanatata-index";
+
+    /// Open index of given output directory.
+    ///
+    /// If `create` is set, the index file is created and opened for appending.
+    pub(crate) async fn open(dir: &Path, create: bool) -> Result<Self> {
+        let path = dir.join(Self::FILE_NAME);
+
+        let existing: HashMap<String, PathBuf> = match tokio::fs::read_to_string(&path).await {
+            Ok(s) => s
+                .lines()
+                .filter_map(|l| l.split_once('\t'))
+                .map(|(mail_id, path)| (mail_id.to_owned(), PathBuf::from(path)))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                return Err(e).context("read eml index");
+            }
+        };
+
+        let file = if create {
+            let f = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(&path)
+                .await
+                .context("open eml index")?;
+            Some(tokio::sync::Mutex::new(f))
+        } else {
+            None
+        };
+
+        let owners = existing
+            .iter()
+            .map(|(mail_id, path)| (path.clone(), mail_id.clone()))
+            .collect();
+
+        Ok(Self {
+            dir: dir.to_owned(),
+            existing,
+            owners: std::sync::Mutex::new(owners),
+            file,
+        })
+    }
+
+    /// Path of given mail if it is listed in the index and still exists.
+    pub(crate) async fn get(&self, mail_id: &str) -> Result<Option<PathBuf>> {
+        let Some(path) = self.existing.get(mail_id) else {
+            return Ok(None);
+        };
+        let path = self.dir.join(path);
+        let exists = tokio::fs::try_exists(&path)
+            .await
+            .context("check file existence")?;
+        Ok(exists.then_some(path))
+    }
+
+    /// ID of the mail that owns given path according to the index.
+    pub(crate) fn owner(&self, path: &Path) -> Option<String> {
+        let path = path.strip_prefix(&self.dir).unwrap_or(path);
+        self.owners.lock().expect("not poisoned").get(path).cloned()
+    }
+
+    /// Record path of given mail.
+    ///
+    /// Only updates the in-memory state if the index was opened read-only.
+    pub(crate) async fn insert(&self, mail_id: &str, path: &Path) -> Result<()> {
+        let path = path.strip_prefix(&self.dir).unwrap_or(path);
+        self.owners
+            .lock()
+            .expect("not poisoned")
+            .insert(path.to_owned(), mail_id.to_owned());
+
+        let Some(file) = &self.file else {
+            return Ok(());
+        };
+
+        let mut file = file.lock().await;
+        file.write_all(format!("{mail_id}\t{}\n", path.display()).as_bytes())
+            .await
+            .context("write to eml index")?;
+        file.flush().await.context("flush eml index")?;
+
+        Ok(())
+    }
+}
+
+/// A [Maildir](https://cr.yp.to/proto/maildir.html).
+#[derive(Debug)]
+pub(crate) struct Maildir {
+    path: PathBuf,
+
+    /// Unique names (without info part) and paths of all messages that existed when opening the
+    /// maildir.
+    existing: HashMap<String, PathBuf>,
+}
+
+impl Maildir {
+    /// Open maildir and index existing messages.
+    ///
+    /// If `create` is set, missing directories are created.
+    pub(crate) async fn open(path: &Path, create: bool) -> Result<Self> {
+        let mut existing = HashMap::new();
+
+        for sub in ["cur", "new", "tmp"] {
+            let sub_path = path.join(sub);
+            if create {
+                tokio::fs::create_dir_all(&sub_path)
+                    .await
+                    .with_context(|| format!("create `{}`", sub_path.display()))?;
+            }
+
+            if sub == "tmp" {
+                continue;
+            }
+            let mut entries = match tokio::fs::read_dir(&sub_path).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e).with_context(|| format!("read `{}`", sub_path.display()));
+                }
+            };
+            while let Some(entry) = entries.next_entry().await.context("read dir entry")? {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                let unique = name.split_once(':').map(|(u, _info)| u).unwrap_or(&name);
+                existing.insert(unique.to_owned(), entry.path());
+            }
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            existing,
+        })
+    }
+
+    /// Stable unique name for a mail.
+    ///
+    /// The mail ID makes the name unique and idempotent across runs.
+    pub(crate) fn unique_name(date: DateTime<Utc>, mail_id: &str) -> String {
+        format!("{}.{}.tatutanatata", date.timestamp(), mail_id)
+    }
+
+    /// Check if a message with the given unique name already existed.
+    pub(crate) fn contains(&self, unique: &str) -> bool {
+        self.existing.contains_key(unique)
+    }
+
+    /// Path of a message that already existed, its flags may differ from the current ones.
+    pub(crate) fn existing_path(&self, unique: &str) -> Option<&Path> {
+        self.existing.get(unique).map(|p| p.as_path())
+    }
+
+    /// Final path of a message.
+    pub(crate) fn message_path(&self, unique: &str, flags: &str) -> PathBuf {
+        self.path.join("cur").join(format!("{unique}:2,{flags}"))
+    }
+
+    /// Deliver message.
+    ///
+    /// The message is written to `tmp` first and then moved to `cur`. An existing message with the
+    /// same unique name is replaced, even if its flags differ.
+    pub(crate) async fn write(&self, unique: &str, flags: &str, content: &[u8]) -> Result<()> {
+        let tmp_path = self.path.join("tmp").join(unique);
+        tokio::fs::write(&tmp_path, content)
+            .await
+            .context("write to tmp")?;
+
+        let path = self.message_path(unique, flags);
+        rename(&tmp_path, &path).await.context("move to cur")?;
+
+        if let Some(old) = self.existing.get(unique).filter(|old| **old != path) {
+            match tokio::fs::remove_file(old).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    return Err(e).with_context(|| format!("remove replaced `{}`", old.display()));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An mbox file in the mboxrd flavor.
+///
+/// The IDs of all mails that were written are tracked in a sidecar index file so that re-runs do
+/// not duplicate messages.
+#[derive(Debug)]
+pub(crate) struct Mbox {
+    path: PathBuf,
+
+    /// Mail IDs that existed when opening the mbox.
+    existing: HashSet<String>,
+
+    /// Open files, `None` if opened read-only.
+    files: Option<tokio::sync::Mutex<MboxFiles>>,
+}
+
+#[derive(Debug)]
+struct MboxFiles {
+    mbox: tokio::fs::File,
+    index: tokio::fs::File,
+}
+
+impl Mbox {
+    fn index_path(path: &Path) -> PathBuf {
+        let mut name = path.file_name().unwrap_or_default().to_owned();
+        name.push(".index");
+        path.with_file_name(name)
+    }
+
+    /// Open mbox and its index.
+    ///
+    /// If `create` is set, the files are created and opened for appending.
+    pub(crate) async fn open(path: &Path, create: bool) -> Result<Self> {
+        let index_path = Self::index_path(path);
+
+        let existing = match tokio::fs::read_to_string(&index_path).await {
+            Ok(s) => s.lines().map(|l| l.to_owned()).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => {
+                return Err(e).context("read mbox index");
+            }
+        };
+
+        let files = if create {
+            let mut options = OpenOptions::new();
+            options.append(true).create(true);
+            let mbox = options.open(path).await.context("open mbox")?;
+            let index = options.open(&index_path).await.context("open mbox index")?;
+            Some(tokio::sync::Mutex::new(MboxFiles { mbox, index }))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            path: path.to_owned(),
+            existing,
+            files,
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Check if mail with given ID already existed.
+    pub(crate) fn contains(&self, mail_id: &str) -> bool {
+        self.existing.contains(mail_id)
+    }
+
+    /// Append message.
+    ///
+    /// The index is only updated after the message was fully written, so a crash may lead to a
+    /// duplicate on the next run but never to a lost message.
+    pub(crate) async fn append(
+        &self,
+        mail_id: &str,
+        sender: &str,
+        date: DateTime<Utc>,
+        content: &[u8],
+    ) -> Result<()> {
+        let files = self.files.as_ref().context("mbox not opened for writing")?;
+        let mut files = files.lock().await;
+
+        files
+            .mbox
+            .write_all(&mbox_frame(sender, date, content))
+            .await
+            .context("write to mbox")?;
+        files.mbox.flush().await.context("flush mbox")?;
+
+        files
+            .index
+            .write_all(format!("{mail_id}\n").as_bytes())
+            .await
+            .context("write to mbox index")?;
+        files.index.flush().await.context("flush mbox index")?;
+
+        Ok(())
+    }
+}
+
+/// Frame message for mbox.
+///
+/// Adds the `From ` separator line and quotes existing `From ` lines (with any number of leading
+/// `>`) by prepending another `>`.
+fn mbox_frame(sender: &str, date: DateTime<Utc>, content: &[u8]) -> Vec<u8> {
+    let sender = if sender.is_empty() || sender.contains(char::is_whitespace) {
+        "MAILER-DAEMON"
+    } else {
+        sender
+    };
+
+    let mut out = Vec::with_capacity(content.len() + 128);
+    out.extend_from_slice(
+        format!("From {} {}\n", sender, date.format("%a %b %e %T %Y")).as_bytes(),
+    );
+
+    for line in content.split_inclusive(|b| *b == b'\n') {
+        let quotes = line.iter().take_while(|b| **b == b'>').count();
+        let unquoted = &line[quotes..];
+        if unquoted.starts_with(b"From ") {
+            out.push(b'>');
+        }
+        out.extend_from_slice(line);
+    }
+    if !content.ends_with(b"\n") {
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+
+    out
+}
+
+/// Derive unique, escaped file names for attachments.
+///
+/// File extensions are kept. Duplicates get a numeric suffix.
+pub(crate) fn attachment_file_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+    let mut seen = HashSet::new();
+
+    names
+        .into_iter()
+        .map(|name| {
+            let (stem, ext) = match name.rsplit_once('.') {
+                Some((stem, ext)) => (escape_file_string(stem), escape_file_string(ext)),
+                None => (escape_file_string(name), String::new()),
+            };
+            let stem = if stem.trim().is_empty() {
+                "attachment".to_owned()
+            } else {
+                stem
+            };
+            let with_ext = |stem: &str| {
+                if ext.is_empty() {
+                    stem.to_owned()
+                } else {
+                    format!("{stem}.{ext}")
+                }
+            };
+
+            let mut candidate = with_ext(&stem);
+            let mut counter = 1;
+            while !seen.insert(candidate.to_lowercase()) {
+                candidate = with_ext(&format!("{stem}-{counter}"));
+                counter += 1;
+            }
+            candidate
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -64,4 +617,210 @@ mod tests {
         assert_eq!(escape_file_string("azaZ09 "), "azaZ09 ");
         assert_eq!(escape_file_string("fOo1!@/\\bar19"), "fOo1bar19");
     }
+
+    #[test]
+    fn test_transliterate() {
+        assert_eq!(transliterate(""), "");
+        assert_eq!(transliterate("Grüße aus Köln"), "Grusse aus Koln");
+        assert_eq!(transliterate("Ærøskøbing, Łódź"), "AEroskobing, Lodz");
+        assert_eq!(transliterate("ﬁle①"), "file1");
+        assert_eq!(transliterate("日本 mail 🎉"), " mail ");
+    }
+
+    #[test]
+    fn test_portable_file_name() {
+        assert_eq!(portable_file_name(""), "");
+        assert_eq!(portable_file_name("Re: a/b <c>?"), "Re ab c");
+        assert_eq!(portable_file_name("über*\t|\"x\""), "uberx");
+        assert_eq!(portable_file_name("trailing. . "), "trailing");
+        assert_eq!(portable_file_name("..."), "");
+        assert_eq!(portable_file_name("CON"), "CON_");
+        assert_eq!(portable_file_name("prn"), "prn_");
+        assert_eq!(portable_file_name("Com1.txt"), "Com1_.txt");
+        assert_eq!(portable_file_name("nul "), "nul_");
+        assert_eq!(portable_file_name("CONTACT"), "CONTACT");
+        assert_eq!(portable_file_name("COM10"), "COM10");
+    }
+
+    #[tokio::test]
+    async fn test_write_to_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let path = dir.path().join("foo.eml");
+        write_to_file(b"foo", &path).await.unwrap();
+        write_to_file(b"bar", &path).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"bar");
+
+        // rename fails because target is a directory
+        let path = dir.path().join("dir");
+        std::fs::create_dir(&path).unwrap();
+        write_to_file(b"foo", &path).await.unwrap_err();
+
+        let mut files = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, ["dir", "foo.eml"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_atomic_file_private_existing_temp_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("secret");
+        let tmp_path = dir.path().join("secret.part");
+        std::fs::write(&tmp_path, b"old").unwrap();
+        std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        let mut f = AtomicFile::create(&path, true).await.unwrap();
+        assert_eq!(
+            std::fs::metadata(&tmp_path).unwrap().permissions().mode() & 0o777,
+            0o600,
+        );
+        f.writer().write_all(b"foo").await.unwrap();
+        f.commit().await.unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"foo");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600,
+        );
+    }
+
+    #[test]
+    fn test_attachment_file_names() {
+        assert_eq!(attachment_file_names([]), Vec::<String>::new());
+        assert_eq!(
+            attachment_file_names(["report.pdf", "fo/o", "", ".bashrc", "archive.tar.gz"]),
+            [
+                "report.pdf",
+                "foo",
+                "attachment",
+                "attachment.bashrc",
+                "archivetar.gz"
+            ],
+        );
+        assert_eq!(
+            attachment_file_names(["a.txt", "a.txt", "A.txt", "a-1.txt", "b", "b"]),
+            ["a.txt", "a-1.txt", "A-2.txt", "a1.txt", "b", "b-1"],
+        );
+    }
+
+    #[tokio::test]
+    async fn test_maildir() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let maildir = Maildir::open(dir.path(), false).await.unwrap();
+        assert!(!dir.path().join("cur").exists());
+        assert!(!maildir.contains("1.a.tatutanatata"));
+
+        let maildir = Maildir::open(dir.path(), true).await.unwrap();
+        let unique = Maildir::unique_name(DateTime::from_timestamp(1, 0).unwrap(), "a");
+        assert_eq!(unique, "1.a.tatutanatata");
+        maildir.write(&unique, "S", b"foo").await.unwrap();
+        assert_eq!(
+            std::fs::read(dir.path().join("cur").join("1.a.tatutanatata:2,S")).unwrap(),
+            b"foo",
+        );
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("tmp")).unwrap().count(),
+            0
+        );
+
+        let maildir = Maildir::open(dir.path(), true).await.unwrap();
+        assert!(maildir.contains("1.a.tatutanatata"));
+        assert!(!maildir.contains("1.b.tatutanatata"));
+
+        // overwriting replaces the message with the old flags
+        maildir.write(&unique, "RS", b"bar").await.unwrap();
+        let mut files = std::fs::read_dir(dir.path().join("cur"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(files, ["1.a.tatutanatata:2,RS"]);
+    }
+
+    #[test]
+    fn test_mbox_frame() {
+        let date = DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+            .unwrap()
+            .to_utc();
+
+        assert_eq!(
+            String::from_utf8(mbox_frame(
+                "foo@example.com",
+                date,
+                b"Subject: x\r\n\r\nFrom me\r\n>From you\r\n From\r\nFrom"
+            ))
+            .unwrap(),
+            "From foo@example.com Wed Mar  4 11:22:33 2020\nSubject: x\r\n\r\n>From me\r\n>>From you\r\n From\r\nFrom\n\n",
+        );
+        assert_eq!(
+            String::from_utf8(mbox_frame("", date, b"x\n")).unwrap(),
+            "From MAILER-DAEMON Wed Mar  4 11:22:33 2020\nx\n\n",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_eml_index() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let mail = dir.path().join("sub").join("a.eml");
+
+        let index = EmlIndex::open(dir.path(), false).await.unwrap();
+        index.insert("a", &mail).await.unwrap();
+        assert!(!dir.path().join(EmlIndex::FILE_NAME).exists());
+        assert_eq!(index.owner(&mail).as_deref(), Some("a"));
+
+        let index = EmlIndex::open(dir.path(), true).await.unwrap();
+        index.insert("a", &mail).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(EmlIndex::FILE_NAME)).unwrap(),
+            "a\tsub/a.eml\n",
+        );
+
+        // listed but missing files are ignored
+        let index = EmlIndex::open(dir.path(), true).await.unwrap();
+        assert_eq!(index.get("a").await.unwrap(), None);
+
+        std::fs::create_dir(dir.path().join("sub")).unwrap();
+        std::fs::write(&mail, b"x").unwrap();
+        assert_eq!(index.get("a").await.unwrap(), Some(mail.clone()));
+        assert_eq!(index.get("b").await.unwrap(), None);
+
+        // reverse lookup, the latest entry wins
+        assert_eq!(index.owner(&mail).as_deref(), Some("a"));
+        assert_eq!(index.owner(Path::new("sub/a.eml")).as_deref(), Some("a"));
+        assert_eq!(index.owner(&dir.path().join("other.eml")), None);
+        index.insert("b", &mail).await.unwrap();
+        assert_eq!(index.owner(&mail).as_deref(), Some("b"));
+    }
+
+    #[tokio::test]
+    async fn test_mbox() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("out.mbox");
+        let date = DateTime::from_timestamp(0, 0).unwrap();
+
+        let mbox = Mbox::open(&path, false).await.unwrap();
+        assert!(!path.exists());
+        mbox.append("a", "x@y", date, b"foo").await.unwrap_err();
+
+        let mbox = Mbox::open(&path, true).await.unwrap();
+        assert!(!mbox.contains("a"));
+        mbox.append("a", "x@y", date, b"foo").await.unwrap();
+        mbox.append("b", "x@y", date, b"bar").await.unwrap();
+
+        let mbox = Mbox::open(&path, true).await.unwrap();
+        assert!(mbox.contains("a"));
+        assert!(mbox.contains("b"));
+        assert!(!mbox.contains("c"));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "From x@y Thu Jan  1 00:00:00 1970\nfoo\n\nFrom x@y Thu Jan  1 00:00:00 1970\nbar\n\n",
+        );
+    }
 }
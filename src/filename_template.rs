@@ -0,0 +1,359 @@
+//! Configurable file names for EML output.
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::{bail, Context, Error, Result};
+use chrono::{DateTime, Utc};
+
+use crate::file_output::{escape_file_string, portable_file_name, transliterate};
+
+/// Default template, matches the historic file names.
+pub(crate) const DEFAULT_FILENAME_TEMPLATE: &str = "{date}-{subject}";
+
+/// Maximum number of characters taken from the subject.
+const SUBJECT_MAX_CHARS: usize = 64;
+
+/// Maximum length of a rendered path component in bytes, without suffix and extension.
+///
+/// Many file systems limit names to 255 bytes, this leaves room for the mail ID suffix, the
+/// extension and temporary file names.
+const COMPONENT_MAX_BYTES: usize = 200;
+
+/// Values that can be used in a [`FilenameTemplate`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FilenameValues<'a> {
+    pub(crate) date: DateTime<Utc>,
+    pub(crate) subject: &'a str,
+    pub(crate) id: &'a str,
+    pub(crate) from: &'a str,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Date,
+    Subject,
+    Id,
+    From,
+}
+
+/// File name template.
+///
+/// Placeholders:
+///
+/// - `{date}`: receive date, e.g. `2020-03-04-11h22m33s`
+/// - `{subject}`: subject, escaped and limited to 64 characters
+/// - `{id}`: mail ID
+/// - `{from}`: sender address, escaped
+///
+/// `/` separates directories. The `.eml` extension is appended automatically.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FilenameTemplate {
+    components: Vec<Vec<Part>>,
+}
+
+impl FilenameTemplate {
+    /// Render template to a relative path, including the `.eml` extension.
+    ///
+    /// If `with_id` is set, the mail ID is appended to the file name to avoid collisions. If
+    /// `ascii` is set, placeholders are transliterated instead of dropping non-ASCII characters and
+    /// every component is made portable, see [`portable_file_name`].
+    pub(crate) fn render(&self, values: FilenameValues<'_>, with_id: bool, ascii: bool) -> PathBuf {
+        let escape = |s: &str| {
+            if ascii {
+                escape_file_string(&transliterate(s))
+            } else {
+                escape_file_string(s)
+            }
+        };
+
+        let mut path = PathBuf::new();
+        let n = self.components.len();
+        for (idx, parts) in self.components.iter().enumerate() {
+            let mut component = String::new();
+            for part in parts {
+                match part {
+                    Part::Literal(s) => component.push_str(s),
+                    Part::Date => {
+                        component.push_str(&values.date.format("%Y-%m-%d-%Hh%Mm%Ss").to_string())
+                    }
+                    Part::Subject => {
+                        component.extend(escape(values.subject).chars().take(SUBJECT_MAX_CHARS))
+                    }
+                    Part::Id => component.push_str(values.id),
+                    Part::From => component.push_str(&escape(values.from)),
+                }
+            }
+            if ascii {
+                component = portable_file_name(&component);
+            }
+            component.truncate(floor_char_boundary(&component, COMPONENT_MAX_BYTES));
+            if component.is_empty() {
+                component.push_str("unnamed");
+            }
+            if idx + 1 == n {
+                if with_id {
+                    component.push('-');
+                    component.push_str(values.id);
+                }
+                component.push_str(".eml");
+            }
+            path.push(component);
+        }
+        path
+    }
+}
+
+/// Largest index that is at most `max` and at a char boundary.
+fn floor_char_boundary(s: &str, max: usize) -> usize {
+    if s.len() <= max {
+        return s.len();
+    }
+    (0..=max)
+        .rev()
+        .find(|idx| s.is_char_boundary(*idx))
+        .unwrap_or_default()
+}
+
+/// File names that were assigned during this run, to detect different mails that render to the
+/// same file name.
+///
+/// Names are compared case-insensitively because some file systems do so as well.
+#[derive(Debug, Default)]
+pub(crate) struct NameClaims(Mutex<HashMap<String, String>>);
+
+impl NameClaims {
+    /// Claim name for given mail.
+    ///
+    /// Returns `false` if the name already belongs to a different mail.
+    pub(crate) fn claim(&self, name: &Path, mail_id: &str) -> bool {
+        let key = name.to_string_lossy().to_lowercase();
+        let mut claims = self.0.lock().expect("not poisoned");
+        claims.entry(key).or_insert_with(|| mail_id.to_owned()) == mail_id
+    }
+}
+
+impl Default for FilenameTemplate {
+    fn default() -> Self {
+        DEFAULT_FILENAME_TEMPLATE
+            .parse()
+            .expect("default template is valid")
+    }
+}
+
+impl FromStr for FilenameTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let components = s
+            .split('/')
+            .map(|component| {
+                let parts = parse_component(component)
+                    .with_context(|| format!("invalid path component: `{component}`"))?;
+                Ok(parts)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { components })
+    }
+}
+
+fn parse_component(s: &str) -> Result<Vec<Part>> {
+    if s.is_empty() {
+        bail!("empty");
+    }
+    if s == "." || s == ".." {
+        bail!("relative path");
+    }
+    if s.contains('\0') {
+        bail!("NUL character");
+    }
+
+    let mut parts = vec![];
+    let mut rest = s;
+    while !rest.is_empty() {
+        match rest.find(['{', '}']) {
+            None => {
+                parts.push(Part::Literal(rest.to_owned()));
+                break;
+            }
+            Some(pos) => {
+                if pos > 0 {
+                    parts.push(Part::Literal(rest[..pos].to_owned()));
+                }
+                rest = &rest[pos..];
+                if rest.starts_with('}') {
+                    bail!("unmatched `}}`");
+                }
+                let end = rest.find('}').context("unmatched `{`")?;
+                let part = match &rest[1..end] {
+                    "date" => Part::Date,
+                    "subject" => Part::Subject,
+                    "id" => Part::Id,
+                    "from" => Part::From,
+                    other => bail!("unknown placeholder: `{other}`"),
+                };
+                parts.push(part);
+                rest = &rest[end + 1..];
+            }
+        }
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> FilenameValues<'static> {
+        FilenameValues {
+            date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                .unwrap()
+                .to_utc(),
+            subject: "Re: Hello/World!",
+            id: "M1_H61c-1234",
+            from: "foo@example.com",
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            FilenameTemplate::default().render(values(), false, false),
+            PathBuf::from("2020-03-04-11h22m33s-Re HelloWorld.eml"),
+        );
+        assert_eq!(
+            "{id}"
+                .parse::<FilenameTemplate>()
+                .unwrap()
+                .render(values(), false, false),
+            PathBuf::from("M1_H61c-1234.eml"),
+        );
+        assert_eq!(
+            "{from}/{date} {subject}"
+                .parse::<FilenameTemplate>()
+                .unwrap()
+                .render(values(), false, false),
+            PathBuf::from("fooexamplecom/2020-03-04-11h22m33s Re HelloWorld.eml"),
+        );
+        assert_eq!(
+            "{subject}".parse::<FilenameTemplate>().unwrap().render(
+                FilenameValues {
+                    subject: "",
+                    ..values()
+                },
+                false,
+                false,
+            ),
+            PathBuf::from("unnamed.eml"),
+        );
+    }
+
+    #[test]
+    fn test_render_long() {
+        let subject = "ä".repeat(150) + &"x".repeat(150);
+        assert_eq!(subject.chars().count(), 300);
+        let values = FilenameValues {
+            subject: &subject,
+            ..values()
+        };
+
+        // subject is limited anyways
+        let name = FilenameTemplate::default().render(values, true, false);
+        assert_eq!(
+            name,
+            PathBuf::from(format!(
+                "2020-03-04-11h22m33s-{}-M1_H61c-1234.eml",
+                "x".repeat(64)
+            )),
+        );
+
+        // literals are not
+        let template = format!("{}{{subject}}", "ö".repeat(150))
+            .parse::<FilenameTemplate>()
+            .unwrap();
+        let name = template.render(values, true, false);
+        let name = name.to_str().unwrap();
+        assert_eq!(name, format!("{}-M1_H61c-1234.eml", "ö".repeat(100)));
+        assert!(name.len() <= 255);
+    }
+
+    #[test]
+    fn test_render_ascii() {
+        let values = FilenameValues {
+            subject: "Grüße: CON. ",
+            from: "jörg@example.com",
+            ..values()
+        };
+        assert_eq!(
+            FilenameTemplate::default().render(values, false, false),
+            PathBuf::from("2020-03-04-11h22m33s-Gre CON .eml"),
+        );
+        assert_eq!(
+            FilenameTemplate::default().render(values, false, true),
+            PathBuf::from("2020-03-04-11h22m33s-Grusse CON.eml"),
+        );
+        assert_eq!(
+            "{subject}/{from}/Ablage?. /{id}"
+                .parse::<FilenameTemplate>()
+                .unwrap()
+                .render(
+                    FilenameValues {
+                        subject: "con",
+                        ..values
+                    },
+                    true,
+                    true,
+                ),
+            PathBuf::from("con_/jorgexamplecom/Ablage/M1_H61c-1234-M1_H61c-1234.eml"),
+        );
+    }
+
+    #[test]
+    fn test_name_claims() {
+        let claims = NameClaims::default();
+        let name = FilenameTemplate::default().render(values(), false, false);
+
+        // same second, same subject
+        assert!(claims.claim(&name, "a"));
+        assert!(!claims.claim(&name, "b"));
+        assert!(claims.claim(&name, "a"));
+        assert!(!claims.claim(&PathBuf::from(name.to_str().unwrap().to_uppercase()), "c"));
+        assert_ne!(
+            FilenameTemplate::default().render(
+                FilenameValues {
+                    id: "b",
+                    ..values()
+                },
+                true,
+                false,
+            ),
+            name,
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (s, err) in [
+            ("", "invalid path component: ``"),
+            ("a//b", "invalid path component: ``"),
+            ("../{id}", "invalid path component: `..`"),
+            ("{foo}", "invalid path component: `{foo}`"),
+            ("{id", "invalid path component: `{id`"),
+            ("id}", "invalid path component: `id}`"),
+        ] {
+            assert_eq!(
+                s.parse::<FilenameTemplate>().unwrap_err().to_string(),
+                err,
+                "{s}"
+            );
+        }
+        assert_eq!(
+            format!("{:#}", "{foo}".parse::<FilenameTemplate>().unwrap_err()),
+            "invalid path component: `{foo}`: unknown placeholder: `foo`",
+        );
+    }
+}
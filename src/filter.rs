@@ -0,0 +1,315 @@
+//! Client-side mail filters.
+//!
+//! Filtering happens after the mail metadata was fetched, so it does not reduce the number of
+//! requests required to list a folder but it does reduce downloads and writes.
+use anyhow::{Context, Result};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use clap::Parser;
+use regex::{Regex, RegexBuilder};
+
+use crate::{
+    mails::{Address, DownloadedMail, Mail},
+    proto::ids::timestamp_to_generated_id,
+};
+
+/// Filter CLI config.
+#[derive(Debug, Default, Parser)]
+pub(crate) struct FilterCLIConfig {
+    /// Only mails received at or after this date.
+    ///
+    /// Accepts `YYYY-MM-DD` (start of the day, UTC) or an RFC 3339 timestamp.
+    #[clap(long, action, value_parser = parse_since)]
+    since: Option<DateTime<Utc>>,
+
+    /// Only mails received at or before this date.
+    ///
+    /// Accepts `YYYY-MM-DD` (whole day included, UTC) or an RFC 3339 timestamp.
+    #[clap(long, action, value_parser = parse_until)]
+    until: Option<DateTime<Utc>>,
+
+    /// Only mails that were not read yet.
+    #[clap(long, action)]
+    unread_only: bool,
+
+    /// Only mails whose sender address or name contains this text, ignoring case.
+    #[clap(long, action)]
+    from: Option<String>,
+
+    /// Only mails where the address or name of a recipient (To, CC, or BCC) contains this text,
+    /// ignoring case.
+    ///
+    /// Recipients are part of the mail content, so mails that do not match are still fetched but
+    /// not written.
+    #[clap(long, action)]
+    to: Option<String>,
+
+    /// Only mails whose subject contains this text, ignoring case.
+    #[clap(long, action)]
+    subject: Option<String>,
+
+    /// Treat `--from`, `--to`, and `--subject` as regular expressions.
+    #[clap(long, action)]
+    regex: bool,
+}
+
+impl FilterCLIConfig {
+    /// Start cursor for the mail list.
+    ///
+    /// Mail IDs are generated when the mail is stored, which is never before it was received, so
+    /// everything before `--since` can be skipped on the server side.
+    pub(crate) fn start_cursor(&self) -> Option<String> {
+        self.since.map(timestamp_to_generated_id)
+    }
+
+    /// Compile filter.
+    pub(crate) fn build(&self) -> Result<Filter> {
+        let pattern = |s: &Option<String>, arg: &str| {
+            s.as_deref()
+                .map(|s| Pattern::new(s, self.regex))
+                .transpose()
+                .with_context(|| format!("invalid `--{arg}`"))
+        };
+
+        Ok(Filter {
+            since: self.since,
+            until: self.until,
+            unread_only: self.unread_only,
+            from: pattern(&self.from, "from")?,
+            to: pattern(&self.to, "to")?,
+            subject: pattern(&self.subject, "subject")?,
+        })
+    }
+}
+
+/// Compiled [`FilterCLIConfig`].
+#[derive(Debug)]
+pub(crate) struct Filter {
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    unread_only: bool,
+    from: Option<Pattern>,
+    to: Option<Pattern>,
+    subject: Option<Pattern>,
+}
+
+impl Filter {
+    /// Check if mail matches the filter.
+    ///
+    /// All configured conditions must match. See [`matches_details`](Self::matches_details) for the
+    /// parts of the filter that need the mail content.
+    pub(crate) fn matches(&self, mail: &Mail) -> bool {
+        if let Some(since) = self.since {
+            if mail.date < since {
+                return false;
+            }
+        }
+
+        if let Some(until) = self.until {
+            if mail.date > until {
+                return false;
+            }
+        }
+
+        if self.unread_only && !mail.unread {
+            return false;
+        }
+
+        if let Some(from) = &self.from {
+            if !from.matches_address(&mail.sender) {
+                return false;
+            }
+        }
+
+        if let Some(subject) = &self.subject {
+            if !subject.matches(&mail.subject) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Filter needs the mail content, see [`matches_details`](Self::matches_details).
+    pub(crate) fn needs_details(&self) -> bool {
+        self.to.is_some()
+    }
+
+    /// Check if downloaded mail matches the parts of the filter that need the mail content.
+    pub(crate) fn matches_details(&self, mail: &DownloadedMail) -> bool {
+        if let Some(to) = &self.to {
+            let mut recipients = mail.to.iter().chain(&mail.cc).chain(&mail.bcc);
+            if !recipients.any(|addr| to.matches_address(addr)) {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Text pattern, either a case-insensitive substring or a regular expression.
+#[derive(Debug)]
+enum Pattern {
+    /// Lowercase substring.
+    Substring(String),
+    Regex(Regex),
+}
+
+impl Pattern {
+    fn new(s: &str, regex: bool) -> Result<Self> {
+        if regex {
+            let re = RegexBuilder::new(s)
+                .case_insensitive(true)
+                .build()
+                .context("parse regex")?;
+            Ok(Self::Regex(re))
+        } else {
+            Ok(Self::Substring(s.to_lowercase()))
+        }
+    }
+
+    fn matches(&self, s: &str) -> bool {
+        match self {
+            Self::Substring(sub) => s.to_lowercase().contains(sub.as_str()),
+            Self::Regex(re) => re.is_match(s),
+        }
+    }
+
+    fn matches_address(&self, addr: &Address) -> bool {
+        self.matches(&addr.mail) || self.matches(&addr.name)
+    }
+}
+
+fn parse_since(s: &str) -> Result<DateTime<Utc>> {
+    parse_date(s, false)
+}
+
+fn parse_until(s: &str) -> Result<DateTime<Utc>> {
+    parse_date(s, true)
+}
+
+/// Parse ISO-8601 date or timestamp.
+///
+/// For plain dates, `end_of_day` selects the last millisecond of that day instead of the first one.
+fn parse_date(s: &str, end_of_day: bool) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        let start = date.and_time(Default::default()).and_utc();
+        if end_of_day {
+            let next = start
+                .checked_add_days(Days::new(1))
+                .context("date out of range")?;
+            return Ok(next - chrono::Duration::milliseconds(1));
+        } else {
+            return Ok(start);
+        }
+    }
+
+    let ts = DateTime::parse_from_rfc3339(s).context("neither YYYY-MM-DD nor RFC 3339")?;
+    Ok(ts.to_utc())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::keys::Key;
+
+    use super::*;
+
+    fn mail(subject: &str) -> Mail {
+        Mail {
+            folder_id: "folder_id".to_owned(),
+            mail_id: "mail_id".to_owned(),
+            archive_id: "archive_id".to_owned(),
+            blob_id: "blob_id".to_owned(),
+            is_draft: false,
+            session_key: Key::Aes256([0; 32]),
+            date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                .unwrap()
+                .to_utc(),
+            subject: subject.to_owned(),
+            sender: Address {
+                mail: "foo@example.com".to_owned(),
+                name: "Me".to_owned(),
+            },
+            attachments: vec![],
+            conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+            unread: false,
+            replied: false,
+            forwarded: false,
+        }
+    }
+
+    #[test]
+    fn test_filter_subject() {
+        let filter = FilterCLIConfig {
+            subject: Some("invoice".to_owned()),
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        assert!(filter.matches(&mail("Your INVOICE for March")));
+        assert!(!filter.matches(&mail("Your receipt for March")));
+        assert!(!filter.needs_details());
+
+        let filter = FilterCLIConfig {
+            subject: Some(r"^re:\s".to_owned()),
+            regex: true,
+            ..Default::default()
+        }
+        .build()
+        .unwrap();
+        assert!(filter.matches(&mail("RE: hello")));
+        assert!(!filter.matches(&mail("Fwd: RE: hello")));
+
+        let err = FilterCLIConfig {
+            subject: Some("(".to_owned()),
+            regex: true,
+            ..Default::default()
+        }
+        .build()
+        .unwrap_err();
+        assert_eq!(err.to_string(), "invalid `--subject`");
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(
+            parse_since("2020-03-04").unwrap().to_rfc3339(),
+            "2020-03-04T00:00:00+00:00",
+        );
+        assert_eq!(
+            parse_until("2020-03-04").unwrap().to_rfc3339(),
+            "2020-03-04T23:59:59.999+00:00",
+        );
+        assert_eq!(
+            parse_until("2020-03-04T11:22:33+01:00")
+                .unwrap()
+                .to_rfc3339(),
+            "2020-03-04T10:22:33+00:00",
+        );
+        assert_eq!(
+            parse_since("foo").unwrap_err().to_string(),
+            "neither YYYY-MM-DD nor RFC 3339",
+        );
+    }
+
+    #[test]
+    fn test_pattern() {
+        let addr = Address {
+            mail: "Foo.Bar@example.com".to_owned(),
+            name: "Jane Doe".to_owned(),
+        };
+
+        let substring = Pattern::new("foo.bar", false).unwrap();
+        assert!(substring.matches_address(&addr));
+        assert!(Pattern::new("jane", false).unwrap().matches_address(&addr));
+        assert!(!Pattern::new("fo.bar", false)
+            .unwrap()
+            .matches_address(&addr));
+
+        let re = Pattern::new(r"^foo\.\w+@example\.com$", true).unwrap();
+        assert!(re.matches_address(&addr));
+        assert!(!Pattern::new("^doe", true).unwrap().matches_address(&addr));
+
+        Pattern::new("(", true).unwrap_err();
+    }
+}
@@ -1,16 +1,19 @@
 use std::{
-    collections::{hash_map::Entry, HashMap},
+    collections::{hash_map::Entry, HashMap, HashSet},
     sync::Arc,
 };
 
 use anyhow::{bail, Context, Result};
+use clap::{Parser, ValueEnum};
 use futures::{Stream, TryStreamExt};
 use reqwest::Method;
+use serde::Serialize;
 use tracing::debug;
 
 use crate::{
-    client::{Client, Prefix, Request, DEFAULT_HOST},
+    client::{Client, Prefix, Request},
     crypto::encryption::{decrypt_key, decrypt_value},
+    exit_code::ErrorKind,
     proto::{
         enums::{GroupType, MailFolderType},
         messages::{FolderResponse, MailboxGroupRootResponse, MailboxResponse, UserMembership},
@@ -18,24 +21,76 @@ use crate::{
     session::{GroupKeys, Session},
 };
 
-#[derive(Debug)]
-pub(crate) struct Folder {
-    pub(crate) name: String,
-    pub(crate) mails: String,
-    pub(crate) id: String,
+/// List folders CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct ListFoldersCLIConfig {
+    /// Print folder ID and name, separated by a tab.
+    #[clap(long, action)]
+    pub(crate) with_ids: bool,
+
+    /// Print folders as flat list instead of a tree.
+    #[clap(long, action)]
+    pub(crate) flat: bool,
+
+    /// Print one JSON object per folder and line.
+    #[clap(long, action, conflicts_with_all = ["with_ids", "flat"])]
+    pub(crate) json: bool,
+}
+
+/// System folder that every mailbox has.
+///
+/// Unlike the name, this does not depend on the language of the account.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemFolder {
+    Inbox,
+    Sent,
+    Trash,
+    Spam,
+    Archive,
+    Drafts,
+}
+
+impl SystemFolder {
+    fn from_folder_type(folder_type: MailFolderType) -> Option<Self> {
+        match folder_type {
+            MailFolderType::Custom => None,
+            MailFolderType::Inbox => Some(Self::Inbox),
+            MailFolderType::Sent => Some(Self::Sent),
+            MailFolderType::Trash => Some(Self::Trash),
+            MailFolderType::Archive => Some(Self::Archive),
+            MailFolderType::Spam => Some(Self::Spam),
+            MailFolderType::Draft => Some(Self::Drafts),
+        }
+    }
+}
+
+/// Mail folder.
+#[derive(Debug, Serialize)]
+pub struct Folder {
+    pub name: String,
+    pub mails: String,
+    pub id: String,
+
+    /// ID of the parent folder, `None` for top-level folders.
+    pub parent: Option<String>,
+
+    /// Type of system folders, `None` for custom folders.
+    pub system_folder: Option<SystemFolder>,
 }
 
 impl Folder {
-    pub(crate) async fn list(
+    /// List all mail folders.
+    pub async fn list(
         client: &Client,
         session: &Session,
     ) -> Result<impl Stream<Item = Result<Self>>> {
-        let mail_group = get_mail_membership(session).context("get mail group")?;
+        let mail_group = get_membership(session, GroupType::Mail).context("get mail group")?;
 
         let resp: MailboxGroupRootResponse = client
             .do_json(Request {
                 method: Method::GET,
-                host: DEFAULT_HOST,
+                host: None,
                 prefix: Prefix::Tutanota,
                 path: &format!("mailboxgrouproot/{}", mail_group.group),
                 data: &(),
@@ -51,7 +106,7 @@ impl Folder {
         let resp: MailboxResponse = client
             .do_json(Request {
                 method: Method::GET,
-                host: DEFAULT_HOST,
+                host: None,
                 prefix: Prefix::Tutanota,
                 path: &format!("mailbox/{mailbox}"),
                 data: &(),
@@ -67,8 +122,11 @@ impl Folder {
         let group_keys = Arc::clone(&session.group_keys);
         let stream = client
             .stream::<FolderResponse>(
+                Prefix::Tutanota,
                 &format!("mailfolder/{folders}"),
                 Some(&session.access_token),
+                None,
+                false,
             )
             .and_then(move |f| {
                 let group_keys = Arc::clone(&group_keys);
@@ -78,10 +136,39 @@ impl Folder {
         Ok(stream)
     }
 
+    /// Find folder by either name, ID, or system folder type.
+    pub(crate) fn find<'a>(
+        folders: &'a [Self],
+        name: Option<&str>,
+        id: Option<&str>,
+        system_folder: Option<SystemFolder>,
+    ) -> Result<&'a Self> {
+        let mut candidates = folders.iter().filter(|f| match (name, id, system_folder) {
+            (Some(name), None, None) => f.name == name,
+            (None, Some(id), None) => f.id == id,
+            (None, None, Some(system_folder)) => f.system_folder == Some(system_folder),
+            _ => unreachable!("checked by CLI parser"),
+        });
+        let folder = candidates.next().context(ErrorKind::FolderNotFound)?;
+        if candidates.next().is_some() {
+            bail!("folder name is ambiguous, use `--folder-id` instead");
+        }
+        Ok(folder)
+    }
+
+    /// Check if the folder is selected by a name, an ID, or a system folder type like `trash`.
+    pub(crate) fn matches(&self, selector: &str) -> bool {
+        self.name == selector
+            || self.id == selector
+            || self
+                .system_folder
+                .is_some_and(|sf| SystemFolder::from_str(selector, true) == Ok(sf))
+    }
+
     fn decode(resp: FolderResponse, group_keys: &GroupKeys) -> Result<Self> {
         let session_key = decrypt_key(
             group_keys
-                .get(&resp.owner_group)
+                .get(&resp.owner_group, resp.owner_key_version)
                 .context("getting owner group key")?,
             resp.owner_enc_session_key,
         )
@@ -100,12 +187,63 @@ impl Folder {
             name,
             mails: resp.mails,
             id: resp.id[1].clone(),
+            parent: resp.parent_folder.map(|[_list, id]| id),
+            system_folder: SystemFolder::from_folder_type(resp.folder_type),
         })
     }
 }
 
-fn get_mail_membership(session: &Session) -> Result<UserMembership> {
-    debug!("get mail membership");
+/// Sort folders depth-first so that every folder follows its parent.
+///
+/// Returns the nesting depth for every folder. The order of siblings is kept. Folders with an
+/// unknown parent are treated as top-level folders.
+pub(crate) fn folder_tree(folders: &[Folder]) -> Vec<(usize, &Folder)> {
+    let ids = folders
+        .iter()
+        .map(|f| f.id.as_str())
+        .collect::<HashSet<_>>();
+    let mut children: HashMap<Option<&str>, Vec<&Folder>> = HashMap::new();
+    for f in folders {
+        let parent = f.parent.as_deref().filter(|p| ids.contains(p));
+        children.entry(parent).or_default().push(f);
+    }
+
+    let mut out = Vec::with_capacity(folders.len());
+    let mut stack = children
+        .get(&None)
+        .map(|roots| roots.iter().rev().map(|f| (0, *f)).collect::<Vec<_>>())
+        .unwrap_or_default();
+    let mut visited = HashSet::with_capacity(folders.len());
+    while let Some((depth, f)) = stack.pop() {
+        if !visited.insert(f.id.as_str()) {
+            continue;
+        }
+        out.push((depth, f));
+        if let Some(c) = children.get(&Some(f.id.as_str())) {
+            stack.extend(c.iter().rev().map(|c| (depth + 1, *c)));
+        }
+    }
+
+    out
+}
+
+/// Text line of `list-folders`, with the system folder type as suffix, e.g. `Posteingang (inbox)`.
+pub(crate) fn folder_line(depth: usize, folder: &Folder, with_ids: bool) -> String {
+    let indent = "  ".repeat(depth);
+    let mut line = if with_ids {
+        format!("{}\t{indent}{}", folder.id, folder.name)
+    } else {
+        format!("{indent}{}", folder.name)
+    };
+    if let Some(value) = folder.system_folder.and_then(|sf| sf.to_possible_value()) {
+        line.push_str(&format!(" ({})", value.get_name()));
+    }
+    line
+}
+
+/// Get membership of the user in the group of the given type.
+pub(crate) fn get_membership(session: &Session, group_type: GroupType) -> Result<UserMembership> {
+    debug!(?group_type, "get membership");
 
     let mut memberships = HashMap::with_capacity(session.user_data.memberships.len());
     for membership in &session.user_data.memberships {
@@ -121,10 +259,131 @@ fn get_mail_membership(session: &Session) -> Result<UserMembership> {
     }
 
     let membership = *memberships
-        .get(&GroupType::Mail)
-        .context("no mail group found")?;
+        .get(&group_type)
+        .with_context(|| format!("no {group_type:?} group found"))?;
 
-    debug!(group = membership.group.as_str(), "got mail membership");
+    debug!(group = membership.group.as_str(), "got membership");
 
     Ok(membership.clone())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(id: &str, parent: Option<&str>) -> Folder {
+        Folder {
+            name: format!("name-{id}"),
+            mails: format!("mails-{id}"),
+            id: id.to_owned(),
+            parent: parent.map(|p| p.to_owned()),
+            system_folder: None,
+        }
+    }
+
+    #[test]
+    fn test_folder_tree() {
+        let folders = [
+            folder("c", Some("a")),
+            folder("a", None),
+            folder("b", None),
+            folder("d", Some("c")),
+            folder("e", Some("a")),
+            folder("f", Some("unknown")),
+        ];
+
+        let tree = folder_tree(&folders)
+            .into_iter()
+            .map(|(depth, f)| (depth, f.id.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            tree,
+            [(0, "a"), (1, "c"), (2, "d"), (1, "e"), (0, "b"), (0, "f")],
+        );
+    }
+
+    #[test]
+    fn test_folder_line() {
+        let mut f = folder("a", None);
+        assert_eq!(folder_line(0, &f, false), "name-a");
+        assert_eq!(folder_line(2, &f, true), "a\t    name-a");
+
+        f.system_folder = Some(SystemFolder::Inbox);
+        f.name = "Posteingang".to_owned();
+        assert_eq!(folder_line(0, &f, false), "Posteingang (inbox)");
+        assert_eq!(folder_line(1, &f, true), "a\t  Posteingang (inbox)");
+    }
+
+    #[test]
+    fn test_folder_json() {
+        assert_eq!(
+            serde_json::to_string(&folder("b", Some("a"))).unwrap(),
+            r#"{"name":"name-b","mails":"mails-b","id":"b","parent":"a","system_folder":null}"#,
+        );
+
+        let mut f = folder("a", None);
+        f.system_folder = Some(SystemFolder::Drafts);
+        assert_eq!(
+            serde_json::to_string(&f).unwrap(),
+            r#"{"name":"name-a","mails":"mails-a","id":"a","parent":null,"system_folder":"drafts"}"#,
+        );
+    }
+
+    #[test]
+    fn test_matches() {
+        let mut f = folder("a", None);
+        assert!(f.matches("name-a"));
+        assert!(f.matches("a"));
+        assert!(!f.matches("Name-A"));
+        assert!(!f.matches("trash"));
+
+        f.system_folder = Some(SystemFolder::Trash);
+        assert!(f.matches("trash"));
+        assert!(f.matches("Trash"));
+        assert!(!f.matches("spam"));
+    }
+
+    #[test]
+    fn test_find() {
+        let mut folders = vec![folder("a", None), folder("b", None)];
+
+        assert_eq!(
+            Folder::find(&folders, Some("name-b"), None, None)
+                .unwrap()
+                .id,
+            "b"
+        );
+        assert_eq!(
+            Folder::find(&folders, None, Some("a"), None).unwrap().id,
+            "a"
+        );
+        assert_eq!(
+            Folder::find(&folders, None, Some("c"), None)
+                .unwrap_err()
+                .to_string(),
+            "folder not found",
+        );
+
+        assert_eq!(
+            Folder::find(&folders, None, None, Some(SystemFolder::Inbox))
+                .unwrap_err()
+                .to_string(),
+            "folder not found",
+        );
+        folders[1].system_folder = Some(SystemFolder::Inbox);
+        assert_eq!(
+            Folder::find(&folders, None, None, Some(SystemFolder::Inbox))
+                .unwrap()
+                .id,
+            "b"
+        );
+
+        folders.push(folder("b", Some("a")));
+        assert_eq!(
+            Folder::find(&folders, Some("name-b"), None, None)
+                .unwrap_err()
+                .to_string(),
+            "folder name is ambiguous, use `--folder-id` instead",
+        );
+    }
+}
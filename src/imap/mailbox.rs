@@ -0,0 +1,566 @@
+//! Read-only view of downloaded Maildirs and mbox files.
+use std::{
+    borrow::Cow,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::file_output::write_to_file;
+
+/// Where a mailbox is stored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Source {
+    Maildir(PathBuf),
+    Mbox(PathBuf),
+}
+
+/// Find mailboxes at given path.
+///
+/// The path can be a single Maildir or mbox file (served as `INBOX`) or a directory as written by
+/// `download --all-folders`, where every subdirectory is a Maildir or holds an mbox file of the
+/// same name. Mailbox names are the directory names, a folder called "Inbox" becomes `INBOX`.
+pub(crate) async fn discover(path: &Path) -> Result<BTreeMap<String, Source>> {
+    let mut mailboxes = BTreeMap::new();
+
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .with_context(|| format!("read `{}`", path.display()))?;
+    if metadata.is_file() {
+        mailboxes.insert("INBOX".to_owned(), Source::Mbox(path.to_owned()));
+        return Ok(mailboxes);
+    }
+    if is_dir(&path.join("cur")).await {
+        mailboxes.insert("INBOX".to_owned(), Source::Maildir(path.to_owned()));
+        return Ok(mailboxes);
+    }
+
+    let mut entries = tokio::fs::read_dir(path)
+        .await
+        .with_context(|| format!("read `{}`", path.display()))?;
+    while let Some(entry) = entries.next_entry().await.context("read dir entry")? {
+        let entry_path = entry.path();
+        let file_name = entry.file_name();
+        let file_name = file_name.to_string_lossy();
+
+        let (name, source) = if is_dir(&entry_path).await {
+            let mbox_path = entry_path.join(format!("{file_name}.mbox"));
+            if is_dir(&entry_path.join("cur")).await {
+                (file_name.as_ref(), Source::Maildir(entry_path))
+            } else if tokio::fs::try_exists(&mbox_path).await.unwrap_or_default() {
+                (file_name.as_ref(), Source::Mbox(mbox_path))
+            } else {
+                continue;
+            }
+        } else if let Some(stem) = file_name.strip_suffix(".mbox") {
+            (stem, Source::Mbox(entry_path))
+        } else {
+            continue;
+        };
+
+        if !name
+            .bytes()
+            .all(|b| matches!(b, b' '..=b'~') && b != b'"' && b != b'\\')
+        {
+            warn!(name, "skip mailbox with unsupported name");
+            continue;
+        }
+        let name = if name.eq_ignore_ascii_case("inbox") {
+            "INBOX".to_owned()
+        } else {
+            name.to_owned()
+        };
+        if mailboxes.contains_key(&name) {
+            warn!(name, "skip duplicate mailbox");
+            continue;
+        }
+        mailboxes.insert(name, source);
+    }
+
+    Ok(mailboxes)
+}
+
+async fn is_dir(path: &Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .map(|m| m.is_dir())
+        .unwrap_or_default()
+}
+
+/// A loaded mailbox.
+///
+/// Messages are ordered by UID, the sequence number of a message is its position plus one.
+#[derive(Debug)]
+pub(crate) struct Mailbox {
+    pub(crate) uid_validity: u32,
+    uid_next: u32,
+    pub(crate) messages: Vec<Message>,
+}
+
+impl Mailbox {
+    /// Load mailbox.
+    ///
+    /// Maildir messages are only indexed and read on demand, mbox files are read into memory. New
+    /// messages get UIDs in the order of their internal date.
+    pub(crate) async fn load(source: &Source, uids: &UidStore) -> Result<Self> {
+        let mut messages = match source {
+            Source::Maildir(path) => load_maildir(path).await?,
+            Source::Mbox(path) => {
+                let data = tokio::fs::read(path)
+                    .await
+                    .with_context(|| format!("read `{}`", path.display()))?;
+                parse_mbox(&data)
+            }
+        };
+        messages.sort_by(|a, b| {
+            a.internal_date
+                .cmp(&b.internal_date)
+                .then_with(|| a.key.cmp(&b.key))
+        });
+
+        let state_path = uid_state_path(source);
+        let mut states = uids.states.lock().await;
+        let state = match states.entry(state_path.clone()) {
+            Entry::Occupied(e) => e.into_mut(),
+            Entry::Vacant(e) => e.insert(UidState::load(&state_path).await),
+        };
+
+        // UIDs of removed messages are never reused, so they can be forgotten
+        let keys = messages
+            .iter()
+            .map(|m| m.key.as_str())
+            .collect::<HashSet<_>>();
+        let known = state.uids.len();
+        state.uids.retain(|key, _uid| keys.contains(key.as_str()));
+        let mut changed = state.uids.len() != known;
+
+        for msg in &mut messages {
+            msg.uid = match state.uids.get(&msg.key) {
+                Some(uid) => *uid,
+                None => {
+                    let uid = state.uid_next;
+                    state.uid_next = uid.checked_add(1).context("UIDs exhausted")?;
+                    state.uids.insert(msg.key.clone(), uid);
+                    changed = true;
+                    uid
+                }
+            };
+        }
+        messages.sort_by_key(|m| m.uid);
+
+        if changed {
+            state.store(&state_path).await;
+        }
+
+        debug!(?source, messages = messages.len(), "mailbox loaded");
+
+        Ok(Self {
+            uid_validity: state.uid_validity,
+            uid_next: state.uid_next,
+            messages,
+        })
+    }
+
+    pub(crate) fn uid_next(&self) -> u32 {
+        self.uid_next
+    }
+
+    /// Messages with their sequence numbers.
+    pub(crate) fn numbered(&self) -> impl Iterator<Item = (u32, &Message)> {
+        (1..).zip(&self.messages)
+    }
+
+    /// Value of `*` in a set of UIDs or sequence numbers.
+    pub(crate) fn max(&self, uid: bool) -> u32 {
+        match (uid, self.messages.last()) {
+            (true, Some(msg)) => msg.uid,
+            (true, None) => 0,
+            (false, _) => u32::try_from(self.messages.len()).unwrap_or(u32::MAX),
+        }
+    }
+
+    pub(crate) fn unseen(&self) -> usize {
+        self.messages
+            .iter()
+            .filter(|m| !m.flags.contains(&"\\Seen"))
+            .count()
+    }
+}
+
+/// UIDs of all mailboxes that were loaded by any connection.
+///
+/// UIDs are assigned once and kept in a state file next to the mailbox, so that new messages do
+/// not invalidate the UIDs of older ones and the UID validity survives restarts of the server. If
+/// the state file cannot be written, e.g. for read-only archives, UIDs are only stable while the
+/// server runs.
+#[derive(Debug, Default)]
+pub(crate) struct UidStore {
+    states: tokio::sync::Mutex<HashMap<PathBuf, UidState>>,
+}
+
+/// Persisted UIDs of a single mailbox.
+#[derive(Debug, Serialize, Deserialize)]
+struct UidState {
+    uid_validity: u32,
+    uid_next: u32,
+
+    /// UIDs by message key.
+    uids: BTreeMap<String, u32>,
+}
+
+impl UidState {
+    /// State for a mailbox that was never served.
+    ///
+    /// Like most servers, the current time is used as UID validity, so that it differs if the
+    /// state is lost.
+    fn new() -> Self {
+        let uid_validity = u32::try_from(Utc::now().timestamp()).unwrap_or(u32::MAX);
+        Self {
+            uid_validity: uid_validity.max(1),
+            uid_next: 1,
+            uids: BTreeMap::new(),
+        }
+    }
+
+    /// Load state file, an unreadable one is replaced.
+    async fn load(path: &Path) -> Self {
+        let data = match tokio::fs::read(path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Self::new(),
+            Err(e) => {
+                warn!(%e, path = %path.display(), "cannot read UID state, UIDs are reset");
+                return Self::new();
+            }
+        };
+        match serde_json::from_slice(&data) {
+            Ok(state) => state,
+            Err(e) => {
+                warn!(%e, path = %path.display(), "invalid UID state, UIDs are reset");
+                Self::new()
+            }
+        }
+    }
+
+    /// Write state file, failures only keep the UIDs from surviving a restart.
+    async fn store(&self, path: &Path) {
+        let res = match serde_json::to_vec(self) {
+            Ok(data) => write_to_file(&data, path).await,
+            Err(e) => Err(e).context("serialize UID state"),
+        };
+        if let Err(e) = res {
+            warn!(error = format!("{e:#}"), path = %path.display(), "cannot write UID state");
+        }
+    }
+}
+
+/// Hidden state file of a mailbox, within the Maildir or next to the mbox file.
+fn uid_state_path(source: &Source) -> PathBuf {
+    const NAME: &str = "tatutanatata-imap-uids.json";
+    match source {
+        Source::Maildir(path) => path.join(format!(".{NAME}")),
+        Source::Mbox(path) => {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+            path.with_file_name(format!(".{file_name}.{NAME}"))
+        }
+    }
+}
+
+/// A single message.
+#[derive(Debug)]
+pub(crate) struct Message {
+    pub(crate) uid: u32,
+    pub(crate) flags: Vec<&'static str>,
+    pub(crate) internal_date: DateTime<Utc>,
+    pub(crate) size: u64,
+
+    /// Unique part of the Maildir file name or content hash for mbox, identifies the message
+    /// across loads.
+    key: String,
+
+    content: Content,
+}
+
+#[derive(Debug)]
+enum Content {
+    File(PathBuf),
+    Memory(Vec<u8>),
+}
+
+impl Message {
+    /// Full message with `\r\n` line endings.
+    pub(crate) async fn content(&self) -> Result<Cow<'_, [u8]>> {
+        match &self.content {
+            Content::File(path) => {
+                let data = tokio::fs::read(path)
+                    .await
+                    .with_context(|| format!("read `{}`", path.display()))?;
+                Ok(Cow::Owned(data))
+            }
+            Content::Memory(data) => Ok(Cow::Borrowed(data)),
+        }
+    }
+}
+
+async fn load_maildir(path: &Path) -> Result<Vec<Message>> {
+    let mut messages = vec![];
+
+    for sub in ["cur", "new"] {
+        let sub_path = path.join(sub);
+        let mut entries = match tokio::fs::read_dir(&sub_path).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                continue;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("read `{}`", sub_path.display()));
+            }
+        };
+        while let Some(entry) = entries.next_entry().await.context("read dir entry")? {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let metadata = entry.metadata().await.context("read metadata")?;
+            if !metadata.is_file() {
+                continue;
+            }
+
+            let (unique, info) = name.split_once(':').unwrap_or((&name, ""));
+            let internal_date = unique
+                .split_once('.')
+                .and_then(|(ts, _)| ts.parse().ok())
+                .and_then(|ts| DateTime::from_timestamp(ts, 0))
+                .or_else(|| metadata.modified().ok().map(DateTime::from))
+                .unwrap_or_default();
+            let flags = info
+                .strip_prefix("2,")
+                .map(maildir_flags)
+                .unwrap_or_default();
+
+            messages.push(Message {
+                uid: 0,
+                flags,
+                internal_date,
+                size: metadata.len(),
+                key: unique.to_owned(),
+                content: Content::File(entry.path()),
+            });
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Map Maildir flags to IMAP flags.
+fn maildir_flags(flags: &str) -> Vec<&'static str> {
+    flags
+        .chars()
+        .filter_map(|c| match c {
+            'D' => Some("\\Draft"),
+            'F' => Some("\\Flagged"),
+            'P' => Some("$Forwarded"),
+            'R' => Some("\\Answered"),
+            'S' => Some("\\Seen"),
+            'T' => Some("\\Deleted"),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Split mboxrd file into messages.
+///
+/// Reverts the framing of `download --format=mbox` and converts line endings to `\r\n`.
+/// mbox has no flags, so all messages are unread.
+fn parse_mbox(data: &[u8]) -> Vec<Message> {
+    let mut messages = vec![];
+    let mut seen = HashMap::<u64, usize>::new();
+    let mut current: Option<(DateTime<Utc>, Vec<u8>)> = None;
+
+    let mut finish = |current: Option<(DateTime<Utc>, Vec<u8>)>| {
+        let Some((internal_date, mut content)) = current else {
+            return;
+        };
+        // separator line and the newline that terminates the last line
+        if content.ends_with(b"\n") {
+            content.pop();
+        }
+        if content.ends_with(b"\n") && !content.ends_with(b"\r\n") {
+            content.pop();
+        }
+        let content = crlf(&content);
+
+        // positions shift if a message is removed, identical messages are told apart by their
+        // order
+        let hash = fnv1a(&content);
+        let n = seen.entry(hash).or_insert(0);
+        *n += 1;
+
+        messages.push(Message {
+            uid: 0,
+            flags: vec![],
+            internal_date,
+            size: content.len() as u64,
+            key: format!("{hash:016x}-{n}"),
+            content: Content::Memory(content),
+        });
+    };
+
+    for line in data.split_inclusive(|b| *b == b'\n') {
+        if line.starts_with(b"From ") {
+            finish(current.take());
+            current = Some((mbox_date(line), vec![]));
+            continue;
+        }
+        let Some((_, content)) = current.as_mut() else {
+            continue;
+        };
+
+        let quotes = line.iter().take_while(|b| **b == b'>').count();
+        if quotes > 0 && line[quotes..].starts_with(b"From ") {
+            content.extend_from_slice(&line[1..]);
+        } else {
+            content.extend_from_slice(line);
+        }
+    }
+    finish(current);
+
+    messages
+}
+
+/// Parse date of a `From ` separator line, e.g. `From foo@example.com Thu Jan  1 00:00:01 1970`.
+fn mbox_date(line: &[u8]) -> DateTime<Utc> {
+    let line = String::from_utf8_lossy(line);
+    line["From ".len()..]
+        .trim()
+        .split_once(' ')
+        .and_then(|(_sender, date)| {
+            NaiveDateTime::parse_from_str(date.trim(), "%a %b %e %H:%M:%S %Y").ok()
+        })
+        .map(|date| date.and_utc())
+        .unwrap_or_default()
+}
+
+/// 64-bit FNV-1a hash.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Convert bare `\n` line endings to `\r\n`.
+fn crlf(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (idx, b) in data.iter().enumerate() {
+        if *b == b'\n' && (idx == 0 || data[idx - 1] != b'\r') {
+            out.push(b'\r');
+        }
+        out.push(*b);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mbox() {
+        let data = b"From a@example.com Thu Jan  1 00:00:01 1970\n\
+            Subject: a\r\n\r\nfoo\r\n>From here\r\n>>From there\r\n\n\
+            From MAILER-DAEMON Fri Jan  2 00:00:00 1970\n\
+            Subject: b\n\nbar\n\n";
+        let messages = parse_mbox(data);
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].internal_date.timestamp(), 1);
+        let Content::Memory(content) = &messages[0].content else {
+            panic!("not in memory");
+        };
+        assert_eq!(
+            content.as_slice(),
+            b"Subject: a\r\n\r\nfoo\r\nFrom here\r\n>From there\r\n",
+        );
+        assert_eq!(messages[0].size, content.len() as u64);
+
+        assert_eq!(messages[1].internal_date.timestamp(), 86400);
+        let Content::Memory(content) = &messages[1].content else {
+            panic!("not in memory");
+        };
+        assert_eq!(content.as_slice(), b"Subject: b\r\n\r\nbar");
+    }
+
+    #[tokio::test]
+    async fn test_discover_and_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let inbox = dir.path().join("Inbox");
+        std::fs::create_dir_all(inbox.join("cur")).unwrap();
+        std::fs::write(inbox.join("cur").join("2.b.tatutanatata:2,RS"), b"b").unwrap();
+        std::fs::write(inbox.join("cur").join("1.a.tatutanatata:2,"), b"aa").unwrap();
+        std::fs::create_dir_all(dir.path().join("Archive")).unwrap();
+        std::fs::write(
+            dir.path().join("Archive").join("Archive.mbox"),
+            b"From x Thu Jan  1 00:00:01 1970\nfoo\n\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(dir.path().join("empty")).unwrap();
+
+        let mailboxes = discover(dir.path()).await.unwrap();
+        assert_eq!(
+            mailboxes.into_iter().collect::<Vec<_>>(),
+            [
+                (
+                    "Archive".to_owned(),
+                    Source::Mbox(dir.path().join("Archive").join("Archive.mbox"))
+                ),
+                ("INBOX".to_owned(), Source::Maildir(inbox.clone())),
+            ],
+        );
+
+        let uids = UidStore::default();
+        let mailbox = Mailbox::load(&Source::Maildir(inbox.clone()), &uids)
+            .await
+            .unwrap();
+        assert_eq!(mailbox.uid_next(), 3);
+        assert_eq!(mailbox.unseen(), 1);
+        assert_eq!(mailbox.messages[0].uid, 1);
+        assert_eq!(mailbox.messages[0].size, 2);
+        assert!(mailbox.messages[0].flags.is_empty());
+        assert_eq!(mailbox.messages[1].flags, ["\\Answered", "\\Seen"]);
+        assert_eq!(mailbox.messages[1].content().await.unwrap().as_ref(), b"b");
+
+        // new messages and removals keep existing UIDs, also across restarts
+        std::fs::write(inbox.join("cur").join("0.c.tatutanatata:2,"), b"c").unwrap();
+        std::fs::remove_file(inbox.join("cur").join("1.a.tatutanatata:2,")).unwrap();
+        let mailbox2 = Mailbox::load(&Source::Maildir(inbox.clone()), &UidStore::default())
+            .await
+            .unwrap();
+        assert_eq!(mailbox.uid_validity, mailbox2.uid_validity);
+        assert_eq!(
+            mailbox2
+                .messages
+                .iter()
+                .map(|m| (m.key.as_str(), m.uid))
+                .collect::<Vec<_>>(),
+            [("2.b.tatutanatata", 2), ("0.c.tatutanatata", 3)],
+        );
+        assert_eq!(mailbox2.uid_next(), 4);
+
+        let archive = Source::Mbox(dir.path().join("Archive").join("Archive.mbox"));
+        let mailbox3 = Mailbox::load(&archive, &uids).await.unwrap();
+        assert_eq!(mailbox3.messages[0].uid, 1);
+        assert!(dir
+            .path()
+            .join("Archive")
+            .join(".Archive.mbox.tatutanatata-imap-uids.json")
+            .exists());
+
+        let single = discover(&dir.path().join("Archive").join("Archive.mbox"))
+            .await
+            .unwrap();
+        assert_eq!(single.keys().collect::<Vec<_>>(), ["INBOX"]);
+    }
+}
@@ -0,0 +1,586 @@
+//! Rendering of message parts for `FETCH` responses.
+use std::borrow::Cow;
+
+use crate::imap::parser::Section;
+
+/// Maximum nesting of MIME parts that is resolved, deeper parts are treated as opaque data.
+const MAX_DEPTH: usize = 16;
+
+/// Split message into header, including the empty line that ends it, and body.
+pub(crate) fn split_header(content: &[u8]) -> (&[u8], &[u8]) {
+    let end = content
+        .windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| pos + 4)
+        .unwrap_or(content.len());
+    content.split_at(end)
+}
+
+/// Extract section from message.
+///
+/// A part that does not exist is empty.
+pub(crate) fn section<'a>(content: &'a [u8], section: &Section) -> Cow<'a, [u8]> {
+    let (header, body) = split_header(content);
+    match section {
+        Section::Full => Cow::Borrowed(content),
+        Section::Header => Cow::Borrowed(header),
+        Section::Text => Cow::Borrowed(body),
+        Section::HeaderFields { fields, not } => {
+            let mut out = header_fields(header)
+                .filter(|(name, _raw)| fields.iter().any(|f| f.eq_ignore_ascii_case(name)) != *not)
+                .flat_map(|(_name, raw)| raw.iter().copied())
+                .collect::<Vec<_>>();
+            out.extend_from_slice(b"\r\n");
+            Cow::Owned(out)
+        }
+        Section::Part(path) => Cow::Borrowed(find_part(content, path).unwrap_or_default()),
+    }
+}
+
+/// MIME entity, i.e. a message or a body part.
+struct Entity<'a> {
+    /// Header, including the empty line that ends it. Empty for body parts without header.
+    header: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> Entity<'a> {
+    fn parse(data: &'a [u8]) -> Self {
+        let (header, body) = if data.starts_with(b"\r\n") {
+            data.split_at(2)
+        } else {
+            split_header(data)
+        };
+        Self { header, body }
+    }
+
+    fn content_type(&self) -> ContentType {
+        header_value(self.header, "Content-Type")
+            .and_then(|value| ContentType::parse(&value))
+            .unwrap_or_else(ContentType::text_plain)
+    }
+
+    /// Sub-parts of a multipart entity.
+    fn parts(&self, content_type: &ContentType) -> Vec<&'a [u8]> {
+        match content_type.param("BOUNDARY") {
+            Some(boundary) if content_type.kind == "MULTIPART" => {
+                split_multipart(self.body, boundary.as_bytes())
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// Parsed `Content-Type` header field.
+#[derive(Debug, PartialEq, Eq)]
+struct ContentType {
+    /// Upper-cased type, e.g. `TEXT`.
+    kind: String,
+
+    /// Upper-cased subtype, e.g. `PLAIN`.
+    subtype: String,
+
+    /// Parameters with upper-cased names.
+    params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    /// Default for entities without a valid `Content-Type`, see RFC 2045.
+    fn text_plain() -> Self {
+        Self {
+            kind: "TEXT".to_owned(),
+            subtype: "PLAIN".to_owned(),
+            params: vec![("CHARSET".to_owned(), "us-ascii".to_owned())],
+        }
+    }
+
+    fn octet_stream() -> Self {
+        Self {
+            kind: "APPLICATION".to_owned(),
+            subtype: "OCTET-STREAM".to_owned(),
+            params: vec![],
+        }
+    }
+
+    /// Parse header value, RFC 2231 continuations and comments are not supported.
+    fn parse(value: &[u8]) -> Option<Self> {
+        let value = String::from_utf8_lossy(value);
+
+        // split at semicolons outside of quoted strings
+        let mut items = vec![];
+        let mut current = String::new();
+        let mut chars = value.chars();
+        let mut quoted = false;
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => quoted = !quoted,
+                '\\' if quoted => current.extend(chars.next()),
+                ';' if !quoted => items.push(std::mem::take(&mut current)),
+                c => current.push(c),
+            }
+        }
+        items.push(current);
+        let mut items = items.into_iter();
+
+        let (kind, subtype) = items.next()?.trim().split_once('/').and_then(|(k, s)| {
+            let (k, s) = (k.trim(), s.trim());
+            (!k.is_empty() && !s.is_empty())
+                .then(|| (k.to_ascii_uppercase(), s.to_ascii_uppercase()))
+        })?;
+        let params = items
+            .filter_map(|item| {
+                let (name, value) = item.split_once('=')?;
+                Some((name.trim().to_ascii_uppercase(), value.trim().to_owned()))
+            })
+            .collect();
+
+        Some(Self {
+            kind,
+            subtype,
+            params,
+        })
+    }
+
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _value)| n == name)
+            .map(|(_name, value)| value.as_str())
+    }
+}
+
+/// Split multipart body into parts, ignoring preamble and epilogue.
+///
+/// A missing close delimiter ends the last part at the end of the body.
+fn split_multipart<'a>(body: &'a [u8], boundary: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = vec![];
+    let mut start = None;
+    let mut pos = 0;
+    for line in body.split_inclusive(|b| *b == b'\n') {
+        let line_start = pos;
+        pos += line.len();
+
+        let Some(rest) = line
+            .strip_prefix(b"--")
+            .and_then(|l| l.strip_prefix(boundary))
+        else {
+            continue;
+        };
+        let rest = rest.trim_ascii_end();
+        let close = rest == b"--";
+        if !close && !rest.is_empty() {
+            continue;
+        }
+
+        if let Some(start) = start {
+            // the line break before the delimiter belongs to it
+            let end = if body[..line_start].ends_with(b"\r\n") {
+                line_start - 2
+            } else {
+                line_start
+            };
+            parts.push(&body[start..end.max(start)]);
+        }
+        if close {
+            return parts;
+        }
+        start = Some(pos);
+    }
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+/// Find body of MIME part, numbered like in `BODY[1.2]`.
+///
+/// Part 1 of a message that is not multipart is its body, parts of an encapsulated message are
+/// the parts of its body.
+fn find_part<'a>(content: &'a [u8], path: &[u32]) -> Option<&'a [u8]> {
+    let mut message = Entity::parse(content);
+    let mut path = path.iter().take(MAX_DEPTH);
+    let mut n = *path.next()?;
+    loop {
+        let content_type = message.content_type();
+        let part = if content_type.kind == "MULTIPART" {
+            let idx = usize::try_from(n).ok()?.checked_sub(1)?;
+            Entity::parse(message.parts(&content_type).get(idx)?)
+        } else if n == 1 {
+            message
+        } else {
+            return None;
+        };
+
+        let Some(next) = path.next() else {
+            return Some(part.body);
+        };
+        n = *next;
+        let content_type = part.content_type();
+        message = match (content_type.kind.as_str(), content_type.subtype.as_str()) {
+            ("MULTIPART", _) => part,
+            ("MESSAGE", "RFC822") => Entity::parse(part.body),
+            _ => return None,
+        };
+    }
+}
+
+/// Write non-extensible `BODYSTRUCTURE` of message.
+pub(crate) fn write_body_structure(out: &mut Vec<u8>, content: &[u8]) {
+    write_body(out, &Entity::parse(content), 0);
+}
+
+fn write_body(out: &mut Vec<u8>, entity: &Entity<'_>, depth: usize) {
+    let mut content_type = entity.content_type();
+    let nested = depth < MAX_DEPTH;
+
+    if content_type.kind == "MULTIPART" {
+        let parts = entity.parts(&content_type);
+        if nested && !parts.is_empty() {
+            out.push(b'(');
+            for part in parts {
+                write_body(out, &Entity::parse(part), depth + 1);
+            }
+            out.push(b' ');
+            write_string(out, content_type.subtype.as_bytes());
+            out.push(b')');
+            return;
+        }
+        content_type = ContentType::octet_stream();
+    }
+    let message = content_type.kind == "MESSAGE" && content_type.subtype == "RFC822";
+    if message && !nested {
+        content_type = ContentType::octet_stream();
+    }
+    let message = message && nested;
+
+    out.push(b'(');
+    write_string(out, content_type.kind.as_bytes());
+    out.push(b' ');
+    write_string(out, content_type.subtype.as_bytes());
+    out.push(b' ');
+    if content_type.params.is_empty() {
+        out.extend_from_slice(b"NIL");
+    } else {
+        out.push(b'(');
+        for (idx, (name, value)) in content_type.params.iter().enumerate() {
+            if idx > 0 {
+                out.push(b' ');
+            }
+            write_string(out, name.as_bytes());
+            out.push(b' ');
+            write_string(out, value.as_bytes());
+        }
+        out.push(b')');
+    }
+    out.push(b' ');
+    write_nstring(out, header_value(entity.header, "Content-ID").as_deref());
+    out.push(b' ');
+    write_nstring(
+        out,
+        header_value(entity.header, "Content-Description").as_deref(),
+    );
+    out.push(b' ');
+    let encoding = header_value(entity.header, "Content-Transfer-Encoding")
+        .map(|e| e.to_ascii_uppercase())
+        .unwrap_or_else(|| b"7BIT".to_vec());
+    write_string(out, &encoding);
+    out.extend_from_slice(format!(" {}", entity.body.len()).as_bytes());
+
+    let lines = entity.body.iter().filter(|b| **b == b'\n').count()
+        + usize::from(!entity.body.is_empty() && !entity.body.ends_with(b"\n"));
+    if message {
+        out.push(b' ');
+        write_envelope(out, entity.body);
+        out.push(b' ');
+        write_body(out, &Entity::parse(entity.body), depth + 1);
+        out.extend_from_slice(format!(" {lines}").as_bytes());
+    } else if content_type.kind == "TEXT" {
+        out.extend_from_slice(format!(" {lines}").as_bytes());
+    }
+    out.push(b')');
+}
+
+/// Iterate over header fields as name and raw field, including continuation lines.
+fn header_fields(header: &[u8]) -> impl Iterator<Item = (&str, &[u8])> {
+    let mut rest = header;
+    std::iter::from_fn(move || loop {
+        if rest.is_empty() || rest.starts_with(b"\r\n") {
+            return None;
+        }
+
+        let mut end = 0;
+        for line in rest.split_inclusive(|b| *b == b'\n') {
+            if end > 0 && !line.starts_with(b" ") && !line.starts_with(b"\t") {
+                break;
+            }
+            end += line.len();
+        }
+        let (field, tail) = rest.split_at(end);
+        rest = tail;
+
+        let name = field
+            .iter()
+            .position(|b| *b == b':')
+            .and_then(|pos| std::str::from_utf8(&field[..pos]).ok());
+        if let Some(name) = name {
+            return Some((name.trim(), field));
+        }
+    })
+}
+
+/// Unfolded value of the first header field with the given name.
+fn header_value(header: &[u8], name: &str) -> Option<Vec<u8>> {
+    let (_name, raw) = header_fields(header).find(|(n, _raw)| n.eq_ignore_ascii_case(name))?;
+    let pos = raw.iter().position(|b| *b == b':')?;
+    let value = raw[pos + 1..]
+        .iter()
+        .copied()
+        .filter(|b| *b != b'\r' && *b != b'\n')
+        .collect::<Vec<_>>();
+    Some(value.trim_ascii().to_vec())
+}
+
+/// Write string as quoted string or, if needed, as literal.
+pub(crate) fn write_string(out: &mut Vec<u8>, s: &[u8]) {
+    if s.iter().all(|b| matches!(b, b' '..=b'~')) {
+        out.push(b'"');
+        for b in s {
+            if matches!(b, b'"' | b'\\') {
+                out.push(b'\\');
+            }
+            out.push(*b);
+        }
+        out.push(b'"');
+    } else {
+        write_literal(out, s);
+    }
+}
+
+/// Write data as literal.
+fn write_literal(out: &mut Vec<u8>, data: &[u8]) {
+    out.extend_from_slice(format!("{{{}}}\r\n", data.len()).as_bytes());
+    out.extend_from_slice(data);
+}
+
+fn write_nstring(out: &mut Vec<u8>, s: Option<&[u8]>) {
+    match s {
+        Some(s) => write_string(out, s),
+        None => out.extend_from_slice(b"NIL"),
+    }
+}
+
+/// Write `ENVELOPE` structure from message header.
+pub(crate) fn write_envelope(out: &mut Vec<u8>, content: &[u8]) {
+    let (header, _body) = split_header(content);
+    let from = header_value(header, "From");
+
+    out.push(b'(');
+    write_nstring(out, header_value(header, "Date").as_deref());
+    out.push(b' ');
+    write_nstring(out, header_value(header, "Subject").as_deref());
+    for name in ["From", "Sender", "Reply-To", "To", "Cc", "Bcc"] {
+        out.push(b' ');
+        let value = header_value(header, name);
+        // RFC 3501 requires sender and reply-to to default to from
+        let value = match name {
+            "Sender" | "Reply-To" => value.or_else(|| from.clone()),
+            _ => value,
+        };
+        write_addresses(out, value.as_deref());
+    }
+    out.push(b' ');
+    write_nstring(out, header_value(header, "In-Reply-To").as_deref());
+    out.push(b' ');
+    write_nstring(out, header_value(header, "Message-ID").as_deref());
+    out.push(b')');
+}
+
+/// Write address list, ignoring group syntax.
+fn write_addresses(out: &mut Vec<u8>, value: Option<&[u8]>) {
+    let addresses = value.map(split_addresses).unwrap_or_default();
+    if addresses.is_empty() {
+        out.extend_from_slice(b"NIL");
+        return;
+    }
+
+    out.push(b'(');
+    for addr in addresses {
+        let (name, addr) = match (addr.iter().position(|b| *b == b'<'), addr.last()) {
+            (Some(pos), Some(b'>')) => {
+                let name = addr[..pos].trim_ascii();
+                let name = name
+                    .strip_prefix(b"\"")
+                    .and_then(|n| n.strip_suffix(b"\""))
+                    .unwrap_or(name);
+                (Some(name), &addr[pos + 1..addr.len() - 1])
+            }
+            _ => (None, addr),
+        };
+        let (mailbox, host) = match addr.iter().rposition(|b| *b == b'@') {
+            Some(pos) => (&addr[..pos], Some(&addr[pos + 1..])),
+            None => (addr, None),
+        };
+
+        out.push(b'(');
+        write_nstring(out, name.filter(|n| !n.is_empty()));
+        out.extend_from_slice(b" NIL ");
+        write_string(out, mailbox);
+        out.push(b' ');
+        write_nstring(out, host);
+        out.push(b')');
+    }
+    out.push(b')');
+}
+
+/// Split address list at commas that are not quoted or within angle brackets.
+fn split_addresses(value: &[u8]) -> Vec<&[u8]> {
+    let mut out = vec![];
+    let mut start = 0;
+    let mut quoted = false;
+    let mut angle = false;
+    for (idx, b) in value.iter().enumerate() {
+        match b {
+            b'"' => quoted = !quoted,
+            b'<' if !quoted => angle = true,
+            b'>' if !quoted => angle = false,
+            b',' if !quoted && !angle => {
+                out.push(&value[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    out.push(&value[start..]);
+
+    out.into_iter()
+        .map(|a| a.trim_ascii())
+        .filter(|a| !a.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &[u8] = b"From: \"Doe, Jane\" <jane@example.com>\r\n\
+        To: a@example.com,\r\n b@example.com\r\n\
+        Subject: Hello\r\n\
+        Message-ID: <1@example.com>\r\n\
+        \r\n\
+        body\r\n";
+
+    #[test]
+    fn test_section() {
+        assert_eq!(section(MESSAGE, &Section::Text).as_ref(), b"body\r\n");
+        assert!(section(MESSAGE, &Section::Header).ends_with(b"<1@example.com>\r\n\r\n"));
+        assert_eq!(
+            section(
+                MESSAGE,
+                &Section::HeaderFields {
+                    fields: vec!["TO".to_owned(), "SUBJECT".to_owned()],
+                    not: false,
+                }
+            )
+            .as_ref(),
+            b"To: a@example.com,\r\n b@example.com\r\nSubject: Hello\r\n\r\n",
+        );
+        assert_eq!(
+            section(
+                MESSAGE,
+                &Section::HeaderFields {
+                    fields: vec!["FROM".to_owned(), "TO".to_owned()],
+                    not: true,
+                }
+            )
+            .as_ref(),
+            b"Subject: Hello\r\nMessage-ID: <1@example.com>\r\n\r\n",
+        );
+        assert!(section(b"no header", &Section::Text).is_empty());
+    }
+
+    const MULTIPART: &[u8] = b"Subject: parts\r\n\
+        Content-Type: multipart/mixed; boundary=\"b;1\"\r\n\
+        \r\n\
+        preamble\r\n\
+        --b;1\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\
+        Content-Transfer-Encoding: quoted-printable\r\n\
+        \r\n\
+        hello\r\n\
+        --b;1\r\n\
+        Content-Type: message/rfc822\r\n\
+        \r\n\
+        Subject: inner\r\n\
+        \r\n\
+        inner body\r\n\
+        --b;1--\r\n\
+        epilogue\r\n";
+
+    #[test]
+    fn test_multipart() {
+        assert_eq!(
+            section(MULTIPART, &Section::Part(vec![1])).as_ref(),
+            b"hello",
+        );
+        assert_eq!(
+            section(MULTIPART, &Section::Part(vec![2])).as_ref(),
+            b"Subject: inner\r\n\r\ninner body",
+        );
+        assert_eq!(
+            section(MULTIPART, &Section::Part(vec![2, 1])).as_ref(),
+            b"inner body",
+        );
+        assert!(section(MULTIPART, &Section::Part(vec![3])).is_empty());
+        assert_eq!(
+            section(MESSAGE, &Section::Part(vec![1])).as_ref(),
+            b"body\r\n"
+        );
+
+        let mut out = vec![];
+        write_body_structure(&mut out, MULTIPART);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"utf-8\") NIL NIL \"QUOTED-PRINTABLE\" 5 1)\
+            (\"MESSAGE\" \"RFC822\" NIL NIL NIL \"7BIT\" 28 \
+            (NIL \"inner\" NIL NIL NIL NIL NIL NIL NIL NIL) \
+            (\"TEXT\" \"PLAIN\" (\"CHARSET\" \"us-ascii\") NIL NIL \"7BIT\" 10 1) 3) \
+            \"MIXED\")",
+        );
+
+        let mut out = vec![];
+        write_body_structure(
+            &mut out,
+            b"Content-Type: multipart/mixed; boundary=x\r\n\r\nno parts",
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "(\"APPLICATION\" \"OCTET-STREAM\" NIL NIL NIL \"7BIT\" 8)",
+        );
+    }
+
+    #[test]
+    fn test_write_string() {
+        let mut out = vec![];
+        write_string(&mut out, b"a \"b\" \\");
+        assert_eq!(out, b"\"a \\\"b\\\" \\\\\"");
+
+        let mut out = vec![];
+        write_string(&mut out, b"a\r\nb");
+        assert_eq!(out, b"{4}\r\na\r\nb");
+    }
+
+    #[test]
+    fn test_write_envelope() {
+        let mut out = vec![];
+        write_envelope(&mut out, MESSAGE);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "(NIL \"Hello\" \
+            ((\"Doe, Jane\" NIL \"jane\" \"example.com\")) \
+            ((\"Doe, Jane\" NIL \"jane\" \"example.com\")) \
+            ((\"Doe, Jane\" NIL \"jane\" \"example.com\")) \
+            ((NIL NIL \"a\" \"example.com\")(NIL NIL \"b\" \"example.com\")) \
+            NIL NIL NIL \"<1@example.com>\")",
+        );
+    }
+}
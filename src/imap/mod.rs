@@ -0,0 +1,827 @@
+//! Experimental read-only IMAP server for downloaded mails.
+//!
+//! Only the parts of IMAP4rev1 that mail clients need for browsing are implemented. There is no
+//! TLS and any credentials are accepted, so this must only listen on local addresses.
+use std::{collections::BTreeMap, net::SocketAddr, path::PathBuf, sync::Arc};
+
+use anyhow::{bail, ensure, Context, Result};
+use clap::Parser;
+use tokio::{
+    io::{
+        AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+        BufWriter,
+    },
+    net::TcpListener,
+};
+use tracing::{debug, info, warn};
+
+use crate::imap::{
+    mailbox::{discover, Mailbox, Message, Source, UidStore},
+    message::{section, write_body_structure, write_envelope, write_string},
+    parser::{
+        parse_command, parse_fetch_attrs, split_tag, Arg, Command, FetchAttr, Section, SequenceSet,
+    },
+};
+
+mod mailbox;
+mod message;
+mod parser;
+
+const CAPABILITIES: &str = "IMAP4rev1 UNSELECT";
+
+/// Maximum length of a single command line, excluding literals.
+const MAX_LINE: u64 = 64 * 1024;
+
+/// Maximum length of a command, including literals.
+const MAX_COMMAND: usize = 1024 * 1024;
+
+/// Serve IMAP CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct ServeImapCLIConfig {
+    /// Downloaded mails: a Maildir, an mbox file, or the `--path` of `download --all-folders`.
+    #[clap(long, action)]
+    path: PathBuf,
+
+    /// Address to listen on.
+    ///
+    /// There is no TLS and any credentials are accepted, so do not expose this to other machines.
+    #[clap(long, action, default_value = "127.0.0.1:1143")]
+    listen: SocketAddr,
+}
+
+/// Serve downloaded mails via IMAP until cancelled.
+pub(crate) async fn serve_imap(cfg: ServeImapCLIConfig) -> Result<()> {
+    let mailboxes = discover(&cfg.path).await.context("find mailboxes")?;
+    ensure!(
+        !mailboxes.is_empty(),
+        "no Maildir or mbox found at `{}`",
+        cfg.path.display()
+    );
+    let mailboxes = Arc::new(mailboxes);
+    let uids = Arc::new(UidStore::default());
+
+    let listener = TcpListener::bind(cfg.listen)
+        .await
+        .with_context(|| format!("listen on {}", cfg.listen))?;
+    info!(
+        addr=%listener.local_addr().context("get local address")?,
+        mailboxes=mailboxes.len(),
+        "serving IMAP",
+    );
+
+    loop {
+        let (stream, peer) = listener.accept().await.context("accept connection")?;
+        debug!(%peer, "connection accepted");
+
+        let mailboxes = Arc::clone(&mailboxes);
+        let uids = Arc::clone(&uids);
+        tokio::spawn(async move {
+            match serve_connection(stream, &mailboxes, &uids).await {
+                Ok(()) => debug!(%peer, "connection closed"),
+                Err(e) => warn!(%e, %peer, "connection failed"),
+            }
+        });
+    }
+}
+
+async fn serve_connection<S>(
+    stream: S,
+    mailboxes: &BTreeMap<String, Source>,
+    uids: &UidStore,
+) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin,
+{
+    let (reader, writer) = tokio::io::split(stream);
+    let mut reader = tokio::io::BufReader::new(reader);
+    let mut writer = BufWriter::new(writer);
+
+    writer
+        .write_all(format!("* OK [CAPABILITY {CAPABILITIES}] tatutanatata ready\r\n").as_bytes())
+        .await
+        .context("write greeting")?;
+    writer.flush().await.context("flush greeting")?;
+
+    let mut session = ImapSession {
+        mailboxes,
+        uids,
+        authenticated: false,
+        selected: None,
+    };
+    while let Some(line) = read_command(&mut reader, &mut writer).await? {
+        let (tag, rest) = split_tag(&line);
+        let mut out = vec![];
+        let mut logout = false;
+
+        let res = match parse_command(tag.clone(), rest) {
+            Ok(cmd) => {
+                debug!(tag = cmd.tag.as_str(), name = cmd.name.as_str(), "command");
+                logout = cmd.name == "LOGOUT";
+                session.exec(&cmd, &mut out, &mut writer).await?
+            }
+            Err(e) => Err(bad(e)),
+        };
+        let (status, text) = match res {
+            Ok(text) => ("OK", text),
+            Err(Reject { status, text }) => (status, text),
+        };
+        let text = text.replace(['\r', '\n'], " ");
+        out.extend_from_slice(format!("{tag} {status} {text}\r\n").as_bytes());
+
+        writer.write_all(&out).await.context("write response")?;
+        writer.flush().await.context("flush response")?;
+        if logout {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Read command, including literals, without the final line break.
+///
+/// Returns `None` if the client closed the connection.
+async fn read_command<R, W>(reader: &mut R, writer: &mut W) -> Result<Option<Vec<u8>>>
+where
+    R: AsyncBufRead + Send + Unpin,
+    W: AsyncWrite + Send + Unpin,
+{
+    let mut command = vec![];
+    loop {
+        let start = command.len();
+        let n = (&mut *reader)
+            .take(MAX_LINE)
+            .read_until(b'\n', &mut command)
+            .await
+            .context("read command")?;
+        if n == 0 && command.is_empty() {
+            return Ok(None);
+        }
+        ensure!(command.ends_with(b"\n"), "command line too long or cut off");
+        command.pop();
+        if command.ends_with(b"\r") {
+            command.pop();
+        }
+
+        // a line that ends with `{n}` announces a literal of n bytes, `{n+}` does not need a
+        // continuation request
+        let line = &command[start..];
+        let Some(literal) = line
+            .strip_suffix(b"}")
+            .and_then(|l| l.iter().rposition(|b| *b == b'{').map(|pos| &l[pos + 1..]))
+        else {
+            return Ok(Some(command));
+        };
+        let (literal, sync) = match literal.strip_suffix(b"+") {
+            Some(literal) => (literal, false),
+            None => (literal, true),
+        };
+        let Some(len) = std::str::from_utf8(literal)
+            .ok()
+            .and_then(|l| l.parse::<usize>().ok())
+        else {
+            return Ok(Some(command));
+        };
+        if command.len() + len > MAX_COMMAND {
+            bail!("command too long");
+        }
+
+        if sync {
+            writer
+                .write_all(b"+ ready for literal\r\n")
+                .await
+                .context("write continuation request")?;
+            writer.flush().await.context("flush continuation request")?;
+        }
+        command.extend_from_slice(b"\r\n");
+        let start = command.len();
+        command.resize(start + len, 0);
+        reader
+            .read_exact(&mut command[start..])
+            .await
+            .context("read literal")?;
+    }
+}
+
+/// Tagged `NO` or `BAD` response.
+#[derive(Debug)]
+struct Reject {
+    status: &'static str,
+    text: String,
+}
+
+fn no(text: impl Into<String>) -> Reject {
+    Reject {
+        status: "NO",
+        text: text.into(),
+    }
+}
+
+fn bad(text: impl Into<String>) -> Reject {
+    Reject {
+        status: "BAD",
+        text: text.into(),
+    }
+}
+
+fn untagged(out: &mut Vec<u8>, line: &str) {
+    out.extend_from_slice(format!("* {line}\r\n").as_bytes());
+}
+
+/// State of a single connection.
+#[derive(Debug)]
+struct ImapSession<'a> {
+    mailboxes: &'a BTreeMap<String, Source>,
+    uids: &'a UidStore,
+    authenticated: bool,
+    selected: Option<Mailbox>,
+}
+
+impl ImapSession<'_> {
+    /// Execute command, writing untagged responses to `out`.
+    ///
+    /// `FETCH` responses are written to `writer` instead, so that message data is not buffered.
+    /// Returns the text of the tagged `OK` response, errors are failures of the connection.
+    async fn exec<W>(
+        &mut self,
+        cmd: &Command,
+        out: &mut Vec<u8>,
+        writer: &mut W,
+    ) -> Result<Result<String, Reject>>
+    where
+        W: AsyncWrite + Send + Unpin,
+    {
+        if self.authenticated && cmd.name == "FETCH" {
+            return self.fetch(cmd, out, writer).await;
+        }
+        Ok(self.exec_buffered(cmd, out).await)
+    }
+
+    /// Execute command other than `FETCH`.
+    async fn exec_buffered(&mut self, cmd: &Command, out: &mut Vec<u8>) -> Result<String, Reject> {
+        match cmd.name.as_str() {
+            "CAPABILITY" => {
+                untagged(out, &format!("CAPABILITY {CAPABILITIES}"));
+                return Ok("CAPABILITY completed".to_owned());
+            }
+            "NOOP" => return Ok("NOOP completed".to_owned()),
+            "LOGOUT" => {
+                untagged(out, "BYE logging out");
+                return Ok("LOGOUT completed".to_owned());
+            }
+            "LOGIN" => {
+                let [user, password] = cmd.args.as_slice() else {
+                    return Err(bad("expected user name and password"));
+                };
+                if user.as_str().is_none() || password.as_str().is_none() {
+                    return Err(bad("expected user name and password"));
+                }
+                self.authenticated = true;
+                return Ok("LOGIN completed".to_owned());
+            }
+            "AUTHENTICATE" => return Err(no("only LOGIN is supported")),
+            _ => {}
+        }
+
+        if !self.authenticated {
+            return Err(bad("log in first"));
+        }
+
+        match cmd.name.as_str() {
+            "LIST" | "LSUB" => self.list(cmd, out),
+            "SELECT" | "EXAMINE" => self.select(cmd, out).await,
+            "STATUS" => self.status(cmd, out).await,
+            "CHECK" => {
+                self.selected()?;
+                Ok("CHECK completed".to_owned())
+            }
+            "CLOSE" | "UNSELECT" => {
+                self.selected()?;
+                self.selected = None;
+                Ok(format!("{} completed", cmd.name))
+            }
+            "SEARCH" => self.search(cmd, out),
+            "APPEND" | "COPY" | "CREATE" | "DELETE" | "EXPUNGE" | "MOVE" | "RENAME" | "STORE"
+            | "SUBSCRIBE" | "UNSUBSCRIBE" => Err(no("server is read-only")),
+            _ => Err(bad("unknown command")),
+        }
+    }
+
+    fn selected(&self) -> Result<&Mailbox, Reject> {
+        self.selected
+            .as_ref()
+            .ok_or_else(|| bad("no mailbox selected"))
+    }
+
+    fn source(&self, name: &Arg) -> Result<&Source, Reject> {
+        let name = name.as_str().ok_or_else(|| bad("invalid mailbox name"))?;
+        let name = if name.eq_ignore_ascii_case("INBOX") {
+            "INBOX"
+        } else {
+            name
+        };
+        self.mailboxes
+            .get(name)
+            .ok_or_else(|| no("mailbox does not exist"))
+    }
+
+    fn list(&self, cmd: &Command, out: &mut Vec<u8>) -> Result<String, Reject> {
+        let (Some(reference), Some(pattern)) = (
+            cmd.args.first().and_then(Arg::as_str),
+            cmd.args.get(1).and_then(Arg::as_str),
+        ) else {
+            return Err(bad("expected reference and mailbox pattern"));
+        };
+
+        if pattern.is_empty() {
+            // request for hierarchy delimiter
+            untagged(out, &format!("{} (\\Noselect) \"/\" \"\"", cmd.name));
+        } else {
+            let pattern = format!("{reference}{pattern}");
+            for name in self.mailboxes.keys() {
+                let matches = if name == "INBOX" {
+                    matches_pattern(pattern.to_ascii_uppercase().as_bytes(), name.as_bytes())
+                } else {
+                    matches_pattern(pattern.as_bytes(), name.as_bytes())
+                };
+                if matches {
+                    let mut line = format!("* {} (\\HasNoChildren) \"/\" ", cmd.name).into_bytes();
+                    write_string(&mut line, name.as_bytes());
+                    out.extend_from_slice(&line);
+                    out.extend_from_slice(b"\r\n");
+                }
+            }
+        }
+
+        Ok(format!("{} completed", cmd.name))
+    }
+
+    async fn select(&mut self, cmd: &Command, out: &mut Vec<u8>) -> Result<String, Reject> {
+        self.selected = None;
+
+        let [name] = cmd.args.as_slice() else {
+            return Err(bad("expected mailbox name"));
+        };
+        let mailbox = Mailbox::load(self.source(name)?, self.uids)
+            .await
+            .map_err(|e| no(format!("{e:#}")))?;
+
+        untagged(
+            out,
+            "FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft $Forwarded)",
+        );
+        untagged(out, &format!("{} EXISTS", mailbox.messages.len()));
+        untagged(out, "0 RECENT");
+        untagged(
+            out,
+            &format!("OK [UIDVALIDITY {}] UIDs valid", mailbox.uid_validity),
+        );
+        untagged(
+            out,
+            &format!("OK [UIDNEXT {}] predicted next UID", mailbox.uid_next()),
+        );
+        untagged(out, "OK [PERMANENTFLAGS ()] read-only");
+
+        self.selected = Some(mailbox);
+        Ok(format!("[READ-ONLY] {} completed", cmd.name))
+    }
+
+    async fn status(&self, cmd: &Command, out: &mut Vec<u8>) -> Result<String, Reject> {
+        let [name, Arg::List(items)] = cmd.args.as_slice() else {
+            return Err(bad("expected mailbox name and status items"));
+        };
+        let mailbox = Mailbox::load(self.source(name)?, self.uids)
+            .await
+            .map_err(|e| no(format!("{e:#}")))?;
+
+        let items = items
+            .iter()
+            .map(|item| {
+                let item = item.as_str().unwrap_or_default().to_ascii_uppercase();
+                let value = match item.as_str() {
+                    "MESSAGES" => mailbox.messages.len(),
+                    "RECENT" => 0,
+                    "UIDNEXT" => mailbox.uid_next() as usize,
+                    "UIDVALIDITY" => mailbox.uid_validity as usize,
+                    "UNSEEN" => mailbox.unseen(),
+                    _ => return Err(bad(format!("unsupported status item `{item}`"))),
+                };
+                Ok(format!("{item} {value}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        out.extend_from_slice(b"* STATUS ");
+        write_string(out, name.as_str().unwrap_or_default().as_bytes());
+        out.extend_from_slice(format!(" ({})\r\n", items.join(" ")).as_bytes());
+
+        Ok("STATUS completed".to_owned())
+    }
+
+    async fn fetch<W>(
+        &self,
+        cmd: &Command,
+        out: &mut Vec<u8>,
+        writer: &mut W,
+    ) -> Result<Result<String, Reject>>
+    where
+        W: AsyncWrite + Send + Unpin,
+    {
+        let (mailbox, set, attrs) = match self.fetch_args(cmd) {
+            Ok(args) => args,
+            Err(e) => return Ok(Err(e)),
+        };
+        let needs_content = attrs.iter().any(|attr| {
+            !matches!(
+                attr,
+                FetchAttr::Flags | FetchAttr::InternalDate | FetchAttr::Rfc822Size | FetchAttr::Uid
+            )
+        });
+
+        let max = mailbox.max(cmd.uid);
+        for (seq, msg) in mailbox.numbered() {
+            if !set.contains(if cmd.uid { msg.uid } else { seq }, max) {
+                continue;
+            }
+            let content = if needs_content {
+                match msg.content().await.context("read message") {
+                    Ok(content) => Some(content),
+                    Err(e) => return Ok(Err(no(format!("{e:#}")))),
+                }
+            } else {
+                None
+            };
+            let content = content.as_deref().unwrap_or_default();
+            write_fetch(writer, out, seq, msg, content, &attrs).await?;
+        }
+
+        Ok(Ok("FETCH completed".to_owned()))
+    }
+
+    fn fetch_args(&self, cmd: &Command) -> Result<(&Mailbox, SequenceSet, Vec<FetchAttr>), Reject> {
+        let mailbox = self.selected()?;
+        let [set, attrs] = cmd.args.as_slice() else {
+            return Err(bad("expected sequence set and fetch attributes"));
+        };
+        let set = SequenceSet::parse(set.as_str().unwrap_or_default()).map_err(bad)?;
+        let mut attrs = parse_fetch_attrs(attrs).map_err(bad)?;
+        if cmd.uid && !attrs.contains(&FetchAttr::Uid) {
+            attrs.insert(0, FetchAttr::Uid);
+        }
+        Ok((mailbox, set, attrs))
+    }
+
+    fn search(&self, cmd: &Command, out: &mut Vec<u8>) -> Result<String, Reject> {
+        enum Criterion {
+            Flag(&'static str, bool),
+            Set(SequenceSet),
+            Uid(SequenceSet),
+        }
+
+        let mailbox = self.selected()?;
+
+        let mut args = cmd.args.as_slice();
+        if let [Arg::Atom(charset), _charset, rest @ ..] = args {
+            if charset.eq_ignore_ascii_case("CHARSET") {
+                args = rest;
+            }
+        }
+
+        let mut criteria = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let key = arg.as_str().unwrap_or_default().to_ascii_uppercase();
+            let (flag, negated) = match key.strip_prefix("UN") {
+                Some(flag) => (flag, true),
+                None => (key.as_str(), false),
+            };
+            let flag = match flag {
+                "ANSWERED" => Some("\\Answered"),
+                "DELETED" => Some("\\Deleted"),
+                "DRAFT" => Some("\\Draft"),
+                "FLAGGED" => Some("\\Flagged"),
+                "SEEN" => Some("\\Seen"),
+                _ => None,
+            };
+
+            let criterion = match (flag, key.as_str()) {
+                (Some(flag), _) => Criterion::Flag(flag, !negated),
+                (None, "ALL") => continue,
+                (None, "UID") => {
+                    let set = args.next().and_then(Arg::as_str).unwrap_or_default();
+                    Criterion::Uid(SequenceSet::parse(set).map_err(bad)?)
+                }
+                (None, key) => match SequenceSet::parse(key) {
+                    Ok(set) => Criterion::Set(set),
+                    Err(_) => return Err(no(format!("unsupported search key `{key}`"))),
+                },
+            };
+            criteria.push(criterion);
+        }
+
+        let mut line = "* SEARCH".to_owned();
+        for (seq, msg) in mailbox.numbered() {
+            let matches = criteria.iter().all(|c| match c {
+                Criterion::Flag(flag, set) => msg.flags.contains(flag) == *set,
+                Criterion::Set(set) => set.contains(seq, mailbox.max(false)),
+                Criterion::Uid(set) => set.contains(msg.uid, mailbox.max(true)),
+            });
+            if matches {
+                line.push_str(&format!(" {}", if cmd.uid { msg.uid } else { seq }));
+            }
+        }
+        out.extend_from_slice(line.as_bytes());
+        out.extend_from_slice(b"\r\n");
+
+        Ok("SEARCH completed".to_owned())
+    }
+}
+
+/// Write `FETCH` response for a single message.
+///
+/// Literals are written to `writer` directly, everything else is collected in `buf` and written
+/// before the next literal or with the tagged response.
+async fn write_fetch<W>(
+    writer: &mut W,
+    buf: &mut Vec<u8>,
+    seq: u32,
+    msg: &Message,
+    content: &[u8],
+    attrs: &[FetchAttr],
+) -> Result<()>
+where
+    W: AsyncWrite + Send + Unpin,
+{
+    buf.extend_from_slice(format!("* {seq} FETCH (").as_bytes());
+    for (idx, attr) in attrs.iter().enumerate() {
+        if idx > 0 {
+            buf.push(b' ');
+        }
+        match attr {
+            FetchAttr::Envelope => {
+                buf.extend_from_slice(b"ENVELOPE ");
+                write_envelope(buf, content);
+            }
+            FetchAttr::Flags => {
+                buf.extend_from_slice(format!("FLAGS ({})", msg.flags.join(" ")).as_bytes());
+            }
+            FetchAttr::InternalDate => {
+                let date = msg.internal_date.format("%d-%b-%Y %H:%M:%S +0000");
+                buf.extend_from_slice(format!("INTERNALDATE \"{date}\"").as_bytes());
+            }
+            FetchAttr::Rfc822 => {
+                buf.extend_from_slice(b"RFC822 ");
+                stream_literal(writer, buf, content).await?;
+            }
+            FetchAttr::Rfc822Header => {
+                buf.extend_from_slice(b"RFC822.HEADER ");
+                stream_literal(writer, buf, &section(content, &Section::Header)).await?;
+            }
+            FetchAttr::Rfc822Size => {
+                buf.extend_from_slice(format!("RFC822.SIZE {}", msg.size).as_bytes());
+            }
+            FetchAttr::Rfc822Text => {
+                buf.extend_from_slice(b"RFC822.TEXT ");
+                stream_literal(writer, buf, &section(content, &Section::Text)).await?;
+            }
+            FetchAttr::Uid => {
+                buf.extend_from_slice(format!("UID {}", msg.uid).as_bytes());
+            }
+            FetchAttr::BodyStructure { extensible } => {
+                buf.extend_from_slice(if *extensible {
+                    b"BODYSTRUCTURE "
+                } else {
+                    b"BODY "
+                });
+                write_body_structure(buf, content);
+            }
+            FetchAttr::Body {
+                section: s,
+                name,
+                partial,
+            } => {
+                let data = section(content, s);
+                buf.extend_from_slice(format!("BODY[{name}]").as_bytes());
+                let data = match partial {
+                    Some((offset, len)) => {
+                        buf.extend_from_slice(format!("<{offset}>").as_bytes());
+                        let start = (*offset).min(data.len());
+                        let end = offset.saturating_add(*len).min(data.len());
+                        &data[start..end]
+                    }
+                    None => &data,
+                };
+                buf.push(b' ');
+                stream_literal(writer, buf, data).await?;
+            }
+        }
+    }
+    buf.extend_from_slice(b")\r\n");
+
+    Ok(())
+}
+
+/// Write buffered response and literal.
+async fn stream_literal<W>(writer: &mut W, buf: &mut Vec<u8>, data: &[u8]) -> Result<()>
+where
+    W: AsyncWrite + Send + Unpin,
+{
+    buf.extend_from_slice(format!("{{{}}}\r\n", data.len()).as_bytes());
+    writer.write_all(buf).await.context("write response")?;
+    buf.clear();
+    writer.write_all(data).await.context("write literal")?;
+    Ok(())
+}
+
+/// Match mailbox name against `LIST` pattern.
+///
+/// There is no hierarchy, so the wildcards `*` and `%` are the same.
+fn matches_pattern(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*' | b'%') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(c) if *c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| matches!(c, b'*' | b'%'))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{BufReader, DuplexStream};
+
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern(b"*", b"INBOX"));
+        assert!(matches_pattern(b"%", b""));
+        assert!(matches_pattern(b"IN*X", b"INBOX"));
+        assert!(matches_pattern(b"*B*", b"INBOX"));
+        assert!(matches_pattern(b"INBOX", b"INBOX"));
+        assert!(!matches_pattern(b"INBOX", b"INBOX2"));
+        assert!(!matches_pattern(b"*A", b"INBOX"));
+        assert!(!matches_pattern(b"", b"INBOX"));
+    }
+
+    /// Read responses up to the tagged one.
+    async fn read_response(client: &mut BufReader<DuplexStream>, tag: &str) -> String {
+        let mut out = String::new();
+        loop {
+            let n = client.read_line(&mut out).await.unwrap();
+            assert_ne!(n, 0, "connection closed");
+            if out.lines().last().unwrap().starts_with(&format!("{tag} ")) {
+                return out;
+            }
+        }
+    }
+
+    /// Send command and read responses up to the tagged one.
+    async fn roundtrip(client: &mut BufReader<DuplexStream>, command: &str) -> String {
+        client
+            .get_mut()
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await
+            .unwrap();
+        read_response(client, command.split(' ').next().unwrap()).await
+    }
+
+    #[tokio::test]
+    async fn test_session() {
+        let dir = tempfile::tempdir().unwrap();
+        let inbox = dir.path().join("Inbox");
+        std::fs::create_dir_all(inbox.join("cur")).unwrap();
+        std::fs::write(
+            inbox.join("cur").join("1.a.tatutanatata:2,S"),
+            b"From: a@example.com\r\nSubject: first\r\n\r\nfoo\r\n",
+        )
+        .unwrap();
+        std::fs::write(
+            inbox.join("cur").join("2.b.tatutanatata:2,"),
+            b"From: b@example.com\r\nSubject: second\r\n\r\nbar\r\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join("Archive.mbox"),
+            b"From x Thu Jan  1 00:00:01 1970\nSubject: old\n\nbaz\n\n",
+        )
+        .unwrap();
+        let mailboxes = discover(dir.path()).await.unwrap();
+
+        let (client, server) = tokio::io::duplex(1024);
+        let handle = tokio::spawn(async move {
+            serve_connection(server, &mailboxes, &UidStore::default()).await
+        });
+        let mut client = BufReader::new(client);
+
+        let mut greeting = String::new();
+        client.read_line(&mut greeting).await.unwrap();
+        assert_eq!(
+            greeting,
+            "* OK [CAPABILITY IMAP4rev1 UNSELECT] tatutanatata ready\r\n"
+        );
+
+        assert_eq!(
+            roundtrip(&mut client, "a1 SELECT INBOX").await,
+            "a1 BAD log in first\r\n",
+        );
+        client
+            .get_mut()
+            .write_all(b"a2 LOGIN {4}\r\n")
+            .await
+            .unwrap();
+        let mut continuation = String::new();
+        client.read_line(&mut continuation).await.unwrap();
+        assert_eq!(continuation, "+ ready for literal\r\n");
+        client.get_mut().write_all(b"user pass\r\n").await.unwrap();
+        assert_eq!(
+            read_response(&mut client, "a2").await,
+            "a2 OK LOGIN completed\r\n",
+        );
+        assert_eq!(
+            roundtrip(&mut client, "a3 LIST \"\" *").await,
+            "* LIST (\\HasNoChildren) \"/\" \"Archive\"\r\n\
+            * LIST (\\HasNoChildren) \"/\" \"INBOX\"\r\n\
+            a3 OK LIST completed\r\n",
+        );
+        assert_eq!(
+            roundtrip(&mut client, "a4 STATUS archive (MESSAGES)").await,
+            "a4 NO mailbox does not exist\r\n",
+        );
+        assert_eq!(
+            roundtrip(&mut client, "a5 STATUS Archive (MESSAGES UIDNEXT UNSEEN)").await,
+            "* STATUS \"Archive\" (MESSAGES 1 UIDNEXT 2 UNSEEN 1)\r\n\
+            a5 OK STATUS completed\r\n",
+        );
+
+        let select = roundtrip(&mut client, "a6 EXAMINE inbox").await;
+        assert!(select.contains("* 2 EXISTS\r\n"), "{select}");
+        assert!(select.ends_with("a6 OK [READ-ONLY] EXAMINE completed\r\n"));
+
+        assert_eq!(
+            roundtrip(&mut client, "a7 FETCH 1:* (FLAGS RFC822.SIZE INTERNALDATE)").await,
+            "* 1 FETCH (FLAGS (\\Seen) RFC822.SIZE 44 INTERNALDATE \"01-Jan-1970 00:00:01 +0000\")\r\n\
+            * 2 FETCH (FLAGS () RFC822.SIZE 45 INTERNALDATE \"01-Jan-1970 00:00:02 +0000\")\r\n\
+            a7 OK FETCH completed\r\n",
+        );
+        assert_eq!(
+            roundtrip(
+                &mut client,
+                "a8 UID FETCH 2:* (BODY.PEEK[HEADER.FIELDS (SUBJECT)] BODY[TEXT]<1.10>)"
+            )
+            .await,
+            "* 2 FETCH (UID 2 BODY[HEADER.FIELDS (SUBJECT)] {19}\r\n\
+            Subject: second\r\n\
+            \r\n \
+            BODY[TEXT]<1> {4}\r\n\
+            ar\r\n\
+            )\r\n\
+            a8 OK FETCH completed\r\n",
+        );
+        assert_eq!(
+            roundtrip(&mut client, "a9 FETCH 2 ENVELOPE").await,
+            "* 2 FETCH (ENVELOPE (NIL \"second\" \
+            ((NIL NIL \"b\" \"example.com\")) ((NIL NIL \"b\" \"example.com\")) \
+            ((NIL NIL \"b\" \"example.com\")) NIL NIL NIL NIL NIL))\r\n\
+            a9 OK FETCH completed\r\n",
+        );
+        assert_eq!(
+            roundtrip(&mut client, "a10 UID SEARCH UNSEEN").await,
+            "* SEARCH 2\r\na10 OK SEARCH completed\r\n",
+        );
+        assert_eq!(
+            roundtrip(&mut client, "a11 SEARCH BEFORE 1-Jan-2000").await,
+            "a11 NO unsupported search key `BEFORE`\r\n",
+        );
+        assert_eq!(
+            roundtrip(&mut client, "a12 STORE 1 +FLAGS (\\Seen)").await,
+            "a12 NO server is read-only\r\n",
+        );
+        assert_eq!(
+            roundtrip(&mut client, "a13 FETCH 1 BODYSTRUCTURE").await,
+            "* 1 FETCH (BODYSTRUCTURE (\"TEXT\" \"PLAIN\" (\"CHARSET\" \"us-ascii\") NIL NIL \"7BIT\" 5 1))\r\n\
+            a13 OK FETCH completed\r\n",
+        );
+        assert_eq!(
+            roundtrip(&mut client, "a14 LOGOUT").await,
+            "* BYE logging out\r\na14 OK LOGOUT completed\r\n",
+        );
+
+        handle.await.unwrap().unwrap();
+    }
+}
@@ -0,0 +1,503 @@
+//! Parser for IMAP commands, see [RFC 3501](https://www.rfc-editor.org/rfc/rfc3501).
+
+/// Command argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Arg {
+    /// Atom, including `[...]` sections and `<...>` partials of fetch attributes.
+    Atom(String),
+
+    /// Quoted string or literal.
+    String(String),
+
+    /// Parenthesized list.
+    List(Vec<Self>),
+}
+
+impl Arg {
+    /// Atom or string value.
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::Atom(s) | Self::String(s) => Some(s),
+            Self::List(_) => None,
+        }
+    }
+}
+
+/// Tagged command.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Command {
+    pub(crate) tag: String,
+
+    /// Upper-cased command name, e.g. `FETCH`.
+    pub(crate) name: String,
+
+    /// Command was prefixed with `UID`.
+    pub(crate) uid: bool,
+
+    pub(crate) args: Vec<Arg>,
+}
+
+/// Split tag from command line.
+///
+/// This never fails so that errors can be reported with the right tag.
+pub(crate) fn split_tag(line: &[u8]) -> (String, &[u8]) {
+    let end = line.iter().position(|b| *b == b' ').unwrap_or(line.len());
+    let tag = String::from_utf8_lossy(&line[..end]).into_owned();
+    let rest = line.get(end + 1..).unwrap_or_default();
+    (tag, rest)
+}
+
+/// Parse command line without tag.
+///
+/// Literals must be contained in the line, i.e. `{3}\r\nfoo`.
+pub(crate) fn parse_command(tag: String, line: &[u8]) -> Result<Command, String> {
+    let mut args = Parser {
+        input: line,
+        pos: 0,
+    }
+    .list(0)?
+    .into_iter();
+
+    let mut name = match args.next() {
+        Some(Arg::Atom(name)) => name.to_ascii_uppercase(),
+        _ => return Err("missing command".to_owned()),
+    };
+    let uid = name == "UID";
+    if uid {
+        name = match args.next() {
+            Some(Arg::Atom(name)) => name.to_ascii_uppercase(),
+            _ => return Err("missing command after UID".to_owned()),
+        };
+    }
+
+    Ok(Command {
+        tag,
+        name,
+        uid,
+        args: args.collect(),
+    })
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn list(&mut self, depth: usize) -> Result<Vec<Arg>, String> {
+        let mut args = vec![];
+        loop {
+            while self.peek() == Some(b' ') {
+                self.pos += 1;
+            }
+            match self.peek() {
+                None if depth == 0 => return Ok(args),
+                None => return Err("unterminated list".to_owned()),
+                Some(b')') if depth == 0 => return Err("unexpected `)`".to_owned()),
+                Some(b')') => {
+                    self.pos += 1;
+                    return Ok(args);
+                }
+                Some(b'(') => {
+                    self.pos += 1;
+                    args.push(Arg::List(self.list(depth + 1)?));
+                }
+                Some(b'"') => {
+                    self.pos += 1;
+                    args.push(Arg::String(self.quoted()?));
+                }
+                Some(b'{') => {
+                    self.pos += 1;
+                    args.push(Arg::String(self.literal()?));
+                }
+                Some(_) => {
+                    args.push(Arg::Atom(self.atom()?));
+                }
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<String, String> {
+        let mut out = vec![];
+        loop {
+            let b = self.peek().ok_or("unterminated string")?;
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    out.push(self.peek().ok_or("unterminated string")?);
+                    self.pos += 1;
+                }
+                b'\r' | b'\n' => return Err("line break in quoted string".to_owned()),
+                b => out.push(b),
+            }
+        }
+        Ok(String::from_utf8_lossy(&out).into_owned())
+    }
+
+    fn literal(&mut self) -> Result<String, String> {
+        let rest = &self.input[self.pos..];
+        let end = rest
+            .iter()
+            .position(|b| *b == b'}')
+            .ok_or("unterminated literal")?;
+        let len = std::str::from_utf8(&rest[..end])
+            .ok()
+            .map(|s| s.trim_end_matches('+'))
+            .and_then(|s| s.parse::<usize>().ok())
+            .ok_or("invalid literal length")?;
+        let rest = rest[end + 1..]
+            .strip_prefix(b"\r\n")
+            .ok_or("literal without line break")?;
+        let data = rest.get(..len).ok_or("literal too short")?;
+        self.pos = self.input.len() - rest.len() + len;
+        Ok(String::from_utf8_lossy(data).into_owned())
+    }
+
+    fn atom(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        let mut in_brackets = false;
+        while let Some(b) = self.peek() {
+            match b {
+                b'[' => in_brackets = true,
+                b']' => in_brackets = false,
+                b' ' | b'(' | b')' if !in_brackets => break,
+                b'"' | b'{' if !in_brackets => break,
+                b'\r' | b'\n' => return Err("unexpected line break".to_owned()),
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        if in_brackets {
+            return Err("unterminated `[`".to_owned());
+        }
+        Ok(String::from_utf8_lossy(&self.input[start..self.pos]).into_owned())
+    }
+}
+
+/// Set of sequence numbers or UIDs, e.g. `1:3,7,9:*`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct SequenceSet(Vec<(Option<u32>, Option<u32>)>);
+
+impl SequenceSet {
+    /// Parse sequence set, `None` stands for `*`.
+    pub(crate) fn parse(s: &str) -> Result<Self, String> {
+        fn num(s: &str) -> Result<Option<u32>, String> {
+            match s {
+                "*" => Ok(None),
+                s => match s.parse() {
+                    Ok(0) | Err(_) => Err(format!("invalid sequence number `{s}`")),
+                    Ok(n) => Ok(Some(n)),
+                },
+            }
+        }
+
+        s.split(',')
+            .map(|range| match range.split_once(':') {
+                Some((a, b)) => Ok((num(a)?, num(b)?)),
+                None => {
+                    let n = num(range)?;
+                    Ok((n, n))
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+
+    /// Check if set contains `n`, where `max` is the value of `*`.
+    pub(crate) fn contains(&self, n: u32, max: u32) -> bool {
+        self.0.iter().any(|(a, b)| {
+            let a = a.unwrap_or(max);
+            let b = b.unwrap_or(max);
+            (a.min(b)..=a.max(b)).contains(&n)
+        })
+    }
+}
+
+/// Part of a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Section {
+    /// Whole message.
+    Full,
+
+    /// Header, including the empty line that ends it.
+    Header,
+
+    /// Selected header fields.
+    HeaderFields {
+        /// Upper-cased field names.
+        fields: Vec<String>,
+
+        /// Return all fields except the given ones.
+        not: bool,
+    },
+
+    /// Body.
+    Text,
+
+    /// Body of a MIME part, e.g. `[1, 2]` for `BODY[1.2]`.
+    Part(Vec<u32>),
+}
+
+/// Fetch attribute.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum FetchAttr {
+    Envelope,
+    Flags,
+    InternalDate,
+    Rfc822,
+    Rfc822Header,
+    Rfc822Size,
+    Rfc822Text,
+    Uid,
+
+    /// `BODYSTRUCTURE`, or `BODY` if not extensible. Extension data is optional and never sent.
+    BodyStructure {
+        extensible: bool,
+    },
+    Body {
+        section: Section,
+
+        /// Section as it is echoed in the response, e.g. `HEADER.FIELDS (FROM)`.
+        name: String,
+
+        /// Offset and length.
+        partial: Option<(usize, usize)>,
+    },
+}
+
+/// Parse fetch attributes, either a single one, a macro, or a list.
+pub(crate) fn parse_fetch_attrs(arg: &Arg) -> Result<Vec<FetchAttr>, String> {
+    match arg {
+        Arg::List(args) => args
+            .iter()
+            .map(|arg| match arg {
+                Arg::Atom(s) => parse_fetch_attr(s),
+                _ => Err("invalid fetch attribute".to_owned()),
+            })
+            .collect(),
+        Arg::Atom(s) => match s.to_ascii_uppercase().as_str() {
+            "ALL" => Ok(vec![
+                FetchAttr::Flags,
+                FetchAttr::InternalDate,
+                FetchAttr::Rfc822Size,
+                FetchAttr::Envelope,
+            ]),
+            "FAST" => Ok(vec![
+                FetchAttr::Flags,
+                FetchAttr::InternalDate,
+                FetchAttr::Rfc822Size,
+            ]),
+            "FULL" => Ok(vec![
+                FetchAttr::Flags,
+                FetchAttr::InternalDate,
+                FetchAttr::Rfc822Size,
+                FetchAttr::Envelope,
+                FetchAttr::BodyStructure { extensible: false },
+            ]),
+            _ => Ok(vec![parse_fetch_attr(s)?]),
+        },
+        Arg::String(_) => Err("invalid fetch attribute".to_owned()),
+    }
+}
+
+fn parse_fetch_attr(s: &str) -> Result<FetchAttr, String> {
+    let upper = s.to_ascii_uppercase();
+    let simple = match upper.as_str() {
+        "ENVELOPE" => Some(FetchAttr::Envelope),
+        "FLAGS" => Some(FetchAttr::Flags),
+        "INTERNALDATE" => Some(FetchAttr::InternalDate),
+        "RFC822" => Some(FetchAttr::Rfc822),
+        "RFC822.HEADER" => Some(FetchAttr::Rfc822Header),
+        "RFC822.SIZE" => Some(FetchAttr::Rfc822Size),
+        "RFC822.TEXT" => Some(FetchAttr::Rfc822Text),
+        "UID" => Some(FetchAttr::Uid),
+        "BODY" => Some(FetchAttr::BodyStructure { extensible: false }),
+        "BODYSTRUCTURE" => Some(FetchAttr::BodyStructure { extensible: true }),
+        _ => None,
+    };
+    if let Some(attr) = simple {
+        return Ok(attr);
+    }
+
+    let rest = upper
+        .strip_prefix("BODY.PEEK[")
+        .or_else(|| upper.strip_prefix("BODY["))
+        .ok_or_else(|| format!("unsupported fetch attribute `{s}`"))?;
+    let (name, partial) = rest
+        .split_once(']')
+        .ok_or_else(|| format!("invalid fetch attribute `{s}`"))?;
+
+    let section_args = Parser {
+        input: name.as_bytes(),
+        pos: 0,
+    }
+    .list(0)?;
+    let section = match section_args.as_slice() {
+        [] => Section::Full,
+        [Arg::Atom(a)] if a == "HEADER" => Section::Header,
+        [Arg::Atom(a)] if a == "TEXT" => Section::Text,
+        [Arg::Atom(a), Arg::List(fields)] if a == "HEADER.FIELDS" || a == "HEADER.FIELDS.NOT" => {
+            let fields = fields
+                .iter()
+                .map(|f| f.as_str().map(|f| f.to_ascii_uppercase()))
+                .collect::<Option<Vec<_>>>()
+                .ok_or("invalid header field")?;
+            Section::HeaderFields {
+                fields,
+                not: a == "HEADER.FIELDS.NOT",
+            }
+        }
+        [Arg::Atom(a)] => a
+            .split('.')
+            .map(|n| n.parse().ok().filter(|n| *n > 0))
+            .collect::<Option<Vec<_>>>()
+            .map(Section::Part)
+            .ok_or_else(|| format!("unsupported section `{name}`"))?,
+        _ => return Err(format!("unsupported section `{name}`")),
+    };
+
+    let partial = if partial.is_empty() {
+        None
+    } else {
+        let (offset, len) = partial
+            .strip_prefix('<')
+            .and_then(|p| p.strip_suffix('>'))
+            .and_then(|p| p.split_once('.'))
+            .and_then(|(offset, len)| Some((offset.parse().ok()?, len.parse().ok()?)))
+            .ok_or_else(|| format!("invalid partial `{partial}`"))?;
+        Some((offset, len))
+    };
+
+    Ok(FetchAttr::Body {
+        section,
+        name: name.to_owned(),
+        partial,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn atom(s: &str) -> Arg {
+        Arg::Atom(s.to_owned())
+    }
+
+    #[test]
+    fn test_parse_command() {
+        let (tag, rest) = split_tag(b"a1 uid fetch 1:* (FLAGS BODY.PEEK[HEADER.FIELDS (FROM TO)])");
+        assert_eq!(
+            parse_command(tag, rest).unwrap(),
+            Command {
+                tag: "a1".to_owned(),
+                name: "FETCH".to_owned(),
+                uid: true,
+                args: vec![
+                    atom("1:*"),
+                    Arg::List(vec![
+                        atom("FLAGS"),
+                        atom("BODY.PEEK[HEADER.FIELDS (FROM TO)]")
+                    ]),
+                ],
+            },
+        );
+
+        let (tag, rest) = split_tag(b"a2 LOGIN \"us\\\"er\" {4}\r\np\"ss");
+        assert_eq!(
+            parse_command(tag, rest).unwrap().args,
+            [
+                Arg::String("us\"er".to_owned()),
+                Arg::String("p\"ss".to_owned())
+            ],
+        );
+
+        let (tag, rest) = split_tag(b"a3 NOOP");
+        assert_eq!(parse_command(tag, rest).unwrap().name, "NOOP");
+
+        for line in [
+            &b"a LIST (\"\""[..],
+            b"a LIST )",
+            b"a LOGIN \"foo",
+            b"a LOGIN {5}\r\nfoo",
+            b"a",
+        ] {
+            let (tag, rest) = split_tag(line);
+            assert!(parse_command(tag, rest).is_err());
+        }
+    }
+
+    #[test]
+    fn test_sequence_set() {
+        let set = SequenceSet::parse("1:3,7,9:*").unwrap();
+        let contained = (1..=12)
+            .filter(|n| set.contains(*n, 10))
+            .collect::<Vec<_>>();
+        assert_eq!(contained, [1, 2, 3, 7, 9, 10]);
+
+        let set = SequenceSet::parse("*:4").unwrap();
+        assert!(set.contains(5, 6));
+        assert!(!set.contains(3, 6));
+
+        assert!(SequenceSet::parse("0").is_err());
+        assert!(SequenceSet::parse("1:x").is_err());
+    }
+
+    #[test]
+    fn test_parse_fetch_attrs() {
+        assert_eq!(
+            parse_fetch_attrs(&atom("fast")).unwrap(),
+            [
+                FetchAttr::Flags,
+                FetchAttr::InternalDate,
+                FetchAttr::Rfc822Size
+            ],
+        );
+        assert_eq!(
+            parse_fetch_attrs(&Arg::List(vec![
+                atom("UID"),
+                atom("BODY[]<0.100>"),
+                atom("body.peek[header.fields.not (Subject)]"),
+                atom("BODY[TEXT]"),
+                atom("BODY[2.1]"),
+                atom("BODYSTRUCTURE"),
+            ]))
+            .unwrap(),
+            [
+                FetchAttr::Uid,
+                FetchAttr::Body {
+                    section: Section::Full,
+                    name: "".to_owned(),
+                    partial: Some((0, 100)),
+                },
+                FetchAttr::Body {
+                    section: Section::HeaderFields {
+                        fields: vec!["SUBJECT".to_owned()],
+                        not: true,
+                    },
+                    name: "HEADER.FIELDS.NOT (SUBJECT)".to_owned(),
+                    partial: None,
+                },
+                FetchAttr::Body {
+                    section: Section::Text,
+                    name: "TEXT".to_owned(),
+                    partial: None,
+                },
+                FetchAttr::Body {
+                    section: Section::Part(vec![2, 1]),
+                    name: "2.1".to_owned(),
+                    partial: None,
+                },
+                FetchAttr::BodyStructure { extensible: true },
+            ],
+        );
+
+        assert!(parse_fetch_attrs(&atom("BODY[0]")).is_err());
+        assert!(parse_fetch_attrs(&atom("BODY[1.MIME]")).is_err());
+        assert!(parse_fetch_attrs(&atom("BODY[]<1>")).is_err());
+    }
+}
@@ -0,0 +1,384 @@
+//! JSON emission of decoded mails, e.g. for search indexes.
+use anyhow::{Context, Result};
+use base64::{prelude::BASE64_STANDARD, Engine};
+use serde::Serialize;
+
+use crate::{
+    eml::emit_eml,
+    mails::{Address, DownloadedMail},
+};
+
+/// JSON document of a single mail.
+#[derive(Debug, Serialize)]
+struct JsonMail<'a> {
+    id: &'a str,
+    folder_id: &'a str,
+
+    /// Receive date in RFC 3339 format.
+    date: String,
+    subject: &'a str,
+    from: &'a Address,
+    to: &'a [Address],
+    cc: &'a [Address],
+    bcc: &'a [Address],
+    unread: bool,
+    replied: bool,
+    forwarded: bool,
+
+    /// Plain text derived from the HTML body.
+    body_text: String,
+    body_html: &'a str,
+    attachments: Vec<JsonAttachment<'a>>,
+
+    /// Base64-encoded EML, only if requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    eml: Option<String>,
+}
+
+/// Attachment metadata, without the content.
+#[derive(Debug, Serialize)]
+struct JsonAttachment<'a> {
+    name: &'a str,
+    mime_type: &'a str,
+    cid: Option<&'a str>,
+    size: u64,
+
+    /// Attachment was not downloaded because it exceeds the size limit, `size` is the one reported
+    /// by the server.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    skipped: bool,
+}
+
+/// Emit mail as pretty-printed JSON document.
+///
+/// If `include_eml` is set, the full mail in EML format is added as base64 string.
+pub(crate) fn emit_json(mail: &DownloadedMail, include_eml: bool) -> Result<Vec<u8>> {
+    let body_html = String::from_utf8_lossy(&mail.body);
+    let doc = json_mail(mail, &body_html, include_eml)?;
+
+    let mut out = serde_json::to_vec_pretty(&doc).context("serialize mail")?;
+    out.push(b'\n');
+    Ok(out)
+}
+
+/// Emit mail as single line of JSON Lines, including the full mail in EML format.
+pub(crate) fn emit_json_line(mail: &DownloadedMail) -> Result<Vec<u8>> {
+    let body_html = String::from_utf8_lossy(&mail.body);
+    let doc = json_mail(mail, &body_html, true)?;
+
+    let mut out = serde_json::to_vec(&doc).context("serialize mail")?;
+    out.push(b'\n');
+    Ok(out)
+}
+
+fn json_mail<'a>(
+    mail: &'a DownloadedMail,
+    body_html: &'a str,
+    include_eml: bool,
+) -> Result<JsonMail<'a>> {
+    let eml = if include_eml {
+        Some(BASE64_STANDARD.encode(emit_eml(mail).context("emit eml")?))
+    } else {
+        None
+    };
+
+    Ok(JsonMail {
+        id: &mail.mail.mail_id,
+        folder_id: &mail.mail.folder_id,
+        date: mail.mail.date.to_rfc3339(),
+        subject: &mail.mail.subject,
+        from: &mail.mail.sender,
+        to: &mail.to,
+        cc: &mail.cc,
+        bcc: &mail.bcc,
+        unread: mail.mail.unread,
+        replied: mail.mail.replied,
+        forwarded: mail.mail.forwarded,
+        body_text: html_to_text(body_html),
+        body_html,
+        attachments: mail
+            .attachments
+            .iter()
+            .map(|a| JsonAttachment {
+                name: &a.name,
+                mime_type: &a.mime_type,
+                cid: a.cid.as_deref(),
+                size: a.data.len() as u64,
+                skipped: false,
+            })
+            .chain(mail.skipped_attachments.iter().map(|a| JsonAttachment {
+                name: &a.name,
+                mime_type: &a.mime_type,
+                cid: a.cid.as_deref(),
+                size: a.size,
+                skipped: true,
+            }))
+            .collect(),
+        eml,
+    })
+}
+
+/// Convert HTML to plain text.
+///
+/// This only drops tags, comments, scripts, and styles, turns block elements into line breaks, and
+/// decodes entities. It is meant for search and analysis, not for display.
+fn html_to_text(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut rest = html;
+
+    loop {
+        let Some(start) = rest.find('<') else {
+            push_text(&mut out, rest);
+            break;
+        };
+        push_text(&mut out, &rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(comment) = rest.strip_prefix("<!--") {
+            rest = comment
+                .find("-->")
+                .map(|end| &comment[end + 3..])
+                .unwrap_or_default();
+            continue;
+        }
+
+        let end = rest.find('>').map(|end| end + 1).unwrap_or(rest.len());
+        let tag = rest[1..end].trim_end_matches('>');
+        rest = &rest[end..];
+
+        let closing = tag.starts_with('/');
+        let name = tag
+            .trim_start_matches('/')
+            .split(|c: char| c.is_ascii_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        match name.as_str() {
+            "script" | "style" | "head" if !closing => {
+                // skip content, the closing tag is handled like any other tag
+                let end = rest
+                    .to_ascii_lowercase()
+                    .find(&format!("</{name}"))
+                    .unwrap_or(rest.len());
+                rest = &rest[end..];
+            }
+            "br" => out.push('\n'),
+            "address" | "blockquote" | "div" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "hr"
+            | "li" | "ol" | "p" | "pre" | "table" | "tr" | "ul"
+                if !out.is_empty() && !out.ends_with('\n') =>
+            {
+                out.push('\n');
+            }
+            _ => {}
+        }
+    }
+
+    // trim lines and allow at most one empty line in a row
+    let mut text = String::with_capacity(out.len());
+    let mut empty_lines = 0;
+    for line in out.lines().map(|l| l.trim_matches(' ')) {
+        if line.is_empty() {
+            empty_lines += 1;
+            if empty_lines > 1 {
+                continue;
+            }
+        } else {
+            empty_lines = 0;
+        }
+        text.push_str(line);
+        text.push('\n');
+    }
+    text.trim().to_owned()
+}
+
+/// Push text content, collapsing whitespace like a browser would.
+fn push_text(out: &mut String, text: &str) {
+    for c in decode_entities(text).chars() {
+        if c.is_ascii_whitespace() {
+            if !out.is_empty() && !out.ends_with([' ', '\n']) {
+                out.push(' ');
+            }
+        } else {
+            out.push(c);
+        }
+    }
+}
+
+/// Decode named entities that are common in mails and all numeric ones.
+fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest.find(';').filter(|end| *end <= 10).and_then(|end| {
+            let c = match &rest[1..end] {
+                "amp" => '&',
+                "apos" => '\'',
+                "gt" => '>',
+                "lt" => '<',
+                "nbsp" => '\u{a0}',
+                "quot" => '"',
+                entity => {
+                    let num = entity.strip_prefix('#')?;
+                    let num = match num.strip_prefix(['x', 'X']) {
+                        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                        None => num.parse().ok()?,
+                    };
+                    char::from_u32(num)?
+                }
+            };
+            Some((c, end + 1))
+        });
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use chrono::DateTime;
+
+    use crate::{
+        mails::{Attachment, Mail, SkippedAttachment},
+        proto::keys::Key,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_html_to_text() {
+        assert_eq!(
+            html_to_text(
+                "<html><head><title>x</title><style>p { color: red; }</style></head>\
+                <body><!-- comment --><p>Hello&nbsp;<b>World</b>,</p>\n\n<p>a &amp;\n  b&#33; \
+                &#x3C;c&gt; &unknown; &</p><div><br></div><div>bye<br/>me</div>\
+                <SCRIPT>alert(1)</SCRIPT></body></html>"
+            ),
+            "Hello\u{a0}World,\na & b! <c> &unknown; &\n\nbye\nme",
+        );
+        assert_eq!(html_to_text("plain text"), "plain text");
+        assert_eq!(html_to_text("unterminated <b"), "unterminated");
+    }
+
+    #[test]
+    fn test_emit_json() {
+        let mail = DownloadedMail {
+            mail: Arc::new(Mail {
+                folder_id: "folder_id".to_owned(),
+                mail_id: "mail_id".to_owned(),
+                archive_id: "archive_id".to_owned(),
+                blob_id: "blob_id".to_owned(),
+                is_draft: false,
+                session_key: Key::Aes256([0; 32]),
+                date: DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc(),
+                subject: "Hällö".to_owned(),
+                sender: Address {
+                    mail: "foo@example.com".to_owned(),
+                    name: "Me".to_owned(),
+                },
+                attachments: vec![],
+                conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+                unread: true,
+                replied: false,
+                forwarded: true,
+            }),
+            headers: None,
+            body: b"<p>hello</p><p>world</p>".to_vec(),
+            attachments: vec![Attachment {
+                cid: None,
+                mime_type: "text/plain".to_owned(),
+                name: "notes.txt".to_owned(),
+                data: b"notes".to_vec(),
+            }],
+            bcc: vec![],
+            cc: vec![],
+            to: vec![Address {
+                mail: "bar@example.com".to_owned(),
+                name: "".to_owned(),
+            }],
+            message_id: None,
+            references: vec![],
+            skipped_attachments: vec![SkippedAttachment {
+                cid: None,
+                mime_type: "video/mp4".to_owned(),
+                name: "vacation.mp4".to_owned(),
+                size: 123_456_789,
+            }],
+        };
+
+        let json = String::from_utf8(emit_json(&mail, false).unwrap()).unwrap();
+        insta::assert_snapshot!(json, @r###"
+        {
+          "id": "mail_id",
+          "folder_id": "folder_id",
+          "date": "2020-03-04T11:22:33+00:00",
+          "subject": "Hällö",
+          "from": {
+            "mail": "foo@example.com",
+            "name": "Me"
+          },
+          "to": [
+            {
+              "mail": "bar@example.com",
+              "name": ""
+            }
+          ],
+          "cc": [],
+          "bcc": [],
+          "unread": true,
+          "replied": false,
+          "forwarded": true,
+          "body_text": "hello\nworld",
+          "body_html": "<p>hello</p><p>world</p>",
+          "attachments": [
+            {
+              "name": "notes.txt",
+              "mime_type": "text/plain",
+              "cid": null,
+              "size": 5
+            },
+            {
+              "name": "vacation.mp4",
+              "mime_type": "video/mp4",
+              "cid": null,
+              "size": 123456789,
+              "skipped": true
+            }
+          ]
+        }
+        "###);
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&emit_json(&mail, true).unwrap()).unwrap();
+        let eml = BASE64_STANDARD
+            .decode(json["eml"].as_str().unwrap())
+            .unwrap();
+        assert_eq!(String::from_utf8(eml).unwrap(), emit_eml(&mail).unwrap());
+
+        let line = emit_json_line(&mail).unwrap();
+        assert_eq!(line.iter().filter(|b| **b == b'\n').count(), 1);
+        assert!(line.ends_with(b"\n"));
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&line).unwrap(),
+            json
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! Library for [Tutanota/Tuta](https://tuta.com), mostly meant for mass export.
+//!
+//! The `tatutanatata` binary is a thin wrapper around [`cli::run`].
+//!
+//! # Example
+//! ```no_run
+//! # async fn example(client_cfg: tatutanatata::ClientCLIConfig, login_cfg: tatutanatata::LoginCLIConfig) -> anyhow::Result<()> {
+//! use futures::TryStreamExt;
+//! use tatutanatata::{emit_eml, Client, Folder, Mail, Session};
+//!
+//! let client = Client::try_new(client_cfg, None).await?;
+//! let session = Session::login(login_cfg, &client).await?;
+//!
+//! let folders = Folder::list(&client, &session)
+//!     .await?
+//!     .try_collect::<Vec<_>>()
+//!     .await?;
+//! for folder in &folders {
+//!     let mails = Mail::list(&client, &session, folder, None, false)
+//!         .try_collect::<Vec<_>>()
+//!         .await?;
+//!     for mail in mails {
+//!         let mail = mail.download(&client, &session).await?;
+//!         println!("{}", emit_eml(&mail)?);
+//!     }
+//! }
+//!
+//! session.logout(&client).await?;
+//! # Ok(())
+//! # }
+//! ```
+pub use crate::{
+    client::{Client, ClientCLIConfig},
+    compression::{read_tar_gz, write_tar_gz},
+    eml::{emit_eml, write_eml},
+    folders::{Folder, SystemFolder},
+    mails::{Address, Attachment, DownloadedMail, Mail, SkippedAttachment},
+    non_empty_string::NonEmptyString,
+    session::{Kdf, LoginCLIConfig, Session},
+};
+
+// Workaround for "unused crate" lint false positives.
+#[cfg(test)]
+use assert_cmd as _;
+#[cfg(test)]
+use similar_asserts as _;
+#[cfg(test)]
+use tempfile as _;
+
+pub mod cli;
+
+mod accounts;
+mod blob;
+mod calendar;
+mod client;
+mod completions;
+mod compression;
+mod constants;
+mod contacts;
+mod content_lines;
+mod count;
+mod crypto;
+mod download;
+mod eml;
+mod exit_code;
+mod file_output;
+mod filename_template;
+mod filter;
+mod folders;
+mod imap;
+mod json;
+mod logging;
+mod mails;
+mod manifest;
+mod manpage;
+mod non_empty_string;
+mod progress;
+mod proto;
+mod raw;
+mod resume;
+mod retry;
+mod session;
+mod signal;
+mod throttle;
+mod verify;
+mod whoami;
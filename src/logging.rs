@@ -2,31 +2,65 @@
 use std::io::IsTerminal;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use tracing_log::LogTracer;
 use tracing_subscriber::{EnvFilter, FmtSubscriber};
 
+use crate::progress::ProgressAwareStderr;
+
+/// Target of the request/response logs that are enabled by `--verbose-http`.
+pub(crate) const HTTP_LOG_TARGET: &str = concat!(env!("CARGO_PKG_NAME"), "::http");
+
 /// Logging CLI config.
 #[derive(Debug, Parser)]
 pub(crate) struct LoggingCLIConfig {
     /// Log filter.
     ///
-    /// Conflicts with `-v`/`--verbose`.
-    #[clap(conflicts_with = "log_verbose_count", long, action)]
+    /// Conflicts with `-v`/`--verbose` and `-q`/`--quiet`.
+    #[clap(conflicts_with_all = ["log_verbose_count", "quiet"], long, action)]
     log_filter: Option<String>,
 
     /// Verbose logs.
     ///
     /// Repeat to increase verbosity.
     ///
-    /// Conflicts with `--log-filter`.
+    /// Conflicts with `--log-filter` and `-q`/`--quiet`.
     #[clap(
         short = 'v',
         long = "verbose",
-        conflicts_with="log_filter",
+        conflicts_with_all = ["log_filter", "quiet"],
         action = clap::ArgAction::Count,
     )]
     log_verbose_count: u8,
+
+    /// Only log errors.
+    ///
+    /// Warnings like retried requests are hidden. Output that is not a log, e.g. summaries, is
+    /// still printed.
+    ///
+    /// Conflicts with `--log-filter` and `-v`/`--verbose`.
+    #[clap(short = 'q', long, action)]
+    quiet: bool,
+
+    /// Log format.
+    #[clap(long, value_enum, default_value_t = LogFormat::Pretty)]
+    log_format: LogFormat,
+
+    /// Log URL, method, status, timing, and response size of every HTTP request.
+    ///
+    /// Access tokens and bodies are never logged. This works with any other log setting.
+    #[clap(long, action)]
+    verbose_http: bool,
+}
+
+/// Log output format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// Human-readable text.
+    Pretty,
+
+    /// One JSON object per line, event fields are top-level keys.
+    Json,
 }
 
 /// Setup process-wide logging.
@@ -35,6 +69,7 @@ pub(crate) fn setup_logging(config: LoggingCLIConfig) -> Result<()> {
 
     let filter = match config.log_filter {
         Some(filter) => filter,
+        None if config.quiet => "error".to_owned(),
         None => match config.log_verbose_count {
             0 => "warn".to_owned(),
             1 => "info".to_owned(),
@@ -43,16 +78,29 @@ pub(crate) fn setup_logging(config: LoggingCLIConfig) -> Result<()> {
             _ => "trace".to_owned(),
         },
     };
-    let filter = EnvFilter::try_new(filter)?;
+    let mut filter = EnvFilter::try_new(filter)?;
+    if config.verbose_http {
+        filter = filter.add_directive(format!("{HTTP_LOG_TARGET}=debug").parse()?);
+    }
 
-    let writer = std::io::stderr;
-    let subscriber = FmtSubscriber::builder()
-        .with_ansi(writer().is_terminal())
+    let builder = FmtSubscriber::builder()
         .with_env_filter(filter)
-        .with_writer(writer)
-        .finish();
+        .with_writer(ProgressAwareStderr::default);
 
-    tracing::subscriber::set_global_default(subscriber)?;
+    match config.log_format {
+        LogFormat::Pretty => {
+            let subscriber = builder.with_ansi(std::io::stderr().is_terminal()).finish();
+            tracing::subscriber::set_global_default(subscriber)?;
+        }
+        LogFormat::Json => {
+            let subscriber = builder
+                .json()
+                .flatten_event(true)
+                .with_current_span(false)
+                .finish();
+            tracing::subscriber::set_global_default(subscriber)?;
+        }
+    }
 
     Ok(())
 }
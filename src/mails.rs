@@ -3,26 +3,31 @@ use std::sync::Arc;
 use anyhow::{bail, ensure, Context, Result};
 use chrono::{DateTime, Utc};
 use futures::{Stream, TryStreamExt};
-use reqwest::Method;
-use tracing::warn;
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
 
 use crate::{
     blob::{get_attachment_blob, get_mail_blob, get_mail_draft_blob},
-    client::{Client, Prefix, Request, DEFAULT_HOST},
+    client::{error_status, Client, Prefix, Request},
     compression::decompress_value,
     crypto::encryption::{decrypt_key, decrypt_value},
     folders::Folder,
     proto::{
+        date::UnixDate,
+        enums::MailReplyType,
+        ids::generated_id_to_timestamp,
         keys::Key,
-        messages::{FileReponse, MailAddress, MailReponse},
+        messages::{ConversationEntryResponse, FileReponse, MailAddress, MailReponse},
     },
     session::{GroupKeys, Session},
 };
 
-#[derive(Debug)]
-pub(crate) struct Address {
-    pub(crate) mail: String,
-    pub(crate) name: String,
+/// Mail address with display name.
+#[derive(Debug, Serialize)]
+pub struct Address {
+    pub mail: String,
+    pub name: String,
 }
 
 impl Address {
@@ -37,33 +42,53 @@ impl Address {
     }
 }
 
+/// Mail metadata, see [`Mail::download`] for the content.
 #[derive(Debug)]
-pub(crate) struct Mail {
+pub struct Mail {
     #[allow(dead_code)]
-    pub(crate) folder_id: String,
-    pub(crate) mail_id: String,
+    pub folder_id: String,
+    pub mail_id: String,
     pub(crate) archive_id: String,
     pub(crate) blob_id: String,
     pub(crate) is_draft: bool,
     pub(crate) session_key: Key,
-    pub(crate) date: DateTime<Utc>,
-    pub(crate) subject: String,
-    pub(crate) sender: Address,
+    pub date: DateTime<Utc>,
+    pub subject: String,
+    pub sender: Address,
     pub(crate) attachments: Vec<[String; 2]>,
+    pub(crate) conversation_entry: [String; 2],
+
+    /// Mail was not opened yet.
+    pub unread: bool,
+
+    /// Mail was replied to.
+    pub replied: bool,
+
+    /// Mail was forwarded.
+    pub forwarded: bool,
 }
 
 impl Mail {
-    pub(crate) fn list(
+    /// List mails of given folder.
+    ///
+    /// If `start` is given, only mails after this mail ID are returned. If `reverse` is set, the
+    /// newest mails are returned first.
+    pub fn list(
         client: &Client,
         session: &Session,
         folder: &Folder,
+        start: Option<&str>,
+        reverse: bool,
     ) -> impl Stream<Item = Result<Arc<Self>>> {
         let group_keys = Arc::clone(&session.group_keys);
         let folder_id = folder.id.clone();
         client
             .stream::<MailReponse>(
+                Prefix::Tutanota,
                 &format!("mail/{}", folder.mails),
                 Some(&session.access_token),
+                start,
+                reverse,
             )
             .and_then(move |m| {
                 let group_keys = Arc::clone(&group_keys);
@@ -75,10 +100,35 @@ impl Mail {
             })
     }
 
+    /// Get single mail by the ID of its mail list and its own ID.
+    ///
+    /// The folder is not known in this case, so [`folder_id`](Self::folder_id) is set to the list
+    /// ID.
+    pub async fn get(
+        client: &Client,
+        session: &Session,
+        list_id: &str,
+        mail_id: &str,
+    ) -> Result<Self> {
+        let resp: MailReponse = match client
+            .get_entity(Some(list_id), mail_id, Some(&session.access_token))
+            .await
+        {
+            Ok(resp) => resp,
+            Err(e) if error_status(&e) == Some(StatusCode::NOT_FOUND) => {
+                bail!("mail not found: `{list_id}/{mail_id}`");
+            }
+            Err(e) => {
+                return Err(e).context("get mail");
+            }
+        };
+        Self::decode(resp, &session.group_keys, list_id.to_owned())
+    }
+
     fn decode(resp: MailReponse, group_keys: &GroupKeys, folder_id: String) -> Result<Self> {
         let session_key = decrypt_key(
             group_keys
-                .get(&resp.owner_group)
+                .get(&resp.owner_group, resp.owner_key_version)
                 .context("getting owner group key")?,
             resp.owner_enc_session_key,
         )
@@ -100,6 +150,21 @@ impl Mail {
             }
         };
 
+        let date = mail_date(resp.received_date, &resp.id[1])?;
+
+        // only used for flags, so a broken value should not prevent the download
+        let reply_type = match decode_reply_type(session_key, resp.reply_type.as_deref()) {
+            Ok(reply_type) => reply_type,
+            Err(e) => {
+                warn!(
+                    mail_id = resp.id[1].as_str(),
+                    error = format!("{e:#}"),
+                    "cannot decode reply type"
+                );
+                MailReplyType::None
+            }
+        };
+
         Ok(Self {
             folder_id,
             mail_id: resp.id[1].clone(),
@@ -107,21 +172,69 @@ impl Mail {
             blob_id,
             is_draft,
             session_key,
-            date: resp.received_date.0,
+            date,
             subject,
             sender,
             attachments: resp.attachments,
+            conversation_entry: resp.conversation_entry,
+            unread: resp.unread.0,
+            replied: matches!(
+                reply_type,
+                MailReplyType::Reply | MailReplyType::ReplyForward
+            ),
+            forwarded: matches!(
+                reply_type,
+                MailReplyType::Forward | MailReplyType::ReplyForward
+            ),
         })
     }
 
-    pub(crate) fn ui_url(&self) -> String {
-        format!("{}/mail/{}/{}", DEFAULT_HOST, self.folder_id, self.mail_id)
+    /// [Maildir](https://cr.yp.to/proto/maildir.html) flags, in ASCII order.
+    pub(crate) fn maildir_flags(&self) -> String {
+        let mut flags = String::new();
+        if self.is_draft {
+            flags.push('D');
+        }
+        if self.forwarded {
+            flags.push('P');
+        }
+        if self.replied {
+            flags.push('R');
+        }
+        if !self.unread {
+            flags.push('S');
+        }
+        flags
+    }
+
+    /// URL of the mail in the web client.
+    pub fn ui_url(&self, client: &Client) -> String {
+        format!(
+            "{}/mail/{}/{}",
+            client.base_url(),
+            self.folder_id,
+            self.mail_id
+        )
+    }
+
+    /// Download and decrypt mail content, including attachments.
+    pub async fn download(
+        self: Arc<Self>,
+        client: &Client,
+        session: &Session,
+    ) -> Result<DownloadedMail> {
+        self.download_with_limit(client, session, None).await
     }
 
-    pub(crate) async fn download(
+    /// Same as [`download`](Self::download), but attachments that are larger than
+    /// `max_attachment_size` bytes are not downloaded.
+    ///
+    /// They are listed in [`DownloadedMail::skipped_attachments`] instead.
+    pub async fn download_with_limit(
         self: Arc<Self>,
         client: &Client,
         session: &Session,
+        max_attachment_size: Option<u64>,
     ) -> Result<DownloadedMail> {
         let mail_details = if self.is_draft {
             get_mail_draft_blob(client, session, &self.archive_id, &self.blob_id)
@@ -135,12 +248,18 @@ impl Mail {
                 .details
         };
 
-        let body = decrypt_and_decompress(
-            self.session_key,
-            mail_details.body.text.as_deref(),
-            mail_details.body.compressed_text.as_deref(),
-        )
-        .context("decode body")?;
+        let session_key = self.session_key;
+        let body = mail_details.body;
+        let body = client
+            .spawn_cpu(move || {
+                decrypt_and_decompress(
+                    session_key,
+                    body.text.as_deref(),
+                    body.compressed_text.as_deref(),
+                )
+            })
+            .await
+            .context("decode body")?;
 
         let headers = if let Some(headers) = mail_details.headers {
             let headers = decrypt_and_decompress(
@@ -156,6 +275,15 @@ impl Mail {
             None
         };
 
+        // original headers already carry the thread IDs
+        let (message_id, references) = if headers.is_none() {
+            self.get_thread_ids(client, session)
+                .await
+                .context("get thread IDs")?
+        } else {
+            (None, vec![])
+        };
+
         let bcc = mail_details
             .recipients
             .bcc_recipients
@@ -178,42 +306,23 @@ impl Mail {
             .collect::<Result<Vec<_>>>()
             .context("decode To")?;
 
-        let mut attachments = vec![];
-        if !self.attachments.is_empty() {
-            let group = &self.attachments[0][0];
-            if self.attachments.iter().any(|[g_id, _id]| g_id != group) {
-                bail!("inconsistent attachement group IDs")
-            }
-            let ids = self
-                .attachments
-                .iter()
-                .map(|[_g_id, id]| id.as_str())
-                .collect::<Vec<_>>();
-            let files: Vec<FileReponse> = client
-                .do_json(Request {
-                    method: Method::GET,
-                    host: DEFAULT_HOST,
-                    prefix: Prefix::Tutanota,
-                    path: &format!("file/{group}"),
-                    data: &(),
-                    access_token: Some(&session.access_token),
-                    query: &[("ids", &ids.join(","))],
-                })
+        let files = self.get_files(client, session).await?;
+        let mut attachments = Vec::with_capacity(files.len());
+        let mut skipped_attachments = vec![];
+        for (idx, ([group, id], file)) in self.attachments.iter().zip(files).enumerate() {
+            match Self::download_file(client, session, group, file, id, max_attachment_size)
                 .await
-                .context("get file infos")?;
-
-            ensure!(
-                ids.len() == files.len(),
-                "attachment IDs and files match, but got {} IDs and {} files",
-                ids.len(),
-                files.len(),
-            );
-            for (idx, (id, file)) in ids.into_iter().zip(files).enumerate() {
-                attachments.push(
-                    Self::download_file(client, session, group, file, id)
-                        .await
-                        .with_context(|| format!("download file #{}", idx + 1))?,
-                );
+                .with_context(|| format!("download file #{}", idx + 1))?
+            {
+                FileDownload::Attachment(attachment) => attachments.push(attachment),
+                FileDownload::Skipped(skipped) => {
+                    info!(
+                        mail_id = self.mail_id.as_str(),
+                        size = skipped.size,
+                        "attachment exceeds size limit, skipped",
+                    );
+                    skipped_attachments.push(skipped);
+                }
             }
         }
 
@@ -222,23 +331,108 @@ impl Mail {
             headers,
             body,
             attachments,
+            skipped_attachments,
             bcc,
             cc,
             to,
+            message_id,
+            references,
         })
     }
 
+    /// Get `Message-ID` of this mail and of the earlier mails in its thread from the conversation
+    /// entries.
+    ///
+    /// The thread is followed through the `previous` entries for at most [`MAX_REFERENCES`] mails,
+    /// the returned references are ordered oldest first. Entries may be gone if the thread was
+    /// partially deleted, the thread ends there then.
+    async fn get_thread_ids(
+        &self,
+        client: &Client,
+        session: &Session,
+    ) -> Result<(Option<String>, Vec<String>)> {
+        let Some(entry) = get_conversation_entry(client, session, &self.conversation_entry)
+            .await
+            .context("get conversation entry")?
+        else {
+            return Ok((None, vec![]));
+        };
+
+        let mut references = vec![];
+        let mut previous = entry.previous;
+        while let Some(id) = previous {
+            if references.len() >= MAX_REFERENCES {
+                break;
+            }
+            let Some(entry) = get_conversation_entry(client, session, &id)
+                .await
+                .context("get previous conversation entry")?
+            else {
+                break;
+            };
+            references.push(entry.message_id);
+            previous = entry.previous;
+        }
+        references.reverse();
+
+        Ok((Some(entry.message_id), references))
+    }
+
+    /// Total size of all attachments in bytes.
+    pub(crate) async fn attachments_size(&self, client: &Client, session: &Session) -> Result<u64> {
+        let files = self.get_files(client, session).await?;
+        Ok(files.iter().map(|f| f.size.0).sum())
+    }
+
+    /// Get attachment metadata, in the same order as [`attachments`](Self::attachments).
+    async fn get_files(&self, client: &Client, session: &Session) -> Result<Vec<FileReponse>> {
+        let Some([group, _id]) = self.attachments.first() else {
+            return Ok(vec![]);
+        };
+        if self.attachments.iter().any(|[g_id, _id]| g_id != group) {
+            bail!("inconsistent attachement group IDs")
+        }
+        let ids = self
+            .attachments
+            .iter()
+            .map(|[_g_id, id]| id.as_str())
+            .collect::<Vec<_>>();
+        let files: Vec<FileReponse> = client
+            .do_json(Request {
+                method: Method::GET,
+                host: None,
+                prefix: Prefix::Tutanota,
+                path: &format!("file/{group}"),
+                data: &(),
+                access_token: Some(&session.access_token),
+                query: &[("ids", &ids.join(","))],
+            })
+            .await
+            .context("get file infos")?;
+
+        ensure!(
+            ids.len() == files.len(),
+            "attachment IDs and files match, but got {} IDs and {} files",
+            ids.len(),
+            files.len(),
+        );
+
+        Ok(files)
+    }
+
+    /// Download and decrypt file, unless it is larger than `max_size` bytes.
     async fn download_file(
         client: &Client,
         session: &Session,
         group: &str,
         file: FileReponse,
         id: &str,
-    ) -> Result<Attachment> {
+        max_size: Option<u64>,
+    ) -> Result<FileDownload> {
         let session_key = decrypt_key(
             session
                 .group_keys
-                .get(&file.owner_group)
+                .get(&file.owner_group, file.owner_key_version)
                 .context("getting file owner group key")?,
             file.owner_enc_session_key,
         )
@@ -259,6 +453,15 @@ impl Mail {
         let name = decrypt_value(session_key, file.name.as_ref()).context("decrypt file name")?;
         let name = String::from_utf8(name).context("decode name")?;
 
+        if max_size.is_some_and(|max| file.size.0 > max) {
+            return Ok(FileDownload::Skipped(SkippedAttachment {
+                cid,
+                mime_type,
+                name,
+                size: file.size.0,
+            }));
+        }
+
         let mut data_all = Vec::with_capacity(file.size.0 as usize);
         let mut encrypted_size_sum = 0;
         for blob in file.blobs {
@@ -273,7 +476,10 @@ impl Mail {
                 data.len(),
             );
             encrypted_size_sum += data.len();
-            let mut data = decrypt_value(session_key, &data).context("decrypt attachment data")?;
+            let mut data = client
+                .spawn_cpu(move || decrypt_value(session_key, &data))
+                .await
+                .context("decrypt attachment data")?;
             data_all.append(&mut data);
         }
         if encrypted_size_sum != file.size.0 as usize {
@@ -285,12 +491,39 @@ impl Mail {
             );
         }
 
-        Ok(Attachment {
+        Ok(FileDownload::Attachment(Attachment {
             cid,
             mime_type,
             name,
             data: data_all,
-        })
+        }))
+    }
+}
+
+/// Result of [`Mail::download_file`].
+enum FileDownload {
+    Attachment(Attachment),
+    Skipped(SkippedAttachment),
+}
+
+/// Maximum number of earlier mails in the `References` header, each one costs a request.
+const MAX_REFERENCES: usize = 20;
+
+async fn get_conversation_entry(
+    client: &Client,
+    session: &Session,
+    [list_id, id]: &[String; 2],
+) -> Result<Option<ConversationEntryResponse>> {
+    match client
+        .get_entity(Some(list_id), id, Some(&session.access_token))
+        .await
+    {
+        Ok(entry) => Ok(Some(entry)),
+        Err(e) if error_status(&e) == Some(StatusCode::NOT_FOUND) => {
+            warn!(list_id, id, "conversation entry not found");
+            Ok(None)
+        }
+        Err(e) => Err(e),
     }
 }
 
@@ -315,21 +548,245 @@ fn decrypt_and_decompress(
     }
 }
 
+/// Mail with its content.
 #[derive(Debug)]
-pub(crate) struct DownloadedMail {
-    pub(crate) mail: Arc<Mail>,
-    pub(crate) headers: Option<String>,
-    pub(crate) body: Vec<u8>,
-    pub(crate) attachments: Vec<Attachment>,
-    pub(crate) bcc: Vec<Address>,
-    pub(crate) cc: Vec<Address>,
-    pub(crate) to: Vec<Address>,
+pub struct DownloadedMail {
+    pub mail: Arc<Mail>,
+    pub headers: Option<String>,
+    pub body: Vec<u8>,
+    pub attachments: Vec<Attachment>,
+
+    /// Attachments that exceeded the size limit of [`Mail::download_with_limit`].
+    pub skipped_attachments: Vec<SkippedAttachment>,
+
+    pub bcc: Vec<Address>,
+    pub cc: Vec<Address>,
+    pub to: Vec<Address>,
+
+    /// `Message-ID` stored by Tuta, only fetched if [`headers`](Self::headers) are missing.
+    pub message_id: Option<String>,
+
+    /// `Message-ID`s of the earlier mails in the thread, oldest first, so the last one is the mail
+    /// this one replies to. Only fetched if [`headers`](Self::headers) are missing.
+    pub references: Vec<String>,
 }
 
+/// Decrypted attachment.
 #[derive(Debug)]
-pub(crate) struct Attachment {
-    pub(crate) cid: Option<String>,
-    pub(crate) mime_type: String,
-    pub(crate) name: String,
-    pub(crate) data: Vec<u8>,
+pub struct Attachment {
+    pub cid: Option<String>,
+    pub mime_type: String,
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// Attachment that was not downloaded because it exceeded the size limit.
+#[derive(Debug)]
+pub struct SkippedAttachment {
+    pub cid: Option<String>,
+    pub mime_type: String,
+    pub name: String,
+
+    /// Size in bytes, as reported by the server.
+    pub size: u64,
+}
+
+/// Date of a mail.
+///
+/// Drafts and some outgoing mails have no received date, so the creation time that is encoded in
+/// the mail ID is used instead.
+fn mail_date(received_date: Option<UnixDate>, mail_id: &str) -> Result<DateTime<Utc>> {
+    match received_date {
+        Some(date) => Ok(date.0),
+        None => generated_id_to_timestamp(mail_id)
+            .with_context(|| format!("mail has no date: `{mail_id}`")),
+    }
+}
+
+/// Decrypt the reply type of a mail.
+///
+/// A missing or empty value is the default, i.e. [`MailReplyType::None`].
+fn decode_reply_type(session_key: Key, value: Option<&[u8]>) -> Result<MailReplyType> {
+    let value = decrypt_value(session_key, value.unwrap_or_default()).context("decrypt")?;
+    if value.is_empty() {
+        return Ok(MailReplyType::None);
+    }
+
+    let value = String::from_utf8(value).context("decode string")?;
+    MailReplyType::deserialize(serde::de::value::StringDeserializer::<
+        serde::de::value::Error,
+    >::new(value))
+    .context("parse")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{crypto::encryption::encrypt, proto::messages::MailDetailsBlob};
+
+    use super::*;
+
+    #[test]
+    fn test_decrypt_and_decompress_body() {
+        let key = Key::Aes256([1; 32]);
+        let body = b"<div>Hello <b>World</b></div>".repeat(10);
+
+        let plain = encrypt(key, [2; 16], &body, true, true);
+        assert_eq!(
+            decrypt_and_decompress(key, Some(&plain), None).unwrap(),
+            body
+        );
+
+        let compressed = encrypt(key, [2; 16], &lz4_flex::block::compress(&body), true, true);
+        assert_eq!(
+            decrypt_and_decompress(key, None, Some(&compressed)).unwrap(),
+            body
+        );
+
+        // uncompressed variant takes precedence
+        assert_eq!(
+            decrypt_and_decompress(key, Some(&plain), Some(b"garbage")).unwrap(),
+            body
+        );
+
+        assert_eq!(
+            decrypt_and_decompress(key, None, None)
+                .unwrap_err()
+                .to_string(),
+            "neither compressed or uncompressed data available",
+        );
+    }
+
+    #[test]
+    fn test_decode_reply_type() {
+        let key = Key::Aes256([1; 32]);
+
+        assert_eq!(decode_reply_type(key, None).unwrap(), MailReplyType::None);
+        assert_eq!(
+            decode_reply_type(key, Some(&[])).unwrap(),
+            MailReplyType::None
+        );
+
+        let forward = encrypt(key, [2; 16], b"3", true, true);
+        assert_eq!(
+            decode_reply_type(key, Some(&forward)).unwrap(),
+            MailReplyType::ReplyForward,
+        );
+
+        let unknown = encrypt(key, [2; 16], b"20", true, true);
+        assert_eq!(
+            format!("{:#}", decode_reply_type(key, Some(&unknown)).unwrap_err()),
+            "parse: unknown variant: 20",
+        );
+    }
+
+    #[test]
+    fn test_mail_date() {
+        let received = DateTime::from_timestamp_millis(1707932070000).unwrap();
+        assert_eq!(
+            mail_date(Some(UnixDate(received)), "Nqcfn0k----0").unwrap(),
+            received,
+        );
+        assert_eq!(
+            mail_date(None, "M1_H61c-----").unwrap(),
+            DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z").unwrap(),
+        );
+        assert_eq!(
+            mail_date(None, "custom").unwrap_err().to_string(),
+            "mail has no date: `custom`",
+        );
+    }
+
+    #[test]
+    fn test_sent_mail_fixture() {
+        let resp: MailReponse = serde_json::from_str(
+            r#"{
+                "_format": "0",
+                "_id": ["mail_list", "M1_H61c-----"],
+                "_ownerEncSessionKey": "AAAAAAAAAAAAAAAAAAAAAA==",
+                "_ownerGroup": "group",
+                "mailDetails": null,
+                "mailDetailsDraft": ["archive", "blob"],
+                "subject": "AAAA",
+                "sender": {"address": "me@example.com", "name": "AAAA"},
+                "attachments": [],
+                "unread": "0",
+                "replyType": "",
+                "conversationEntry": ["entry_list", "entry"]
+            }"#,
+        )
+        .unwrap();
+        assert!(resp.received_date.is_none());
+        assert_eq!(
+            mail_date(resp.received_date, &resp.id[1]).unwrap(),
+            DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z").unwrap(),
+        );
+
+        let details: MailDetailsBlob = serde_json::from_str(
+            r#"{
+                "_format": "0",
+                "details": {
+                    "body": {"text": null, "compressedText": "AAAA"},
+                    "headers": null,
+                    "recipients": {
+                        "toRecipients": [
+                            {"address": "to1@example.com", "name": "AAAA"},
+                            {"address": "to2@example.com", "name": "AAAA"}
+                        ],
+                        "ccRecipients": [{"address": "cc@example.com", "name": "AAAA"}],
+                        "bccRecipients": [{"address": "bcc@example.com", "name": "AAAA"}]
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        let recipients = details.details.recipients;
+        assert_eq!(recipients.to_recipients.len(), 2);
+        assert_eq!(recipients.cc_recipients[0].address, "cc@example.com");
+        assert_eq!(recipients.bcc_recipients[0].address, "bcc@example.com");
+
+        // drafts that were never addressed
+        let details: MailDetailsBlob = serde_json::from_str(
+            r#"{
+                "_format": "0",
+                "details": {
+                    "body": {"text": "AAAA"},
+                    "recipients": {}
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(details.details.recipients.to_recipients.is_empty());
+    }
+
+    #[test]
+    fn test_maildir_flags() {
+        let mut mail = Mail {
+            folder_id: "folder_id".to_owned(),
+            mail_id: "mail_id".to_owned(),
+            archive_id: "archive_id".to_owned(),
+            blob_id: "blob_id".to_owned(),
+            is_draft: false,
+            session_key: Key::Aes256([0; 32]),
+            date: DateTime::from_timestamp(0, 0).unwrap(),
+            subject: "subject".to_owned(),
+            sender: Address {
+                mail: "foo@example.com".to_owned(),
+                name: "Me".to_owned(),
+            },
+            attachments: vec![],
+            conversation_entry: ["list_id".to_owned(), "entry_id".to_owned()],
+            unread: true,
+            replied: false,
+            forwarded: false,
+        };
+        assert_eq!(mail.maildir_flags(), "");
+
+        mail.unread = false;
+        mail.replied = true;
+        mail.forwarded = true;
+        assert_eq!(mail.maildir_flags(), "PRS");
+
+        mail.is_draft = true;
+        assert_eq!(mail.maildir_flags(), "DPRS");
+    }
 }
@@ -0,0 +1,178 @@
+//! Per-folder index of downloaded mails, see `--manifest`.
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{file_output::write_to_file, folders::Folder, mails::Mail};
+
+/// Name of the manifest within the output directory.
+const MANIFEST_FILE_NAME: &str = ".tatutanatata-manifest.json";
+
+/// Mail within the manifest.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ManifestEntry {
+    pub(crate) id: String,
+
+    /// Receive date in RFC 3339 format.
+    pub(crate) date: String,
+    pub(crate) subject: String,
+    pub(crate) from: String,
+
+    /// Output file, relative to the output directory.
+    ///
+    /// For mbox output, this is the mbox file that contains the mail.
+    pub(crate) file: PathBuf,
+
+    /// Size of the mail in bytes, without extracted attachments.
+    ///
+    /// Unknown for mails that were already stored in an mbox file by earlier runs without
+    /// `--manifest`.
+    pub(crate) size: Option<u64>,
+}
+
+impl ManifestEntry {
+    pub(crate) fn new(mail: &Mail, file: PathBuf, size: Option<u64>) -> Self {
+        Self {
+            id: mail.mail_id.clone(),
+            date: mail.date.to_rfc3339(),
+            subject: mail.subject.clone(),
+            from: mail.sender.mail.clone(),
+            file,
+            size,
+        }
+    }
+}
+
+/// Index of all mails of a folder within the output directory.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Manifest {
+    folder_id: String,
+    folder_name: String,
+
+    /// Sorted by mail ID, i.e. by receive date.
+    mails: Vec<ManifestEntry>,
+}
+
+impl Manifest {
+    fn path(dir: &Path) -> PathBuf {
+        dir.join(MANIFEST_FILE_NAME)
+    }
+
+    /// Empty manifest for the folder.
+    pub(crate) fn new(folder: &Folder) -> Self {
+        Self {
+            folder_id: folder.id.clone(),
+            folder_name: folder.name.clone(),
+            mails: vec![],
+        }
+    }
+
+    /// Load manifest of an earlier run, so that incremental runs extend it.
+    ///
+    /// Starts from scratch if there is none or if it belongs to a different folder.
+    pub(crate) async fn load(dir: &Path, folder: &Folder) -> Result<Self> {
+        let data = match tokio::fs::read(Self::path(dir)).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::new(folder));
+            }
+            Err(e) => {
+                return Err(e).context("read manifest");
+            }
+        };
+
+        let manifest: Self = serde_json::from_slice(&data).context("parse manifest")?;
+        if manifest.folder_id != folder.id {
+            return Ok(Self::new(folder));
+        }
+        Ok(Self {
+            // folders may have been renamed
+            folder_name: folder.name.clone(),
+            ..manifest
+        })
+    }
+
+    /// Add mail, replacing an existing entry with the same ID.
+    ///
+    /// Existing entries are kept if the new one does not know the size.
+    pub(crate) fn insert(&mut self, entry: ManifestEntry) {
+        match self.mails.binary_search_by(|e| e.id.cmp(&entry.id)) {
+            Ok(idx) => {
+                if entry.size.is_some() || self.mails[idx].file != entry.file {
+                    self.mails[idx] = entry;
+                }
+            }
+            Err(idx) => self.mails.insert(idx, entry),
+        }
+    }
+
+    /// Persist manifest.
+    pub(crate) async fn store(&self, dir: &Path) -> Result<()> {
+        let mut data = serde_json::to_vec_pretty(self).context("serialize manifest")?;
+        data.push(b'\n');
+        write_to_file(&data, &Self::path(dir))
+            .await
+            .context("write manifest")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn entry(id: &str, size: Option<u64>) -> ManifestEntry {
+        ManifestEntry {
+            id: id.to_owned(),
+            date: "2020-03-04T11:22:33+00:00".to_owned(),
+            subject: "Hello".to_owned(),
+            from: "foo@example.com".to_owned(),
+            file: PathBuf::from(format!("{id}.eml")),
+            size,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let folder = Folder {
+            name: "Inbox".to_owned(),
+            mails: "mails".to_owned(),
+            id: "f1".to_owned(),
+            parent: None,
+            system_folder: None,
+        };
+
+        let mut manifest = Manifest::load(dir.path(), &folder).await.unwrap();
+        assert_eq!(manifest, Manifest::new(&folder));
+
+        manifest.insert(entry("m2", Some(2)));
+        manifest.insert(entry("m1", Some(1)));
+        manifest.insert(entry("m3", Some(3)));
+        manifest.insert(entry("m2", None));
+        manifest.insert(entry("m3", Some(30)));
+        manifest.store(dir.path()).await.unwrap();
+
+        let loaded = Manifest::load(dir.path(), &folder).await.unwrap();
+        assert_eq!(loaded, manifest);
+        assert_eq!(
+            loaded.mails,
+            [
+                entry("m1", Some(1)),
+                entry("m2", Some(2)),
+                entry("m3", Some(30))
+            ],
+        );
+
+        let other = Folder {
+            id: "f2".to_owned(),
+            ..folder
+        };
+        assert_eq!(
+            Manifest::load(dir.path(), &other).await.unwrap(),
+            Manifest::new(&other)
+        );
+    }
+}
@@ -0,0 +1,87 @@
+//! Man page generation.
+use std::{io::Write, path::PathBuf};
+
+use anyhow::{Context, Result};
+use clap::{CommandFactory, Parser};
+use clap_mangen::Man;
+
+use crate::cli::Args;
+
+/// Man page CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct ManpageCLIConfig {
+    /// Write one page per subcommand into this directory instead of printing the main page.
+    #[clap(long)]
+    out_dir: Option<PathBuf>,
+}
+
+/// Print main man page or write all pages, see [`ManpageCLIConfig`].
+pub(crate) async fn write_manpages(cfg: ManpageCLIConfig) -> Result<()> {
+    let cmd = Args::command().name(env!("CARGO_PKG_NAME"));
+
+    let Some(out_dir) = cfg.out_dir else {
+        let mut out = vec![];
+        Man::new(cmd).render(&mut out).context("render man page")?;
+        std::io::stdout()
+            .write_all(&out)
+            .context("write man page to stdout")?;
+        return Ok(());
+    };
+
+    tokio::fs::create_dir_all(&out_dir)
+        .await
+        .context("create output directory")?;
+    for (name, page) in render_all(cmd).context("render man pages")? {
+        let path = out_dir.join(format!("{name}.1"));
+        tokio::fs::write(&path, page)
+            .await
+            .with_context(|| format!("write man page: `{}`", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Render pages for the command and all visible subcommands, named like `tool-subcommand`.
+fn render_all(cmd: clap::Command) -> Result<Vec<(String, Vec<u8>)>> {
+    let name = cmd.get_name().to_owned();
+    let mut pages = vec![];
+
+    for sub in cmd.get_subcommands().filter(|s| !s.is_hide_set()) {
+        let sub_name = format!("{name}-{}", sub.get_name());
+        let mut page = vec![];
+        Man::new(sub.clone())
+            .title(sub_name.clone())
+            .render(&mut page)
+            .with_context(|| format!("render `{sub_name}`"))?;
+        pages.push((sub_name, page));
+    }
+
+    let mut page = vec![];
+    Man::new(cmd)
+        .render(&mut page)
+        .context("render main page")?;
+    pages.insert(0, (name, page));
+
+    Ok(pages)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_all() {
+        let pages = render_all(Args::command().name("tatutanatata")).unwrap();
+        let names = pages
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names[0], "tatutanatata");
+        assert!(names.contains(&"tatutanatata-download"));
+        assert!(!names.contains(&"tatutanatata-manpage"));
+
+        let main = String::from_utf8(pages[0].1.clone()).unwrap();
+        assert!(main.contains(".TH tatutanatata 1"), "{main}");
+        assert!(main.contains("list\\-folders"), "{main}");
+    }
+}
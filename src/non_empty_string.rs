@@ -2,7 +2,7 @@ use std::{ops::Deref, str::FromStr};
 
 /// Non-empty [`String`].
 #[derive(Clone, PartialEq, Eq, Hash)]
-pub(crate) struct NonEmptyString(String);
+pub struct NonEmptyString(String);
 
 impl std::fmt::Debug for NonEmptyString {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error> {
@@ -42,6 +42,16 @@ impl FromStr for NonEmptyString {
     }
 }
 
+impl<'de> serde::Deserialize<'de> for NonEmptyString {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        Self::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,65 @@
+//! Progress display.
+use std::{
+    io::{IsTerminal, Write},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+static PROGRESS: OnceLock<MultiProgress> = OnceLock::new();
+
+fn progress() -> &'static MultiProgress {
+    PROGRESS.get_or_init(MultiProgress::new)
+}
+
+/// Check if progress can be displayed.
+///
+/// Progress is drawn to stderr but it would interleave with regular output, so both have to be
+/// terminals.
+pub(crate) fn progress_supported() -> bool {
+    std::io::stdout().is_terminal() && std::io::stderr().is_terminal()
+}
+
+/// Create progress bar for a single folder.
+///
+/// Without a known total, this is a spinner with a running count.
+pub(crate) fn folder_progress(folder: &str, total: Option<u64>) -> ProgressBar {
+    let (bar, template) = match total {
+        Some(total) => (
+            ProgressBar::new(total),
+            "{msg} [{bar:40}] {pos}/{len} mails ({per_sec}, ETA {eta})",
+        ),
+        None => (
+            ProgressBar::new_spinner(),
+            "{spinner} {msg}: {pos} mails ({per_sec})",
+        ),
+    };
+    let bar = progress().add(bar);
+    bar.set_style(
+        ProgressStyle::with_template(template)
+            .expect("valid template")
+            .progress_chars("=> "),
+    );
+    bar.set_message(folder.to_owned());
+    bar.enable_steady_tick(Duration::from_millis(100));
+    bar
+}
+
+/// Writer to stderr that hides progress bars while writing, so that logs and progress do not
+/// clobber each other.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct ProgressAwareStderr;
+
+impl Write for ProgressAwareStderr {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match PROGRESS.get() {
+            Some(progress) => progress.suspend(|| std::io::stderr().write(buf)),
+            None => std::io::stderr().write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
@@ -153,11 +153,56 @@ impl<'de> serde::Deserialize<'de> for Base64Url {
     }
 }
 
+/// Access token of a session.
+///
+/// Unlike [`Base64Url`], this does not implement [`Display`](std::fmt::Display) and its `Debug`
+/// output is redacted, so it cannot leak into logs or error chains by accident. Use
+/// [`expose`](Self::expose) to get the value for a request.
+#[derive(Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(transparent)]
+pub(crate) struct AccessToken(Base64Url);
+
+impl AccessToken {
+    /// Encoded token, only for sending it to the server.
+    pub(crate) fn expose(&self) -> String {
+        self.0.url()
+    }
+}
+
+impl std::fmt::Debug for AccessToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "[redacted]")
+    }
+}
+
+impl From<Base64Url> for AccessToken {
+    fn from(value: Base64Url) -> Self {
+        Self(value)
+    }
+}
+
+impl AsRef<[u8]> for AccessToken {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::proto::testing::assert_roundtrip;
 
+    #[test]
+    fn test_access_token() {
+        let token = AccessToken::from(Base64Url::from(b"secret token"));
+        assert_roundtrip(token.clone(), r#""c2VjcmV0IHRva2Vu""#);
+        assert_eq!(token.expose(), "c2VjcmV0IHRva2Vu");
+
+        let debug = format!("{token:?} {:?}", Some(&token));
+        assert_eq!(debug, "[redacted] Some([redacted])");
+        assert!(!debug.contains("c2VjcmV0IHRva2Vu"));
+    }
+
     #[test]
     fn test_roundtrip_base64string() {
         assert_roundtrip(Base64String::from(b""), r#""""#);
@@ -0,0 +1,44 @@
+use anyhow::Result;
+use serde::{de::Error, Deserializer, Serializer};
+
+/// Boolean that is encoded as `"0"` or `"1"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Boolean(pub(crate) bool);
+
+impl serde::Serialize for Boolean {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(if self.0 { "1" } else { "0" })
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for Boolean {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "0" => Ok(Self(false)),
+            "1" => Ok(Self(true)),
+            s => Err(D::Error::custom(format!("invalid boolean: {s}"))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::testing::{assert_deser_error, assert_roundtrip};
+
+    use super::*;
+
+    #[test]
+    fn test_boolean_roundtrip() {
+        assert_roundtrip(Boolean(false), r#""0""#);
+        assert_roundtrip(Boolean(true), r#""1""#);
+
+        assert_deser_error::<Boolean>(r#""true""#, "invalid boolean: true");
+    }
+}
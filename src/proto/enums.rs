@@ -87,6 +87,10 @@ build_enum!(
     ],
 );
 
+build_enum!(CounterType, [UserStorageLegacy = "2",]);
+
+build_enum!(ChallengeType, [U2f = "0", Otp = "1", Email = "2",]);
+
 build_enum!(
     ArchiveDataType,
     [
@@ -96,6 +100,11 @@ build_enum!(
     ],
 );
 
+build_enum!(
+    MailReplyType,
+    [None = "0", Reply = "1", Forward = "2", ReplyForward = "3",],
+);
+
 #[cfg(test)]
 mod tests {
     use crate::proto::testing::{assert_deser_error, assert_roundtrip};
@@ -141,6 +150,22 @@ mod tests {
         assert_deser_error::<MailFolderType>(r#""20""#, "unknown variant: 20");
     }
 
+    #[test]
+    fn test_roundtrip_counter_type() {
+        assert_roundtrip(CounterType::UserStorageLegacy, r#""2""#);
+
+        assert_deser_error::<CounterType>(r#""20""#, "unknown variant: 20");
+    }
+
+    #[test]
+    fn test_roundtrip_challenge_type() {
+        assert_roundtrip(ChallengeType::U2f, r#""0""#);
+        assert_roundtrip(ChallengeType::Otp, r#""1""#);
+        assert_roundtrip(ChallengeType::Email, r#""2""#);
+
+        assert_deser_error::<ChallengeType>(r#""20""#, "unknown variant: 20");
+    }
+
     #[test]
     fn test_roundtrip_archive_data_type() {
         assert_roundtrip(ArchiveDataType::AuthorityRequests, r#""0""#);
@@ -149,4 +174,14 @@ mod tests {
 
         assert_deser_error::<ArchiveDataType>(r#""20""#, "unknown variant: 20");
     }
+
+    #[test]
+    fn test_roundtrip_mail_reply_type() {
+        assert_roundtrip(MailReplyType::None, r#""0""#);
+        assert_roundtrip(MailReplyType::Reply, r#""1""#);
+        assert_roundtrip(MailReplyType::Forward, r#""2""#);
+        assert_roundtrip(MailReplyType::ReplyForward, r#""3""#);
+
+        assert_deser_error::<MailReplyType>(r#""20""#, "unknown variant: 20");
+    }
 }
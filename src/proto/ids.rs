@@ -0,0 +1,148 @@
+use std::sync::OnceLock;
+
+use anyhow::{Context, Result};
+use base64::{
+    alphabet::Alphabet,
+    engine::general_purpose::{NO_PAD, URL_SAFE_NO_PAD},
+    engine::GeneralPurpose,
+    Engine,
+};
+use chrono::{DateTime, Utc};
+
+/// Sortable base64 variant that is used for generated IDs.
+const BASE64_EXT_ALPHABET: &str =
+    "-0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz";
+
+/// Smallest possible generated ID, used as list start for ascending order.
+pub(crate) const GENERATED_MIN_ID: &str = "------------";
+
+/// Largest possible generated ID, used as list start for descending order.
+pub(crate) const GENERATED_MAX_ID: &str = "zzzzzzzzzzzz";
+
+/// Smallest possible custom ID, i.e. for lists with IDs that are not generated by the server.
+pub(crate) const CUSTOM_MIN_ID: &str = "";
+
+static BASE64_EXT: OnceLock<GeneralPurpose> = OnceLock::new();
+
+fn base64_ext() -> &'static GeneralPurpose {
+    BASE64_EXT.get_or_init(|| {
+        GeneralPurpose::new(
+            &Alphabet::new(BASE64_EXT_ALPHABET).expect("valid alphabet"),
+            NO_PAD,
+        )
+    })
+}
+
+/// Length of a generated ID.
+const GENERATED_ID_LENGTH: usize = 12;
+
+/// Check if given string is a well-formed generated ID.
+pub(crate) fn is_generated_id(s: &str) -> bool {
+    s.len() == GENERATED_ID_LENGTH && s.chars().all(|c| BASE64_EXT_ALPHABET.contains(c))
+}
+
+/// Decode custom ID that was created from a string, e.g. the version of a former group key.
+///
+/// These IDs are the unpadded base64url encoding of the UTF-8 string.
+pub(crate) fn custom_id_to_string(id: &str) -> Result<String> {
+    let bytes = URL_SAFE_NO_PAD
+        .decode(id)
+        .with_context(|| format!("invalid custom ID: `{id}`"))?;
+    String::from_utf8(bytes).with_context(|| format!("invalid custom ID: `{id}`"))
+}
+
+/// Inverse of [`custom_id_to_string`].
+#[cfg(test)]
+pub(crate) fn string_to_custom_id(s: &str) -> String {
+    URL_SAFE_NO_PAD.encode(s)
+}
+
+/// Smallest generated ID that could have been created at the given time.
+///
+/// Generated IDs start with the creation timestamp (in milliseconds, shifted by two bits) followed
+/// by a counter and a server ID, so they can be used as a cursor for list ranges.
+pub(crate) fn timestamp_to_generated_id(ts: DateTime<Utc>) -> String {
+    let millis = ts.timestamp_millis().max(0) as u128;
+    let id = (millis << 2) << 28;
+    let bytes = id.to_be_bytes();
+    base64_ext().encode(&bytes[bytes.len() - 9..])
+}
+
+/// Creation time of a generated ID, inverse of [`timestamp_to_generated_id`].
+///
+/// Returns `None` for custom IDs.
+pub(crate) fn generated_id_to_timestamp(id: &str) -> Option<DateTime<Utc>> {
+    if !is_generated_id(id) {
+        return None;
+    }
+    let bytes = base64_ext().decode(id).ok()?;
+    let mut buf = [0u8; 16];
+    buf[16 - bytes.len()..].copy_from_slice(&bytes);
+    let millis = (u128::from_be_bytes(buf) >> 28) >> 2;
+    DateTime::from_timestamp_millis(millis.try_into().ok()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_generated_id() {
+        assert!(is_generated_id(GENERATED_MIN_ID));
+        assert!(is_generated_id(GENERATED_MAX_ID));
+        assert!(is_generated_id("Nqcfn0k-_-9z"));
+        assert!(!is_generated_id(""));
+        assert!(!is_generated_id("Nqcfn0k-----x"));
+        assert!(!is_generated_id("Nqcfn0k----+"));
+    }
+
+    #[test]
+    fn test_custom_id_to_string() {
+        assert_eq!(string_to_custom_id("1"), "MQ");
+        assert_eq!(custom_id_to_string("MQ").unwrap(), "1");
+        assert_eq!(custom_id_to_string("MTA").unwrap(), "10");
+        assert_eq!(
+            custom_id_to_string("M+").unwrap_err().to_string(),
+            "invalid custom ID: `M+`",
+        );
+    }
+
+    #[test]
+    fn test_timestamp_to_generated_id() {
+        assert_eq!(
+            timestamp_to_generated_id(DateTime::from_timestamp_millis(0).unwrap()),
+            "------------",
+        );
+        assert_eq!(
+            timestamp_to_generated_id(DateTime::from_timestamp_millis(1707932070000).unwrap()),
+            "Nqcfn0k-----",
+        );
+        assert_eq!(
+            timestamp_to_generated_id(
+                DateTime::parse_from_rfc3339("2020-03-04T11:22:33Z")
+                    .unwrap()
+                    .to_utc()
+            ),
+            "M1_H61c-----",
+        );
+    }
+
+    #[test]
+    fn test_generated_id_to_timestamp() {
+        assert_eq!(
+            generated_id_to_timestamp(GENERATED_MIN_ID),
+            DateTime::from_timestamp_millis(0),
+        );
+        assert_eq!(
+            generated_id_to_timestamp("Nqcfn0k-_-9z"),
+            DateTime::from_timestamp_millis(1707932070000),
+        );
+        assert_eq!(
+            generated_id_to_timestamp(&timestamp_to_generated_id(
+                DateTime::from_timestamp_millis(1583320953123).unwrap()
+            )),
+            DateTime::from_timestamp_millis(1583320953123),
+        );
+        assert_eq!(generated_id_to_timestamp("MQ"), None);
+    }
+}
@@ -9,18 +9,15 @@ pub(crate) enum Key {
     Aes256([u8; 32]),
 }
 
+/// Plain keys are redacted, so they cannot leak into logs or error chains.
 impl std::fmt::Debug for Key {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let (name, k) = match self {
-            Self::Aes128(k) => ("Aes128", k.as_slice()),
-            Self::Aes256(k) => ("Aes256", k.as_slice()),
+        let name = match self {
+            Self::Aes128(_) => "Aes128",
+            Self::Aes256(_) => "Aes256",
         };
 
-        write!(f, "{name}(")?;
-        fmt_hex(k, f)?;
-        write!(f, ")")?;
-
-        Ok(())
+        write!(f, "{name}([redacted])")
     }
 }
 
@@ -57,6 +54,9 @@ pub(crate) enum EncryptedKey {
     Aes128NoMac([u8; 16]),
     Aes128WithMac([u8; 65]),
     Aes256NoMac([u8; 32]),
+
+    /// AES-256 key encrypted with an AES-256 key.
+    Aes256WithMac([u8; 81]),
 }
 
 impl std::fmt::Debug for EncryptedKey {
@@ -65,6 +65,7 @@ impl std::fmt::Debug for EncryptedKey {
             Self::Aes128NoMac(k) => ("Aes128NoMac", k.as_slice()),
             Self::Aes128WithMac(k) => ("Aes128WithMac", k.as_slice()),
             Self::Aes256NoMac(k) => ("Aes256NoMac", k.as_slice()),
+            Self::Aes256WithMac(k) => ("Aes256WithMac", k.as_slice()),
         };
 
         write!(f, "{name}(")?;
@@ -81,6 +82,7 @@ impl AsRef<[u8]> for EncryptedKey {
             Self::Aes128NoMac(k) => k,
             Self::Aes128WithMac(k) => k,
             Self::Aes256NoMac(k) => k,
+            Self::Aes256WithMac(k) => k,
         }
     }
 }
@@ -93,6 +95,7 @@ impl Deref for EncryptedKey {
             Self::Aes128NoMac(k) => k,
             Self::Aes128WithMac(k) => k,
             Self::Aes256NoMac(k) => k,
+            Self::Aes256WithMac(k) => k,
         }
     }
 }
@@ -150,6 +153,8 @@ impl<'de> serde::Deserialize<'de> for OptionalEncryptedKey {
             Ok(Self(Some(EncryptedKey::Aes256NoMac(k))))
         } else if let Ok(k) = TryInto::<[u8; 65]>::try_into(s.deref()) {
             Ok(Self(Some(EncryptedKey::Aes128WithMac(k))))
+        } else if let Ok(k) = TryInto::<[u8; 81]>::try_into(s.deref()) {
+            Ok(Self(Some(EncryptedKey::Aes256WithMac(k))))
         } else {
             Err(D::Error::custom(format!(
                 "invalid key length: {}",
@@ -181,6 +186,10 @@ mod tests {
             EncryptedKey::Aes256NoMac([42; 32]),
             r#""KioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKio=""#,
         );
+        assert_roundtrip(
+            EncryptedKey::Aes256WithMac([42; 81]),
+            r#""KioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioqKioq""#,
+        );
 
         assert_deser_error::<EncryptedKey>(r#""""#, "key must not be empty");
         assert_deser_error::<EncryptedKey>(r#""eAo=""#, "invalid key length: 2");
@@ -212,7 +221,7 @@ mod tests {
                 "{:?}",
                 Key::Aes128(hex!("3556747514a3da176d423cf3153b27ba"))
             ),
-            "Aes128(3556747514a3da176d423cf3153b27ba)",
+            "Aes128([redacted])",
         );
         assert_eq!(
             format!(
@@ -221,7 +230,7 @@ mod tests {
                     "32918beb02de716886e6adab052d096dcc2c9eddbb4ee43acec3c260e0044f38"
                 ))
             ),
-            "Aes256(32918beb02de716886e6adab052d096dcc2c9eddbb4ee43acec3c260e0044f38)",
+            "Aes256([redacted])",
         );
     }
 
@@ -1,10 +1,13 @@
 use serde::{Deserialize, Serialize};
 
+use crate::client::Prefix;
+
 use super::{
-    binary::{Base64String, Base64Url},
+    binary::{AccessToken, Base64String, Base64Url},
+    boolean::Boolean,
     constants::{Format, Null},
     date::UnixDate,
-    enums::{ArchiveDataType, GroupType, KdfVersion, MailFolderType},
+    enums::{ArchiveDataType, ChallengeType, CounterType, GroupType, KdfVersion, MailFolderType},
     keys::{EncryptedKey, OptionalEncryptedKey},
     numbers::Number,
 };
@@ -13,6 +16,15 @@ pub(crate) trait Entity {
     fn id(&self) -> &str;
 }
 
+/// Entity type that can be fetched by ID, see [`Client::get_entity`](crate::client::Client::get_entity).
+pub(crate) trait TypedEntity {
+    /// Service that owns the type.
+    const PREFIX: Prefix;
+
+    /// Type name, i.e. the first path segment.
+    const NAME: &'static str;
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct SaltServiceRequest {
@@ -43,26 +55,47 @@ pub(crate) struct SessionServiceRequest {
 
     pub(crate) auth_token: Null,
 
-    pub(crate) auth_verifier: Base64Url,
+    pub(crate) auth_verifier: Option<Base64Url>,
 
     pub(crate) client_identifier: String,
 
     pub(crate) mail_address: String,
 
-    pub(crate) recover_code_verifier: Null,
+    pub(crate) recover_code_verifier: Option<Base64Url>,
 
     pub(crate) user: Null,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct U2fKey {
+    pub(crate) app_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct U2fChallenge {
+    pub(crate) keys: Vec<U2fKey>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct Challenge {
+    #[serde(rename = "type")]
+    pub(crate) challenge_type: ChallengeType,
+
+    pub(crate) u2f: Option<U2fChallenge>,
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct SessionServiceResponse {
     #[serde(rename = "_format")]
     pub(crate) _format: Format<0>,
 
-    pub(crate) access_token: Base64Url,
+    pub(crate) access_token: AccessToken,
 
-    pub(crate) challenges: Vec<String>,
+    pub(crate) challenges: Vec<Challenge>,
 
     pub(crate) user: String,
 }
@@ -73,12 +106,34 @@ pub(crate) struct UserMembership {
     pub(crate) group_type: GroupType,
     pub(crate) group: String,
     pub(crate) sym_enc_g_key: OptionalEncryptedKey,
+
+    /// Current version of the group key, increased on every key rotation.
+    #[serde(default)]
+    pub(crate) group_key_version: Number,
+
+    /// [`GroupInfoResponse`] of the group.
+    pub(crate) group_info: [String; 2],
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct UserAuth {
     pub(crate) sessions: String,
+    pub(crate) recover_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RecoverCodeResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    pub(crate) recover_code_enc_user_group_key: EncryptedKey,
+}
+
+impl TypedEntity for RecoverCodeResponse {
+    const PREFIX: Prefix = Prefix::Sys;
+    const NAME: &'static str = "recovercode";
 }
 
 #[derive(Debug, Deserialize)]
@@ -90,6 +145,63 @@ pub(crate) struct UserResponse {
     pub(crate) memberships: Vec<UserMembership>,
     pub(crate) auth: UserAuth,
     pub(crate) user_group: UserMembership,
+
+    /// Customer that owns the account, used to look up the storage usage.
+    pub(crate) customer: Option<String>,
+}
+
+impl TypedEntity for UserResponse {
+    const PREFIX: Prefix = Prefix::Sys;
+    const NAME: &'static str = "user";
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct MailAddressAlias {
+    pub(crate) mail_address: String,
+
+    /// Disabled aliases no longer receive mails.
+    pub(crate) enabled: Boolean,
+}
+
+/// Public information about a group, e.g. the mail addresses of a user.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GroupInfoResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    /// Primary mail address, not set for groups without one.
+    pub(crate) mail_address: Option<String>,
+
+    #[serde(default)]
+    pub(crate) mail_address_aliases: Vec<MailAddressAlias>,
+}
+
+impl TypedEntity for GroupInfoResponse {
+    const PREFIX: Prefix = Prefix::Sys;
+    const NAME: &'static str = "groupinfo";
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReadCounterRequest {
+    #[serde(rename = "_format")]
+    pub(crate) format: Format<0>,
+
+    pub(crate) counter_type: CounterType,
+    pub(crate) row_name: String,
+    pub(crate) column_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ReadCounterResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    /// Not set if the counter does not exist.
+    pub(crate) value: Option<Number>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -131,9 +243,15 @@ pub(crate) struct FolderResponse {
     #[serde(rename = "_ownerGroup")]
     pub(crate) owner_group: String,
 
+    /// Version of the owner group key that encrypts the session key, `0` if the key was never
+    /// rotated.
+    #[serde(rename = "_ownerKeyVersion", default)]
+    pub(crate) owner_key_version: Number,
+
     pub(crate) folder_type: MailFolderType,
     pub(crate) name: Base64String,
     pub(crate) mails: String,
+    pub(crate) parent_folder: Option<[String; 2]>,
 }
 
 impl Entity for FolderResponse {
@@ -161,16 +279,28 @@ pub(crate) struct MailReponse {
     #[serde(rename = "_ownerGroup")]
     pub(crate) owner_group: String,
 
+    #[serde(rename = "_ownerKeyVersion", default)]
+    pub(crate) owner_key_version: Number,
+
     #[serde(rename = "_id")]
     pub(crate) id: [String; 2],
 
     pub(crate) mail_details: Option<[String; 2]>,
     pub(crate) mail_details_draft: Option<[String; 2]>,
 
-    pub(crate) received_date: UnixDate,
+    /// Missing for some drafts and outgoing mails.
+    pub(crate) received_date: Option<UnixDate>,
+
     pub(crate) subject: Base64String,
     pub(crate) sender: MailAddress,
     pub(crate) attachments: Vec<[String; 2]>,
+    pub(crate) unread: Boolean,
+
+    /// Encrypted [`MailReplyType`](crate::proto::enums::MailReplyType), empty if the mail was
+    /// neither replied to nor forwarded.
+    #[serde(default)]
+    pub(crate) reply_type: Option<Base64String>,
+    pub(crate) conversation_entry: [String; 2],
 }
 
 impl Entity for MailReponse {
@@ -179,6 +309,30 @@ impl Entity for MailReponse {
     }
 }
 
+impl TypedEntity for MailReponse {
+    const PREFIX: Prefix = Prefix::Tutanota;
+    const NAME: &'static str = "mail";
+}
+
+/// Position of a mail within its thread.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ConversationEntryResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    /// `Message-ID` of the mail, unencrypted.
+    pub(crate) message_id: String,
+
+    /// Entry of the mail this one replies to or forwards.
+    pub(crate) previous: Option<[String; 2]>,
+}
+
+impl TypedEntity for ConversationEntryResponse {
+    const PREFIX: Prefix = Prefix::Tutanota;
+    const NAME: &'static str = "conversationentry";
+}
+
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct BlobReadRequestInstanceId {
@@ -246,11 +400,15 @@ pub(crate) struct MailHeaders {
     pub(crate) compressed_headers: Option<Base64String>,
 }
 
-#[derive(Debug, Deserialize)]
+// drafts may lack recipients entirely
+#[derive(Debug, Default, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct MailRecipients {
+    #[serde(default)]
     pub(crate) bcc_recipients: Vec<MailAddress>,
+    #[serde(default)]
     pub(crate) cc_recipients: Vec<MailAddress>,
+    #[serde(default)]
     pub(crate) to_recipients: Vec<MailAddress>,
 }
 
@@ -264,6 +422,7 @@ pub(crate) struct MailDetails {
     /// These only appear for true emails, not for internal messages.
     pub(crate) headers: Option<MailHeaders>,
 
+    #[serde(default)]
     pub(crate) recipients: MailRecipients,
 }
 
@@ -296,6 +455,9 @@ pub(crate) struct FileReponse {
     #[serde(rename = "_ownerGroup")]
     pub(crate) owner_group: String,
 
+    #[serde(rename = "_ownerKeyVersion", default)]
+    pub(crate) owner_key_version: Number,
+
     pub(crate) cid: Option<Base64String>,
     pub(crate) mime_type: Base64String,
     pub(crate) name: Base64String,
@@ -313,3 +475,232 @@ pub(crate) struct BlobServiceRequest {
     pub(crate) blob_id: String,
     pub(crate) blob_ids: Vec<()>,
 }
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SessionResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    #[serde(rename = "_id")]
+    pub(crate) id: [String; 2],
+
+    #[serde(rename = "_ownerEncSessionKey")]
+    pub(crate) owner_enc_session_key: OptionalEncryptedKey,
+
+    #[serde(rename = "_ownerGroup")]
+    pub(crate) owner_group: Option<String>,
+
+    #[serde(rename = "_ownerKeyVersion", default)]
+    pub(crate) owner_key_version: Number,
+
+    pub(crate) client_identifier: Base64String,
+}
+
+impl Entity for SessionResponse {
+    fn id(&self) -> &str {
+        &self.id[1]
+    }
+}
+
+/// Reference to the root entity of a type within a group.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct RootInstanceResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    pub(crate) reference: String,
+}
+
+impl TypedEntity for RootInstanceResponse {
+    const PREFIX: Prefix = Prefix::Sys;
+    const NAME: &'static str = "rootinstance";
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ContactListResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    pub(crate) contacts: String,
+}
+
+impl TypedEntity for ContactListResponse {
+    const PREFIX: Prefix = Prefix::Tutanota;
+    const NAME: &'static str = "contactlist";
+}
+
+/// Email address, phone number, or postal address of a contact.
+///
+/// All fields are encrypted, the value field is named after the kind.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ContactValue {
+    #[serde(rename = "type")]
+    pub(crate) value_type: Base64String,
+
+    #[serde(alias = "number")]
+    pub(crate) address: Base64String,
+
+    pub(crate) custom_type_name: Base64String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct ContactResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    #[serde(rename = "_id")]
+    pub(crate) id: [String; 2],
+
+    #[serde(rename = "_ownerEncSessionKey")]
+    pub(crate) owner_enc_session_key: EncryptedKey,
+
+    #[serde(rename = "_ownerGroup")]
+    pub(crate) owner_group: String,
+
+    #[serde(rename = "_ownerKeyVersion", default)]
+    pub(crate) owner_key_version: Number,
+
+    pub(crate) first_name: Base64String,
+    pub(crate) last_name: Base64String,
+    pub(crate) nickname: Option<Base64String>,
+    pub(crate) title: Option<Base64String>,
+    pub(crate) company: Base64String,
+    pub(crate) role: Base64String,
+    pub(crate) birthday_iso: Option<Base64String>,
+    pub(crate) comment: Base64String,
+    pub(crate) mail_addresses: Vec<ContactValue>,
+    pub(crate) phone_numbers: Vec<ContactValue>,
+    pub(crate) addresses: Vec<ContactValue>,
+}
+
+impl Entity for ContactResponse {
+    fn id(&self) -> &str {
+        &self.id[1]
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CalendarGroupRootResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    pub(crate) short_events: String,
+    pub(crate) long_events: String,
+}
+
+impl TypedEntity for CalendarGroupRootResponse {
+    const PREFIX: Prefix = Prefix::Tutanota;
+    const NAME: &'static str = "calendargrouproot";
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct DateWrapper {
+    pub(crate) date: UnixDate,
+}
+
+/// Recurrence of a calendar event.
+///
+/// All fields except the excluded dates are encrypted.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CalendarRepeatRule {
+    pub(crate) frequency: Base64String,
+    pub(crate) interval: Base64String,
+    pub(crate) end_type: Base64String,
+    pub(crate) end_value: Option<Base64String>,
+
+    /// IANA time zone in which the event repeats, encrypted.
+    #[serde(default)]
+    pub(crate) time_zone: Option<Base64String>,
+
+    #[serde(default)]
+    pub(crate) excluded_dates: Vec<DateWrapper>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CalendarEventResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    #[serde(rename = "_id")]
+    pub(crate) id: [String; 2],
+
+    #[serde(rename = "_ownerEncSessionKey")]
+    pub(crate) owner_enc_session_key: EncryptedKey,
+
+    #[serde(rename = "_ownerGroup")]
+    pub(crate) owner_group: String,
+
+    #[serde(rename = "_ownerKeyVersion", default)]
+    pub(crate) owner_key_version: Number,
+
+    pub(crate) uid: Option<Base64String>,
+    pub(crate) summary: Base64String,
+    pub(crate) description: Base64String,
+    pub(crate) location: Base64String,
+
+    /// Encrypted timestamp in milliseconds.
+    pub(crate) start_time: Base64String,
+
+    /// Encrypted timestamp in milliseconds.
+    pub(crate) end_time: Base64String,
+    pub(crate) repeat_rule: Option<CalendarRepeatRule>,
+}
+
+impl Entity for CalendarEventResponse {
+    fn id(&self) -> &str {
+        &self.id[1]
+    }
+}
+
+/// Reference to the list of former keys of a group.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GroupKeysRef {
+    pub(crate) list: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GroupResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    /// Only set if the group key was rotated.
+    pub(crate) former_group_keys: Option<GroupKeysRef>,
+}
+
+impl TypedEntity for GroupResponse {
+    const PREFIX: Prefix = Prefix::Sys;
+    const NAME: &'static str = "group";
+}
+
+/// Former key of a group.
+///
+/// The element ID encodes the version, see [`custom_id_to_string`](super::ids::custom_id_to_string).
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GroupKeyResponse {
+    #[serde(rename = "_format")]
+    pub(crate) _format: Format<0>,
+
+    #[serde(rename = "_id")]
+    pub(crate) id: [String; 2],
+
+    /// Key encrypted with the next newer version of the group key.
+    pub(crate) owner_enc_g_key: EncryptedKey,
+}
+
+impl Entity for GroupKeyResponse {
+    fn id(&self) -> &str {
+        &self.id[1]
+    }
+}
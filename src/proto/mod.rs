@@ -1,7 +1,9 @@
 pub(crate) mod binary;
+pub(crate) mod boolean;
 pub(crate) mod constants;
 pub(crate) mod date;
 pub(crate) mod enums;
+pub(crate) mod ids;
 pub(crate) mod keys;
 pub(crate) mod messages;
 pub(crate) mod numbers;
@@ -1,7 +1,7 @@
 use anyhow::Result;
 use serde::{de::Error, Deserializer, Serializer};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct Number(pub(crate) u64);
 
 impl serde::Serialize for Number {
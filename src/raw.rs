@@ -0,0 +1,56 @@
+//! Raw API requests for debugging.
+use anyhow::{Context, Result};
+use clap::Parser;
+use tokio::io::AsyncWriteExt;
+use tracing::info;
+
+use crate::{
+    client::{Client, Prefix, Request},
+    session::Session,
+};
+
+/// Raw GET CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct RawGetCLIConfig {
+    /// Service prefix.
+    #[clap(long, action, value_enum)]
+    prefix: Prefix,
+
+    /// Path below the prefix, e.g. `mailbox/<id>`.
+    #[clap(long, action)]
+    path: String,
+
+    /// Confirm that the response is printed as-is.
+    ///
+    /// Responses contain encrypted data and IDs of your account, but may also contain secrets like
+    /// session keys in plain text. Do not share them without checking.
+    #[clap(long, action, required = true)]
+    allow_raw: bool,
+}
+
+/// Perform an authenticated GET request and print the raw response body to stdout.
+pub(crate) async fn raw_get(
+    client: &Client,
+    session: &Session,
+    cfg: RawGetCLIConfig,
+) -> Result<()> {
+    let path = cfg.path.trim_start_matches('/');
+    info!(prefix = ?cfg.prefix, path, "raw GET");
+
+    let body = client
+        .do_bytes(Request {
+            access_token: Some(&session.access_token),
+            ..Request::new(cfg.prefix, path, &())
+        })
+        .await
+        .context("request")?;
+
+    let mut stdout = tokio::io::stdout();
+    stdout.write_all(&body).await.context("write stdout")?;
+    if !body.ends_with(b"\n") {
+        stdout.write_all(b"\n").await.context("write stdout")?;
+    }
+    stdout.flush().await.context("flush stdout")?;
+
+    Ok(())
+}
@@ -0,0 +1,90 @@
+//! Resume state for interrupted downloads.
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::file_output::write_to_file;
+
+/// Name of the state file within the output directory.
+const STATE_FILE_NAME: &str = ".tatutanatata-resume.json";
+
+/// Download progress of a single folder.
+///
+/// Mail IDs are ordered by creation time, so everything up to and including `last_mail_id` was
+/// already processed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ResumeState {
+    pub(crate) folder_id: String,
+    pub(crate) last_mail_id: String,
+}
+
+impl ResumeState {
+    fn path(dir: &Path) -> PathBuf {
+        dir.join(STATE_FILE_NAME)
+    }
+
+    /// Load state for given folder.
+    ///
+    /// Returns `None` if there is no state or if it belongs to a different folder.
+    pub(crate) async fn load(dir: &Path, folder_id: &str) -> Result<Option<Self>> {
+        let path = Self::path(dir);
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            Err(e) => {
+                return Err(e).context("read resume state");
+            }
+        };
+
+        let state: Self = serde_json::from_slice(&data).context("parse resume state")?;
+        if state.folder_id != folder_id {
+            warn!(
+                path=%path.display(),
+                expected=folder_id,
+                actual=state.folder_id.as_str(),
+                "resume state belongs to different folder, ignoring it",
+            );
+            return Ok(None);
+        }
+
+        debug!(last_mail_id = state.last_mail_id.as_str(), "resume");
+        Ok(Some(state))
+    }
+
+    /// Persist state.
+    pub(crate) async fn store(&self, dir: &Path) -> Result<()> {
+        let data = serde_json::to_vec(self).context("serialize resume state")?;
+        write_to_file(&data, &Self::path(dir))
+            .await
+            .context("write resume state")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let dir = TempDir::new().unwrap();
+
+        assert_eq!(ResumeState::load(dir.path(), "f1").await.unwrap(), None);
+
+        let state = ResumeState {
+            folder_id: "f1".to_owned(),
+            last_mail_id: "m1".to_owned(),
+        };
+        state.store(dir.path()).await.unwrap();
+        assert_eq!(
+            ResumeState::load(dir.path(), "f1").await.unwrap(),
+            Some(state)
+        );
+        assert_eq!(ResumeState::load(dir.path(), "f2").await.unwrap(), None);
+    }
+}
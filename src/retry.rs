@@ -0,0 +1,211 @@
+//! Retry mails that failed in `--keep-going` mode.
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{ensure, Context, Result};
+use clap::Parser;
+use tracing::{info, warn};
+
+use crate::{
+    client::Client,
+    download::{check_failures, eml_file_name, eml_target, write_eml_file},
+    file_output::{write_to_file, EmlIndex},
+    filename_template::{FilenameTemplate, DEFAULT_FILENAME_TEMPLATE},
+    mails::Mail,
+    proto::ids::is_generated_id,
+    session::Session,
+};
+
+/// Retry-failed CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct RetryFailedCLIConfig {
+    /// File with failed mails, as written by `download --failed-file`.
+    ///
+    /// Mails are removed from the file once they were downloaded.
+    #[clap(action)]
+    file: PathBuf,
+
+    /// Target directory.
+    ///
+    /// Mails are named and indexed like `download` does, so the directory of the failed run can be
+    /// used without creating duplicates.
+    #[clap(long, action)]
+    path: PathBuf,
+
+    /// File name template, see `download --help`.
+    #[clap(long, action, default_value = DEFAULT_FILENAME_TEMPLATE)]
+    filename_template: FilenameTemplate,
+
+    /// Only use portable ASCII file and folder names, see `download --help`.
+    #[clap(long, action)]
+    ascii_filenames: bool,
+}
+
+/// Mail that could not be downloaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct FailedMail {
+    /// ID of the mail list that contains the mail.
+    pub(crate) list_id: String,
+    pub(crate) mail_id: String,
+}
+
+/// Serialize as one line per mail with list ID and mail ID, separated by a tab.
+fn format_failed(mails: &[FailedMail]) -> String {
+    mails
+        .iter()
+        .map(|m| format!("{}\t{}\n", m.list_id, m.mail_id))
+        .collect()
+}
+
+/// Parse output of [`format_failed`], empty lines are ignored.
+fn parse_failed(s: &str) -> Result<Vec<FailedMail>> {
+    s.lines()
+        .enumerate()
+        .filter(|(_idx, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            let parse = || {
+                let (list_id, mail_id) = line
+                    .trim()
+                    .split_once('\t')
+                    .context("expected list ID and mail ID separated by a tab")?;
+                ensure!(
+                    is_generated_id(list_id) && is_generated_id(mail_id),
+                    "not a valid ID"
+                );
+                Ok(FailedMail {
+                    list_id: list_id.to_owned(),
+                    mail_id: mail_id.to_owned(),
+                })
+            };
+            parse().with_context(|| format!("line {}", idx + 1))
+        })
+        .collect()
+}
+
+/// Overwrite file with given mails.
+pub(crate) async fn write_failed(path: &Path, mails: &[FailedMail]) -> Result<()> {
+    write_to_file(format_failed(mails).as_bytes(), path)
+        .await
+        .with_context(|| format!("write failed mails: `{}`", path.display()))
+}
+
+/// Download mails from the failed file again.
+pub(crate) async fn retry_failed(
+    client: &Client,
+    session: &Session,
+    cfg: RetryFailedCLIConfig,
+) -> Result<()> {
+    let content = tokio::fs::read_to_string(&cfg.file)
+        .await
+        .with_context(|| format!("read failed mails: `{}`", cfg.file.display()))?;
+    let mails = parse_failed(&content)
+        .with_context(|| format!("parse failed mails: `{}`", cfg.file.display()))?;
+
+    tokio::fs::create_dir_all(&cfg.path)
+        .await
+        .context("create output dir")?;
+
+    let index = EmlIndex::open(&cfg.path, true)
+        .await
+        .context("open eml index")?;
+
+    let mut remaining = mails.clone();
+    for mail in &mails {
+        match retry_mail(client, session, &cfg, &index, mail).await {
+            Ok(()) => {
+                remaining.retain(|m| m != mail);
+                write_failed(&cfg.file, &remaining).await?;
+            }
+            Err(e) => {
+                warn!(
+                    mail_id = mail.mail_id.as_str(),
+                    error = format!("{e:#}"),
+                    "mail failed again",
+                );
+            }
+        }
+    }
+
+    info!(
+        downloaded = mails.len() - remaining.len(),
+        remaining = remaining.len(),
+        "retry done",
+    );
+
+    check_failures(&[], 0, &remaining)
+}
+
+/// Download failed mail into the target directory and record it in the index.
+async fn retry_mail(
+    client: &Client,
+    session: &Session,
+    cfg: &RetryFailedCLIConfig,
+    index: &EmlIndex,
+    failed: &FailedMail,
+) -> Result<()> {
+    let mail = Mail::get(client, session, &failed.list_id, &failed.mail_id).await?;
+    let (path, _exists) = eml_target(index, &cfg.path, &mail, false, |with_id| {
+        eml_file_name(&cfg.filename_template, cfg.ascii_filenames, &mail, with_id)
+    })
+    .await?;
+
+    info!(mail_id = mail.mail_id.as_str(), target_file = %path.display(), "download");
+
+    let mail = Arc::new(mail)
+        .download(client, session)
+        .await
+        .context("download mail")?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .context("create parent dir")?;
+    }
+    write_eml_file(&mail, &path).await?;
+
+    index
+        .insert(&failed.mail_id, &path)
+        .await
+        .context("update eml index")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_roundtrip() {
+        let mails = vec![
+            FailedMail {
+                list_id: "Nqcfn0k-----".to_owned(),
+                mail_id: "NqcfnGq--3-9".to_owned(),
+            },
+            FailedMail {
+                list_id: "Nqcfn0k-----".to_owned(),
+                mail_id: "NqcfnGq--3-A".to_owned(),
+            },
+        ];
+        let s = format_failed(&mails);
+        assert_eq!(
+            s,
+            "Nqcfn0k-----\tNqcfnGq--3-9\nNqcfn0k-----\tNqcfnGq--3-A\n"
+        );
+        assert_eq!(parse_failed(&s).unwrap(), mails);
+
+        assert_eq!(parse_failed("").unwrap(), vec![]);
+        assert_eq!(parse_failed("\n  \n").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_parse_failed_invalid() {
+        let e = parse_failed("Nqcfn0k-----\tNqcfnGq--3-9\nfoo\n").unwrap_err();
+        assert_eq!(
+            format!("{e:#}"),
+            "line 2: expected list ID and mail ID separated by a tab"
+        );
+
+        let e = parse_failed("Nqcfn0k-----\tfoo").unwrap_err();
+        assert_eq!(format!("{e:#}"), "line 1: not a valid ID");
+    }
+}
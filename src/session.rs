@@ -1,165 +1,882 @@
-use std::{collections::HashMap, ops::Deref, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use anyhow::{bail, Context, Result};
-use clap::Parser;
-use reqwest::Method;
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::{ArgGroup, Parser, ValueEnum};
+use futures::TryStreamExt;
+use reqwest::{Method, StatusCode};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use tracing::debug;
+use tokio::io::AsyncReadExt;
+use tracing::{debug, info, warn};
 
 use crate::{
-    client::{Client, Prefix, Request, DEFAULT_HOST},
-    constants::APP_USER_AGENT,
+    client::{error_status, Client, Prefix, Request},
     crypto::{
-        auth::{derive_passkey, encode_auth_verifier, UserPassphraseKey},
-        encryption::decrypt_key,
+        auth::{derive_passkey, encode_auth_verifier, parse_recover_code, UserPassphraseKey},
+        encryption::{decrypt_key, decrypt_value, encrypt_value},
     },
+    file_output::write_to_private_file,
     non_empty_string::NonEmptyString,
     proto::{
-        binary::Base64Url,
-        keys::Key,
+        binary::{AccessToken, Base64String, Base64Url},
+        enums::{ChallengeType, KdfVersion},
+        ids::{custom_id_to_string, CUSTOM_MIN_ID},
+        keys::{EncryptedKey, Key},
         messages::{
-            SaltServiceRequest, SaltServiceResponse, SessionServiceRequest, SessionServiceResponse,
+            Challenge, GroupKeyResponse, GroupResponse, RecoverCodeResponse, SaltServiceRequest,
+            SaltServiceResponse, SessionResponse, SessionServiceRequest, SessionServiceResponse,
             UserResponse,
         },
+        numbers::Number,
     },
 };
 
 /// Login CLI config.
 #[derive(Debug, Parser)]
-pub(crate) struct LoginCLIConfig {
+#[clap(group(
+    ArgGroup::new("credentials")
+        .args(["password", "password_file", "password_stdin", "recover_code"]),
+))]
+pub struct LoginCLIConfig {
     /// Username
+    ///
+    /// Required unless `--account` is used.
     #[clap(long, env = "TUTANOTA_CLI_USERNAME")]
-    username: NonEmptyString,
+    pub username: Option<NonEmptyString>,
 
     /// Password
-    #[clap(long, env = "TUTANOTA_CLI_PASSWORD")]
-    password: NonEmptyString,
+    ///
+    /// Passing the password as argument exposes it to other users via the process list, prefer
+    /// the environment variable, `--password-file`, or `--password-stdin`.
+    #[clap(long, env = "TUTANOTA_CLI_PASSWORD", hide_env_values = true)]
+    pub password: Option<NonEmptyString>,
+
+    /// Read password from given file.
+    ///
+    /// A single trailing newline is removed.
+    #[clap(long, env = "TUTANOTA_CLI_PASSWORD_FILE")]
+    pub password_file: Option<PathBuf>,
+
+    /// Read password from stdin.
+    ///
+    /// A single trailing newline is removed.
+    #[clap(long, action)]
+    pub password_stdin: bool,
+
+    /// Recovery code, use this instead of the password.
+    ///
+    /// This is the hex code that the official client shows when setting up the recovery code.
+    /// Whitespace is ignored.
+    #[clap(long, env = "TUTANOTA_CLI_RECOVER_CODE", hide_env_values = true)]
+    pub recover_code: Option<NonEmptyString>,
+
+    /// Cache session in given file and reuse it in later runs.
+    ///
+    /// The session is NOT terminated at the end of the run. The file contains the access token and
+    /// is only readable by the current user.
+    #[clap(long, env = "TUTANOTA_CLI_SESSION_CACHE")]
+    pub session_cache: Option<PathBuf>,
+
+    /// Cache session in the OS keyring and reuse it in later runs.
+    ///
+    /// Uses the Secret Service on Linux, the Keychain on macOS, and the Credential Manager on
+    /// Windows. Like `--session-cache`, the session is NOT terminated at the end of the run.
+    #[clap(long, action, conflicts_with = "session_cache")]
+    pub use_keyring: bool,
+
+    /// Cache the key that is derived from the password in given file and reuse it in later runs.
+    ///
+    /// Deriving the key is intentionally slow. The cache is encrypted with
+    /// `--passkey-cache-secret` and ignored when the password (and with it the salt) changes. The
+    /// key is only cached after a successful login.
+    #[clap(
+        long,
+        env = "TUTANOTA_CLI_PASSKEY_CACHE",
+        requires = "passkey_cache_secret"
+    )]
+    pub passkey_cache: Option<PathBuf>,
+
+    /// Secret that encrypts the `--passkey-cache`.
+    ///
+    /// The secret is not stretched, so use a long random value.
+    #[clap(
+        long,
+        env = "TUTANOTA_CLI_PASSKEY_CACHE_SECRET",
+        hide_env_values = true
+    )]
+    pub passkey_cache_secret: Option<NonEmptyString>,
+
+    /// Fail unless the server asks for this key derivation function for the password.
+    ///
+    /// The server decides how the key is derived from the password. This detects a downgrade to
+    /// the older bcrypt-based derivation, e.g. by a misconfigured or malicious server.
+    #[clap(
+        long,
+        env = "TUTANOTA_CLI_REQUIRE_KDF",
+        value_enum,
+        conflicts_with = "recover_code"
+    )]
+    pub require_kdf: Option<Kdf>,
+}
+
+/// Key derivation function that turns the password into a key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Kdf {
+    Bcrypt,
+    Argon2id,
+}
+
+impl From<KdfVersion> for Kdf {
+    fn from(version: KdfVersion) -> Self {
+        match version {
+            KdfVersion::Bcrypt => Self::Bcrypt,
+            KdfVersion::Argon2id => Self::Argon2id,
+        }
+    }
+}
+
+/// Check KDF that the server asked for against `--require-kdf`.
+fn check_kdf(actual: KdfVersion, required: Option<Kdf>) -> Result<()> {
+    let actual = Kdf::from(actual);
+    info!(kdf = ?actual, "server asks for key derivation function");
+
+    if let Some(required) = required {
+        ensure!(
+            actual == required,
+            "server asks for key derivation function {actual:?} but {required:?} is required by \
+             `--require-kdf`",
+        );
+    }
+    Ok(())
+}
+
+/// Session data that is persisted by `--session-cache`.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedSession {
+    mail_address: String,
+    user_id: String,
+    access_token: AccessToken,
+}
+
+/// Logout-all CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct LogoutAllCLIConfig {
+    /// Actually delete the sessions, otherwise they are only listed.
+    #[clap(long, action)]
+    yes: bool,
+}
+
+/// Password-derived key that is persisted by `--passkey-cache`.
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedPasskey {
+    mail_address: String,
+    kdf_version: KdfVersion,
+    salt: Base64String,
+
+    /// Passkey, encrypted with the cache secret.
+    passkey: Base64String,
 }
 
 /// User session
 #[derive(Debug)]
-pub(crate) struct Session {
-    #[allow(dead_code)]
+pub struct Session {
     pub(crate) user_id: String,
-    pub(crate) access_token: Base64Url,
+    pub(crate) access_token: AccessToken,
     pub(crate) group_keys: Arc<GroupKeys>,
     pub(crate) user_data: UserResponse,
+
+    /// Session is cached and must outlive this process.
+    cached: bool,
 }
 
 impl Session {
     /// Perform tutanota login.
-    pub(crate) async fn login(config: LoginCLIConfig, client: &Client) -> Result<Self> {
+    pub async fn login(config: LoginCLIConfig, client: &Client) -> Result<Self> {
         debug!("perform login");
 
-        let req = SaltServiceRequest {
-            format: Default::default(),
-            mail_address: config.username.to_string(),
+        let username = config
+            .username
+            .as_ref()
+            .context("username is required, use `--username` or `--account`")?;
+        let username = &normalize_username(username)?;
+        check_credentials(&config)?;
+        let password = read_password(&config).await?;
+        let mut uncached_passkey = None;
+        let credentials = match (&password, &config.recover_code) {
+            (Some(password), None) => {
+                let req = SaltServiceRequest {
+                    format: Default::default(),
+                    mail_address: username.to_string(),
+                };
+                let resp: SaltServiceResponse = client
+                    .do_json(Request::new(Prefix::Sys, "saltservice", &req))
+                    .await
+                    .context("get salt")?;
+                check_kdf(resp.kdf_version, config.require_kdf)?;
+
+                let (pk, cache_hit) = passkey(
+                    &config,
+                    username,
+                    resp.kdf_version,
+                    resp.salt.as_ref(),
+                    password,
+                )
+                .await?;
+                if !cache_hit {
+                    uncached_passkey = Some((resp.kdf_version, resp.salt));
+                }
+                Credentials::Password(pk)
+            }
+            (None, Some(recover_code)) => Credentials::RecoverCode(
+                parse_recover_code(recover_code).context("parse recovery code")?,
+            ),
+            (None, None) => bail!(
+                "password or recovery code is required, use `--password`, `--password-file`, \
+                 `--password-stdin`, or `--recover-code`"
+            ),
+            (Some(_), Some(_)) => bail!("password and recovery code cannot be used together"),
         };
-        let resp: SaltServiceResponse = client
-            .do_json(Request::new(Prefix::Sys, "saltservice", &req))
-            .await
-            .context("get salt")?;
-
-        let pk = derive_passkey(resp.kdf_version, &config.password, resp.salt.as_ref())
-            .context("derive passkey")?;
-        let auth_verifier = encode_auth_verifier(&pk);
-
-        let req = SessionServiceRequest {
-            format: Default::default(),
-            access_key: Default::default(),
-            auth_token: Default::default(),
-            auth_verifier,
-            client_identifier: APP_USER_AGENT.to_owned(),
-            mail_address: config.username.to_string(),
-            recover_code_verifier: Default::default(),
-            user: Default::default(),
+
+        let store = SessionStore::new(&config, username);
+        let cached = match &store {
+            Some(store) => load_cached_session(client, store, username)
+                .await
+                .context("load cached session")?,
+            None => None,
         };
-        let resp: SessionServiceResponse = client
-            .do_json(Request {
-                method: Method::POST,
-                ..Request::new(Prefix::Sys, "sessionservice", &req)
-            })
-            .await
-            .context("get session")?;
-        let user_id = resp.user;
-        let access_token = resp.access_token;
 
-        debug!(user = user_id.as_str(), "got user");
+        let (user_id, access_token, user_data) = match cached {
+            Some(cached) => cached,
+            None => {
+                let (user_id, access_token) =
+                    create_session(client, username, &credentials).await?;
 
-        if !resp.challenges.is_empty() {
-            bail!("not implemented: challenges");
-        }
+                let user_data = get_user(client, &user_id, &access_token)
+                    .await
+                    .context("get user")?;
 
-        let user_data: UserResponse = client
-            .do_json(Request {
-                access_token: Some(&access_token),
-                ..Request::new(Prefix::Sys, &format!("user/{}", user_id), &())
-            })
-            .await
-            .context("get user")?;
+                if let Some(store) = &store {
+                    let cached = CachedSession {
+                        mail_address: username.to_string(),
+                        user_id: user_id.clone(),
+                        access_token: access_token.clone(),
+                    };
+                    let data = serde_json::to_vec(&cached).context("serialize session")?;
+                    store.store(data).await.context("write session cache")?;
+                    debug!(store=?store, "session cached");
+                }
+
+                (user_id, access_token, user_data)
+            }
+        };
 
-        let group_keys =
-            Arc::new(GroupKeys::try_new(&pk, &user_data).context("set up group keys")?);
+        let user_key = match &credentials {
+            Credentials::Password(pk) => decrypt_key(
+                *pk.deref(),
+                user_data
+                    .user_group
+                    .sym_enc_g_key
+                    .0
+                    .context("user key must be set")?,
+            )
+            .context("decrypt user group key")?,
+            Credentials::RecoverCode(key) => {
+                let recover_code_id = user_data
+                    .auth
+                    .recover_code
+                    .as_deref()
+                    .context("account has no recovery code")?;
+                let resp: RecoverCodeResponse = client
+                    .get_entity(None, recover_code_id, Some(&access_token))
+                    .await
+                    .context("get recovery code")?;
+                decrypt_key(*key, resp.recover_code_enc_user_group_key)
+                    .context("decrypt user group key with recovery code")?
+            }
+        };
+        if let (Some((kdf_version, salt)), Credentials::Password(pk)) =
+            (uncached_passkey, &credentials)
+        {
+            store_passkey(&config, username, kdf_version, salt.as_ref(), pk).await?;
+        }
+
+        let mut group_keys =
+            GroupKeys::try_new(user_key, &user_data).context("set up group keys")?;
+        group_keys.load_former_keys(client, &access_token).await;
+        let group_keys = Arc::new(group_keys);
 
         Ok(Self {
             user_id,
             access_token,
             group_keys,
             user_data,
+            cached: store.is_some(),
         })
     }
 
-    pub(crate) async fn logout(self, client: &Client) -> Result<()> {
-        let session = &self.user_data.auth.sessions;
+    pub async fn logout(self, client: &Client) -> Result<()> {
+        if self.cached {
+            debug!("keep cached session");
+            return Ok(());
+        }
+
+        debug!(
+            session = self.user_data.auth.sessions.as_str(),
+            "performing logout",
+        );
 
-        debug!(session = session.as_str(), "performing logout",);
+        self.delete_session(client, &session_element_id(&self.access_token).to_string())
+            .await?;
+
+        debug!("logout done");
+
+        Ok(())
+    }
+
+    /// List all sessions of the user and delete them if `--yes` is passed.
+    ///
+    /// The current session is deleted last. If it is not cached, this happens during the regular
+    /// [`logout`](Self::logout).
+    pub(crate) async fn logout_all(&self, client: &Client, cfg: LogoutAllCLIConfig) -> Result<()> {
+        let current = session_element_id(&self.access_token).to_string();
+
+        let sessions = client
+            .stream::<SessionResponse>(
+                Prefix::Sys,
+                &format!("session/{}", self.user_data.auth.sessions),
+                Some(&self.access_token),
+                None,
+                false,
+            )
+            .try_collect::<Vec<_>>()
+            .await
+            .context("list sessions")?;
+
+        for s in &sessions {
+            let client_identifier = self.decrypt_client_identifier(s).unwrap_or_else(|e| {
+                debug!(error = format!("{e:#}"), "cannot decrypt client identifier");
+                "?".to_owned()
+            });
+            let marker = if s.id[1] == current { " (current)" } else { "" };
+            println!("{}\t{client_identifier}{marker}", s.id[1]);
+        }
+
+        if !cfg.yes {
+            bail!(
+                "{} sessions found, pass `--yes` to delete them",
+                sessions.len()
+            );
+        }
+
+        let mut failed = 0;
+        for s in sessions.iter().filter(|s| s.id[1] != current) {
+            match self.delete_session(client, &s.id[1]).await {
+                Ok(()) => info!(session = s.id[1].as_str(), "session deleted"),
+                Err(e) => {
+                    warn!(
+                        session = s.id[1].as_str(),
+                        error = format!("{e:#}"),
+                        "cannot delete session"
+                    );
+                    failed += 1;
+                }
+            }
+        }
+
+        // a cached session is never deleted by the regular logout
+        if self.cached {
+            self.delete_session(client, &current)
+                .await
+                .context("delete current session")?;
+        }
 
+        if failed > 0 {
+            bail!(
+                "{failed} of {} sessions could not be deleted",
+                sessions.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    fn decrypt_client_identifier(&self, s: &SessionResponse) -> Result<String> {
+        let (Some(owner_group), Some(owner_enc_session_key)) =
+            (&s.owner_group, s.owner_enc_session_key.0)
+        else {
+            bail!("session key missing");
+        };
+        let session_key = decrypt_key(
+            self.group_keys.get(owner_group, s.owner_key_version)?,
+            owner_enc_session_key,
+        )
+        .context("decrypt session key")?;
+        let client_identifier =
+            decrypt_value(session_key, &s.client_identifier).context("decrypt")?;
+        String::from_utf8(client_identifier).context("decode string")
+    }
+
+    async fn delete_session(&self, client: &Client, element_id: &str) -> Result<()> {
         client
             .do_no_response(Request {
                 method: Method::DELETE,
-                host: DEFAULT_HOST,
+                host: None,
                 prefix: Prefix::Sys,
-                path: &format!(
-                    "session/{}/{}",
-                    session,
-                    session_element_id(&self.access_token)
-                ),
+                path: &format!("session/{}/{}", self.user_data.auth.sessions, element_id),
                 data: &(),
                 access_token: Some(&self.access_token),
                 query: &[],
             })
             .await
-            .context("session deletion")?;
+            .context("session deletion")
+    }
+}
 
-        debug!("logout done");
+/// Derive passkey from password, using the `--passkey-cache` if configured.
+///
+/// Also returns whether the passkey was loaded from the cache. New passkeys are not cached here,
+/// see [`store_passkey`].
+async fn passkey(
+    config: &LoginCLIConfig,
+    username: &str,
+    kdf_version: KdfVersion,
+    salt: &[u8],
+    password: &str,
+) -> Result<(UserPassphraseKey, bool)> {
+    if let Some((path, cache_key)) = passkey_cache(config) {
+        if let Some(pk) = load_cached_passkey(path, cache_key, username, kdf_version, salt)
+            .await
+            .context("load cached passkey")?
+        {
+            return Ok((pk, true));
+        }
+    }
 
-        Ok(())
+    let pk = derive_passkey(kdf_version, password, salt).context("derive passkey")?;
+    Ok((pk, false))
+}
+
+/// Write passkey to the `--passkey-cache` if configured.
+///
+/// This must only be called once the passkey decrypted the user group key, otherwise a mistyped
+/// password would be cached until the salt changes.
+async fn store_passkey(
+    config: &LoginCLIConfig,
+    username: &str,
+    kdf_version: KdfVersion,
+    salt: &[u8],
+    pk: &UserPassphraseKey,
+) -> Result<()> {
+    let Some((path, cache_key)) = passkey_cache(config) else {
+        return Ok(());
+    };
+
+    let cached = CachedPasskey {
+        mail_address: username.to_owned(),
+        kdf_version,
+        salt: salt.into(),
+        passkey: encrypt_value(cache_key, pk)
+            .context("encrypt passkey")?
+            .into(),
+    };
+    let data = serde_json::to_vec(&cached).context("serialize passkey")?;
+    write_to_private_file(&data, path)
+        .await
+        .context("write passkey cache")?;
+    debug!(path=%path.display(), "passkey cached");
+
+    Ok(())
+}
+
+/// Path and encryption key of the `--passkey-cache`, if configured.
+fn passkey_cache(config: &LoginCLIConfig) -> Option<(&Path, Key)> {
+    match (&config.passkey_cache, &config.passkey_cache_secret) {
+        (Some(path), Some(secret)) => Some((path, passkey_cache_key(secret))),
+        _ => None,
+    }
+}
+
+/// Key that encrypts the `--passkey-cache`.
+fn passkey_cache_key(secret: &str) -> Key {
+    let mut hasher = Sha256::new();
+    hasher.update(secret.as_bytes());
+    Key::Aes256(hasher.finalize().into())
+}
+
+/// Load cached passkey.
+///
+/// Returns `None` if there is no usable cached passkey.
+async fn load_cached_passkey(
+    path: &Path,
+    cache_key: Key,
+    username: &str,
+    kdf_version: KdfVersion,
+    salt: &[u8],
+) -> Result<Option<UserPassphraseKey>> {
+    let data = match tokio::fs::read(path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            debug!(path=%path.display(), "no cached passkey");
+            return Ok(None);
+        }
+        Err(e) => {
+            return Err(e).context("read passkey cache");
+        }
+    };
+
+    let cached: CachedPasskey = match serde_json::from_slice(&data) {
+        Ok(cached) => cached,
+        Err(e) => {
+            warn!(%e, path=%path.display(), "cannot parse cached passkey, ignoring it");
+            return Ok(None);
+        }
+    };
+    if cached.mail_address != username
+        || cached.kdf_version != kdf_version
+        || cached.salt.as_ref() != salt
+    {
+        debug!("cached passkey is outdated or belongs to different user");
+        return Ok(None);
+    }
+
+    let key = match decrypt_value(cache_key, &cached.passkey) {
+        Ok(key) => key,
+        Err(e) => {
+            warn!(
+                error = format!("{e:#}"),
+                "cannot decrypt cached passkey, wrong secret?"
+            );
+            return Ok(None);
+        }
+    };
+    let key = if let Ok(k) = key.as_slice().try_into() {
+        Key::Aes256(k)
+    } else if let Ok(k) = key.as_slice().try_into() {
+        Key::Aes128(k)
+    } else {
+        warn!(len = key.len(), "cached passkey has invalid length");
+        return Ok(None);
+    };
+
+    debug!("reuse cached passkey");
+    Ok(Some(key.into()))
+}
+
+/// Trim and lowercase username, which is always a mail address.
+///
+/// The server treats mail addresses case-insensitively, but the salt and the caches are keyed by
+/// the exact string, so a stray space or uppercase letter causes confusing login failures.
+fn normalize_username(username: &str) -> Result<String> {
+    let username = username.trim().to_lowercase();
+
+    let valid = match username.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && !domain.contains('@')
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !username.contains(char::is_whitespace)
+        }
+        None => false,
+    };
+    ensure!(
+        valid,
+        "invalid username `{username}`, expected a mail address like `me@tuta.com`"
+    );
+
+    Ok(username)
+}
+
+/// Check that at most one credential is configured.
+///
+/// The CLI parser rejects conflicting arguments, but the config may also be built by library users
+/// or be modified by `--account`.
+fn check_credentials(config: &LoginCLIConfig) -> Result<()> {
+    let credentials = [
+        config.password.is_some(),
+        config.password_file.is_some(),
+        config.password_stdin,
+        config.recover_code.is_some(),
+    ];
+    ensure!(
+        credentials.into_iter().filter(|set| *set).count() <= 1,
+        "only one of `--password`, `--password-file`, `--password-stdin`, and `--recover-code` \
+         may be used"
+    );
+    Ok(())
+}
+
+/// Get password from any of the configured sources.
+async fn read_password(config: &LoginCLIConfig) -> Result<Option<NonEmptyString>> {
+    let raw = if let Some(path) = &config.password_file {
+        tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("read password file `{}`", path.display()))?
+    } else if config.password_stdin {
+        let mut s = String::new();
+        tokio::io::stdin()
+            .read_to_string(&mut s)
+            .await
+            .context("read password from stdin")?;
+        s
+    } else {
+        return Ok(config.password.clone());
+    };
+
+    let password = trim_newline(&raw)
+        .parse::<NonEmptyString>()
+        .map_err(|e| anyhow!("password {e}"))?;
+    Ok(Some(password))
+}
+
+/// Remove a single trailing newline.
+fn trim_newline(s: &str) -> &str {
+    s.strip_suffix("\r\n")
+        .or_else(|| s.strip_suffix('\n'))
+        .unwrap_or(s)
+}
+
+/// Secret used to log in.
+#[derive(Debug)]
+enum Credentials {
+    Password(UserPassphraseKey),
+
+    /// The recovery code is a key that encrypts the user group key.
+    RecoverCode(Key),
+}
+
+/// Create new session via the session service.
+///
+/// The verifier is derived from the credential key in both cases, but the server expects it in
+/// different fields.
+async fn create_session(
+    client: &Client,
+    username: &str,
+    credentials: &Credentials,
+) -> Result<(String, AccessToken)> {
+    let (auth_verifier, recover_code_verifier) = match credentials {
+        Credentials::Password(pk) => (Some(encode_auth_verifier(pk)), None),
+        Credentials::RecoverCode(key) => (None, Some(encode_auth_verifier(key))),
+    };
+
+    let req = SessionServiceRequest {
+        format: Default::default(),
+        access_key: Default::default(),
+        auth_token: Default::default(),
+        auth_verifier,
+        client_identifier: client.client_identifier().to_owned(),
+        mail_address: username.to_owned(),
+        recover_code_verifier,
+        user: Default::default(),
+    };
+    let resp: SessionServiceResponse = client
+        .do_json(Request {
+            method: Method::POST,
+            ..Request::new(Prefix::Sys, "sessionservice", &req)
+        })
+        .await
+        .context("get session")?;
+
+    debug!(user = resp.user.as_str(), "got user");
+
+    check_challenges(&resp.challenges).context("second factor")?;
+
+    Ok((resp.user, resp.access_token))
+}
+
+async fn get_user(
+    client: &Client,
+    user_id: &str,
+    access_token: &AccessToken,
+) -> Result<UserResponse> {
+    client.get_entity(None, user_id, Some(access_token)).await
+}
+
+/// Storage for a cached session.
+#[derive(Debug)]
+enum SessionStore {
+    /// `--session-cache`
+    File(PathBuf),
+
+    /// `--use-keyring`
+    Keyring { username: String },
+}
+
+impl SessionStore {
+    /// Service name of keyring entries.
+    const KEYRING_SERVICE: &'static str = "tatutanatata";
+
+    fn new(config: &LoginCLIConfig, username: &str) -> Option<Self> {
+        if let Some(path) = &config.session_cache {
+            Some(Self::File(path.clone()))
+        } else if config.use_keyring {
+            Some(Self::Keyring {
+                username: username.to_owned(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Load data, `None` if nothing was stored yet.
+    async fn load(&self) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::File(path) => match tokio::fs::read(path).await {
+                Ok(data) => Ok(Some(data)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e).context("read session cache file"),
+            },
+            Self::Keyring { username } => {
+                let username = username.clone();
+                // the keyring API is blocking
+                tokio::task::spawn_blocking(move || {
+                    let entry = keyring::Entry::new(Self::KEYRING_SERVICE, &username)?;
+                    match entry.get_secret() {
+                        Ok(data) => Ok(Some(data)),
+                        Err(keyring::Error::NoEntry) => Ok(None),
+                        Err(e) => Err(e),
+                    }
+                })
+                .await
+                .context("join keyring task")?
+                .context("read keyring")
+            }
+        }
+    }
+
+    /// Store data, replacing the previous data.
+    async fn store(&self, data: Vec<u8>) -> Result<()> {
+        match self {
+            Self::File(path) => write_to_private_file(&data, path).await,
+            Self::Keyring { username } => {
+                let username = username.clone();
+                tokio::task::spawn_blocking(move || {
+                    keyring::Entry::new(Self::KEYRING_SERVICE, &username)?.set_secret(&data)
+                })
+                .await
+                .context("join keyring task")?
+                .context("write keyring")
+            }
+        }
+    }
+}
+
+/// Load cached session and check that it is still valid.
+///
+/// Returns `None` if there is no usable cached session.
+async fn load_cached_session(
+    client: &Client,
+    store: &SessionStore,
+    username: &str,
+) -> Result<Option<(String, AccessToken, UserResponse)>> {
+    let Some(data) = store.load().await? else {
+        debug!(store=?store, "no cached session");
+        return Ok(None);
+    };
+
+    let cached: CachedSession = match serde_json::from_slice(&data) {
+        Ok(cached) => cached,
+        Err(e) => {
+            warn!(%e, store=?store, "cannot parse cached session, ignoring it");
+            return Ok(None);
+        }
+    };
+    if cached.mail_address != username {
+        debug!("cached session belongs to different user");
+        return Ok(None);
+    }
+
+    match get_user(client, &cached.user_id, &cached.access_token).await {
+        Ok(user_data) => {
+            debug!(user = cached.user_id.as_str(), "reuse cached session");
+            Ok(Some((cached.user_id, cached.access_token, user_data)))
+        }
+        Err(e) if error_status(&e) == Some(StatusCode::UNAUTHORIZED) => {
+            debug!("cached session expired");
+            Ok(None)
+        }
+        Err(e) => Err(e).context("validate cached session"),
     }
 }
 
+/// Check second-factor challenges returned by the session service.
+///
+/// None of them can be answered by this CLI yet, so this only produces an actionable error.
+fn check_challenges(challenges: &[Challenge]) -> Result<()> {
+    if challenges.is_empty() {
+        return Ok(());
+    }
+
+    let available = challenges
+        .iter()
+        .map(|challenge| match challenge.challenge_type {
+            ChallengeType::U2f => {
+                let app_ids = challenge
+                    .u2f
+                    .iter()
+                    .flat_map(|u2f| u2f.keys.iter().map(|k| k.app_id.as_str()))
+                    .collect::<Vec<_>>();
+                format!(
+                    "U2F/WebAuthn security key (registered for: {})",
+                    app_ids.join(", ")
+                )
+            }
+            ChallengeType::Otp => "TOTP code".to_owned(),
+            ChallengeType::Email => "email confirmation".to_owned(),
+        })
+        .collect::<Vec<_>>();
+    let has_otp = challenges
+        .iter()
+        .any(|challenge| challenge.challenge_type == ChallengeType::Otp);
+
+    if has_otp {
+        bail!(
+            "account requires a second factor ({}), which is not supported yet",
+            available.join(" or "),
+        )
+    } else {
+        bail!(
+            "account requires a second factor ({}), which is not supported yet; \
+             add TOTP as an alternative second factor in the official client",
+            available.join(" or "),
+        )
+    }
+}
+
+/// Keys of all groups the user is a member of.
 #[derive(Debug)]
 pub(crate) struct GroupKeys {
-    keys: HashMap<String, Key>,
+    keys: HashMap<String, VersionedKeys>,
+}
+
+/// Current and former keys of a group.
+#[derive(Debug)]
+struct VersionedKeys {
+    current_version: u64,
+    keys: BTreeMap<u64, Key>,
 }
 
 impl GroupKeys {
-    fn try_new(pk: &UserPassphraseKey, user_data: &UserResponse) -> Result<Self> {
-        let user_key = decrypt_key(
-            *pk.deref(),
-            user_data
-                .user_group
-                .sym_enc_g_key
-                .0
-                .context("user key must be set")?,
-        )
-        .context("decrypt user group key")?;
+    fn try_new(user_key: Key, user_data: &UserResponse) -> Result<Self> {
         let mut group_keys = HashMap::default();
-        group_keys.insert(user_data.user_group.group.clone(), user_key);
+        group_keys.insert(
+            user_data.user_group.group.clone(),
+            VersionedKeys::new(user_data.user_group.group_key_version.0, user_key),
+        );
         for group in &user_data.memberships {
             if let Some(enc_g_key) = group.sym_enc_g_key.0 {
                 group_keys.insert(
                     group.group.clone(),
-                    decrypt_key(user_key, enc_g_key).context("decrypt membership group key")?,
+                    VersionedKeys::new(
+                        group.group_key_version.0,
+                        decrypt_key(user_key, enc_g_key).context("decrypt membership group key")?,
+                    ),
                 );
             }
         }
@@ -167,21 +884,442 @@ impl GroupKeys {
         Ok(Self { keys: group_keys })
     }
 
-    pub(crate) fn get(&self, group: &str) -> Result<Key> {
-        let key = self.keys.get(group).context("group key not found")?;
+    /// Fetch former keys of all groups whose key was rotated.
+    ///
+    /// Failures are only logged, so that accounts still work for data that is encrypted with
+    /// current keys. [`get`](Self::get) reports the missing version instead.
+    async fn load_former_keys(&mut self, client: &Client, access_token: &AccessToken) {
+        for (group, keys) in &mut self.keys {
+            if keys.current_version == 0 {
+                continue;
+            }
+
+            match fetch_former_keys(client, access_token, group).await {
+                Ok(former) => {
+                    if let Err(e) = keys.add_former(former) {
+                        warn!(%e, group = group.as_str(), "cannot decrypt former group keys");
+                    }
+                }
+                Err(e) => {
+                    warn!(%e, group = group.as_str(), "cannot fetch former group keys");
+                }
+            }
+        }
+    }
+
+    /// Get key of given group in the given version.
+    pub(crate) fn get(&self, group: &str, version: Number) -> Result<Key> {
+        let keys = self.keys.get(group).context("group key not found")?;
+        let key = keys.keys.get(&version.0).with_context(|| {
+            format!(
+                "key version {} of group `{group}` not available (current version: {})",
+                version.0, keys.current_version,
+            )
+        })?;
         Ok(*key)
     }
 }
 
+impl VersionedKeys {
+    fn new(current_version: u64, current: Key) -> Self {
+        Self {
+            current_version,
+            keys: BTreeMap::from([(current_version, current)]),
+        }
+    }
+
+    /// Decrypt former keys, each is encrypted with the next newer version.
+    fn add_former(&mut self, mut former: Vec<(u64, EncryptedKey)>) -> Result<()> {
+        former.sort_by_key(|(version, _)| std::cmp::Reverse(*version));
+
+        for (version, enc_key) in former {
+            if version >= self.current_version {
+                continue;
+            }
+            let Some(newer) = self.keys.get(&(version + 1)) else {
+                bail!("former group key version {} missing", version + 1);
+            };
+            let key = decrypt_key(*newer, enc_key)
+                .with_context(|| format!("decrypt former group key version {version}"))?;
+            self.keys.insert(version, key);
+        }
+
+        Ok(())
+    }
+}
+
+async fn fetch_former_keys(
+    client: &Client,
+    access_token: &AccessToken,
+    group: &str,
+) -> Result<Vec<(u64, EncryptedKey)>> {
+    let resp: GroupResponse = client
+        .get_entity(None, group, Some(access_token))
+        .await
+        .context("get group")?;
+    let Some(former_group_keys) = resp.former_group_keys else {
+        return Ok(vec![]);
+    };
+
+    client
+        .stream::<GroupKeyResponse>(
+            Prefix::Sys,
+            &format!("groupkey/{}", former_group_keys.list),
+            Some(access_token),
+            Some(CUSTOM_MIN_ID),
+            false,
+        )
+        .map_err(|e| e.context("get former group key"))
+        .and_then(|k| async move {
+            let version = custom_id_to_string(&k.id[1])?
+                .parse()
+                .context("invalid group key version")?;
+            Ok((version, k.owner_enc_g_key))
+        })
+        .try_collect()
+        .await
+}
+
 const GENERATE_ID_BYTES_LENGTH: usize = 9;
 
-fn session_element_id(access_token: &Base64Url) -> Base64Url {
+fn session_element_id(access_token: &AccessToken) -> Base64Url {
     let mut hasher = Sha256::new();
     hasher.update(&access_token.as_ref()[GENERATE_ID_BYTES_LENGTH..]);
     hasher.finalize().to_vec().into()
 }
 
 #[allow(dead_code)]
-fn session_list_id(access_token: &Base64Url) -> Base64Url {
+fn session_list_id(access_token: &AccessToken) -> Base64Url {
     access_token.as_ref()[..GENERATE_ID_BYTES_LENGTH].into()
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use crate::crypto::encryption::encrypt;
+
+    use super::*;
+
+    #[test]
+    fn test_check_kdf() {
+        check_kdf(KdfVersion::Argon2id, None).unwrap();
+        check_kdf(KdfVersion::Bcrypt, None).unwrap();
+        check_kdf(KdfVersion::Argon2id, Some(Kdf::Argon2id)).unwrap();
+        check_kdf(KdfVersion::Bcrypt, Some(Kdf::Bcrypt)).unwrap();
+        assert_eq!(
+            check_kdf(KdfVersion::Bcrypt, Some(Kdf::Argon2id))
+                .unwrap_err()
+                .to_string(),
+            "server asks for key derivation function Bcrypt but Argon2id is required by \
+             `--require-kdf`",
+        );
+    }
+
+    #[test]
+    fn test_normalize_username() {
+        assert_eq!(normalize_username(" Me@Tuta.com\n").unwrap(), "me@tuta.com");
+        assert_eq!(
+            normalize_username("first.last@sub.example.org").unwrap(),
+            "first.last@sub.example.org"
+        );
+
+        for invalid in [
+            "me",
+            "@tuta.com",
+            "me@",
+            "me@tuta",
+            "me@@tuta.com",
+            "me@tuta.com.",
+            "m e@tuta.com",
+        ] {
+            assert_eq!(
+                normalize_username(invalid).unwrap_err().to_string(),
+                format!(
+                    "invalid username `{}`, expected a mail address like `me@tuta.com`",
+                    invalid.to_lowercase(),
+                ),
+            );
+        }
+    }
+
+    #[test]
+    fn test_group_keys_versions() {
+        let key_v0 = Key::Aes256([1; 32]);
+        let key_v1 = Key::Aes256([2; 32]);
+        let enc_key_v0 = EncryptedKey::Aes256WithMac(
+            encrypt(key_v1, [3; 16], &key_v0, false, true)
+                .try_into()
+                .unwrap(),
+        );
+
+        let mut keys = VersionedKeys::new(1, key_v1);
+        keys.add_former(vec![(0, enc_key_v0)]).unwrap();
+        let group_keys = GroupKeys {
+            keys: HashMap::from([("group".to_owned(), keys)]),
+        };
+
+        assert_eq!(group_keys.get("group", Number(0)).unwrap(), key_v0);
+        assert_eq!(group_keys.get("group", Number(1)).unwrap(), key_v1);
+        assert_eq!(
+            group_keys.get("group", Number(2)).unwrap_err().to_string(),
+            "key version 2 of group `group` not available (current version: 1)",
+        );
+        assert_eq!(
+            group_keys.get("other", Number(0)).unwrap_err().to_string(),
+            "group key not found",
+        );
+
+        // the chain must not have gaps
+        let mut keys = VersionedKeys::new(2, key_v1);
+        assert_eq!(
+            keys.add_former(vec![(0, enc_key_v0)])
+                .unwrap_err()
+                .to_string(),
+            "former group key version 1 missing",
+        );
+    }
+
+    #[tokio::test]
+    async fn test_passkey_cache() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("passkey.json");
+        let config = LoginCLIConfig::parse_from([
+            "x",
+            "--username=foo@example.com",
+            "--password=secret",
+            &format!("--passkey-cache={}", path.display()),
+            "--passkey-cache-secret=cache_secret",
+        ]);
+        let salt = b"saltsaltsaltsalt";
+        let key = passkey_cache_key("cache_secret");
+        let load = |key, username, salt| {
+            load_cached_passkey(&path, key, username, KdfVersion::Bcrypt, salt)
+        };
+
+        assert!(load(key, "foo@example.com", salt).await.unwrap().is_none());
+
+        let (pk, cache_hit) = passkey(
+            &config,
+            "foo@example.com",
+            KdfVersion::Bcrypt,
+            salt,
+            "secret",
+        )
+        .await
+        .unwrap();
+        assert!(!cache_hit);
+
+        // not cached before the login succeeded
+        assert!(load(key, "foo@example.com", salt).await.unwrap().is_none());
+
+        store_passkey(&config, "foo@example.com", KdfVersion::Bcrypt, salt, &pk)
+            .await
+            .unwrap();
+        assert_eq!(
+            *load(key, "foo@example.com", salt).await.unwrap().unwrap(),
+            *pk,
+        );
+
+        // cache hit, password is not used
+        let (pk2, cache_hit) = passkey(
+            &config,
+            "foo@example.com",
+            KdfVersion::Bcrypt,
+            salt,
+            "other",
+        )
+        .await
+        .unwrap();
+        assert!(cache_hit);
+        assert_eq!(*pk2, *pk);
+
+        // invalidated
+        assert!(load(key, "foo@example.com", b"othersaltothersa")
+            .await
+            .unwrap()
+            .is_none());
+        assert!(load(key, "bar@example.com", salt).await.unwrap().is_none());
+        assert!(load(passkey_cache_key("wrong"), "foo@example.com", salt)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_check_credentials() {
+        let mut config = LoginCLIConfig::parse_from(["x", "--password=secret"]);
+        check_credentials(&config).unwrap();
+
+        config.recover_code = Some("code".parse().unwrap());
+        assert_eq!(
+            check_credentials(&config).unwrap_err().to_string(),
+            "only one of `--password`, `--password-file`, `--password-stdin`, and `--recover-code` \
+             may be used",
+        );
+    }
+
+    #[test]
+    fn test_trim_newline() {
+        assert_eq!(trim_newline(""), "");
+        assert_eq!(trim_newline("foo"), "foo");
+        assert_eq!(trim_newline("foo\n"), "foo");
+        assert_eq!(trim_newline("foo\r\n"), "foo");
+        assert_eq!(trim_newline("foo\n\n"), "foo\n");
+        assert_eq!(trim_newline(" foo \n"), " foo ");
+    }
+
+    #[test]
+    fn test_check_challenges() {
+        check_challenges(&[]).unwrap();
+
+        let challenges: Vec<Challenge> = serde_json::from_str(
+            r#"[{"_id": "x", "type": "0", "u2f": {"keys": [{"appId": "https://tuta.com/u2f-appid.json"}]}}]"#,
+        )
+        .unwrap();
+        let err = check_challenges(&challenges).unwrap_err().to_string();
+        assert!(err.contains("U2F/WebAuthn"), "{err}");
+        assert!(err.contains("https://tuta.com/u2f-appid.json"), "{err}");
+        assert!(err.contains("add TOTP"), "{err}");
+
+        let challenges: Vec<Challenge> =
+            serde_json::from_str(r#"[{"_id": "x", "type": "1", "u2f": null}]"#).unwrap();
+        let err = check_challenges(&challenges).unwrap_err().to_string();
+        assert!(err.contains("TOTP code"), "{err}");
+        assert!(!err.contains("add TOTP"), "{err}");
+
+        let challenges: Vec<Challenge> = serde_json::from_str(
+            r#"[
+                {"_id": "x", "type": "0", "u2f": {"keys": [{"appId": "https://tuta.com/u2f-appid.json"}]}},
+                {"_id": "y", "type": "1", "u2f": null}
+            ]"#,
+        )
+        .unwrap();
+        let err = check_challenges(&challenges).unwrap_err().to_string();
+        assert!(err.contains("U2F/WebAuthn"), "{err}");
+        assert!(err.contains("https://tuta.com/u2f-appid.json"), "{err}");
+        assert!(err.contains("TOTP code"), "{err}");
+        assert!(!err.contains("add TOTP"), "{err}");
+    }
+
+    /// Read HTTP/1.1 request, returns the head in lower case and the body.
+    async fn read_request(conn: &mut tokio::net::TcpStream) -> (String, String) {
+        let mut head = vec![];
+        while !head.ends_with(b"\r\n\r\n") {
+            head.push(conn.read_u8().await.unwrap());
+        }
+        let head = String::from_utf8(head).unwrap().to_lowercase();
+        let content_length = head
+            .lines()
+            .find_map(|l| l.strip_prefix("content-length: "))
+            .map(|l| l.parse().unwrap())
+            .unwrap_or_default();
+        let mut body = vec![0; content_length];
+        conn.read_exact(&mut body).await.unwrap();
+        (head, String::from_utf8(body).unwrap())
+    }
+
+    async fn test_client(addr: std::net::SocketAddr, args: &[&str]) -> Client {
+        let server_url = format!("--server-url=http://{addr}");
+        Client::try_new(
+            crate::client::ClientCLIConfig::parse_from(
+                [
+                    "tatutanatata",
+                    &server_url,
+                    "--allow-insecure-http",
+                    "--max-retries=0",
+                ]
+                .iter()
+                .chain(args),
+            ),
+            None,
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_create_session_identifies_client() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let req = read_request(&mut conn).await;
+            conn.write_all(b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n")
+                .await
+                .unwrap();
+            req
+        });
+
+        let client = test_client(
+            addr,
+            &["--user-agent=my-agent/1.0", "--client-identifier=my-backup"],
+        )
+        .await;
+        let e = create_session(
+            &client,
+            "me@example.com",
+            &Credentials::RecoverCode(Key::Aes256([1; 32])),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error_status(&e), Some(StatusCode::UNAUTHORIZED));
+
+        let (head, body) = server.await.unwrap();
+        assert!(head.starts_with("post /rest/sys/sessionservice "), "{head}");
+        assert!(head.contains("\r\nuser-agent: my-agent/1.0\r\n"), "{head}");
+        assert!(
+            body.contains("\"clientIdentifier\":\"my-backup\""),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_require_kdf() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut conn, _) = listener.accept().await.unwrap();
+            let (head, body) = read_request(&mut conn).await;
+            let resp = r#"{"_format":"0","kdfVersion":"0","salt":"AAAAAAAAAAAAAAAAAAAAAA=="}"#;
+            conn.write_all(
+                format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                     content-length: {}\r\n\r\n{resp}",
+                    resp.len()
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+            // no session is requested, the client only closes the connection
+            let mut rest = vec![];
+            conn.read_to_end(&mut rest).await.unwrap();
+            (head, body, rest)
+        });
+
+        let client = test_client(addr, &[]).await;
+        let config = LoginCLIConfig::parse_from([
+            "tatutanatata",
+            "--username=me@example.com",
+            "--password=secret",
+            "--require-kdf=argon2id",
+        ]);
+        let e = Session::login(config, &client).await.unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "server asks for key derivation function Bcrypt but Argon2id is required by \
+             `--require-kdf`",
+        );
+        drop(client);
+
+        let (head, body, rest) = server.await.unwrap();
+        assert!(head.starts_with("get /rest/sys/saltservice "), "{head}");
+        assert!(
+            body.contains("\"mailAddress\":\"me@example.com\""),
+            "{body}"
+        );
+        assert!(rest.is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+//! Bandwidth limit for downloads.
+use std::{
+    num::NonZeroU64,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Token bucket that limits the throughput of all response bodies.
+///
+/// The bucket is shared by all concurrent requests, so the limit applies to the sum of them. It
+/// holds at most one second worth of tokens, so idle periods allow short bursts only.
+#[derive(Debug)]
+pub(crate) struct Throttle {
+    /// Bytes per second.
+    rate: NonZeroU64,
+
+    /// Available tokens and time of the last update.
+    ///
+    /// Tokens become negative if more bytes were received than available, later callers then wait
+    /// for this debt as well.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl Throttle {
+    pub(crate) fn new(rate: NonZeroU64) -> Self {
+        Self {
+            rate,
+            bucket: Mutex::new((rate.get() as f64, Instant::now())),
+        }
+    }
+
+    /// Account for `bytes` that were received and wait until the rate allows them.
+    pub(crate) async fn consume(&self, bytes: usize) {
+        let delay = self.take(bytes, Instant::now());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// Take tokens for `bytes` at time `now`, returns the time to wait until they were available.
+    fn take(&self, bytes: usize, now: Instant) -> Duration {
+        let rate = self.rate.get() as f64;
+        let mut guard = self.bucket.lock().expect("not poisoned");
+        let (tokens, last) = &mut *guard;
+
+        let elapsed = now.saturating_duration_since(*last).as_secs_f64();
+        *tokens = (*tokens + elapsed * rate).min(rate) - bytes as f64;
+        *last = now.max(*last);
+
+        if *tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-*tokens / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take() {
+        let throttle = Throttle::new(NonZeroU64::new(1000).unwrap());
+        let start = throttle.bucket.lock().unwrap().1;
+
+        // initial burst
+        assert_eq!(throttle.take(600, start), Duration::ZERO);
+        assert_eq!(throttle.take(400, start), Duration::ZERO);
+
+        // bucket is empty, concurrent callers queue up behind each other
+        assert_eq!(throttle.take(500, start), Duration::from_millis(500));
+        assert_eq!(throttle.take(500, start), Duration::from_secs(1));
+
+        // debt is paid off over time
+        let now = start + Duration::from_secs(1);
+        assert_eq!(throttle.take(100, now), Duration::from_millis(100));
+
+        // idle time only fills the bucket up to one second worth of tokens
+        let now = now + Duration::from_secs(60);
+        assert_eq!(throttle.take(1000, now), Duration::ZERO);
+        assert_eq!(throttle.take(2000, now), Duration::from_secs(2));
+    }
+}
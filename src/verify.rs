@@ -0,0 +1,203 @@
+//! Integrity check of downloaded EML files.
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{ArgGroup, Parser};
+use futures::TryStreamExt;
+use serde::Serialize;
+
+use crate::{
+    client::Client,
+    eml::verify_eml,
+    file_output::EmlIndex,
+    folders::{Folder, SystemFolder},
+    mails::Mail,
+    session::Session,
+};
+
+/// Verify archive CLI config.
+#[derive(Debug, Parser)]
+#[clap(group(
+    ArgGroup::new("selection")
+        .args(["folder", "folder_id", "system_folder"]),
+))]
+pub(crate) struct VerifyArchiveCLIConfig {
+    /// Output directory of `download`, searched recursively for `.eml` files.
+    #[clap(long, action)]
+    path: PathBuf,
+
+    /// Also check that all mails of the folder with this name exist in the archive.
+    ///
+    /// This logs in and compares the mails on the server with the index within the target path,
+    /// so the path must be the output directory of exactly this folder. Mails that were
+    /// downloaded by versions without an index are reported as missing.
+    #[clap(long, action)]
+    folder: Option<String>,
+
+    /// Same as `--folder` but selects the folder by its ID.
+    #[clap(long, action)]
+    folder_id: Option<String>,
+
+    /// Same as `--folder` but selects a system folder, independent of the account language.
+    #[clap(long, action, value_enum)]
+    system_folder: Option<SystemFolder>,
+
+    /// Print the report as JSON object to stdout instead of text.
+    #[clap(long, action)]
+    json: bool,
+}
+
+impl VerifyArchiveCLIConfig {
+    /// Comparing with the server needs a session.
+    pub(crate) fn needs_login(&self) -> bool {
+        self.folder.is_some() || self.folder_id.is_some() || self.system_folder.is_some()
+    }
+}
+
+/// File that failed verification.
+#[derive(Debug, Serialize)]
+struct CorruptFile {
+    path: PathBuf,
+    error: String,
+}
+
+/// Mail that exists on the server but not in the archive.
+#[derive(Debug, Serialize)]
+struct MissingMail {
+    mail_id: String,
+    ui_url: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    checked: usize,
+    corrupt: Vec<CorruptFile>,
+    missing: Vec<MissingMail>,
+}
+
+/// Check all EML files of an archive and, if a folder is selected, find mails that are missing.
+///
+/// `server` is only used (and required) if [`VerifyArchiveCLIConfig::needs_login`].
+pub(crate) async fn verify_archive(
+    server: Option<(&Client, &Session)>,
+    cfg: VerifyArchiveCLIConfig,
+) -> Result<()> {
+    let mut report = Report::default();
+
+    for path in eml_files(&cfg.path).await? {
+        let content = tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("read `{}`", path.display()))?;
+        report.checked += 1;
+        if let Err(e) = verify_eml(&content) {
+            report.corrupt.push(CorruptFile {
+                path,
+                error: format!("{e:#}"),
+            });
+        }
+    }
+
+    if cfg.needs_login() {
+        let (client, session) = server.context("login required")?;
+        let folders = Folder::list(client, session)
+            .await
+            .context("get folders")?
+            .try_collect::<Vec<_>>()
+            .await
+            .context("list folders")?;
+        let folder = Folder::find(
+            &folders,
+            cfg.folder.as_deref(),
+            cfg.folder_id.as_deref(),
+            cfg.system_folder,
+        )?;
+
+        let index = EmlIndex::open(&cfg.path, false)
+            .await
+            .context("open eml index")?;
+        let mails = Mail::list(client, session, folder, None, false)
+            .try_collect::<Vec<_>>()
+            .await
+            .context("list mails")?;
+        for mail in mails {
+            if index.get(&mail.mail_id).await?.is_none() {
+                report.missing.push(MissingMail {
+                    mail_id: mail.mail_id.clone(),
+                    ui_url: mail.ui_url(client),
+                });
+            }
+        }
+    }
+
+    if cfg.json {
+        println!(
+            "{}",
+            serde_json::to_string(&report).context("serialize report")?
+        );
+    } else {
+        for f in &report.corrupt {
+            println!("corrupt\t{}\t{}", f.path.display(), f.error);
+        }
+        for m in &report.missing {
+            println!("missing\t{}\t{}", m.mail_id, m.ui_url);
+        }
+        eprintln!(
+            "checked {} files, {} corrupt, {} missing",
+            report.checked,
+            report.corrupt.len(),
+            report.missing.len(),
+        );
+    }
+
+    if !report.corrupt.is_empty() || !report.missing.is_empty() {
+        bail!(
+            "archive is damaged: {} corrupt files, {} missing mails",
+            report.corrupt.len(),
+            report.missing.len(),
+        );
+    }
+    Ok(())
+}
+
+/// All `.eml` files within the directory and its subdirectories, sorted by path.
+async fn eml_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = tokio::fs::read_dir(&dir)
+            .await
+            .with_context(|| format!("read `{}`", dir.display()))?;
+        while let Some(entry) = entries.next_entry().await.context("read dir entry")? {
+            let path = entry.path();
+            let file_type = entry.file_type().await.context("get file type")?;
+            if file_type.is_dir() {
+                dirs.push(path);
+            } else if file_type.is_file() && path.extension().is_some_and(|ext| ext == "eml") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_eml_files() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(dir.path().join("sub").join("deeper")).unwrap();
+        for name in ["b.eml", "a.eml", "a.json", "sub/c.eml", "sub/deeper/d.eml"] {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+
+        let files = eml_files(dir.path()).await.unwrap();
+        let files = files
+            .iter()
+            .map(|p| p.strip_prefix(dir.path()).unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["a.eml", "b.eml", "sub/c.eml", "sub/deeper/d.eml"]);
+    }
+}
@@ -0,0 +1,301 @@
+//! Account details of the logged-in user.
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::{
+    client::{Client, Prefix, Request},
+    proto::{
+        enums::CounterType,
+        messages::{GroupInfoResponse, ReadCounterRequest, ReadCounterResponse},
+    },
+    session::Session,
+};
+
+/// Whoami CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct WhoamiCLIConfig {
+    /// Print the details as JSON object to stdout instead of text.
+    #[clap(long, action)]
+    json: bool,
+}
+
+/// List aliases CLI config.
+#[derive(Debug, Parser)]
+pub(crate) struct ListAliasesCLIConfig {
+    /// Print the addresses as JSON object to stdout instead of text.
+    #[clap(long, action)]
+    json: bool,
+}
+
+/// Additional mail address of the account.
+#[derive(Debug, Serialize)]
+pub(crate) struct Alias {
+    pub(crate) mail_address: String,
+    pub(crate) enabled: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct Addresses {
+    mail_address: Option<String>,
+    aliases: Vec<Alias>,
+}
+
+#[derive(Debug, Serialize)]
+struct Membership {
+    group_type: &'static str,
+    group: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Whoami {
+    user_id: String,
+    mail_address: Option<String>,
+    aliases: Vec<Alias>,
+
+    /// Used storage in bytes, if the server reported it.
+    storage_used: Option<u64>,
+    memberships: Vec<Membership>,
+}
+
+/// Print details about the logged-in account.
+pub(crate) async fn whoami(client: &Client, session: &Session, cfg: WhoamiCLIConfig) -> Result<()> {
+    let group_info = user_group_info(client, session).await?;
+
+    let storage_used = match storage_used(client, session).await {
+        Ok(used) => used,
+        Err(e) => {
+            warn!(error = format!("{e:#}"), "cannot get storage usage");
+            None
+        }
+    };
+
+    let user_data = &session.user_data;
+    let whoami = Whoami {
+        user_id: session.user_id.clone(),
+        mail_address: group_info.mail_address.clone(),
+        aliases: aliases(&group_info),
+        storage_used,
+        memberships: std::iter::once(&user_data.user_group)
+            .chain(&user_data.memberships)
+            .map(|m| Membership {
+                group_type: m.group_type.name(),
+                group: m.group.clone(),
+            })
+            .collect(),
+    };
+
+    print!("{}", whoami.format(cfg.json)?);
+
+    Ok(())
+}
+
+impl Whoami {
+    /// Format as tab-separated text or, if `json` is set, as JSON object.
+    fn format(&self, json: bool) -> Result<String> {
+        if json {
+            let mut out = serde_json::to_string(self).context("serialize account details")?;
+            out.push('\n');
+            return Ok(out);
+        }
+
+        let mut out = format!("user id\t{}\n", self.user_id);
+        out.push_str(&format!(
+            "mail address\t{}\n",
+            self.mail_address.as_deref().unwrap_or("-")
+        ));
+        for alias in &self.aliases {
+            let marker = if alias.enabled { "" } else { " (disabled)" };
+            out.push_str(&format!("alias\t{}{marker}\n", alias.mail_address));
+        }
+        match self.storage_used {
+            Some(used) => out.push_str(&format!("storage used\t{used} bytes\n")),
+            None => out.push_str("storage used\tunknown\n"),
+        }
+        for m in &self.memberships {
+            out.push_str(&format!("membership\t{}\t{}\n", m.group_type, m.group));
+        }
+        Ok(out)
+    }
+}
+
+/// Print the primary mail address and all aliases of the account.
+pub(crate) async fn list_aliases(
+    client: &Client,
+    session: &Session,
+    cfg: ListAliasesCLIConfig,
+) -> Result<()> {
+    let group_info = user_group_info(client, session).await?;
+    let addresses = Addresses {
+        aliases: aliases(&group_info),
+        mail_address: group_info.mail_address,
+    };
+
+    print!("{}", addresses.format(cfg.json)?);
+
+    Ok(())
+}
+
+impl Addresses {
+    /// Format as one address per line or, if `json` is set, as JSON object.
+    fn format(&self, json: bool) -> Result<String> {
+        if json {
+            let mut out = serde_json::to_string(self).context("serialize addresses")?;
+            out.push('\n');
+            return Ok(out);
+        }
+
+        let mut out = String::new();
+        if let Some(mail_address) = &self.mail_address {
+            out.push_str(&format!("{mail_address}\tprimary\n"));
+        }
+        for alias in &self.aliases {
+            let state = if alias.enabled { "alias" } else { "disabled" };
+            out.push_str(&format!("{}\t{state}\n", alias.mail_address));
+        }
+        Ok(out)
+    }
+}
+
+/// Public information of the user group, which holds the mail addresses of the account.
+pub(crate) async fn user_group_info(
+    client: &Client,
+    session: &Session,
+) -> Result<GroupInfoResponse> {
+    let [list_id, id] = &session.user_data.user_group.group_info;
+    client
+        .get_entity(Some(list_id), id, Some(&session.access_token))
+        .await
+        .context("get user group info")
+}
+
+/// Aliases of the account, in the order that the server returns them.
+pub(crate) fn aliases(group_info: &GroupInfoResponse) -> Vec<Alias> {
+    group_info
+        .mail_address_aliases
+        .iter()
+        .map(|a| Alias {
+            mail_address: a.mail_address.clone(),
+            enabled: a.enabled.0,
+        })
+        .collect()
+}
+
+/// Storage used by the account in bytes, same as shown in the settings of the official client.
+async fn storage_used(client: &Client, session: &Session) -> Result<Option<u64>> {
+    let Some(customer) = &session.user_data.customer else {
+        return Ok(None);
+    };
+
+    let req = ReadCounterRequest {
+        format: Default::default(),
+        counter_type: CounterType::UserStorageLegacy,
+        row_name: customer.clone(),
+        column_name: session.user_data.user_group.group.clone(),
+    };
+    let resp: ReadCounterResponse = client
+        .do_json(Request {
+            access_token: Some(&session.access_token),
+            ..Request::new(Prefix::Monitor, "counterservice", &req)
+        })
+        .await
+        .context("read storage counter")?;
+
+    Ok(resp.value.map(|v| v.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_aliases() {
+        let group_info: GroupInfoResponse = serde_json::from_str(
+            r#"{
+                "_format": "0",
+                "_id": ["list", "element"],
+                "mailAddress": "me@tuta.com",
+                "mailAddressAliases": [
+                    {"_id": "a1", "mailAddress": "alias@tuta.com", "enabled": "1"},
+                    {"_id": "a2", "mailAddress": "old@tuta.com", "enabled": "0"}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(group_info.mail_address.as_deref(), Some("me@tuta.com"));
+        assert_eq!(
+            serde_json::to_string(&aliases(&group_info)).unwrap(),
+            r#"[{"mail_address":"alias@tuta.com","enabled":true},{"mail_address":"old@tuta.com","enabled":false}]"#,
+        );
+
+        let addresses = Addresses {
+            aliases: aliases(&group_info),
+            mail_address: group_info.mail_address,
+        };
+        assert_eq!(
+            addresses.format(false).unwrap(),
+            "me@tuta.com\tprimary\nalias@tuta.com\talias\nold@tuta.com\tdisabled\n",
+        );
+        assert_eq!(
+            addresses.format(true).unwrap(),
+            "{\"mail_address\":\"me@tuta.com\",\"aliases\":[\
+            {\"mail_address\":\"alias@tuta.com\",\"enabled\":true},\
+            {\"mail_address\":\"old@tuta.com\",\"enabled\":false}]}\n",
+        );
+    }
+
+    #[test]
+    fn test_whoami_format() {
+        let mut whoami = Whoami {
+            user_id: "user".to_owned(),
+            mail_address: Some("me@tuta.com".to_owned()),
+            aliases: vec![
+                Alias {
+                    mail_address: "alias@tuta.com".to_owned(),
+                    enabled: true,
+                },
+                Alias {
+                    mail_address: "old@tuta.com".to_owned(),
+                    enabled: false,
+                },
+            ],
+            storage_used: Some(1024),
+            memberships: vec![Membership {
+                group_type: "Mail",
+                group: "group".to_owned(),
+            }],
+        };
+        assert_eq!(
+            whoami.format(false).unwrap(),
+            "user id\tuser\n\
+            mail address\tme@tuta.com\n\
+            alias\talias@tuta.com\n\
+            alias\told@tuta.com (disabled)\n\
+            storage used\t1024 bytes\n\
+            membership\tMail\tgroup\n",
+        );
+        assert_eq!(
+            whoami.format(true).unwrap(),
+            "{\"user_id\":\"user\",\"mail_address\":\"me@tuta.com\",\"aliases\":[\
+            {\"mail_address\":\"alias@tuta.com\",\"enabled\":true},\
+            {\"mail_address\":\"old@tuta.com\",\"enabled\":false}],\
+            \"storage_used\":1024,\"memberships\":[{\"group_type\":\"Mail\",\"group\":\"group\"}]}\n",
+        );
+
+        whoami.mail_address = None;
+        whoami.aliases.clear();
+        whoami.storage_used = None;
+        whoami.memberships.clear();
+        assert_eq!(
+            whoami.format(false).unwrap(),
+            "user id\tuser\nmail address\t-\nstorage used\tunknown\n",
+        );
+        assert_eq!(
+            whoami.format(true).unwrap(),
+            "{\"user_id\":\"user\",\"mail_address\":null,\"aliases\":[],\
+            \"storage_used\":null,\"memberships\":[]}\n",
+        );
+    }
+}
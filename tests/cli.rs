@@ -26,20 +26,200 @@ fn test_version_arg() {
     cmd.arg("--version").assert().success();
 }
 
+#[test]
+fn test_quiet_conflicts_with_verbose() {
+    let assert = cmd()
+        .env_clear()
+        .args(["-q", "-v", "list-folders"])
+        .assert()
+        .code(2);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    assert!(stderr.contains("cannot be used with"), "{stderr}");
+}
+
+#[test]
+fn test_verify_requires_force() {
+    let assert = cmd()
+        .env_clear()
+        .args(["download", "--folder=Inbox", "--path=out", "--verify"])
+        .assert()
+        .code(2);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    assert!(stderr.contains("--force"), "{stderr}");
+}
+
+#[test]
+fn test_exclude_requires_all_folders() {
+    cmd()
+        .env_clear()
+        .args(["download", "--path=out", "--folder=Inbox", "--exclude=spam"])
+        .assert()
+        .code(2);
+    cmd()
+        .env_clear()
+        .args(["sync", "--path=out", "--folder=Inbox", "--exclude=spam"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_download_stdout() {
+    // needs exactly one output
+    cmd()
+        .env_clear()
+        .args(["download", "--folder=Inbox"])
+        .assert()
+        .code(2);
+    cmd()
+        .env_clear()
+        .args(["download", "--folder=Inbox", "--stdout", "--path=out"])
+        .assert()
+        .code(2);
+    cmd()
+        .env_clear()
+        .args(["download", "--folder=Inbox", "--stdout", "--format=mbox"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_require_kdf() {
+    cmd()
+        .env_clear()
+        .args(["--require-kdf=scrypt", "list-folders"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_user_agent() {
+    // header values must be valid
+    let assert = cmd()
+        .env_clear()
+        .args(["--user-agent=a\nb", "list-folders"])
+        .assert()
+        .code(1);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    assert!(stderr.contains("set up HTTPs client"), "{stderr}");
+}
+
+#[test]
+fn test_max_rate() {
+    cmd()
+        .env_clear()
+        .args(["--max-rate=0", "list-folders"])
+        .assert()
+        .code(2);
+}
+
+#[test]
+fn test_download_one_invalid_id() {
+    let mut cmd = cmd();
+    cmd.args([
+        "download-one",
+        "--id=foo",
+        "--list-id=Nqcfn0k-----",
+        "--path=out.eml",
+    ]);
+    let assert = cmd.assert().failure();
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    assert!(stderr.contains("not a valid ID"), "{stderr}");
+}
+
+#[test]
+fn test_download_one_stdout_conflicts_with_path() {
+    let mut cmd = cmd();
+    cmd.args([
+        "download-one",
+        "--id=Nqcfn0k-----",
+        "--list-id=Nqcfn0k-----",
+        "--path=out.eml",
+        "--stdout",
+    ]);
+    let assert = cmd.assert().code(2);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    assert!(stderr.contains("cannot be used with"), "{stderr}");
+}
+
+#[test]
+fn test_raw_get_requires_allow_raw() {
+    let assert = cmd()
+        .env_clear()
+        .args(["raw-get", "--prefix=sys", "--path=user/x"])
+        .assert()
+        .code(2);
+    let stderr = String::from_utf8_lossy(&assert.get_output().stderr);
+    assert!(stderr.contains("--allow-raw"), "{stderr}");
+}
+
+/// Reference output must be readable by a real MIME parser.
+#[test]
+fn test_reference_parses() {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests");
+    path.push("reference");
+
+    let files = read_files(&path);
+    assert!(!files.is_empty());
+    for (fname, content) in files {
+        let parsed = mail_parser::MessageParser::default()
+            .parse(content.as_bytes())
+            .unwrap_or_else(|| panic!("cannot parse {fname}"));
+        assert!(parsed.from().is_some(), "{fname}");
+        assert!(parsed.subject().is_some(), "{fname}");
+        assert!(parsed.body_html(0).is_some(), "{fname}");
+    }
+}
+
+#[test]
+fn test_verify_archive() {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests");
+    path.push("reference");
+
+    // works without credentials
+    cmd()
+        .env_clear()
+        .args(["verify-archive", "--json", "--path"])
+        .arg(&path)
+        .assert()
+        .success()
+        .stdout(r#"{"checked":7,"corrupt":[],"missing":[]}"#.to_owned() + "\n");
+
+    let dir = TempDir::new().unwrap();
+    let (fname, content) = read_files(&path).into_iter().next().unwrap();
+    std::fs::write(
+        dir.path().join(&fname),
+        &content.as_bytes()[..content.len() / 2],
+    )
+    .unwrap();
+    let assert = cmd()
+        .env_clear()
+        .args(["verify-archive", "--path"])
+        .arg(dir.path())
+        .assert()
+        .code(1);
+    let stdout = String::from_utf8_lossy(&assert.get_output().stdout);
+    assert!(stdout.starts_with("corrupt\t"), "{stdout}");
+    assert!(stdout.contains(&fname), "{stdout}");
+}
+
 fn cmd() -> Command {
     Command::cargo_bin(env!("CARGO_PKG_NAME")).unwrap()
 }
 
+/// Read all files of a directory, except for hidden state files like the index.
 fn read_files(path: &Path) -> HashMap<String, String> {
     let mut out = HashMap::default();
 
     for f in std::fs::read_dir(path).unwrap() {
         let f = f.unwrap();
+        let name = f.path().file_name().unwrap().to_str().unwrap().to_owned();
+        if name.starts_with('.') {
+            continue;
+        }
         assert!(f.file_type().unwrap().is_file());
-        out.insert(
-            f.path().file_name().unwrap().to_str().unwrap().to_owned(),
-            std::fs::read_to_string(f.path()).unwrap(),
-        );
+        out.insert(name, std::fs::read_to_string(f.path()).unwrap());
     }
 
     out
@@ -73,12 +253,12 @@ mod integration {
         let stdout = String::from_utf8(res.get_output().stdout.clone()).unwrap();
 
         insta::assert_snapshot!(stdout, @r###"
-        Inbox
-        Sent
-        Trash
-        Archive
-        Spam
-        Draft
+        Inbox (inbox)
+        Sent (sent)
+        Trash (trash)
+        Archive (archive)
+        Spam (spam)
+        Draft (drafts)
         fooooo
         "###);
     }
@@ -112,7 +292,31 @@ mod integration {
         for fname in actual_files {
             let actual_content = actual.get(fname).unwrap();
             let expected_content = expected.get(fname).unwrap();
-            similar_asserts::assert_eq!(actual_content, expected_content);
+            let actual_content = mask_message_id(actual_content, expected_content);
+            similar_asserts::assert_eq!(&actual_content, expected_content);
         }
     }
+
+    /// Replace the `Message-ID` of `actual` by the `<...>` placeholder if `expected` uses it.
+    ///
+    /// Mails without original headers get the ID that Tuta stored in the conversation entry, which
+    /// differs between test accounts.
+    fn mask_message_id(actual: &str, expected: &str) -> String {
+        const PLACEHOLDER: &str = "Message-ID: <...>";
+        if !expected.lines().any(|l| l == PLACEHOLDER) {
+            return actual.to_owned();
+        }
+
+        actual
+            .split("\r\n")
+            .map(|l| {
+                if l.starts_with("Message-ID: ") {
+                    PLACEHOLDER
+                } else {
+                    l
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\r\n")
+    }
 }